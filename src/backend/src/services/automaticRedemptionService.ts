@@ -242,6 +242,11 @@ export class AutomaticRedemptionService {
         const submissionResult = await algorandMainnet.submitTransaction(new Uint8Array(encodedSignedTxn));
         job.algoTransactionId = submissionResult.txId;
 
+        const redemptionId = SimplifiedBridgeService.redemptionIdOf(job.burnResult?.redemption_id ?? '');
+        if (redemptionId !== null) {
+          await this.simplifiedBridgeService.completeRedemption(redemptionId, submissionResult.txId);
+        }
+
         console.log(`✅ ALGO withdrawal submitted to Algorand mainnet: ${job.algoTransactionId} (round ${submissionResult.confirmedRound})`);
       }
 
@@ -282,6 +287,16 @@ export class AutomaticRedemptionService {
 
         console.error(`❌ Redemption job ${job.id} failed permanently after ${job.attempts} attempts`);
 
+        // The ALGO never left: release the redemption so the user's ckALGO is minted back
+        const redemptionId = SimplifiedBridgeService.redemptionIdOf(job.burnResult?.redemption_id ?? '');
+        if (job.ckAlgoBurned && !job.algoTransactionId && redemptionId !== null) {
+          try {
+            await this.simplifiedBridgeService.failRedemption(redemptionId, errorMessage);
+          } catch (releaseError) {
+            console.error(`❌ Could not release redemption ${redemptionId} for job ${job.id}:`, releaseError);
+          }
+        }
+
         // Send alert for failed redemption
         if (this.alertManager) {
           const alert: Alert = {
@@ -0,0 +1,36 @@
+/**
+ * Backend Identity
+ * The principal the backend signs canister calls with. The simplified bridge
+ * rejects anonymous update calls, so this principal must be passed in the
+ * bridge's InitArgs.minters, and the threshold signer only signs for it if it
+ * is the signer's SignerInitArgs.backend (see docs/deployment/quick-reference.md).
+ */
+
+import { Ed25519KeyIdentity } from '@dfinity/identity';
+
+const SEED_ENV = 'SIPPAR_BACKEND_IDENTITY_SEED';
+
+let cachedIdentity: Ed25519KeyIdentity | null = null;
+
+/**
+ * Load the backend's Ed25519 identity from SIPPAR_BACKEND_IDENTITY_SEED
+ * (32 bytes, hex encoded). Throws if it is missing or malformed: falling back
+ * to the anonymous identity would make every privileged call fail later.
+ */
+export function getBackendIdentity(): Ed25519KeyIdentity {
+  if (cachedIdentity) {
+    return cachedIdentity;
+  }
+
+  const seedHex = process.env[SEED_ENV]?.trim();
+  if (!seedHex) {
+    throw new Error(`${SEED_ENV} is not set: the backend needs an identity to call the bridge and signer canisters`);
+  }
+  if (!/^[0-9a-fA-F]{64}$/.test(seedHex)) {
+    throw new Error(`${SEED_ENV} must be 32 bytes of hex (64 characters)`);
+  }
+
+  cachedIdentity = Ed25519KeyIdentity.generate(Uint8Array.from(Buffer.from(seedHex, 'hex')));
+  console.log(`🔑 Backend identity: ${cachedIdentity.getPrincipal().toText()}`);
+  return cachedIdentity;
+}
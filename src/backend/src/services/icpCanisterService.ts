@@ -5,7 +5,7 @@
 
 import { Actor, HttpAgent } from '@dfinity/agent';
 import { Principal } from '@dfinity/principal';
-import { Ed25519KeyIdentity } from '@dfinity/identity';
+import { getBackendIdentity } from './backendIdentity.js';
 import { getCachedOrDeriveAddress, getCacheStats } from './localAddressDerivation.js';
 
 // In-memory cache for address derivations to save 15B cycles per lookup
@@ -96,10 +96,14 @@ export class ICPCanisterService {
 
   constructor(canisterId: string, hostUrl: string = 'http://127.0.0.1:4943') {
     this.canisterId = canisterId;
+    const isLocal = hostUrl.includes('127.0.0.1') || hostUrl.includes('localhost');
     
-    // Create HTTP Agent with proper timeout and retry configuration
+    // Create HTTP Agent with proper timeout and retry configuration. On mainnet
+    // it signs as the backend principal: the signer only signs transactions for
+    // the bridge canister and the backend principal given at install
     this.agent = new HttpAgent({ 
       host: hostUrl,
+      ...(!isLocal && { identity: getBackendIdentity() }),
       // Add timeout for mainnet calls to prevent hanging
       ...(hostUrl.includes('ic0.app') && {
         fetchOptions: {
@@ -109,7 +113,7 @@ export class ICPCanisterService {
     });
     
     // Only fetch root key in local development
-    if (hostUrl.includes('127.0.0.1') || hostUrl.includes('localhost')) {
+    if (isLocal) {
       this.agent.fetchRootKey().catch(console.error);
     }
     
@@ -119,31 +123,12 @@ export class ICPCanisterService {
       console.log('🌐 Configured for ICP mainnet with 30s timeout');
     }
     
-    // For mainnet, try to load identity from environment or use anonymous
-    if (!hostUrl.includes('127.0.0.1') && !hostUrl.includes('localhost')) {
-      this.setupMainnetIdentity();
-    }
-    
     this.actor = Actor.createActor(idlFactory, {
       agent: this.agent,
       canisterId: this.canisterId,
     });
   }
 
-  private setupMainnetIdentity() {
-    try {
-      // For production, the canister should allow anonymous calls for threshold operations
-      // or we need to set up proper identity management
-      console.log('🔧 Setting up mainnet identity...');
-      
-      // The threshold signature canister should be configured to accept calls
-      // from the subnet or use proper authentication
-      
-    } catch (error) {
-      console.warn('⚠️ Failed to setup identity:', error);
-    }
-  }
-
   /**
    * Test canister connectivity
    */
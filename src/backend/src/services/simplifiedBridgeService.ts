@@ -6,6 +6,7 @@
 
 import { HttpAgent, Actor } from '@dfinity/agent';
 import { Principal } from '@dfinity/principal';
+import { getBackendIdentity } from './backendIdentity.js';
 
 // Simplified Bridge IDL matching the deployed canister
 const simplifiedBridgeIdl = ({ IDL }: any) => {
@@ -38,6 +39,21 @@ const simplifiedBridgeIdl = ({ IDL }: any) => {
     'tx_id': IDL.Text,
   });
 
+  const SwapRecordQuery = IDL.Record({
+    'cursor': IDL.Opt(IDL.Nat64),
+    'limit': IDL.Opt(IDL.Nat32),
+    'user': IDL.Opt(IDL.Principal),
+    'from_time': IDL.Opt(IDL.Nat64),
+    'to_time': IDL.Opt(IDL.Nat64),
+    'direction': IDL.Opt(IDL.Variant({ 'ToCkAlgo': IDL.Null, 'FromCkAlgo': IDL.Null })),
+    'pair': IDL.Opt(IDL.Principal),
+  });
+
+  const SwapRecordPage = IDL.Record({
+    'records': IDL.Vec(IDL.Record({ 'index': IDL.Nat64, 'record': SwapRecord })),
+    'next_cursor': IDL.Opt(IDL.Nat64),
+  });
+
   const SwapConfig = IDL.Record({
     'enabled': IDL.Bool,
     'fee_bps': IDL.Nat64,
@@ -54,6 +70,12 @@ const simplifiedBridgeIdl = ({ IDL }: any) => {
     'cketh_block_index': IDL.Nat,
   });
 
+  const CachedEthAlgoRate = IDL.Record({
+    'rate': IDL.Float64,
+    'age_secs': IDL.Nat64,
+    'fresh': IDL.Bool,
+  });
+
   return IDL.Service({
     // ICRC-1 Standard Methods
     'icrc1_name': IDL.Func([], [IDL.Text], ['query']),
@@ -74,6 +96,8 @@ const simplifiedBridgeIdl = ({ IDL }: any) => {
     'redeem_ck_algo': IDL.Func([IDL.Nat, IDL.Text], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
     'admin_redeem_ck_algo': IDL.Func([IDL.Principal, IDL.Nat, IDL.Text], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
     'admin_transfer_ck_algo': IDL.Func([IDL.Principal, IDL.Principal, IDL.Nat], [IDL.Variant({ 'Ok': IDL.Nat, 'Err': IDL.Text })], []),
+    'complete_redemption': IDL.Func([IDL.Nat64, IDL.Text], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
+    'fail_redemption': IDL.Func([IDL.Nat64, IDL.Text], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
     'get_reserve_ratio': IDL.Func([], [ReserveStatus], ['query']),
     'get_user_deposits': IDL.Func([IDL.Principal], [IDL.Vec(DepositRecord)], ['query']),
 
@@ -89,14 +113,14 @@ const simplifiedBridgeIdl = ({ IDL }: any) => {
     ),
     'get_current_eth_algo_rate': IDL.Func(
       [],
-      [IDL.Variant({ 'Ok': IDL.Float64, 'Err': IDL.Text })],
-      []
+      [IDL.Variant({ 'Ok': CachedEthAlgoRate, 'Err': IDL.Text })],
+      ['query']
     ),
     'set_swap_enabled': IDL.Func([IDL.Bool], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
     'set_swap_fee_bps': IDL.Func([IDL.Nat64], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
     'set_swap_limits': IDL.Func([IDL.Nat, IDL.Nat], [IDL.Variant({ 'Ok': IDL.Text, 'Err': IDL.Text })], []),
     'get_swap_config': IDL.Func([], [SwapConfig], ['query']),
-    'get_swap_records': IDL.Func([IDL.Opt(IDL.Nat32)], [IDL.Vec(SwapRecord)], ['query']),
+    'get_swap_records': IDL.Func([SwapRecordQuery], [SwapRecordPage], ['query']),
 
     // Deposit-Based Swap Functions (Autonomous Agent Flow)
     'get_swap_custody_subaccount': IDL.Func([IDL.Principal], [IDL.Vec(IDL.Nat8)], ['query']),
@@ -171,10 +195,12 @@ export class SimplifiedBridgeService {
 
   private initializeAgent(): void {
     try {
-      // Create HTTP agent for mainnet with proper configuration
+      // Create HTTP agent for mainnet, signing as the backend principal: the
+      // bridge rejects anonymous update calls and checks roles on each method
       this.agent = new HttpAgent({
         host: 'https://ic0.app',
-        verifyQuerySignatures: false // Required for anonymous calls in production
+        identity: getBackendIdentity(),
+        verifyQuerySignatures: false
       });
 
       // Fetch root key for certificate validation (required for mainnet)
@@ -413,6 +439,42 @@ export class SimplifiedBridgeService {
     }, `adminRedeemCkAlgo(${user.toString()}, ${amount}, ${destination})`);
   }
 
+  /**
+   * Redemption ID embedded in a redeem result ("REDEEM_<id>_<destination>" or "WITHDRAW_<id>")
+   */
+  static redemptionIdOf(redeemResult: string): bigint | null {
+    const match = /^(?:REDEEM|WITHDRAW)_(\d+)/.exec(redeemResult);
+    return match ? BigInt(match[1]) : null;
+  }
+
+  /**
+   * Operator function: report a redemption's ALGO payout as confirmed
+   */
+  async completeRedemption(redemptionId: bigint, algorandTxId: string): Promise<string> {
+    return this.retryOperation(async () => {
+      const result = await this.actor.complete_redemption(redemptionId, algorandTxId);
+      if ('Ok' in result) {
+        return result.Ok;
+      } else {
+        throw new Error(`Complete redemption failed: ${result.Err}`);
+      }
+    }, `completeRedemption(${redemptionId}, ${algorandTxId})`);
+  }
+
+  /**
+   * Operator function: report a redemption's ALGO payout as failed (re-mints the burned ckALGO)
+   */
+  async failRedemption(redemptionId: bigint, reason: string): Promise<string> {
+    return this.retryOperation(async () => {
+      const result = await this.actor.fail_redemption(redemptionId, reason);
+      if ('Ok' in result) {
+        return result.Ok;
+      } else {
+        throw new Error(`Fail redemption failed: ${result.Err}`);
+      }
+    }, `failRedemption(${redemptionId})`);
+  }
+
   /**
    * Admin function: transfer ckALGO from one principal to another
    * Used by X402 payment system to move tokens from payer to treasury
@@ -486,13 +548,13 @@ export class SimplifiedBridgeService {
   }
 
   /**
-   * Get current ETH/ALGO exchange rate from XRC
+   * Get the canister's cached ETH/ALGO exchange rate (free query, no XRC call)
    */
   async getCurrentEthAlgoRate(): Promise<number> {
     return this.retryOperation(async () => {
       const result = await this.actor.get_current_eth_algo_rate();
       if ('Ok' in result) {
-        return Number(result.Ok);
+        return Number(result.Ok.rate);
       } else {
         throw new Error(`Failed to get exchange rate: ${result.Err}`);
       }
@@ -504,9 +566,16 @@ export class SimplifiedBridgeService {
    */
   async getSwapRecords(limit?: number): Promise<SwapRecord[]> {
     return this.retryOperation(async () => {
-      const limitOpt = limit !== undefined ? [limit] : [];
-      const result = await this.actor.get_swap_records(limitOpt);
-      return result.map((r: any) => ({
+      const result = await this.actor.get_swap_records({
+        cursor: [],
+        limit: limit !== undefined ? [limit] : [],
+        user: [],
+        from_time: [],
+        to_time: [],
+        direction: [],
+        pair: [],
+      });
+      return result.records.map(({ record: r }: any) => ({
         user: r.user,
         cketh_in: BigInt(r.cketh_in.toString()),
         ckalgo_out: BigInt(r.ckalgo_out.toString()),
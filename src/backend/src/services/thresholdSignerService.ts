@@ -6,6 +6,7 @@
 import { HttpAgent, Actor } from '@dfinity/agent';
 import { Principal } from '@dfinity/principal';
 import crypto from 'crypto';
+import { getBackendIdentity } from './backendIdentity.js';
 
 // Threshold Signer Types (copied from canister declarations)
 export interface AlgorandAddress {
@@ -76,7 +77,8 @@ export class ThresholdSignerServiceClient {
 
   constructor() {
     this.canisterId = 'vj7ly-diaaa-aaaae-abvoq-cai'; // Threshold signer canister ID
-    this.agent = new HttpAgent({ host: 'https://ic0.app' });
+    // Signed as the backend principal, which the signer allowlists for signing
+    this.agent = new HttpAgent({ host: 'https://ic0.app', identity: getBackendIdentity() });
     this.chainFusionEndpoint = 'http://localhost:9002'; // Chain-fusion backend
     
     // Initialize actor
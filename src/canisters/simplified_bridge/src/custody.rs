@@ -0,0 +1,1045 @@
+use super::*;
+
+// ============================================================================
+// CUSTODY SWEEP TYPES
+// ============================================================================
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum SweepStatus {
+    Signed,      // Signed by threshold_signer, waiting for backend to submit
+    Confirmed,   // Backend reported the transaction confirmed on Algorand
+    Failed,      // Rejected or expired - amount returned to the custody balance
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SweepRecord {
+    pub sweep_id: u64,
+    pub custody_address: String,
+    pub owner: Principal,
+    pub destination: String,
+    pub amount: Nat,                  // microALGO moved to the hot wallet
+    pub fee: Nat,                     // Algorand network fee paid by the custody address
+    pub algorand_tx_id: String,
+    pub signed_transaction: Vec<u8>,  // msgpack SignedTxn, ready for submission
+    pub first_valid: u64,
+    pub last_valid: u64,
+    pub status: SweepStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub error: Option<String>,
+}
+
+/// Algorand network parameters reported by the backend (canister has no node access)
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct AlgorandNetworkParams {
+    pub genesis_id: String,
+    pub genesis_hash: Vec<u8>,
+    pub last_round: u64,
+    pub min_fee: u64,
+    pub reported_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SweepConfig {
+    pub enabled: bool,
+    pub hot_wallet_address: Option<String>,
+    pub threshold: Nat,       // Minimum sweepable microALGO before a sweep is worth it
+    pub interval_secs: u64,
+    pub network: Option<AlgorandNetworkParams>,
+}
+
+impl Default for SweepConfig {
+    fn default() -> Self {
+        SweepConfig {
+            enabled: false,
+            hot_wallet_address: None,
+            threshold: Nat::from(10_000_000u64), // 10 ALGO
+            interval_secs: 3_600,
+            network: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SweepStatusSummary {
+    pub config: SweepConfig,
+    pub custody_balances_total: Nat,
+    pub in_flight_sweeps: Nat,
+    pub hot_wallet_balance: Nat,
+    pub sweeps_pending: u64,
+}
+
+// ============================================================================
+// CUSTODY DEPOSIT WATCHER
+// ============================================================================
+//
+// Agents that opt in via set_auto_swap_preference don't need to ping the
+// backend: a timer polls icrc1_balance_of on their ckETH custody subaccounts,
+// swaps any uncredited balance at the current rate and sweeps the swapped ckETH
+// into the canister's main (treasury) account.
+//
+// Per (ledger, agent), CUSTODY_CREDITED tracks what has been swapped but not yet
+// swept, so only balance - credited is ever treated as a new deposit, whether
+// it is picked up here or by swap_deposit_to_ckalgo.
+//
+// The watcher credits a balance, not individual blocks, so it also records the
+// ledger log length at the time (CUSTODY_CREDITED_THROUGH). Deposit blocks below
+// that may be part of what it swapped and can no longer be swapped or refunded
+// one by one.
+
+pub(crate) const MAX_AUTO_SWAP_SLIPPAGE_BPS: u64 = 1_000;  // 10%
+
+/// Per-custody-account lock shared by the watcher, deposit swaps and treasury sweeps
+pub(crate) struct CustodyGuard {
+    key: (Principal, Principal),
+}
+
+impl CustodyGuard {
+    pub(crate) fn acquire(ledger: Principal, agent: Principal) -> Result<Self, String> {
+        let key = (ledger, agent);
+        if !CUSTODY_LOCKS.with(|locks| locks.borrow_mut().insert(key)) {
+            return Err(format!("Custody account of {} is busy. Retry shortly.", agent));
+        }
+        Ok(CustodyGuard { key })
+    }
+}
+
+impl Drop for CustodyGuard {
+    fn drop(&mut self) {
+        CUSTODY_LOCKS.with(|locks| locks.borrow_mut().remove(&self.key));
+    }
+}
+
+pub(crate) fn custody_account(agent: &Principal) -> Account {
+    Account {
+        owner: ic_cdk::api::id(),
+        subaccount: Some(derive_custody_subaccount(agent)),
+    }
+}
+
+pub(crate) fn custody_credited(ledger: &Principal, agent: &Principal) -> Nat {
+    CUSTODY_CREDITED.with(|c| c.borrow().get(&(*ledger, *agent)).map(|v| v.0).unwrap_or(Nat::from(0u64)))
+}
+
+pub(crate) fn add_custody_credited(ledger: Principal, agent: Principal, amount: &Nat) {
+    let credited = custody_credited(&ledger, &agent) + amount.clone();
+    CUSTODY_CREDITED.with(|c| c.borrow_mut().insert((ledger, agent), StableCandid(credited)));
+}
+
+pub(crate) async fn ledger_balance_of(ledger: Principal, account: Account) -> Result<Nat, String> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(code, msg)| format!("icrc1_balance_of on {} failed: {:?} - {}", ledger, code, msg))?;
+    Ok(balance)
+}
+
+pub(crate) async fn ledger_fee(ledger: Principal) -> Result<Nat, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, msg)| format!("icrc1_fee on {} failed: {:?} - {}", ledger, code, msg))?;
+    Ok(fee)
+}
+
+/// Length of the ledger's transaction log; every existing block is below it
+pub(crate) async fn ledger_log_length(ledger: Principal) -> Result<Nat, String> {
+    let request = GetTransactionsRequest {
+        start: Nat::from(0u64),
+        length: Nat::from(0u64),
+    };
+    let (response,): (GetTransactionsResponse,) = ic_cdk::call(ledger, "get_transactions", (request,))
+        .await
+        .map_err(|(code, msg)| format!("get_transactions on {} failed: {:?} - {}", ledger, code, msg))?;
+    Ok(response.log_length)
+}
+
+/// Reject a deposit block the watcher may already have swapped as part of the
+/// custody balance
+pub(crate) fn check_deposit_not_bulk_credited(ledger: &Principal, agent: &Principal, block_index: &Nat) -> Result<(), String> {
+    let credited_through = CUSTODY_CREDITED_THROUGH.with(|c| c.borrow().get(&(*ledger, *agent)).map(|v| v.0));
+    match credited_through {
+        Some(through) if *block_index < through => Err(format!(
+            "Deposit {} may already have been swapped by the deposit watcher (credited through block {})",
+            block_index, through
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Custody subaccount balance not yet credited to the agent as ckALGO
+pub(crate) async fn uncredited_custody_balance(ledger: &Principal, agent: &Principal) -> Result<Nat, String> {
+    let balance = ledger_balance_of(*ledger, custody_account(agent)).await?;
+    let credited = custody_credited(ledger, agent);
+    Ok(if balance > credited { balance - credited } else { Nat::from(0u64) })
+}
+
+pub(crate) fn schedule_deposit_watcher_timer() {
+    if let Some(timer_id) = DEPOSIT_WATCHER_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+
+    let config = DEPOSIT_WATCHER_CONFIG.with(|c| c.borrow().clone());
+    if !config.enabled || config.interval_secs == 0 {
+        return;
+    }
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(config.interval_secs), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = run_deposit_watch_cycle().await {
+                ic_cdk::println!("Deposit watcher cycle skipped: {}", e);
+            }
+        })
+    });
+    DEPOSIT_WATCHER_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+pub(crate) async fn run_deposit_watch_cycle() -> Result<DepositWatcherReport, String> {
+    ensure_no_state_export()?;
+    if DEPOSIT_WATCHER_IN_PROGRESS.with(|flag| flag.replace(true)) {
+        return Err("Deposit watcher cycle already in progress".to_string());
+    }
+
+    let result = watch_custody_deposits().await;
+
+    DEPOSIT_WATCHER_IN_PROGRESS.with(|flag| *flag.borrow_mut() = false);
+    if let Ok(report) = &result {
+        DEPOSIT_WATCHER_LAST_RUN.with(|t| *t.borrow_mut() = time());
+        DEPOSIT_WATCHER_LAST_REPORT.with(|r| *r.borrow_mut() = Some(report.clone()));
+    }
+    result
+}
+
+/// Up to `max` agents in principal order, starting after `cursor` and wrapping
+/// around, so consecutive cycles cover every agent when there are more than `max`
+pub(crate) fn next_agent_batch<T>(mut agents: Vec<(Principal, T)>, cursor: Option<Principal>, max: usize) -> Vec<(Principal, T)> {
+    agents.sort_by_key(|(agent, _)| *agent);
+    let start = cursor.map_or(0, |cursor| agents.partition_point(|(agent, _)| *agent <= cursor));
+    agents.rotate_left(start);
+    agents.truncate(max);
+    agents
+}
+
+pub(crate) async fn watch_custody_deposits() -> Result<DepositWatcherReport, String> {
+    let pair = active_swap_pair(&cketh_ledger())?;
+    let config = DEPOSIT_WATCHER_CONFIG.with(|c| c.borrow().clone());
+
+    // Opted-in agents, plus any agent whose swapped ckETH still awaits a treasury sweep
+    let mut agents: Vec<(Principal, Option<AutoSwapPreference>)> = AUTO_SWAP_PREFERENCES.with(|p| {
+        p.borrow().values().map(|pref| pref.0).filter(|pref| pref.enabled).map(|pref| (pref.agent, Some(pref))).collect()
+    });
+    let unswept: Vec<Principal> = CUSTODY_CREDITED.with(|c| {
+        c.borrow().keys().filter(|(ledger, _)| *ledger == pair.ledger).map(|(_, agent)| agent).collect()
+    });
+    for agent in unswept {
+        if !agents.iter().any(|(a, _)| *a == agent) {
+            agents.push((agent, None));
+        }
+    }
+    let cursor = DEPOSIT_WATCHER_CURSOR.with(|c| *c.borrow());
+    let agents = next_agent_batch(agents, cursor, config.max_agents_per_cycle as usize);
+    if let Some((last, _)) = agents.last() {
+        DEPOSIT_WATCHER_CURSOR.with(|c| *c.borrow_mut() = Some(*last));
+    }
+
+    let fee = ledger_fee(pair.ledger).await?;
+    let mut report = DepositWatcherReport {
+        agents_checked: 0,
+        swaps_executed: 0,
+        treasury_sweeps: 0,
+        errors: Vec::new(),
+    };
+
+    for (agent, preference) in agents {
+        report.agents_checked += 1;
+        if let Err(e) = process_custody_account(&pair, &agent, preference.as_ref(), &fee, &mut report).await {
+            report.errors.push(format!("{}: {}", agent, e));
+        }
+    }
+
+    Ok(report)
+}
+
+/// Swap any new deposit on one agent's custody subaccount (if the agent opted
+/// in), then sweep everything already credited into the treasury
+pub(crate) async fn process_custody_account(
+    pair: &SwapPair,
+    agent: &Principal,
+    preference: Option<&AutoSwapPreference>,
+    fee: &Nat,
+    report: &mut DepositWatcherReport
+) -> Result<(), String> {
+    let agent = *agent;
+    let _custody_guard = CustodyGuard::acquire(pair.ledger, agent)?;
+
+    // One ledger fee of the new deposit is held back to pay for the treasury sweep
+    if let Some(preference) = preference {
+        let uncredited = uncredited_custody_balance(&pair.ledger, &agent).await?;
+        if uncredited > *fee {
+            let mut amount = uncredited - fee.clone();
+            if amount > pair.max_amount {
+                amount = pair.max_amount.clone();
+            }
+
+            if amount >= pair.min_amount {
+                // Slippage is measured against the rate known when the deposit was detected
+                let reference_rate = LAST_ACCEPTED_RATES.with(|r| r.borrow().get(&pair.xrc_symbol).map(|a| a.rate.clone()));
+                let min_out = match reference_rate {
+                    Some(rate) => {
+                        let (expected, _) = compute_swap_output(&amount, pair.decimals, &rate, pair.fee_bps)?;
+                        Some(expected * Nat::from(BPS_DENOMINATOR - preference.max_slippage_bps) / Nat::from(BPS_DENOMINATOR))
+                    }
+                    None => None,
+                };
+
+                // Read after the balance, so every block that balance includes is below it
+                let log_length = ledger_log_length(pair.ledger).await?;
+                let price = resolve_swap_price(pair, &amount, None, min_out.as_ref()).await?;
+                add_custody_credited(pair.ledger, agent, &amount);
+                CUSTODY_CREDITED_THROUGH.with(|c| c.borrow_mut().insert((pair.ledger, agent), StableCandid(log_length)));
+                let tx_id = format!("AUTO_SWAP_{}_{}", agent, time());
+                credit_swap(pair, agent, amount, price, tx_id, Nat::from(0u64));
+                report.swaps_executed += 1;
+            }
+        }
+    }
+
+    if sweep_custody_to_treasury(pair, &agent, fee).await? {
+        report.treasury_sweeps += 1;
+    }
+    Ok(())
+}
+
+/// Move swapped (credited) funds from the agent's custody subaccount into the
+/// canister's main account. Caller must hold the custody guard.
+pub(crate) async fn sweep_custody_to_treasury(pair: &SwapPair, agent: &Principal, fee: &Nat) -> Result<bool, String> {
+    let credited = custody_credited(&pair.ledger, agent);
+    if credited <= *fee {
+        return Ok(false);
+    }
+
+    // Normally the held-back fee pays for the transfer; if it is not there
+    // (deposit swapped in full via swap_deposit_to_ckalgo) the fee comes out of
+    // the credited amount and the treasury absorbs it.
+    let balance = ledger_balance_of(pair.ledger, custody_account(agent)).await?;
+    let amount = if balance >= credited.clone() + fee.clone() {
+        credited.clone()
+    } else if balance > *fee {
+        balance.clone() - fee.clone()
+    } else {
+        return Ok(false);
+    };
+
+    let transfer_args = TransferArgs {
+        from_subaccount: Some(derive_custody_subaccount(agent)),
+        to: Account {
+            owner: ic_cdk::api::id(),
+            subaccount: None,
+        },
+        amount: amount.clone(),
+        fee: Some(fee.clone()),
+        memo: None,
+        created_at_time: Some(time()),
+    };
+
+    let transfer_result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(pair.ledger, "icrc1_transfer", (transfer_args,)).await;
+
+    let spent = if amount == credited { amount.clone() } else { amount.clone() + fee.clone() };
+    match transfer_result {
+        Ok((Ok(block_index),)) => {
+            release_custody_credited(pair.ledger, *agent, &spent);
+            ic_cdk::println!(
+                "Swept {} {} from custody of {} to treasury, block {}",
+                amount, pair.symbol, agent, block_index
+            );
+            Ok(true)
+        }
+        Ok((Err(e),)) => Err(format!("{} treasury sweep failed: {:?}", pair.symbol, e)),
+        Err((code, msg)) => {
+            // The transfer may have executed anyway. If the custody balance dropped
+            // by the full debit it did, and the credit must be released, or the next
+            // sweep would move uncredited deposits into the treasury.
+            let debit = amount.clone() + fee.clone();
+            match ledger_balance_of(pair.ledger, custody_account(agent)).await {
+                Ok(after) if after.clone() + debit <= balance => {
+                    release_custody_credited(pair.ledger, *agent, &spent);
+                    ic_cdk::println!(
+                        "Swept {} {} from custody of {} to treasury (reply lost: {:?} - {})",
+                        amount, pair.symbol, agent, code, msg
+                    );
+                    Ok(true)
+                }
+                Ok(_) => Err(format!("Inter-canister call to {} failed: {:?} - {}", pair.symbol, code, msg)),
+                Err(e) => Err(format!(
+                    "{} treasury sweep outcome unknown ({:?} - {}) and the custody balance could not be re-checked: {}",
+                    pair.symbol, code, msg, e
+                )),
+            }
+        }
+    }
+}
+
+/// Forget `spent` of the credited (swapped but unswept) custody balance
+pub(crate) fn release_custody_credited(ledger: Principal, agent: Principal, spent: &Nat) {
+    CUSTODY_CREDITED.with(|c| {
+        let mut c = c.borrow_mut();
+        let key = (ledger, agent);
+        let remaining = c.get(&key).map(|v| v.0).unwrap_or(Nat::from(0u64));
+        if remaining > *spent {
+            c.insert(key, StableCandid(remaining - spent.clone()));
+        } else {
+            c.remove(&key);
+        }
+    });
+}
+
+/// Opt an agent in or out of automatic ckETH swaps
+///
+/// Callable by the agent itself or a SwapExecutor.
+#[update]
+pub(crate) fn set_auto_swap_preference(agent: Principal, max_slippage_bps: u64, enabled: bool) -> Result<String, String> {
+    let caller_principal = caller();
+    if caller_principal != agent && !has_role(&caller_principal, Role::SwapExecutor) {
+        return Err(format!(
+            "Unauthorized: only the agent or a SwapExecutor can set auto-swap preferences. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+    if agent == Principal::anonymous() {
+        return Err("Anonymous principal cannot use auto-swaps".to_string());
+    }
+    if max_slippage_bps > MAX_AUTO_SWAP_SLIPPAGE_BPS {
+        return Err(format!("max_slippage_bps cannot exceed {}", MAX_AUTO_SWAP_SLIPPAGE_BPS));
+    }
+
+    AUTO_SWAP_PREFERENCES.with(|p| {
+        p.borrow_mut().insert(agent, StableCandid(AutoSwapPreference {
+            agent,
+            max_slippage_bps,
+            enabled,
+            updated_at: time(),
+        }))
+    });
+
+    Ok(format!(
+        "Auto-swap {} for {} (max slippage {} bps)",
+        if enabled { "enabled" } else { "disabled" }, agent, max_slippage_bps
+    ))
+}
+
+#[query]
+pub(crate) fn get_auto_swap_preference(agent: Principal) -> Option<AutoSwapPreference> {
+    AUTO_SWAP_PREFERENCES.with(|p| p.borrow().get(&agent).map(|pref| pref.0))
+}
+
+pub(crate) fn check_deposit_watcher_config(config: &DepositWatcherConfig) -> Result<(), String> {
+    if config.enabled && config.interval_secs < 10 {
+        return Err("interval_secs must be at least 10".to_string());
+    }
+    if config.max_agents_per_cycle == 0 {
+        return Err("max_agents_per_cycle must be at least 1".to_string());
+    }
+    Ok(())
+}
+
+/// Configure the deposit watcher timer (Admin role)
+#[update]
+pub(crate) fn set_deposit_watcher_config(config: DepositWatcherConfig) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Configuring the deposit watcher requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_deposit_watcher_config(&config)?;
+
+    DEPOSIT_WATCHER_CONFIG.with(|c| *c.borrow_mut() = config.clone());
+    schedule_deposit_watcher_timer();
+    Ok(format!(
+        "Deposit watcher {}: every {}s, up to {} agents per cycle",
+        if config.enabled { "enabled" } else { "disabled" },
+        config.interval_secs, config.max_agents_per_cycle
+    ))
+}
+
+/// Run one watcher cycle now (Operator role)
+#[update]
+pub(crate) async fn run_deposit_watcher() -> Result<DepositWatcherReport, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: running the deposit watcher requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    run_deposit_watch_cycle().await
+}
+
+#[query]
+pub(crate) fn get_deposit_watcher_status() -> DepositWatcherStatus {
+    DepositWatcherStatus {
+        config: DEPOSIT_WATCHER_CONFIG.with(|c| c.borrow().clone()),
+        watched_agents: AUTO_SWAP_PREFERENCES.with(|p| p.borrow().values().filter(|pref| pref.0.enabled).count() as u64),
+        last_run: DEPOSIT_WATCHER_LAST_RUN.with(|t| *t.borrow()),
+        last_report: DEPOSIT_WATCHER_LAST_REPORT.with(|r| r.borrow().clone()),
+    }
+}
+
+// ============================================================================
+// ALGORAND TRANSACTION ENCODING
+// ============================================================================
+//
+// Minimal canonical msgpack encoder for payment transactions. Keys must be
+// sorted and zero-valued fields omitted, matching algosdk's encoding, or the
+// transaction ID and signature will not match what the network computes.
+
+pub(crate) const ALGORAND_BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub(crate) fn msgpack_uint(out: &mut Vec<u8>, value: u64) {
+    if value < 0x80 {
+        out.push(value as u8);
+    } else if value <= u8::MAX as u64 {
+        out.push(0xcc);
+        out.push(value as u8);
+    } else if value <= u16::MAX as u64 {
+        out.push(0xcd);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        out.push(0xce);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(0xcf);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+pub(crate) fn msgpack_str(out: &mut Vec<u8>, value: &str) {
+    let len = value.len();
+    if len < 32 {
+        out.push(0xa0 | len as u8);
+    } else if len <= u8::MAX as usize {
+        out.push(0xd9);
+        out.push(len as u8);
+    } else {
+        out.push(0xda);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    out.extend_from_slice(value.as_bytes());
+}
+
+pub(crate) fn msgpack_bin(out: &mut Vec<u8>, value: &[u8]) {
+    let len = value.len();
+    if len <= u8::MAX as usize {
+        out.push(0xc4);
+        out.push(len as u8);
+    } else {
+        out.push(0xc5);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    out.extend_from_slice(value);
+}
+
+/// Decode a 58-character Algorand address into its 32-byte public key,
+/// verifying the SHA-512/256 checksum
+pub(crate) fn decode_algorand_address(address: &str) -> Result<[u8; 32], String> {
+    if address.len() != 58 {
+        return Err(format!("Invalid Algorand address length: {}", address.len()));
+    }
+
+    let mut bytes = Vec::with_capacity(37);
+    let mut buffer = 0u64;
+    let mut bits_in_buffer = 0;
+    for c in address.bytes() {
+        let value = ALGORAND_BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| format!("Invalid character in Algorand address: {}", c as char))?;
+        buffer = (buffer << 5) | value as u64;
+        bits_in_buffer += 5;
+        if bits_in_buffer >= 8 {
+            bits_in_buffer -= 8;
+            bytes.push((buffer >> bits_in_buffer) as u8);
+        }
+    }
+
+    let mut public_key = [0u8; 32];
+    public_key.copy_from_slice(&bytes[..32]);
+    let checksum = Sha512_256::digest(public_key);
+    if bytes[32..36] != checksum[28..32] {
+        return Err(format!("Invalid Algorand address checksum: {}", address));
+    }
+
+    Ok(public_key)
+}
+
+pub(crate) fn algorand_base32_encode(data: &[u8]) -> String {
+    let mut result = String::new();
+    let mut buffer = 0u64;
+    let mut bits_in_buffer = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u64;
+        bits_in_buffer += 8;
+        while bits_in_buffer >= 5 {
+            bits_in_buffer -= 5;
+            result.push(ALGORAND_BASE32_ALPHABET[((buffer >> bits_in_buffer) & 0x1f) as usize] as char);
+        }
+    }
+    if bits_in_buffer > 0 {
+        result.push(ALGORAND_BASE32_ALPHABET[((buffer << (5 - bits_in_buffer)) & 0x1f) as usize] as char);
+    }
+
+    result
+}
+
+/// Payment transaction fields needed for a sweep
+pub(crate) struct AlgorandPayment<'a> {
+    pub(crate) sender: [u8; 32],
+    pub(crate) receiver: [u8; 32],
+    pub(crate) amount: u64,
+    pub(crate) fee: u64,
+    pub(crate) first_valid: u64,
+    pub(crate) last_valid: u64,
+    pub(crate) genesis_id: &'a str,
+    pub(crate) genesis_hash: &'a [u8],
+}
+
+impl AlgorandPayment<'_> {
+    /// Canonical msgpack encoding of the transaction (without the "TX" prefix)
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(256);
+        out.push(0x80 | 9); // fixmap, 9 entries
+        msgpack_str(&mut out, "amt");
+        msgpack_uint(&mut out, self.amount);
+        msgpack_str(&mut out, "fee");
+        msgpack_uint(&mut out, self.fee);
+        msgpack_str(&mut out, "fv");
+        msgpack_uint(&mut out, self.first_valid);
+        msgpack_str(&mut out, "gen");
+        msgpack_str(&mut out, self.genesis_id);
+        msgpack_str(&mut out, "gh");
+        msgpack_bin(&mut out, self.genesis_hash);
+        msgpack_str(&mut out, "lv");
+        msgpack_uint(&mut out, self.last_valid);
+        msgpack_str(&mut out, "rcv");
+        msgpack_bin(&mut out, &self.receiver);
+        msgpack_str(&mut out, "snd");
+        msgpack_bin(&mut out, &self.sender);
+        msgpack_str(&mut out, "type");
+        msgpack_str(&mut out, "pay");
+        out
+    }
+}
+
+/// Bytes the signer must sign: "TX" || msgpack(txn)
+pub(crate) fn algorand_bytes_to_sign(encoded_txn: &[u8]) -> Vec<u8> {
+    let mut bytes = b"TX".to_vec();
+    bytes.extend_from_slice(encoded_txn);
+    bytes
+}
+
+/// Algorand transaction ID: base32(SHA-512/256("TX" || msgpack(txn)))
+pub(crate) fn algorand_transaction_id(encoded_txn: &[u8]) -> String {
+    algorand_base32_encode(&Sha512_256::digest(algorand_bytes_to_sign(encoded_txn)))
+}
+
+/// msgpack SignedTxn { sig, txn } ready for /v2/transactions
+pub(crate) fn algorand_signed_transaction(encoded_txn: &[u8], signature: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(encoded_txn.len() + 80);
+    out.push(0x80 | 2); // fixmap, 2 entries
+    msgpack_str(&mut out, "sig");
+    msgpack_bin(&mut out, signature);
+    msgpack_str(&mut out, "txn");
+    out.extend_from_slice(encoded_txn);
+    out
+}
+
+// ============================================================================
+// CUSTODY CONSOLIDATION SWEEPS
+// ============================================================================
+//
+// Deposits land on per-user threshold-derived custody addresses. A timer
+// periodically signs payments moving everything above the 0.1 ALGO minimum
+// balance into the hot wallet. The canister cannot reach Algorand directly, so
+// the backend reports network params, submits the signed transactions from
+// get_pending_sweeps() and reports the outcome via confirm_sweep/fail_sweep.
+
+pub(crate) const ALGORAND_MIN_BALANCE_MICROALGOS: u64 = 100_000;
+pub(crate) const ALGORAND_MAX_VALIDITY_ROUNDS: u64 = 1_000;
+pub(crate) const NETWORK_PARAMS_MAX_AGE_NS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+pub(crate) const MAX_SWEEPS_PER_CYCLE: usize = 20;
+
+pub(crate) fn custody_balance(custody_address: &str) -> Nat {
+    CUSTODY_BALANCES.with(|b| b.borrow().get(&custody_address.to_string()).map(|v| v.0).unwrap_or_else(|| Nat::from(0u64)))
+}
+
+pub(crate) fn set_custody_balance(custody_address: &str, balance: Nat) {
+    CUSTODY_BALANCES.with(|b| b.borrow_mut().insert(custody_address.to_string(), StableCandid(balance)));
+}
+
+pub(crate) fn schedule_sweep_timer() {
+    if let Some(timer_id) = SWEEP_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+
+    let config = SWEEP_CONFIG.with(|c| c.borrow().clone());
+    if !config.enabled || config.interval_secs == 0 {
+        return;
+    }
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(config.interval_secs), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = run_sweep_cycle().await {
+                ic_cdk::println!("Custody sweep cycle skipped: {}", e);
+            }
+        })
+    });
+    SWEEP_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+pub(crate) async fn run_sweep_cycle() -> Result<Vec<u64>, String> {
+    ensure_no_state_export()?;
+    if SWEEP_IN_PROGRESS.with(|flag| flag.replace(true)) {
+        return Err("Sweep cycle already in progress".to_string());
+    }
+
+    let result = sweep_custody_addresses().await;
+
+    SWEEP_IN_PROGRESS.with(|flag| *flag.borrow_mut() = false);
+    result
+}
+
+pub(crate) async fn sweep_custody_addresses() -> Result<Vec<u64>, String> {
+    let config = SWEEP_CONFIG.with(|c| c.borrow().clone());
+    if !config.enabled {
+        return Err("Custody sweeps are disabled".to_string());
+    }
+
+    let hot_wallet = config.hot_wallet_address
+        .ok_or("Hot wallet address not configured")?;
+    let network = config.network
+        .ok_or("Algorand network params not reported")?;
+    if time().saturating_sub(network.reported_at) > NETWORK_PARAMS_MAX_AGE_NS {
+        return Err(format!("Algorand network params are stale (reported at {})", network.reported_at));
+    }
+    let receiver = decode_algorand_address(&hot_wallet)?;
+
+    let reserved = Nat::from(ALGORAND_MIN_BALANCE_MICROALGOS + network.min_fee);
+    let candidates: Vec<(String, Principal, Nat)> = CUSTODY_BALANCES.with(|balances| {
+        balances.borrow()
+            .iter()
+            .filter(|(address, _)| *address != hot_wallet)
+            .filter(|(_, balance)| balance.0 > reserved.clone() + config.threshold.clone())
+            .filter_map(|(address, balance)| {
+                let owner = DEPOSIT_ADDRESSES.with(|a| a.borrow().get(&address))?;
+                Some((address, owner, balance.0 - reserved.clone()))
+            })
+            .take(MAX_SWEEPS_PER_CYCLE)
+            .collect()
+    });
+
+    let mut swept = Vec::new();
+    for (address, owner, amount) in candidates {
+        match sweep_custody_address(&address, owner, amount, &hot_wallet, receiver, &network).await {
+            Ok(sweep_id) => swept.push(sweep_id),
+            Err(e) => ic_cdk::println!("Sweep of {} failed: {}", address, e),
+        }
+    }
+
+    Ok(swept)
+}
+
+pub(crate) async fn sweep_custody_address(
+    custody_address: &str,
+    owner: Principal,
+    amount: Nat,
+    hot_wallet: &str,
+    receiver: [u8; 32],
+    network: &AlgorandNetworkParams,
+) -> Result<u64, String> {
+    let sender = decode_algorand_address(custody_address)?;
+    let amount_u64 = amount.0.to_u64().ok_or("Sweep amount too large")?;
+
+    let payment = AlgorandPayment {
+        sender,
+        receiver,
+        amount: amount_u64,
+        fee: network.min_fee,
+        first_valid: network.last_round,
+        last_valid: network.last_round + ALGORAND_MAX_VALIDITY_ROUNDS,
+        genesis_id: &network.genesis_id,
+        genesis_hash: &network.genesis_hash,
+    };
+    let encoded_txn = payment.encode();
+    let debit = amount.clone() + Nat::from(network.min_fee);
+
+    // Reserve the amount before awaiting so the next cycle cannot sweep it twice
+    set_custody_balance(custody_address, custody_balance(custody_address) - debit.clone());
+    IN_FLIGHT_SWEEPS.with(|f| {
+        let mut in_flight = f.borrow_mut();
+        *in_flight = in_flight.clone() + debit.clone();
+    });
+
+    let signer = Principal::from_text(THRESHOLD_SIGNER_CANISTER_ID)
+        .map_err(|e| format!("Invalid threshold signer canister ID: {}", e))?;
+    let sign_result: Result<(Result<SignedTransaction, SigningError>,), _> = ic_cdk::call(
+        signer,
+        "sign_algorand_transaction",
+        (owner, algorand_bytes_to_sign(&encoded_txn)),
+    ).await;
+
+    let signature = match sign_result {
+        Ok((Ok(signed),)) => signed.signature,
+        Ok((Err(e),)) => {
+            release_in_flight_sweep(custody_address, &debit);
+            return Err(format!("Threshold signing failed: {} (code {})", e.message, e.code));
+        }
+        Err((code, msg)) => {
+            release_in_flight_sweep(custody_address, &debit);
+            return Err(format!("Inter-canister call to threshold signer failed: {:?} - {}", code, msg));
+        }
+    };
+
+    let now = time();
+    let sweep_id = SWEEP_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        let sweep_id = records.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        records.insert(sweep_id, StableCandid(SweepRecord {
+            sweep_id,
+            custody_address: custody_address.to_string(),
+            owner,
+            destination: hot_wallet.to_string(),
+            amount,
+            fee: Nat::from(network.min_fee),
+            algorand_tx_id: algorand_transaction_id(&encoded_txn),
+            signed_transaction: algorand_signed_transaction(&encoded_txn, &signature),
+            first_valid: payment.first_valid,
+            last_valid: payment.last_valid,
+            status: SweepStatus::Signed,
+            created_at: now,
+            updated_at: now,
+            error: None,
+        }));
+        sweep_id
+    });
+
+    Ok(sweep_id)
+}
+
+/// Return an in-flight sweep amount to its custody address balance
+pub(crate) fn release_in_flight_sweep(custody_address: &str, debit: &Nat) {
+    IN_FLIGHT_SWEEPS.with(|f| {
+        let mut in_flight = f.borrow_mut();
+        *in_flight = in_flight.clone() - debit.clone();
+    });
+    set_custody_balance(custody_address, custody_balance(custody_address) + debit.clone());
+}
+
+pub(crate) fn check_sweep_config(hot_wallet_address: &str, interval_secs: u64) -> Result<(), String> {
+    decode_algorand_address(hot_wallet_address)?;
+    if interval_secs < 60 {
+        return Err("Sweep interval must be at least 60 seconds".to_string());
+    }
+    Ok(())
+}
+
+/// Configure custody sweeps (Admin role)
+#[update]
+pub(crate) fn set_sweep_config(
+    enabled: bool,
+    hot_wallet_address: String,
+    threshold: Nat,
+    interval_secs: u64
+) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Configuring custody sweeps requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_sweep_config(&hot_wallet_address, interval_secs)?;
+
+    SWEEP_CONFIG.with(|c| {
+        let mut config = c.borrow_mut();
+        config.enabled = enabled;
+        config.hot_wallet_address = Some(hot_wallet_address.clone());
+        config.threshold = threshold.clone();
+        config.interval_secs = interval_secs;
+    });
+    schedule_sweep_timer();
+
+    Ok(format!(
+        "Custody sweeps {} to {} (threshold {} microALGO, every {}s)",
+        if enabled { "enabled" } else { "disabled" }, hot_wallet_address, threshold, interval_secs
+    ))
+}
+
+/// Report current Algorand network params used to build sweep transactions
+/// Requires the Operator role
+#[update]
+pub(crate) fn report_algorand_network_params(
+    genesis_id: String,
+    genesis_hash: Vec<u8>,
+    last_round: u64,
+    min_fee: u64
+) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: reporting network params requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    if genesis_hash.len() != 32 {
+        return Err(format!("Genesis hash must be 32 bytes, got {}", genesis_hash.len()));
+    }
+    if min_fee == 0 {
+        return Err("Minimum fee must be greater than 0".to_string());
+    }
+
+    SWEEP_CONFIG.with(|c| {
+        c.borrow_mut().network = Some(AlgorandNetworkParams {
+            genesis_id: genesis_id.clone(),
+            genesis_hash,
+            last_round,
+            min_fee,
+            reported_at: time(),
+        });
+    });
+
+    Ok(format!("Network params updated: {} round {}", genesis_id, last_round))
+}
+
+/// Run a sweep cycle now instead of waiting for the timer
+/// Requires the Operator role
+#[update]
+pub(crate) async fn run_custody_sweep() -> Result<Vec<u64>, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: running sweeps requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    run_sweep_cycle().await
+}
+
+/// Mark a submitted sweep as confirmed on Algorand
+/// Requires the Operator role
+#[update]
+pub(crate) fn confirm_sweep(sweep_id: u64) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: confirming sweeps requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    let record = SWEEP_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        let mut record = records.get(&sweep_id).ok_or(format!("Sweep {} not found", sweep_id))?.0;
+        if record.status != SweepStatus::Signed {
+            return Err(format!("Sweep {} is already {:?}", sweep_id, record.status));
+        }
+        record.status = SweepStatus::Confirmed;
+        record.updated_at = time();
+        records.insert(sweep_id, StableCandid(record.clone()));
+        Ok(record)
+    })?;
+
+    IN_FLIGHT_SWEEPS.with(|f| {
+        let mut in_flight = f.borrow_mut();
+        *in_flight = in_flight.clone() - record.amount.clone() - record.fee.clone();
+    });
+    HOT_WALLET_BALANCE.with(|h| {
+        let mut hot = h.borrow_mut();
+        *hot = hot.clone() + record.amount.clone();
+    });
+
+    // The network fee left the bridge's custody for good
+    LOCKED_ALGO_RESERVES.with(|reserves| {
+        let mut locked = reserves.borrow_mut();
+        *locked = locked.clone() - record.fee.clone();
+    });
+    certify_state(&[]);
+    evaluate_reserve_policy();
+
+    Ok(format!("Sweep {} confirmed: {} microALGO to {}", sweep_id, record.amount, record.destination))
+}
+
+/// Mark a sweep as failed and release its amount. `current_round` is the latest
+/// Algorand round the backend has seen; it must be past the sweep's last_valid,
+/// or the signed transaction could still be submitted and land.
+/// Requires the Operator role
+#[update]
+pub(crate) fn fail_sweep(sweep_id: u64, current_round: u64, reason: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: failing sweeps requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    let record = SWEEP_RECORDS.with(|records| {
+        let mut records = records.borrow_mut();
+        let mut record = records.get(&sweep_id).ok_or(format!("Sweep {} not found", sweep_id))?.0;
+        if record.status != SweepStatus::Signed {
+            return Err(format!("Sweep {} is already {:?}", sweep_id, record.status));
+        }
+        if current_round <= record.last_valid {
+            return Err(format!(
+                "Sweep {} is valid until round {} and may still land (current round {})",
+                sweep_id, record.last_valid, current_round
+            ));
+        }
+        record.status = SweepStatus::Failed;
+        record.updated_at = time();
+        record.error = Some(reason.clone());
+        records.insert(sweep_id, StableCandid(record.clone()));
+        Ok(record)
+    })?;
+
+    release_in_flight_sweep(&record.custody_address, &(record.amount.clone() + record.fee.clone()));
+
+    Ok(format!("Sweep {} marked failed: {}", sweep_id, reason))
+}
+
+/// Signed sweeps waiting for the backend to submit them
+#[query]
+pub(crate) fn get_pending_sweeps() -> Vec<SweepRecord> {
+    SWEEP_RECORDS.with(|records| {
+        records.borrow()
+            .iter()
+            .map(|(_, record)| record.0)
+            .filter(|r| r.status == SweepStatus::Signed)
+            .collect()
+    })
+}
+
+/// Query sweep history (most recent first)
+#[query]
+pub(crate) fn get_sweep_records(limit: Option<u32>) -> Vec<SweepRecord> {
+    let limit = limit.unwrap_or(100) as usize;
+    SWEEP_RECORDS.with(|records| {
+        records.borrow().iter().rev().take(limit).map(|(_, record)| record.0).collect()
+    })
+}
+
+#[query]
+pub(crate) fn get_sweep_status() -> SweepStatusSummary {
+    SweepStatusSummary {
+        config: SWEEP_CONFIG.with(|c| c.borrow().clone()),
+        custody_balances_total: CUSTODY_BALANCES.with(|b| {
+            b.borrow().values().fold(Nat::from(0u64), |acc, v| acc + v.0)
+        }),
+        in_flight_sweeps: IN_FLIGHT_SWEEPS.with(|f| f.borrow().clone()),
+        hot_wallet_balance: HOT_WALLET_BALANCE.with(|h| h.borrow().clone()),
+        sweeps_pending: get_pending_sweeps().len() as u64,
+    }
+}
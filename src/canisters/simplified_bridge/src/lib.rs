@@ -1,20 +1,45 @@
-// Simplified Bridge Canister - Sprint X Architecture Fix
-// Core Bridge Functionality Only (<500 lines vs 68k+ monolithic)
-
-use ic_cdk::{init, query, update, caller, api::time, pre_upgrade, post_upgrade};
+// Simplified Bridge Canister
+// ckALGO ledger, Algorand deposits and redemptions, access control and upgrades.
+// Swaps, custody sweeps, hot/cold reserves and state snapshots live in their own modules.
+
+use ic_cdk::{init, query, update, pre_upgrade, post_upgrade, inspect_message};
+#[cfg(not(test))]
+use ic_cdk::{caller, api::{is_controller, set_certified_data, time}};
+#[cfg(test)]
+use tests::env::{caller, is_controller, set_certified_data, time};
 use candid::{CandidType, Principal, Nat, Deserialize};
 use std::collections::{HashMap, HashSet};
 use std::cell::RefCell;
 use serde::Serialize;
 use num_traits::cast::ToPrimitive;
-use sha2::{Sha256, Digest};
+use num_traits::Zero;
+use sha2::{Sha256, Sha512_256, Digest};
+use std::time::Duration;
+use std::ops::Bound::{Excluded, Unbounded};
+use ic_cdk_timers::TimerId;
+use ic_certified_map::{AsHashTree, RbTree, labeled, labeled_hash};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::writer::Writer;
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _, StableBTreeMap, StableBTreeSet, StableLog, Storable};
+use std::borrow::Cow;
+
+mod custody;
+mod reserves;
+mod snapshot;
+mod swaps;
+
+use custody::*;
+use reserves::*;
+use snapshot::*;
+use swaps::*;
 
 // ============================================================================
 // ICRC-2 TYPES (defined manually to avoid dependency conflicts)
 // ============================================================================
 
 /// ICRC-1 Account structure
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Account {
     pub owner: Principal,
     pub subaccount: Option<[u8; 32]>,
@@ -70,109 +95,79 @@ pub enum TransferError {
     GenericError { error_code: Nat, message: String },
 }
 
-// ============================================================================
-// CANISTER IDs
-// ============================================================================
-
-const CKETH_CANISTER_ID: &str = "ss2fx-dyaaa-aaaar-qacoq-cai";
-const XRC_CANISTER_ID: &str = "uf6dk-hyaaa-aaaaq-qaaaq-cai";
-
-// ============================================================================
-// EXCHANGE RATE CANISTER (XRC) TYPES
-// ============================================================================
-
+/// ICRC-1 ledger transaction log (get_transactions), used to verify swap deposits.
+/// Only the fields we check are declared; Candid skips the rest when decoding.
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub enum AssetClass {
-    Cryptocurrency,
-    FiatCurrency,
+pub struct GetTransactionsRequest {
+    pub start: Nat,
+    pub length: Nat,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct Asset {
-    pub symbol: String,
-    pub class: AssetClass,
+pub struct LedgerTransfer {
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct GetExchangeRateRequest {
-    pub base_asset: Asset,
-    pub quote_asset: Asset,
-    pub timestamp: Option<u64>,
+pub struct LedgerTransaction {
+    pub kind: String,
+    pub transfer: Option<LedgerTransfer>,
+    pub timestamp: u64,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct ExchangeRateMetadata {
-    pub decimals: u32,
-    pub base_asset_num_received_rates: u64,
-    pub base_asset_num_queried_sources: u64,
-    pub quote_asset_num_received_rates: u64,
-    pub quote_asset_num_queried_sources: u64,
-    pub standard_deviation: u64,
-    pub forex_timestamp: Option<u64>,
+pub struct TransactionRange {
+    pub transactions: Vec<LedgerTransaction>,
 }
 
+candid::define_function!(pub ArchiveGetTransactionsFn : (GetTransactionsRequest) -> (TransactionRange) query);
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct ExchangeRate {
-    pub base_asset: Asset,
-    pub quote_asset: Asset,
-    pub timestamp: u64,
-    pub rate: u64,
-    pub metadata: ExchangeRateMetadata,
+pub struct ArchivedTransactionRange {
+    pub start: Nat,
+    pub length: Nat,
+    pub callback: ArchiveGetTransactionsFn,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub enum ExchangeRateError {
-    AnonymousPrincipalNotAllowed,
-    Pending,
-    CryptoQuoteAssetNotFound,
-    CryptoBaseAssetNotFound,
-    StablecoinRateTooFewRates,
-    StablecoinRateZeroRate,
-    StablecoinRateNotFound,
-    ForexInvalidTimestamp,
-    ForexBaseAssetNotFound,
-    ForexQuoteAssetNotFound,
-    ForexAssetsNotFound,
-    RateLimited,
-    NotEnoughCycles,
-    FailedToAcceptCycles,
-    InconsistentRatesReceived,
-    Other { code: u32, description: String },
-}
-
-pub type GetExchangeRateResult = Result<ExchangeRate, ExchangeRateError>;
+pub struct GetTransactionsResponse {
+    pub log_length: Nat,
+    pub first_index: Nat,
+    pub transactions: Vec<LedgerTransaction>,
+    pub archived_transactions: Vec<ArchivedTransactionRange>,
+}
 
 // ============================================================================
-// SWAP DATA STRUCTURES
+// CANISTER IDs
 // ============================================================================
 
-#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
-pub struct SwapRecord {
-    pub user: Principal,
-    pub cketh_in: Nat,
-    pub ckalgo_out: Nat,
-    pub rate_used: f64,       // ETH/ALGO rate at time of swap
-    pub fee_collected: Nat,   // Fee in microALGO
-    pub timestamp: u64,
-    pub tx_id: String,
+const CKETH_CANISTER_ID: &str = "ss2fx-dyaaa-aaaar-qacoq-cai";
+const XRC_CANISTER_ID: &str = "uf6dk-hyaaa-aaaaq-qaaaq-cai";
+const THRESHOLD_SIGNER_CANISTER_ID: &str = "vj7ly-diaaa-aaaae-abvoq-cai";
+
+// ============================================================================
+// THRESHOLD SIGNER TYPES (mirrors algorand_threshold_signer_backend.did)
+// ============================================================================
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SignedTransaction {
+    pub transaction_bytes: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub signed_tx_id: String,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct SwapConfig {
-    pub enabled: bool,
-    pub fee_bps: u64,         // Fee in basis points (30 = 0.3%)
-    pub min_cketh: Nat,       // Minimum swap amount (0.0001 ETH)
-    pub max_cketh: Nat,       // Maximum swap amount (1 ETH)
-    pub cketh_backed_ckalgo: Nat,   // Total ckALGO minted via swaps (not ALGO-backed)
-    pub total_cketh_received: Nat,  // Total ckETH held by canister
+pub struct SigningError {
+    pub code: u32,
+    pub message: String,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct SwapResult {
-    pub cketh_in: Nat,
-    pub ckalgo_out: Nat,
-    pub rate_used: f64,
-    pub cketh_block_index: Nat,
+pub struct AlgorandAddress {
+    pub address: String,
+    pub public_key: Vec<u8>,
 }
 
 // ============================================================================
@@ -187,6 +182,8 @@ pub struct PendingDeposit {
     pub timestamp: u64,
     pub confirmations: u8,
     pub required_confirmations: u8,
+    // Custody address the deposit landed on (None for deposits registered before sweeps)
+    pub custody_address: Option<String>,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
@@ -209,814 +206,3169 @@ pub struct ReserveStatus {
     pub last_verification: u64,
 }
 
-// CRITICAL FIX 2: Stable storage structure for canister upgrades
-#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
-pub struct StableStorage {
-    pub pending_deposits: Vec<(String, PendingDeposit)>,
-    pub deposit_records: Vec<DepositRecord>,
-    pub deposit_addresses: Vec<(String, Principal)>,
-    pub balances: Vec<(String, Nat)>,
-    pub total_supply: Nat,
-    pub locked_algo_reserves: Nat,
-    pub authorized_minters: Vec<Principal>,
-    pub reserve_health_status: bool,
-    pub last_reserve_check: u64,
-    // Swap state (added for ckETH → ckALGO swap feature)
-    pub swap_enabled: Option<bool>,
-    pub swap_fee_bps: Option<u64>,
-    pub min_swap_cketh: Option<Nat>,
-    pub max_swap_cketh: Option<Nat>,
-    pub swap_records: Option<Vec<SwapRecord>>,
-    pub cketh_backed_ckalgo: Option<Nat>,
-    pub total_cketh_received: Option<Nat>,
-    // Deposit-based swap tracking
-    pub processed_swap_deposits: Option<Vec<String>>,
-}
-
 // ============================================================================
-// SIMPLIFIED GLOBAL STATE - Core Bridge Only
+// CERTIFIED QUERY TYPES
 // ============================================================================
 
-thread_local! {
-    // Core ICRC-1 token state
-    static BALANCES: RefCell<HashMap<String, Nat>> = RefCell::new(HashMap::new());
-    static TOTAL_SUPPLY: RefCell<Nat> = RefCell::new(Nat::from(0u64));
-    static TOKEN_NAME: RefCell<String> = RefCell::new("Chain-Key ALGO".to_string());
-    static TOKEN_SYMBOL: RefCell<String> = RefCell::new("ckALGO".to_string());
-    static DECIMALS: RefCell<u8> = RefCell::new(6u8);
-    static FEE: RefCell<Nat> = RefCell::new(Nat::from(10000u64));
-    static AUTHORIZED_MINTERS: RefCell<Vec<Principal>> = RefCell::new(Vec::new());
-    
-    // Bridge-specific state
-    static DEPOSIT_ADDRESSES: RefCell<HashMap<String, Principal>> = RefCell::new(HashMap::new());
-    static LOCKED_ALGO_RESERVES: RefCell<Nat> = RefCell::new(Nat::from(0u64));
-    static PENDING_DEPOSITS: RefCell<HashMap<String, PendingDeposit>> = RefCell::new(HashMap::new());
-    static DEPOSIT_RECORDS: RefCell<Vec<DepositRecord>> = RefCell::new(Vec::new());
-    
-    // Reserve verification
-    static LAST_RESERVE_CHECK: RefCell<u64> = RefCell::new(0u64);
-    static RESERVE_HEALTH_STATUS: RefCell<bool> = RefCell::new(true);
+/// Certified ckALGO balance.
+///
+/// `certificate` is the IC system certificate for this canister and `witness`
+/// is a CBOR-encoded hash tree. The leaf at `["bridge", "balance/<principal>"]`
+/// holds the Candid-encoded balance; its reconstructed root must match the
+/// certified data in the certificate.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CertifiedBalance {
+    pub balance: Nat,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
 
-    // Swap state (ckETH → ckALGO)
-    static SWAP_ENABLED: RefCell<bool> = RefCell::new(false);  // Disabled by default
-    static SWAP_FEE_BPS: RefCell<u64> = RefCell::new(30);      // 0.3% fee
-    static MIN_SWAP_CKETH: RefCell<Nat> = RefCell::new(Nat::from(100_000_000_000_000u64));      // 0.0001 ETH (18 decimals)
-    static MAX_SWAP_CKETH: RefCell<Nat> = RefCell::new(Nat::from(1_000_000_000_000_000_000u64)); // 1 ETH (18 decimals)
-    static SWAP_RECORDS: RefCell<Vec<SwapRecord>> = RefCell::new(Vec::new());
-    // Reserve tracking: separate ckETH-backed vs ALGO-backed ckALGO
-    static CKETH_BACKED_CKALGO: RefCell<Nat> = RefCell::new(Nat::from(0u64));  // Total ckALGO minted via swaps
-    static TOTAL_CKETH_RECEIVED: RefCell<Nat> = RefCell::new(Nat::from(0u64)); // Total ckETH held by canister
+/// Certified reserve status (leaf `["bridge", "reserve_status"]`)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CertifiedReserveStatus {
+    pub reserve_status: ReserveStatus,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
 
-    // Deposit-based swap tracking (anti-replay protection)
-    static PROCESSED_SWAP_DEPOSITS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+/// Certified swap configuration (leaf `["bridge", "swap_config"]`)
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CertifiedSwapConfig {
+    pub swap_config: SwapConfig,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
 }
 
 // ============================================================================
-// INITIALIZATION
+// PROOF-OF-LIABILITIES TYPES
 // ============================================================================
 
-#[init]
-fn init() {
-    // Initialize token parameters
-    TOKEN_NAME.with(|name| *name.borrow_mut() = "Chain-Key ALGO".to_string());
-    TOKEN_SYMBOL.with(|symbol| *symbol.borrow_mut() = "ckALGO".to_string());
-    DECIMALS.with(|decimals| *decimals.borrow_mut() = 6u8);
-    FEE.with(|fee| *fee.borrow_mut() = Nat::from(10000u64));
-    
-    // Initialize authorized minters
-    AUTHORIZED_MINTERS.with(|minters| {
-        let mut minters_vec = minters.borrow_mut();
-        minters_vec.push(Principal::management_canister());
-        // Add backend principal from Sprint 012.5
-        let backend_principal = Principal::from_text("2vxsx-fae").unwrap();
-        minters_vec.push(backend_principal);
-    });
-    
-    // Initialize reserve state
-    LOCKED_ALGO_RESERVES.with(|reserves| *reserves.borrow_mut() = Nat::from(0u64));
-    RESERVE_HEALTH_STATUS.with(|health| *health.borrow_mut() = true);
+/// Node of the liability Merkle sum tree: SHA-256 hash plus summed balance
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LiabilityNode {
+    pub hash: Vec<u8>,
+    pub sum: Nat,
 }
 
-// CRITICAL FIX 2: Stable storage for canister upgrades
-// Save all state before upgrade to prevent data loss
-#[pre_upgrade]
-fn pre_upgrade() {
-    let stable_data = StableStorage {
-        pending_deposits: PENDING_DEPOSITS.with(|deposits| {
-            deposits.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
-        }),
-        deposit_records: DEPOSIT_RECORDS.with(|records| records.borrow().clone()),
-        deposit_addresses: DEPOSIT_ADDRESSES.with(|addresses| {
-            addresses.borrow().iter().map(|(k, v)| (k.clone(), *v)).collect()
-        }),
-        balances: BALANCES.with(|balances| {
-            balances.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
-        }),
-        total_supply: TOTAL_SUPPLY.with(|supply| supply.borrow().clone()),
-        locked_algo_reserves: LOCKED_ALGO_RESERVES.with(|reserves| reserves.borrow().clone()),
-        authorized_minters: AUTHORIZED_MINTERS.with(|minters| minters.borrow().clone()),
-        reserve_health_status: RESERVE_HEALTH_STATUS.with(|health| *health.borrow()),
-        last_reserve_check: LAST_RESERVE_CHECK.with(|check| *check.borrow()),
-        // Swap state
-        swap_enabled: Some(SWAP_ENABLED.with(|e| *e.borrow())),
-        swap_fee_bps: Some(SWAP_FEE_BPS.with(|f| *f.borrow())),
-        min_swap_cketh: Some(MIN_SWAP_CKETH.with(|m| m.borrow().clone())),
-        max_swap_cketh: Some(MAX_SWAP_CKETH.with(|m| m.borrow().clone())),
-        swap_records: Some(SWAP_RECORDS.with(|r| r.borrow().clone())),
-        cketh_backed_ckalgo: Some(CKETH_BACKED_CKALGO.with(|b| b.borrow().clone())),
-        total_cketh_received: Some(TOTAL_CKETH_RECEIVED.with(|t| t.borrow().clone())),
-        processed_swap_deposits: Some(PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow().iter().cloned().collect())),
-    };
-
-    // Store in stable memory
-    ic_cdk::storage::stable_save((stable_data,))
-        .expect("Failed to save state to stable memory");
+/// One step of an inclusion proof, ordered from leaf to root
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LiabilityProofStep {
+    pub sibling: LiabilityNode,
+    pub sibling_is_left: bool,
 }
 
-// CRITICAL FIX 2: Restore all state after upgrade
-#[post_upgrade]
-fn post_upgrade() {
-    // Restore from stable memory
-    let (stable_data,): (StableStorage,) = ic_cdk::storage::stable_restore()
-        .expect("Failed to restore state from stable memory");
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LiabilityRoot {
+    pub root_hash: Vec<u8>,
+    pub total_liabilities: Nat,
+    pub leaf_count: u64,
+    pub snapshot_at: u64,
+}
 
-    // Restore all state
-    PENDING_DEPOSITS.with(|deposits| {
-        let mut deposits_map = deposits.borrow_mut();
-        deposits_map.clear();
-        for (k, v) in stable_data.pending_deposits {
-            deposits_map.insert(k, v);
-        }
-    });
+/// Liability root, certified at leaf `["bridge", "liability_root"]`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CertifiedLiabilityRoot {
+    pub root: LiabilityRoot,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
 
-    DEPOSIT_RECORDS.with(|records| {
-        *records.borrow_mut() = stable_data.deposit_records;
-    });
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LiabilityProof {
+    pub account: Principal,
+    pub balance: Nat,
+    pub leaf_index: u64,
+    pub path: Vec<LiabilityProofStep>,
+    pub root: LiabilityRoot,
+}
 
-    DEPOSIT_ADDRESSES.with(|addresses| {
-        let mut addresses_map = addresses.borrow_mut();
-        addresses_map.clear();
-        for (k, v) in stable_data.deposit_addresses {
-            addresses_map.insert(k, v);
-        }
-    });
+/// In-memory liability sum tree. levels[0] are the leaves (sorted by account),
+/// the last level holds the single root node.
+#[derive(Default)]
+struct LiabilityTree {
+    levels: Vec<Vec<([u8; 32], u128)>>,
+    leaf_index: HashMap<String, usize>,
+    snapshot_at: u64,
+}
 
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
-        balances_map.clear();
-        for (k, v) in stable_data.balances {
-            balances_map.insert(k, v);
-        }
-    });
+// ============================================================================
+// ACCESS CONTROL TYPES
+// ============================================================================
 
-    TOTAL_SUPPLY.with(|supply| {
-        *supply.borrow_mut() = stable_data.total_supply;
-    });
+/// Roles are granted per principal by controllers (or governance). Controllers
+/// and the governance canister implicitly hold every role, and Admin implies
+/// all the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, CandidType, Deserialize, Serialize)]
+pub enum Role {
+    Minter,           // Mint ckALGO for confirmed deposits
+    DepositReporter,  // Register custody addresses and deposits, report confirmations
+    Operator,         // Run sweeps, rebalances, the deposit watcher, rate refreshes; reserve health
+    Pauser,           // Pause operations
+    SwapExecutor,     // Execute swaps, quotes and deposit refunds on behalf of users
+    Admin,            // Bridge configuration and admin_* fund movements
+}
 
-    LOCKED_ALGO_RESERVES.with(|reserves| {
-        *reserves.borrow_mut() = stable_data.locked_algo_reserves;
-    });
+/// Install/upgrade argument. Minters are granted the legacy minter roles; on
+/// upgrade they are added to the existing assignments. The backend's principal
+/// (SIPPAR_BACKEND_IDENTITY_SEED, see backendIdentity.ts) belongs here so its
+/// deposit, swap and redemption calls pass the role checks.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct InitArgs {
+    pub minters: Vec<Principal>,
+}
 
-    AUTHORIZED_MINTERS.with(|minters| {
-        *minters.borrow_mut() = stable_data.authorized_minters;
-    });
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub roles: Vec<Role>,
+}
 
-    RESERVE_HEALTH_STATUS.with(|health| {
-        *health.borrow_mut() = stable_data.reserve_health_status;
-    });
+// ============================================================================
+// EMERGENCY PAUSE TYPES
+// ============================================================================
 
-    LAST_RESERVE_CHECK.with(|check| {
-        *check.borrow_mut() = stable_data.last_reserve_check;
-    });
+/// Operation classes that can be paused independently (one bit each)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, CandidType, Deserialize, Serialize)]
+pub enum PauseClass {
+    Transfers,            // icrc1_transfer
+    Mints,                // mint_after_deposit_confirmed
+    Redemptions,          // redeem_ck_algo, admin_redeem_ck_algo
+    Swaps,                // All swap directions, quotes and the deposit watcher
+    DepositRegistration,  // register_custody_address, register_pending_deposit
+    AdminTransfers,       // admin_transfer_ck_algo, admin_sweep_cketh_to_custody
+}
 
-    // Restore swap state (with defaults for upgrades from older versions)
-    if let Some(enabled) = stable_data.swap_enabled {
-        SWAP_ENABLED.with(|e| *e.borrow_mut() = enabled);
-    }
-    if let Some(fee_bps) = stable_data.swap_fee_bps {
-        SWAP_FEE_BPS.with(|f| *f.borrow_mut() = fee_bps);
-    }
-    if let Some(min_cketh) = stable_data.min_swap_cketh {
-        MIN_SWAP_CKETH.with(|m| *m.borrow_mut() = min_cketh);
-    }
-    if let Some(max_cketh) = stable_data.max_swap_cketh {
-        MAX_SWAP_CKETH.with(|m| *m.borrow_mut() = max_cketh);
-    }
-    if let Some(records) = stable_data.swap_records {
-        SWAP_RECORDS.with(|r| *r.borrow_mut() = records);
-    }
-    if let Some(backed) = stable_data.cketh_backed_ckalgo {
-        CKETH_BACKED_CKALGO.with(|b| *b.borrow_mut() = backed);
-    }
-    if let Some(received) = stable_data.total_cketh_received {
-        TOTAL_CKETH_RECEIVED.with(|t| *t.borrow_mut() = received);
-    }
-    if let Some(deposits) = stable_data.processed_swap_deposits {
-        PROCESSED_SWAP_DEPOSITS.with(|d| {
-            let mut set = d.borrow_mut();
-            for tx_id in deposits {
-                set.insert(tx_id);
-            }
-        });
-    }
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PauseRecord {
+    pub class: PauseClass,
+    pub reason: String,
+    pub paused_by: Principal,
+    pub paused_at: u64,
+}
 
-    // Ensure backend authorization persists
-    AUTHORIZED_MINTERS.with(|minters| {
-        let mut minters_vec = minters.borrow_mut();
-        let backend_principal = Principal::from_text("2vxsx-fae").unwrap();
-        if !minters_vec.contains(&backend_principal) {
-            minters_vec.push(backend_principal);
-        }
-    });
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct PauseState {
+    pub bitmap: u32,
+    pub paused: Vec<PauseRecord>,
 }
 
 // ============================================================================
-// ICRC-1 STANDARD METHODS
+// MULTI-PARTY APPROVAL TYPES
 // ============================================================================
 
-#[query]
-fn icrc1_name() -> String {
-    TOKEN_NAME.with(|name| name.borrow().clone())
+/// admin_* calls at or above a threshold wait for M-of-N approvers
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ApprovalConfig {
+    pub enabled: bool,
+    pub approvers: Vec<Principal>,  // N
+    pub required_approvals: u32,    // M
+    pub ckalgo_threshold: Nat,      // admin_transfer_ck_algo / admin_redeem_ck_algo (microALGO)
+    pub cketh_threshold: Nat,       // admin_sweep_cketh_to_custody (wei)
+    pub expiry_secs: u64,           // Pending operations expire after this
 }
 
-#[query]
-fn icrc1_symbol() -> String {
-    TOKEN_SYMBOL.with(|symbol| symbol.borrow().clone())
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        ApprovalConfig {
+            enabled: false,
+            approvers: Vec::new(),
+            required_approvals: 2,
+            ckalgo_threshold: Nat::from(1_000_000_000u64),        // 1,000 ALGO
+            cketh_threshold: Nat::from(500_000_000_000_000_000u64), // 0.5 ETH
+            expiry_secs: 86_400,
+        }
+    }
 }
 
-#[query]
-fn icrc1_decimals() -> u8 {
-    DECIMALS.with(|decimals| *decimals.borrow())
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum AdminAction {
+    TransferCkAlgo { from: Principal, to: Principal, amount: Nat },
+    RedeemCkAlgo { user: Principal, amount: Nat, destination: String },
+    SweepCkethToCustody { user: Principal, amount: Nat },
 }
 
-#[query]
-fn icrc1_fee() -> Nat {
-    FEE.with(|fee| fee.borrow().clone())
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum AdminOperationStatus {
+    Pending,
+    Executing,
+    Executed { result: String },
+    Failed { error: String },
+    Expired,
+    Cancelled,
 }
 
-#[query]
-fn icrc1_total_supply() -> Nat {
-    TOTAL_SUPPLY.with(|supply| supply.borrow().clone())
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct AdminOperation {
+    pub operation_id: u64,
+    pub action: AdminAction,
+    pub proposed_by: Principal,
+    pub approvals: Vec<Principal>,  // Distinct approvers so far, never the proposer
+    pub required_approvals: u32,    // M at proposal time
+    pub status: AdminOperationStatus,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub updated_at: u64,
 }
 
-#[query]
-fn icrc1_balance_of(account: Principal) -> Nat {
-    let account_str = account.to_text();
-    BALANCES.with(|balances| {
-        balances.borrow().get(&account_str).unwrap_or(&Nat::from(0u64)).clone()
-    })
+/// Admin action executed without approval, counted against the threshold for
+/// APPROVAL_WINDOW_SECS so large amounts cannot be split into small ones
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct UnapprovedAdminAction {
+    pub proposer: Principal,
+    pub target: Principal,      // Whose funds moved
+    pub cketh: bool,            // Amount is wei (ckETH sweep) rather than microALGO
+    pub amount: Nat,
+    pub timestamp: u64,
 }
 
-#[query]
-fn icrc1_supported_standards() -> Vec<(String, String)> {
-    vec![
-        ("ICRC-1".to_string(), "https://github.com/dfinity/ICRC-1".to_string()),
-    ]
+// ============================================================================
+// CONFIG TIMELOCK TYPES
+// ============================================================================
+
+/// Configuration change that takes effect after the timelock delay
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum ConfigChange {
+    SwapEnabled(bool),
+    SwapFeeBps(u64),                                  // ckETH pair
+    SwapLimits { min_cketh: Nat, max_cketh: Nat },    // ckETH pair
+    SwapPair(SwapPair),
+    SwapPairEnabled { ledger: Principal, enabled: bool },
+    ReservePolicy(ReservePolicy),
+    TimelockDelay(u64),
 }
 
-#[update]
-fn icrc1_transfer(to: Principal, amount: Nat) -> Result<Nat, String> {
-    let from = caller();
-    let from_str = from.to_text();
-    let to_str = to.to_text();
-    
-    // Check balance
-    let current_balance = BALANCES.with(|balances| {
-        balances.borrow().get(&from_str).unwrap_or(&Nat::from(0u64)).clone()
-    });
-    
-    if current_balance < amount {
-        return Err("Insufficient balance".to_string());
-    }
-    
-    // Update balances
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
-        let new_from_balance = current_balance.clone() - amount.clone();
-        balances_map.insert(from_str, new_from_balance);
-        
-        let current_to_balance = balances_map.get(&to_str).unwrap_or(&Nat::from(0u64)).clone();
-        let new_to_balance = current_to_balance + amount.clone();
-        balances_map.insert(to_str, new_to_balance);
-    });
-    
-    Ok(Nat::from(time()))
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum ConfigChangeStatus {
+    Pending,
+    Applied { message: String },
+    Failed { error: String },  // Re-validation failed when the delay elapsed
+    Cancelled { by: Principal },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PendingConfigChange {
+    pub change_id: u64,
+    pub change: ConfigChange,
+    pub proposed_by: Principal,
+    pub proposed_at: u64,
+    pub effective_at: u64,
+    pub status: ConfigChangeStatus,
+    pub resolved_at: Option<u64>,
 }
 
 // ============================================================================
-// BRIDGE CORE FUNCTIONS
+// STABLE MEMORY LAYOUT
 // ============================================================================
+//
+// Balances, deposits, swaps, treasury withdrawals, redemptions, the processed
+// deposit log, custody addresses and balances, sweeps, refunds, auto-swap
+// preferences, admin operations, config changes and rebalance proposals grow
+// without bound, so they live directly in stable structures behind a
+// MemoryManager and are never serialized on upgrade. Everything else is small
+// and still round-trips through StableStorage, written to UPGRADES_MEMORY_ID
+// as a length-prefixed StateEnvelope (see STATE VERSIONING).
+//
+// Canisters upgraded from the single-blob layout (stable_save at offset 0, no
+// "MGR" header) are migrated once in post_upgrade.
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(0);
+const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(1);
+const PENDING_DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const DEPOSIT_RECORDS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(3);
+const DEPOSIT_RECORDS_DATA_MEMORY_ID: MemoryId = MemoryId::new(4);
+const SWAP_RECORDS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(5);
+const SWAP_RECORDS_DATA_MEMORY_ID: MemoryId = MemoryId::new(6);
+const PROCESSED_SWAP_DEPOSITS_MEMORY_ID: MemoryId = MemoryId::new(7);
+const PROCESSED_DEPOSIT_LOG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(8);
+const PROCESSED_DEPOSIT_LOG_DATA_MEMORY_ID: MemoryId = MemoryId::new(9);
+const TREASURY_WITHDRAWALS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(10);
+const TREASURY_WITHDRAWALS_DATA_MEMORY_ID: MemoryId = MemoryId::new(11);
+const REDEMPTIONS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const DEPOSIT_ADDRESSES_MEMORY_ID: MemoryId = MemoryId::new(13);
+const CUSTODY_BALANCES_MEMORY_ID: MemoryId = MemoryId::new(14);
+const SWEEP_RECORDS_MEMORY_ID: MemoryId = MemoryId::new(15);
+const AUTO_SWAP_PREFERENCES_MEMORY_ID: MemoryId = MemoryId::new(16);
+const CUSTODY_CREDITED_MEMORY_ID: MemoryId = MemoryId::new(17);
+const CUSTODY_CREDITED_THROUGH_MEMORY_ID: MemoryId = MemoryId::new(18);
+const SWAP_REFUNDS_INDEX_MEMORY_ID: MemoryId = MemoryId::new(19);
+const SWAP_REFUNDS_DATA_MEMORY_ID: MemoryId = MemoryId::new(20);
+const ADMIN_OPERATIONS_MEMORY_ID: MemoryId = MemoryId::new(21);
+const CONFIG_CHANGES_MEMORY_ID: MemoryId = MemoryId::new(22);
+const REBALANCE_PROPOSALS_MEMORY_ID: MemoryId = MemoryId::new(23);
+const DEPOSIT_TX_INDEX_MEMORY_ID: MemoryId = MemoryId::new(24);
+
+/// Candid-encoded value stored in a stable structure
+#[derive(Clone, Debug)]
+pub struct StableCandid<T>(pub T);
+
+impl<T: CandidType + for<'de> Deserialize<'de>> Storable for StableCandid<T> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(&self.0).expect("Failed to encode stable value"))
+    }
 
-// REMOVED: generate_deposit_address — returned fake "BRIDGE..." strings.
-// Real Algorand custody addresses are derived by threshold_signer canister
-// (vj7ly-diaaa-aaaae-abvoq-cai) via derive_algorand_address().
-// Use register_custody_address() to register a real threshold-derived address.
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        StableCandid(candid::decode_one(&bytes).expect("Failed to decode stable value"))
+    }
 
-/// Admin function: register an existing custody address for a user
-/// Authorized minters OR canister controllers can call this
-#[update]
-fn register_custody_address(custody_address: String, user: Principal) -> Result<String, String> {
-    let caller_principal = caller();
+    const BOUND: Bound = Bound::Unbounded;
+}
 
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
-    });
+type StableRecordLog<T> = StableLog<StableCandid<T>, Memory, Memory>;
 
-    // Also allow the canister controller (deploy identity)
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
+fn memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
 
-    if !is_authorized && !is_controller {
-        return Err(format!("Unauthorized: only authorized minters or controllers can register custody addresses. Caller: {}", caller_principal));
-    }
+fn record_log<T: CandidType + for<'de> Deserialize<'de>>(index: MemoryId, data: MemoryId) -> StableRecordLog<T> {
+    StableLog::init(memory(index), memory(data)).expect("Failed to initialize stable log")
+}
 
-    DEPOSIT_ADDRESSES.with(|addresses| {
-        addresses.borrow_mut().insert(custody_address.clone(), user);
+/// Entries [start, end) of a record log, newest first
+fn log_range_rev<T: CandidType + for<'de> Deserialize<'de>>(log: &StableRecordLog<T>, start: u64, end: u64) -> Vec<T> {
+    (start..end).rev().filter_map(|index| log.get(index)).map(|entry| entry.0).collect()
+}
+
+fn append_deposit_record(record: DepositRecord) {
+    let tx_id = record.algorand_tx_id.clone();
+    let index = DEPOSIT_RECORDS.with(|records| {
+        records.borrow().append(&StableCandid(record)).expect("Failed to append deposit record")
     });
+    DEPOSIT_TX_INDEX.with(|i| i.borrow_mut().insert(tx_id, index));
+}
 
-    Ok(format!("Custody address {} registered for user {}", custody_address, user.to_text()))
+/// Index deposit records appended before DEPOSIT_TX_INDEX existed
+fn index_deposit_records() {
+    let indexed = DEPOSIT_TX_INDEX.with(|i| i.borrow().len());
+    DEPOSIT_RECORDS.with(|records| {
+        let records = records.borrow();
+        DEPOSIT_TX_INDEX.with(|i| {
+            let mut index = i.borrow_mut();
+            for n in indexed..records.len() {
+                if let Some(record) = records.get(n) {
+                    index.insert(record.0.algorand_tx_id, n);
+                }
+            }
+        });
+    });
 }
 
-#[update]
-async fn register_pending_deposit(
-    user: Principal,
-    algorand_tx_id: String,
-    amount: Nat,
-    custody_address: String,
-    confirmations: u8
-) -> Result<String, String> {
-    let caller_principal = caller();
+fn balance_of(account: &str) -> Nat {
+    BALANCES.with(|b| b.borrow().get(&account.to_string()).map(|v| v.0).unwrap_or_else(|| Nat::from(0u64)))
+}
 
-    // Check authorization - only authorized minters can register deposits
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
-    });
+fn set_balance(account: &str, balance: Nat) {
+    BALANCES.with(|b| b.borrow_mut().insert(account.to_string(), StableCandid(balance)));
+}
 
-    if !is_authorized {
-        return Err(format!("Unauthorized: only authorized minters can register deposits. Caller: {}", caller_principal));
+/// True if stable memory still holds a pre-MemoryManager stable_save blob
+fn has_legacy_stable_layout() -> bool {
+    if ic_cdk::api::stable::stable_size() == 0 {
+        return false;
     }
+    let mut magic = [0u8; 3];
+    ic_cdk::api::stable::stable_read(0, &mut magic);
+    &magic != b"MGR"
+}
 
-    // CRITICAL FIX 1: Verify custody address belongs to the claimed user
-    let address_owner = DEPOSIT_ADDRESSES.with(|addresses| {
-        addresses.borrow().get(&custody_address).cloned()
-    });
+fn save_heap_state(state: &StableStorage) {
+    let bytes = encode_state_envelope(state);
+    let mut upgrades = memory(UPGRADES_MEMORY_ID);
+    let mut writer = Writer::new(&mut upgrades, 0);
+    writer.write(&(bytes.len() as u64).to_le_bytes()).expect("Failed to write heap state");
+    writer.write(&bytes).expect("Failed to write heap state");
+}
 
-    match address_owner {
-        Some(owner) if owner == user => {
-            // Valid - custody address belongs to user
-        },
-        Some(owner) => {
-            return Err(format!(
-                "Security violation: Custody address {} belongs to {}, not {}",
-                custody_address, owner.to_text(), user.to_text()
-            ));
-        },
-        None => {
-            return Err(format!("Unknown custody address: {}", custody_address));
-        }
+fn read_heap_state() -> Vec<u8> {
+    let upgrades = memory(UPGRADES_MEMORY_ID);
+    if upgrades.size() == 0 {
+        ic_cdk::trap("No heap state in stable memory");
     }
+    let mut len = [0u8; 8];
+    upgrades.read(0, &mut len);
+    let mut bytes = vec![0u8; u64::from_le_bytes(len) as usize];
+    upgrades.read(8, &mut bytes);
+    bytes
+}
 
-    // CRITICAL FIX 3: Check BOTH pending and completed deposits for duplicates
-    let already_pending = PENDING_DEPOSITS.with(|deposits| {
-        deposits.borrow().contains_key(&algorand_tx_id)
-    });
+/// Unbounded collections carried inline by the v1 layout, or decoded from a
+/// state snapshot page, restored into the stable structures
+#[derive(Default)]
+struct StateCollections {
+    balances: Vec<(String, Nat)>,
+    pending_deposits: Vec<(String, PendingDeposit)>,
+    deposit_records: Vec<DepositRecord>,
+    swap_records: Vec<SwapRecord>,
+    processed_swap_deposits: Vec<String>,
+    processed_swap_deposit_log: Vec<ProcessedSwapDeposit>,
+    treasury_withdrawals: Vec<TreasuryWithdrawal>,
+    redemptions: Vec<Redemption>,
+    deposit_addresses: Vec<(String, Principal)>,
+    custody_balances: Vec<(String, Nat)>,
+    sweep_records: Vec<SweepRecord>,
+    auto_swap_preferences: Vec<AutoSwapPreference>,
+    custody_credited: Vec<((Principal, Principal), Nat)>,
+    custody_credited_through: Vec<((Principal, Principal), Nat)>,
+    swap_refunds: Vec<SwapRefund>,
+    admin_operations: Vec<AdminOperation>,
+    config_changes: Vec<PendingConfigChange>,
+    rebalance_proposals: Vec<RebalanceProposal>,
+}
+
+fn restore_state_collections(collections: StateCollections) {
+    BALANCES.with(|b| {
+        let mut balances = b.borrow_mut();
+        for (account, balance) in collections.balances {
+            balances.insert(account, StableCandid(balance));
+        }
+    });
+    PENDING_DEPOSITS.with(|d| {
+        let mut deposits = d.borrow_mut();
+        for (tx_id, deposit) in collections.pending_deposits {
+            deposits.insert(tx_id, StableCandid(deposit));
+        }
+    });
+    for record in collections.deposit_records {
+        append_deposit_record(record);
+    }
+    SWAP_RECORDS.with(|r| {
+        let records = r.borrow();
+        for record in collections.swap_records {
+            records.append(&StableCandid(record)).expect("Failed to restore swap record");
+        }
+    });
+    TREASURY_WITHDRAWALS.with(|w| {
+        let withdrawals = w.borrow();
+        for withdrawal in collections.treasury_withdrawals {
+            withdrawals.append(&StableCandid(withdrawal)).expect("Failed to restore treasury withdrawal");
+        }
+    });
+
+    PROCESSED_SWAP_DEPOSITS.with(|d| {
+        let mut set = d.borrow_mut();
+        for key in collections.processed_swap_deposits {
+            set.insert(key);
+        }
+    });
+    PROCESSED_SWAP_DEPOSIT_LOG.with(|l| {
+        let entries = l.borrow();
+        for entry in collections.processed_swap_deposit_log {
+            entries.append(&StableCandid(entry)).expect("Failed to restore processed deposit log");
+        }
+    });
+
+    REDEMPTIONS.with(|r| {
+        let mut redemptions = r.borrow_mut();
+        for redemption in collections.redemptions {
+            redemptions.insert(redemption.redemption_id, StableCandid(redemption));
+        }
+    });
+    DEPOSIT_ADDRESSES.with(|a| {
+        let mut addresses = a.borrow_mut();
+        for (address, owner) in collections.deposit_addresses {
+            addresses.insert(address, owner);
+        }
+    });
+    CUSTODY_BALANCES.with(|b| {
+        let mut balances = b.borrow_mut();
+        for (address, balance) in collections.custody_balances {
+            balances.insert(address, StableCandid(balance));
+        }
+    });
+    SWEEP_RECORDS.with(|r| {
+        let mut records = r.borrow_mut();
+        for record in collections.sweep_records {
+            records.insert(record.sweep_id, StableCandid(record));
+        }
+    });
+    AUTO_SWAP_PREFERENCES.with(|p| {
+        let mut preferences = p.borrow_mut();
+        for preference in collections.auto_swap_preferences {
+            preferences.insert(preference.agent, StableCandid(preference));
+        }
+    });
+    CUSTODY_CREDITED.with(|c| {
+        let mut credited = c.borrow_mut();
+        for (key, amount) in collections.custody_credited {
+            credited.insert(key, StableCandid(amount));
+        }
+    });
+    CUSTODY_CREDITED_THROUGH.with(|c| {
+        let mut credited_through = c.borrow_mut();
+        for (key, log_length) in collections.custody_credited_through {
+            credited_through.insert(key, StableCandid(log_length));
+        }
+    });
+    SWAP_REFUNDS.with(|r| {
+        let refunds = r.borrow();
+        for refund in collections.swap_refunds {
+            refunds.append(&StableCandid(refund)).expect("Failed to restore swap refund");
+        }
+    });
+    ADMIN_OPERATIONS.with(|o| {
+        let mut operations = o.borrow_mut();
+        for operation in collections.admin_operations {
+            operations.insert(operation.operation_id, StableCandid(operation));
+        }
+    });
+    CONFIG_CHANGES.with(|c| {
+        let mut changes = c.borrow_mut();
+        for change in collections.config_changes {
+            changes.insert(change.change_id, StableCandid(change));
+        }
+    });
+    REBALANCE_PROPOSALS.with(|p| {
+        let mut proposals = p.borrow_mut();
+        for proposal in collections.rebalance_proposals {
+            proposals.insert(proposal.proposal_id, StableCandid(proposal));
+        }
+    });
+}
+
+// ============================================================================
+// STATE VERSIONING
+// ============================================================================
+//
+// The heap state is saved as a StateEnvelope carrying the StableStorage schema
+// version and its candid encoding. post_upgrade decodes any supported version
+// and runs the vN -> vN+1 migrations in order, so restore code only ever sees
+// STATE_VERSION. v1 is the last release before the envelope: a single
+// stable_save blob holding every collection, which carries no version.
+//
+// Changing StableStorage: bump STATE_VERSION once per release, keep the old
+// layout as StableStorageVN, add its migrate_state arm and write the new
+// version's fixture with the ignored write_state_fixture test (see
+// tests/fixtures/README.md).
+
+/// Current StableStorage schema version
+const STATE_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct StateEnvelope {
+    pub version: u32,
+    pub state: Vec<u8>,  // Candid-encoded StableStorage of that version
+}
+
+/// StableStorage decoded from a saved blob and migrated to STATE_VERSION
+pub struct MigratedState {
+    pub source_version: u32,
+    pub migrations: Vec<String>,
+    pub state: StableStorage,
+    collections: StateCollections,  // Carried inline by v1 only
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct UpgradeCompatReport {
+    pub source_version: u32,
+    pub target_version: u32,
+    pub migrations: Vec<String>,
+}
+
+fn encode_state_envelope(state: &StableStorage) -> Vec<u8> {
+    let envelope = StateEnvelope {
+        version: STATE_VERSION,
+        state: candid::encode_one(state).expect("Failed to encode heap state"),
+    };
+    candid::encode_one(&envelope).expect("Failed to encode state envelope")
+}
+
+/// Decode the first candid value, ignoring anything after it (raw stable
+/// memory is padded to whole pages)
+fn decode_first_value<T: CandidType + for<'de> Deserialize<'de>>(bytes: &[u8]) -> Result<T, String> {
+    let mut de = candid::de::IDLDeserialize::new(bytes).map_err(|e| e.to_string())?;
+    de.get_value::<T>().map_err(|e| e.to_string())
+}
+
+/// Decode a saved state blob of any supported version and migrate it.
+/// `legacy_layout` marks a raw stable_save dump from the single-blob layout.
+fn decode_versioned_state(bytes: &[u8], legacy_layout: bool) -> Result<MigratedState, String> {
+    let (source_version, payload) = if legacy_layout {
+        (1, bytes.to_vec())
+    } else {
+        let envelope = candid::decode_one::<StateEnvelope>(bytes)
+            .map_err(|e| format!("Failed to decode state envelope: {}", e))?;
+        (envelope.version, envelope.state)
+    };
+    if source_version == 0 || source_version > STATE_VERSION {
+        return Err(format!(
+            "State version {} is not supported by this build (v1 to v{})",
+            source_version, STATE_VERSION
+        ));
+    }
+
+    let (state, collections, migrations) = migrate_state(&payload, source_version)
+        .map_err(|e| format!("Failed to decode v{} state: {}", source_version, e))?;
+    Ok(MigratedState { source_version, migrations, state, collections })
+}
+
+/// Decode a StateEnvelope, or failing that a raw single-blob stable memory dump
+fn decode_state_blob(bytes: &[u8]) -> Result<MigratedState, String> {
+    let legacy_layout = candid::decode_one::<StateEnvelope>(bytes).is_err();
+    decode_versioned_state(bytes, legacy_layout)
+}
+
+/// Decode a `version` payload with that version's struct and run the
+/// vN -> vN+1 migrations up to STATE_VERSION, describing each step
+fn migrate_state(payload: &[u8], version: u32) -> Result<(StableStorage, StateCollections, Vec<String>), String> {
+    match version {
+        1 => {
+            let (state, collections) = migrate_v1_to_v2(decode_first_value(payload)?);
+            let step = "added the swap pair registry, processed deposit log and roles, and moved the collections into stable structures";
+            Ok((state, collections, vec![format!("v1 -> v2: {}", step)]))
+        }
+        _ => Ok((decode_first_value(payload)?, StateCollections::default(), Vec::new())),
+    }
+}
+
+/// v2 keyed swap settings by input ledger, logged processed deposits in order,
+/// replaced AUTHORIZED_MINTERS with roles and moved the collections into stable
+/// structures. Everything v1 did not have starts at its default.
+fn migrate_v1_to_v2(v1: StableStorageV1) -> (StableStorage, StateCollections) {
+    let ledger = cketh_ledger();
+    let mut pairs = default_swap_pairs();
+    if let Some(pair) = pairs.get_mut(&ledger) {
+        if let Some(fee_bps) = v1.swap_fee_bps {
+            pair.fee_bps = fee_bps;
+        }
+        if let Some(min_cketh) = &v1.min_swap_cketh {
+            pair.min_amount = min_cketh.clone();
+        }
+        if let Some(max_cketh) = &v1.max_swap_cketh {
+            pair.max_amount = max_cketh.clone();
+        }
+    }
+    let swap_backing = vec![(ledger, SwapBacking {
+        total_received: v1.total_cketh_received.unwrap_or(Nat::from(0u64)),
+        ckalgo_minted: v1.cketh_backed_ckalgo.unwrap_or(Nat::from(0u64)),
+    })];
+
+    let swap_records = v1.swap_records.unwrap_or_default();
+    let processed_swap_deposits = v1.processed_swap_deposits.unwrap_or_default();
+    let processed_swap_deposit_log = rebuild_processed_deposit_log(processed_swap_deposits.clone(), &swap_records, &[]);
+
+    let state = StableStorage {
+        total_supply: v1.total_supply,
+        locked_algo_reserves: v1.locked_algo_reserves,
+        reserve_health_status: v1.reserve_health_status,
+        last_reserve_check: v1.last_reserve_check,
+        // Keep what minters could do, minus Admin/Pauser
+        roles: v1.authorized_minters.iter().map(|minter| (*minter, LEGACY_MINTER_ROLES.to_vec())).collect(),
+        approval_config: ApprovalConfig::default(),
+        unapproved_admin_actions: Vec::new(),
+        config_timelock_delay_secs: DEFAULT_CONFIG_TIMELOCK_SECS,
+        pause_bitmap: 0,
+        pause_records: Vec::new(),
+        governance_canister: None,
+        swap_enabled: v1.swap_enabled.unwrap_or(false),
+        swap_pairs: pairs.into_values().collect(),
+        swap_backing,
+        pending_reverse_swaps: Vec::new(),
+        next_reverse_swap_id: 0,
+        rate_guard_config: RateGuardConfig::default(),
+        last_accepted_rates: Vec::new(),
+        rate_circuit_breaker: None,
+        rate_rejections: Vec::new(),
+        rate_cache_config: RateCacheConfig::default(),
+        deposit_watcher_config: DepositWatcherConfig::default(),
+        deposit_watcher_cursor: None,
+        treasury_account: None,
+        treasury_fees: Vec::new(),
+        sweep_config: SweepConfig::default(),
+        in_flight_sweeps: Nat::from(0u64),
+        hot_wallet_balance: Nat::from(0u64),
+        reserve_policy: ReservePolicy::default(),
+        cold_wallets: Vec::new(),
+        in_flight_rebalance: Nat::from(0u64),
+    };
+    let collections = StateCollections {
+        balances: v1.balances,
+        pending_deposits: v1.pending_deposits,
+        deposit_records: v1.deposit_records,
+        deposit_addresses: v1.deposit_addresses,
+        swap_records,
+        processed_swap_deposits,
+        processed_swap_deposit_log,
+        ..StateCollections::default()
+    };
+    (state, collections)
+}
+
+// ============================================================================
+// SIMPLIFIED GLOBAL STATE - Core Bridge Only
+// ============================================================================
+
+thread_local! {
+    // Core ICRC-1 token state
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static BALANCES: RefCell<StableBTreeMap<String, StableCandid<Nat>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(BALANCES_MEMORY_ID)));
+    static TOTAL_SUPPLY: RefCell<Nat> = RefCell::new(Nat::from(0u64));
+    static TOKEN_NAME: RefCell<String> = RefCell::new("Chain-Key ALGO".to_string());
+    static TOKEN_SYMBOL: RefCell<String> = RefCell::new("ckALGO".to_string());
+    static DECIMALS: RefCell<u8> = const { RefCell::new(6u8) };
+    static FEE: RefCell<Nat> = RefCell::new(Nat::from(10000u64));
+    static ROLES: RefCell<HashMap<Principal, HashSet<Role>>> = RefCell::new(HashMap::new());
+
+    // Multi-party approval for high-value admin operations, by operation_id
+    static APPROVAL_CONFIG: RefCell<ApprovalConfig> = RefCell::new(ApprovalConfig::default());
+    static ADMIN_OPERATIONS: RefCell<StableBTreeMap<u64, StableCandid<AdminOperation>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(ADMIN_OPERATIONS_MEMORY_ID)));
+    static UNAPPROVED_ADMIN_ACTIONS: RefCell<Vec<UnapprovedAdminAction>> = const { RefCell::new(Vec::new()) };
+
+    // Timelocked configuration changes, by change_id
+    static CONFIG_TIMELOCK_DELAY_SECS: RefCell<u64> = const { RefCell::new(DEFAULT_CONFIG_TIMELOCK_SECS) };
+    static CONFIG_CHANGES: RefCell<StableBTreeMap<u64, StableCandid<PendingConfigChange>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CONFIG_CHANGES_MEMORY_ID)));
+    static CONFIG_TIMELOCK_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+
+    // Emergency pause: one bit per PauseClass, with the reason for each set bit
+    static PAUSE_BITMAP: RefCell<u32> = const { RefCell::new(0) };
+    static PAUSE_RECORDS: RefCell<HashMap<PauseClass, PauseRecord>> = RefCell::new(HashMap::new());
+
+    // DAO governance canister: may call every config function, like a controller
+    static GOVERNANCE_CANISTER: RefCell<Option<Principal>> = const { RefCell::new(None) };
+
+    // Disaster-recovery snapshots in transit (not kept across upgrades)
+    static STATE_EXPORT: RefCell<Option<StateExport>> = const { RefCell::new(None) };
+    static STATE_IMPORT: RefCell<Option<StateImport>> = const { RefCell::new(None) };
+    
+    // Bridge-specific state
+    static DEPOSIT_ADDRESSES: RefCell<StableBTreeMap<String, Principal, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DEPOSIT_ADDRESSES_MEMORY_ID)));
+    static LOCKED_ALGO_RESERVES: RefCell<Nat> = RefCell::new(Nat::from(0u64));
+    static PENDING_DEPOSITS: RefCell<StableBTreeMap<String, StableCandid<PendingDeposit>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(PENDING_DEPOSITS_MEMORY_ID)));
+    static DEPOSIT_RECORDS: RefCell<StableRecordLog<DepositRecord>> =
+        RefCell::new(record_log(DEPOSIT_RECORDS_INDEX_MEMORY_ID, DEPOSIT_RECORDS_DATA_MEMORY_ID));
+    // algorand_tx_id -> DEPOSIT_RECORDS index, for the duplicate check
+    static DEPOSIT_TX_INDEX: RefCell<StableBTreeMap<String, u64, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(DEPOSIT_TX_INDEX_MEMORY_ID)));
+    
+    // Reserve verification
+    static LAST_RESERVE_CHECK: RefCell<u64> = const { RefCell::new(0u64) };
+    static RESERVE_HEALTH_STATUS: RefCell<bool> = const { RefCell::new(true) };
+
+    // Swap state (ckETH → ckALGO)
+    static SWAP_ENABLED: RefCell<bool> = const { RefCell::new(false) };  // Disabled by default (all pairs)
+    static SWAP_PAIRS: RefCell<HashMap<Principal, SwapPair>> = RefCell::new(default_swap_pairs());
+    static SWAP_RECORDS: RefCell<StableRecordLog<SwapRecord>> =
+        RefCell::new(record_log(SWAP_RECORDS_INDEX_MEMORY_ID, SWAP_RECORDS_DATA_MEMORY_ID));
+    // Reserve tracking: swap-backed ckALGO per input asset, separate from ALGO-backed ckALGO
+    static SWAP_BACKING: RefCell<HashMap<Principal, SwapBacking>> = RefCell::new(HashMap::new());
+    static PENDING_REVERSE_SWAPS: RefCell<HashMap<u64, PendingReverseSwap>> = RefCell::new(HashMap::new());
+    static NEXT_REVERSE_SWAP_ID: RefCell<u64> = const { RefCell::new(0) };
+
+    // Deposit-based swap tracking (anti-replay protection). The set also holds
+    // in-flight refund claims; the log records finished deposits in order.
+    static PROCESSED_SWAP_DEPOSITS: RefCell<StableBTreeSet<String, Memory>> =
+        RefCell::new(StableBTreeSet::init(memory(PROCESSED_SWAP_DEPOSITS_MEMORY_ID)));
+    static PROCESSED_SWAP_DEPOSIT_LOG: RefCell<StableRecordLog<ProcessedSwapDeposit>> =
+        RefCell::new(record_log(PROCESSED_DEPOSIT_LOG_INDEX_MEMORY_ID, PROCESSED_DEPOSIT_LOG_DATA_MEMORY_ID));
+
+    // XRC rate quality guards
+    static RATE_GUARD_CONFIG: RefCell<RateGuardConfig> = RefCell::new(RateGuardConfig::default());
+    static LAST_ACCEPTED_RATES: RefCell<HashMap<String, AcceptedRate>> = RefCell::new(HashMap::new());  // By XRC symbol
+    static RATE_CIRCUIT_BREAKER: RefCell<Option<String>> = const { RefCell::new(None) };  // Some(reason) while tripped
+    static RATE_REJECTIONS: RefCell<Vec<RateRejection>> = const { RefCell::new(Vec::new()) };
+
+    // <asset>/ALGO rate cache. LAST_ACCEPTED_RATES doubles as the cached values.
+    static RATE_CACHE_CONFIG: RefCell<RateCacheConfig> = RefCell::new(RateCacheConfig::default());
+    static LAST_RATE_REFRESH_ATTEMPTS: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    static RATE_REFRESHES_IN_PROGRESS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static RATE_REFRESH_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+
+    // Custody deposit watcher. CUSTODY_CREDITED is the part of each (ledger, agent)
+    // custody subaccount balance that has already been swapped but not yet swept
+    // to the treasury; anything above it is an uncredited deposit.
+    static AUTO_SWAP_PREFERENCES: RefCell<StableBTreeMap<Principal, StableCandid<AutoSwapPreference>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(AUTO_SWAP_PREFERENCES_MEMORY_ID)));
+    static CUSTODY_CREDITED: RefCell<StableBTreeMap<(Principal, Principal), StableCandid<Nat>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CUSTODY_CREDITED_MEMORY_ID)));
+    static CUSTODY_LOCKS: RefCell<HashSet<(Principal, Principal)>> = RefCell::new(HashSet::new());
+    static DEPOSIT_WATCHER_CONFIG: RefCell<DepositWatcherConfig> = RefCell::new(DepositWatcherConfig::default());
+    static DEPOSIT_WATCHER_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static DEPOSIT_WATCHER_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+    static DEPOSIT_WATCHER_LAST_RUN: RefCell<u64> = const { RefCell::new(0) };
+    static DEPOSIT_WATCHER_LAST_REPORT: RefCell<Option<DepositWatcherReport>> = const { RefCell::new(None) };
+    // Last agent visited, so cycles capped at max_agents_per_cycle rotate through everyone
+    static DEPOSIT_WATCHER_CURSOR: RefCell<Option<Principal>> = const { RefCell::new(None) };
+
+    // Refunded swap deposits (refund_id = index)
+    static SWAP_REFUNDS: RefCell<StableRecordLog<SwapRefund>> =
+        RefCell::new(record_log(SWAP_REFUNDS_INDEX_MEMORY_ID, SWAP_REFUNDS_DATA_MEMORY_ID));
+    // Per (ledger, agent), the ledger log length when the watcher last credited the
+    // custody balance: every deposit block below it may have been swapped in bulk
+    static CUSTODY_CREDITED_THROUGH: RefCell<StableBTreeMap<(Principal, Principal), StableCandid<Nat>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CUSTODY_CREDITED_THROUGH_MEMORY_ID)));
+
+    // Swap fee treasury. Fees are minted as ckALGO to TREASURY_ACCOUNT (None = this
+    // canister's own principal); TREASURY_FEES accrues them per pair ledger.
+    static TREASURY_ACCOUNT: RefCell<Option<Principal>> = const { RefCell::new(None) };
+    static TREASURY_FEES: RefCell<HashMap<Principal, Nat>> = RefCell::new(HashMap::new());
+    static TREASURY_WITHDRAWALS: RefCell<StableRecordLog<TreasuryWithdrawal>> =
+        RefCell::new(record_log(TREASURY_WITHDRAWALS_INDEX_MEMORY_ID, TREASURY_WITHDRAWALS_DATA_MEMORY_ID));
+
+    // Outstanding firm quotes (short-lived, not persisted across upgrades)
+    static SWAP_QUOTES: RefCell<HashMap<u64, SwapQuote>> = RefCell::new(HashMap::new());
+    static NEXT_QUOTE_ID: RefCell<u64> = const { RefCell::new(0) };
+
+    // Certified state (rebuilt from the maps above on init/upgrade, not persisted).
+    // After an upgrade balances are recommitted in batches, resuming after RECERTIFY_CURSOR.
+    static CERTIFIED_STATE: RefCell<RbTree<Vec<u8>, Vec<u8>>> = const { RefCell::new(RbTree::new()) };
+    static RECERTIFY_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+    static RECERTIFY_CURSOR: RefCell<Option<String>> = const { RefCell::new(None) };
+
+    // Latest proof-of-liabilities snapshot (taken by snapshot_liabilities, not persisted)
+    static LIABILITY_TREE: RefCell<LiabilityTree> = RefCell::new(LiabilityTree::default());
+
+    // Custody sweep state (custody addresses -> consolidated hot wallet)
+    // LOCKED_ALGO_RESERVES is unchanged while a sweep is in flight: the amount moves
+    // from CUSTODY_BALANCES to IN_FLIGHT_SWEEPS, then to HOT_WALLET_BALANCE on confirmation.
+    static CUSTODY_BALANCES: RefCell<StableBTreeMap<String, StableCandid<Nat>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(CUSTODY_BALANCES_MEMORY_ID)));
+    static SWEEP_RECORDS: RefCell<StableBTreeMap<u64, StableCandid<SweepRecord>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(SWEEP_RECORDS_MEMORY_ID)));
+    static SWEEP_CONFIG: RefCell<SweepConfig> = RefCell::new(SweepConfig::default());
+    static IN_FLIGHT_SWEEPS: RefCell<Nat> = RefCell::new(Nat::from(0u64));
+    static HOT_WALLET_BALANCE: RefCell<Nat> = RefCell::new(Nat::from(0u64));
+    static SWEEP_IN_PROGRESS: RefCell<bool> = const { RefCell::new(false) };
+    static SWEEP_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+
+    // Hot/cold reserve split. Withdrawals are served from HOT_WALLET_BALANCE only.
+    static RESERVE_POLICY: RefCell<ReservePolicy> = RefCell::new(ReservePolicy::default());
+    static COLD_WALLETS: RefCell<Vec<ColdWallet>> = const { RefCell::new(Vec::new()) };
+    static REBALANCE_PROPOSALS: RefCell<StableBTreeMap<u64, StableCandid<RebalanceProposal>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(REBALANCE_PROPOSALS_MEMORY_ID)));
+    static IN_FLIGHT_REBALANCE: RefCell<Nat> = RefCell::new(Nat::from(0u64));
+    static REBALANCE_TIMER: RefCell<Option<TimerId>> = const { RefCell::new(None) };
+    static REDEMPTIONS: RefCell<StableBTreeMap<u64, StableCandid<Redemption>, Memory>> =
+        RefCell::new(StableBTreeMap::init(memory(REDEMPTIONS_MEMORY_ID)));
+}
+
+// ============================================================================
+// INITIALIZATION
+// ============================================================================
+
+#[init]
+fn init(args: Option<InitArgs>) {
+    // Initialize token parameters
+    TOKEN_NAME.with(|name| *name.borrow_mut() = "Chain-Key ALGO".to_string());
+    TOKEN_SYMBOL.with(|symbol| *symbol.borrow_mut() = "ckALGO".to_string());
+    DECIMALS.with(|decimals| *decimals.borrow_mut() = 6u8);
+    FEE.with(|fee| *fee.borrow_mut() = Nat::from(10000u64));
+    
+    // Initialize authorized minters (legacy minter roles) - only from init args
+    grant_init_minters(args.unwrap_or_default());
+    
+    // Initialize reserve state
+    LOCKED_ALGO_RESERVES.with(|reserves| *reserves.borrow_mut() = Nat::from(0u64));
+    RESERVE_HEALTH_STATUS.with(|health| *health.borrow_mut() = true);
+
+    certify_state(&[]);
+    schedule_sweep_timer();
+    schedule_rebalance_timer();
+    schedule_rate_refresh_timer();
+    schedule_deposit_watcher_timer();
+    schedule_config_timelock_timer();
+}
+
+// CRITICAL FIX 2: Stable storage for canister upgrades
+// Save the heap state before upgrade. The unbounded collections already live in
+// stable structures (see STABLE MEMORY LAYOUT).
+#[pre_upgrade]
+fn pre_upgrade() {
+    save_heap_state(&snapshot_heap_state());
+}
+
+/// StableStorage for the heap state at STATE_VERSION
+fn snapshot_heap_state() -> StableStorage {
+    StableStorage {
+        total_supply: TOTAL_SUPPLY.with(|supply| supply.borrow().clone()),
+        locked_algo_reserves: LOCKED_ALGO_RESERVES.with(|reserves| reserves.borrow().clone()),
+        reserve_health_status: RESERVE_HEALTH_STATUS.with(|health| *health.borrow()),
+        last_reserve_check: LAST_RESERVE_CHECK.with(|check| *check.borrow()),
+        roles: ROLES.with(|roles| {
+            roles.borrow().iter().map(|(p, r)| (*p, r.iter().copied().collect())).collect()
+        }),
+        approval_config: APPROVAL_CONFIG.with(|c| c.borrow().clone()),
+        unapproved_admin_actions: UNAPPROVED_ADMIN_ACTIONS.with(|a| a.borrow().clone()),
+        config_timelock_delay_secs: CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow()),
+        pause_bitmap: PAUSE_BITMAP.with(|b| *b.borrow()),
+        pause_records: PAUSE_RECORDS.with(|r| r.borrow().values().cloned().collect()),
+        governance_canister: GOVERNANCE_CANISTER.with(|g| *g.borrow()),
+        // Swap pair registry and reverse swaps
+        swap_enabled: SWAP_ENABLED.with(|e| *e.borrow()),
+        swap_pairs: SWAP_PAIRS.with(|p| p.borrow().values().cloned().collect()),
+        swap_backing: SWAP_BACKING.with(|b| {
+            b.borrow().iter().map(|(k, v)| (*k, v.clone())).collect()
+        }),
+        pending_reverse_swaps: PENDING_REVERSE_SWAPS.with(|p| p.borrow().values().cloned().collect()),
+        next_reverse_swap_id: NEXT_REVERSE_SWAP_ID.with(|n| *n.borrow()),
+        // XRC rate quality guards
+        rate_guard_config: RATE_GUARD_CONFIG.with(|c| c.borrow().clone()),
+        last_accepted_rates: LAST_ACCEPTED_RATES.with(|r| {
+            r.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        }),
+        rate_circuit_breaker: RATE_CIRCUIT_BREAKER.with(|b| b.borrow().clone()),
+        rate_rejections: RATE_REJECTIONS.with(|r| r.borrow().clone()),
+        rate_cache_config: RATE_CACHE_CONFIG.with(|c| c.borrow().clone()),
+        deposit_watcher_config: DEPOSIT_WATCHER_CONFIG.with(|c| c.borrow().clone()),
+        deposit_watcher_cursor: DEPOSIT_WATCHER_CURSOR.with(|c| *c.borrow()),
+        // Swap fee treasury
+        treasury_account: TREASURY_ACCOUNT.with(|t| *t.borrow()),
+        treasury_fees: TREASURY_FEES.with(|f| {
+            f.borrow().iter().map(|(k, v)| (*k, v.clone())).collect()
+        }),
+        // Custody sweep state
+        sweep_config: SWEEP_CONFIG.with(|c| c.borrow().clone()),
+        in_flight_sweeps: IN_FLIGHT_SWEEPS.with(|f| f.borrow().clone()),
+        hot_wallet_balance: HOT_WALLET_BALANCE.with(|h| h.borrow().clone()),
+        // Hot/cold reserve split
+        reserve_policy: RESERVE_POLICY.with(|p| p.borrow().clone()),
+        cold_wallets: COLD_WALLETS.with(|c| c.borrow().clone()),
+        in_flight_rebalance: IN_FLIGHT_REBALANCE.with(|f| f.borrow().clone()),
+    }
+}
+
+// CRITICAL FIX 2: Restore all state after upgrade
+#[post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    // The legacy blob must be read before the MemoryManager claims stable memory
+    let legacy_layout = has_legacy_stable_layout();
+    let bytes = if legacy_layout { ic_cdk::api::stable::stable_bytes() } else { read_heap_state() };
+    let MigratedState { migrations, state: stable_data, collections: legacy_collections, .. } =
+        decode_versioned_state(&bytes, legacy_layout)
+            .unwrap_or_else(|e| ic_cdk::trap(&format!("Upgrade aborted: {}", e)));
+    drop(bytes);
+    for migration in &migrations {
+        ic_cdk::println!("State migration {}", migration);
+    }
+    // Only the v1 blob carries collections inline
+    restore_heap_state(stable_data);
+    restore_state_collections(legacy_collections);
+    index_deposit_records();
+
+    // Earlier versions force-inserted the anonymous principal (letting any
+    // unauthenticated caller mint) and the management canister as minters
+    strip_invalid_role_holders();
+    grant_init_minters(args.unwrap_or_default());
+
+    // Certified data does not survive upgrades - recommit every balance in batches
+    start_recertification();
+
+    // Timers are cleared on upgrade
+    schedule_sweep_timer();
+    schedule_rebalance_timer();
+    schedule_rate_refresh_timer();
+    schedule_deposit_watcher_timer();
+    schedule_config_timelock_timer();
+}
+
+/// Restore everything but the stable structures from a migrated StableStorage
+fn restore_heap_state(stable_data: StableStorage) {
+    TOTAL_SUPPLY.with(|supply| *supply.borrow_mut() = stable_data.total_supply);
+    LOCKED_ALGO_RESERVES.with(|reserves| *reserves.borrow_mut() = stable_data.locked_algo_reserves);
+    RESERVE_HEALTH_STATUS.with(|health| *health.borrow_mut() = stable_data.reserve_health_status);
+    LAST_RESERVE_CHECK.with(|check| *check.borrow_mut() = stable_data.last_reserve_check);
+
+    ROLES.with(|r| {
+        *r.borrow_mut() = stable_data.roles.into_iter().map(|(p, roles)| (p, roles.into_iter().collect())).collect()
+    });
+    APPROVAL_CONFIG.with(|c| *c.borrow_mut() = stable_data.approval_config);
+    UNAPPROVED_ADMIN_ACTIONS.with(|a| *a.borrow_mut() = stable_data.unapproved_admin_actions);
+    CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow_mut() = stable_data.config_timelock_delay_secs);
+    PAUSE_BITMAP.with(|b| *b.borrow_mut() = stable_data.pause_bitmap);
+    PAUSE_RECORDS.with(|r| {
+        *r.borrow_mut() = stable_data.pause_records.into_iter().map(|rec| (rec.class, rec)).collect()
+    });
+    GOVERNANCE_CANISTER.with(|g| *g.borrow_mut() = stable_data.governance_canister);
+
+    SWAP_ENABLED.with(|e| *e.borrow_mut() = stable_data.swap_enabled);
+    SWAP_PAIRS.with(|p| {
+        *p.borrow_mut() = stable_data.swap_pairs.into_iter().map(|pair| (pair.ledger, pair)).collect()
+    });
+    SWAP_BACKING.with(|b| *b.borrow_mut() = stable_data.swap_backing.into_iter().collect());
+    PENDING_REVERSE_SWAPS.with(|p| {
+        *p.borrow_mut() = stable_data.pending_reverse_swaps.into_iter().map(|swap| (swap.swap_id, swap)).collect()
+    });
+    NEXT_REVERSE_SWAP_ID.with(|n| *n.borrow_mut() = stable_data.next_reverse_swap_id);
+
+    RATE_GUARD_CONFIG.with(|c| *c.borrow_mut() = stable_data.rate_guard_config);
+    LAST_ACCEPTED_RATES.with(|rates| *rates.borrow_mut() = stable_data.last_accepted_rates.into_iter().collect());
+    RATE_CIRCUIT_BREAKER.with(|b| *b.borrow_mut() = stable_data.rate_circuit_breaker);
+    RATE_REJECTIONS.with(|r| *r.borrow_mut() = stable_data.rate_rejections);
+    RATE_CACHE_CONFIG.with(|c| *c.borrow_mut() = stable_data.rate_cache_config);
+    DEPOSIT_WATCHER_CONFIG.with(|c| *c.borrow_mut() = stable_data.deposit_watcher_config);
+    DEPOSIT_WATCHER_CURSOR.with(|c| *c.borrow_mut() = stable_data.deposit_watcher_cursor);
+
+    TREASURY_ACCOUNT.with(|t| *t.borrow_mut() = stable_data.treasury_account);
+    TREASURY_FEES.with(|f| *f.borrow_mut() = stable_data.treasury_fees.into_iter().collect());
+
+    SWEEP_CONFIG.with(|c| *c.borrow_mut() = stable_data.sweep_config);
+    IN_FLIGHT_SWEEPS.with(|f| *f.borrow_mut() = stable_data.in_flight_sweeps);
+    HOT_WALLET_BALANCE.with(|h| *h.borrow_mut() = stable_data.hot_wallet_balance);
+
+    RESERVE_POLICY.with(|p| *p.borrow_mut() = stable_data.reserve_policy);
+    COLD_WALLETS.with(|c| *c.borrow_mut() = stable_data.cold_wallets);
+    IN_FLIGHT_REBALANCE.with(|f| *f.borrow_mut() = stable_data.in_flight_rebalance);
+}
+
+/// Dry-run the upgrade decode and migrations for a saved state blob
+///
+/// Pass a StateEnvelope, or a raw stable memory dump from the single-blob layout,
+/// to check that this build can restore it. Without a blob, round-trips this
+/// canister's own heap state. Nothing is applied. Controllers only.
+#[query]
+fn check_upgrade_compat(state: Option<Vec<u8>>) -> Result<UpgradeCompatReport, String> {
+    let caller_principal = caller();
+    if !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only controllers can check upgrade compatibility. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    let bytes = state.unwrap_or_else(|| encode_state_envelope(&snapshot_heap_state()));
+    let migrated = decode_state_blob(&bytes)?;
+    Ok(UpgradeCompatReport {
+        source_version: migrated.source_version,
+        target_version: STATE_VERSION,
+        migrations: migrated.migrations,
+    })
+}
+
+/// Recommit every balance after a bulk restore. Certifying every account in one
+/// message does not fit the instruction limit, so a timer walks BALANCES in
+/// batches; until it finishes, certified queries refuse accounts it has not reached.
+fn start_recertification() {
+    CERTIFIED_STATE.with(|tree| *tree.borrow_mut() = RbTree::new());
+    RECERTIFY_CURSOR.with(|c| *c.borrow_mut() = None);
+    RECERTIFY_IN_PROGRESS.with(|r| *r.borrow_mut() = true);
+    certify_state(&[]);
+    schedule_recertify_batch();
+}
+
+fn schedule_recertify_batch() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if !recertify_next_batch(RECERTIFY_BATCH_SIZE) {
+            schedule_recertify_batch();
+        }
+    });
+}
+
+/// Recommit the next `max` balances after RECERTIFY_CURSOR. Returns true once
+/// every account has been certified.
+fn recertify_next_batch(max: usize) -> bool {
+    let cursor = RECERTIFY_CURSOR.with(|c| c.borrow().clone());
+    let accounts: Vec<String> = BALANCES.with(|b| {
+        let balances = b.borrow();
+        match cursor {
+            Some(last) => balances.range((Excluded(last), Unbounded)).take(max).map(|(account, _)| account).collect(),
+            None => balances.keys().take(max).collect(),
+        }
+    });
+    certify_state(&accounts.iter().map(String::as_str).collect::<Vec<_>>());
+
+    let done = accounts.len() < max;
+    RECERTIFY_CURSOR.with(|c| *c.borrow_mut() = if done { None } else { accounts.last().cloned() });
+    RECERTIFY_IN_PROGRESS.with(|r| *r.borrow_mut() = !done);
+    done
+}
+
+// ============================================================================
+// ICRC-1 STANDARD METHODS
+// ============================================================================
+
+#[query]
+fn icrc1_name() -> String {
+    TOKEN_NAME.with(|name| name.borrow().clone())
+}
+
+#[query]
+fn icrc1_symbol() -> String {
+    TOKEN_SYMBOL.with(|symbol| symbol.borrow().clone())
+}
+
+#[query]
+fn icrc1_decimals() -> u8 {
+    DECIMALS.with(|decimals| *decimals.borrow())
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    FEE.with(|fee| fee.borrow().clone())
+}
+
+#[query]
+fn icrc1_total_supply() -> Nat {
+    TOTAL_SUPPLY.with(|supply| supply.borrow().clone())
+}
+
+#[query]
+fn icrc1_balance_of(account: Principal) -> Nat {
+    balance_of(&account.to_text())
+}
+
+#[query]
+fn icrc1_supported_standards() -> Vec<(String, String)> {
+    vec![
+        ("ICRC-1".to_string(), "https://github.com/dfinity/ICRC-1".to_string()),
+    ]
+}
+
+#[update]
+fn icrc1_transfer(to: Principal, amount: Nat) -> Result<Nat, String> {
+    ensure_not_paused(PauseClass::Transfers)?;
+    let from = caller();
+    let from_str = from.to_text();
+    let to_str = to.to_text();
+    
+    // Check balance
+    let current_balance = balance_of(&from_str);
+    
+    if current_balance < amount {
+        return Err("Insufficient balance".to_string());
+    }
+    
+    // Update balances
+    set_balance(&from_str, current_balance - amount.clone());
+    set_balance(&to_str, balance_of(&to_str) + amount);
+
+    certify_state(&[&from_str, &to_str]);
+
+    Ok(Nat::from(time()))
+}
+
+// ============================================================================
+// CERTIFIED QUERIES
+// ============================================================================
+//
+// Balances, reserve status and swap config are committed into an RbTree whose
+// root is passed to set_certified_data. Every update that changes one of these
+// values must call certify_state() before returning.
+
+const CERTIFIED_TREE_LABEL: &[u8] = b"bridge";
+const CERTIFIED_RESERVE_STATUS_KEY: &[u8] = b"reserve_status";
+const CERTIFIED_SWAP_CONFIG_KEY: &[u8] = b"swap_config";
+/// Balances recommitted per timer tick while recertifying after an upgrade
+const RECERTIFY_BATCH_SIZE: usize = 2_000;
+
+fn certified_balance_key(account: &str) -> Vec<u8> {
+    format!("balance/{}", account).into_bytes()
+}
+
+/// Recommit the given accounts' balances plus the reserve status and swap
+/// config leaves, then publish the new root hash as certified data.
+fn certify_state(accounts: &[&str]) {
+    let reserve_status = candid::encode_one(get_reserve_ratio())
+        .expect("Failed to encode reserve status");
+    let swap_config = candid::encode_one(get_swap_config())
+        .expect("Failed to encode swap config");
+
+    CERTIFIED_STATE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+
+        for account in accounts {
+            let balance = balance_of(account);
+            let encoded = candid::encode_one(balance).expect("Failed to encode balance");
+            tree.insert(certified_balance_key(account), encoded);
+        }
+
+        tree.insert(CERTIFIED_RESERVE_STATUS_KEY.to_vec(), reserve_status);
+        tree.insert(CERTIFIED_SWAP_CONFIG_KEY.to_vec(), swap_config);
+
+        set_certified_data(&labeled_hash(CERTIFIED_TREE_LABEL, &tree.root_hash()));
+    });
+}
+
+/// True while post-upgrade recertification has not yet recommitted `account`
+fn is_recertifying_account(account: &str) -> bool {
+    RECERTIFY_IN_PROGRESS.with(|r| *r.borrow())
+        && RECERTIFY_CURSOR.with(|c| c.borrow().as_deref().is_none_or(|last| account > last))
+}
+
+/// Decode a leaf of the certified tree. Certified queries return these bytes
+/// rather than recomputing the value, so the response always matches the witness.
+fn certified_value<T: CandidType + for<'de> Deserialize<'de>>(key: &[u8]) -> Result<Option<T>, String> {
+    CERTIFIED_STATE.with(|tree| {
+        tree.borrow()
+            .get(key)
+            .map(|bytes| candid::decode_one(bytes).map_err(|e| format!("Corrupt certified value: {}", e)))
+            .transpose()
+    })
+}
+
+/// Build (certificate, CBOR witness) for a key in the certified tree.
+/// Only works in query calls - the certificate is unavailable in updates.
+fn certified_witness(key: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let certificate = ic_cdk::api::data_certificate()
+        .ok_or("Data certificate is only available in query calls")?;
+
+    let witness = CERTIFIED_STATE.with(|tree| {
+        let tree = tree.borrow();
+        let hash_tree = labeled(CERTIFIED_TREE_LABEL, tree.witness(key));
+
+        let mut serializer = serde_cbor::ser::Serializer::new(Vec::new());
+        serializer.self_describe().map_err(|e| format!("CBOR encode failed: {}", e))?;
+        hash_tree.serialize(&mut serializer).map_err(|e| format!("CBOR encode failed: {}", e))?;
+        Ok::<_, String>(serializer.into_inner())
+    })?;
+
+    Ok((certificate, witness))
+}
+
+/// Certified variant of icrc1_balance_of
+#[query]
+fn icrc1_balance_of_certified(account: Principal) -> Result<CertifiedBalance, String> {
+    let key = certified_balance_key(&account.to_text());
+    let (certificate, witness) = certified_witness(&key)?;
+    // An absent leaf is certified too: the witness proves the account holds nothing,
+    // unless recertification after an upgrade has not reached the account yet
+    if CERTIFIED_STATE.with(|tree| tree.borrow().get(&key).is_none()) && is_recertifying_account(&account.to_text()) {
+        return Err("Balances are being recertified after an upgrade; retry shortly".to_string());
+    }
+    Ok(CertifiedBalance {
+        balance: certified_value(&key)?.unwrap_or_else(|| Nat::from(0u64)),
+        certificate,
+        witness,
+    })
+}
+
+/// Certified variant of get_reserve_ratio
+#[query]
+fn get_reserve_ratio_certified() -> Result<CertifiedReserveStatus, String> {
+    let (certificate, witness) = certified_witness(CERTIFIED_RESERVE_STATUS_KEY)?;
+    Ok(CertifiedReserveStatus {
+        reserve_status: certified_value(CERTIFIED_RESERVE_STATUS_KEY)?
+            .ok_or("Reserve status has not been certified yet")?,
+        certificate,
+        witness,
+    })
+}
+
+/// Certified variant of get_swap_config
+#[query]
+fn get_swap_config_certified() -> Result<CertifiedSwapConfig, String> {
+    let (certificate, witness) = certified_witness(CERTIFIED_SWAP_CONFIG_KEY)?;
+    Ok(CertifiedSwapConfig {
+        swap_config: certified_value(CERTIFIED_SWAP_CONFIG_KEY)?
+            .ok_or("Swap config has not been certified yet")?,
+        certificate,
+        witness,
+    })
+}
+
+// ============================================================================
+// PROOF OF LIABILITIES (Merkle sum tree over ckALGO balances)
+// ============================================================================
+//
+// Hashing (all sums are 16-byte big-endian u128):
+//   leaf  = SHA256("sippar-liability-leaf"  || len(account) as u32 BE || account || sum)
+//   node  = SHA256("sippar-liability-node"  || left.hash || left.sum || right.hash || right.sum)
+//   empty = SHA256("sippar-liability-empty"), sum 0 (pads odd-sized levels)
+// `account` is the principal's textual form. A user verifies their proof by
+// folding the path from their leaf up to the certified root hash and total.
+
+const CERTIFIED_LIABILITY_ROOT_KEY: &[u8] = b"liability_root";
+
+fn liability_leaf_hash(account: &str, balance: u128) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"sippar-liability-leaf");
+    hasher.update((account.len() as u32).to_be_bytes());
+    hasher.update(account.as_bytes());
+    hasher.update(balance.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn liability_node_hash(left: &([u8; 32], u128), right: &([u8; 32], u128)) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(b"sippar-liability-node");
+    hasher.update(left.0);
+    hasher.update(left.1.to_be_bytes());
+    hasher.update(right.0);
+    hasher.update(right.1.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn liability_empty_node() -> ([u8; 32], u128) {
+    (Sha256::digest(b"sippar-liability-empty").into(), 0)
+}
+
+impl LiabilityTree {
+    fn root(&self) -> ([u8; 32], u128) {
+        self.levels
+            .last()
+            .and_then(|level| level.first().copied())
+            .unwrap_or_else(liability_empty_node)
+    }
+
+    fn root_info(&self) -> LiabilityRoot {
+        let (hash, sum) = self.root();
+        LiabilityRoot {
+            root_hash: hash.to_vec(),
+            total_liabilities: Nat::from(sum),
+            leaf_count: self.leaf_index.len() as u64,
+            snapshot_at: self.snapshot_at,
+        }
+    }
+}
+
+/// Rebuild the sum tree from current balances and certify its root. O(n log n)
+/// in the number of holders, so it only runs on an explicit snapshot request.
+fn rebuild_liability_tree() -> Result<(), String> {
+    let mut accounts: Vec<(String, u128)> = BALANCES.with(|balances| {
+        balances.borrow()
+            .iter()
+            .filter(|(_, balance)| !balance.0.0.is_zero())
+            .map(|(account, balance)| {
+                let amount = balance.0.0.to_u128()
+                    .ok_or_else(|| format!("Balance of {} exceeds the liability tree's u128 sums", account))?;
+                Ok((account, amount))
+            })
+            .collect::<Result<_, String>>()
+    })?;
+    accounts.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut tree = LiabilityTree {
+        snapshot_at: time(),
+        ..Default::default()
+    };
+
+    let mut level: Vec<([u8; 32], u128)> = accounts
+        .iter()
+        .enumerate()
+        .map(|(index, (account, amount))| {
+            tree.leaf_index.insert(account.clone(), index);
+            (liability_leaf_hash(account, *amount), *amount)
+        })
+        .collect();
+
+    if level.is_empty() {
+        level.push(liability_empty_node());
+    }
+
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(liability_empty_node());
+        }
+        let parent = level
+            .chunks(2)
+            .map(|pair| {
+                let sum = pair[0].1.checked_add(pair[1].1)
+                    .ok_or("Total liabilities exceed the liability tree's u128 sums")?;
+                Ok((liability_node_hash(&pair[0], &pair[1]), sum))
+            })
+            .collect::<Result<_, String>>()?;
+        tree.levels.push(level);
+        level = parent;
+    }
+    tree.levels.push(level);
+
+    let encoded_root = candid::encode_one(tree.root_info()).expect("Failed to encode liability root");
+    LIABILITY_TREE.with(|t| *t.borrow_mut() = tree);
+
+    CERTIFIED_STATE.with(|certified| {
+        certified.borrow_mut().insert(CERTIFIED_LIABILITY_ROOT_KEY.to_vec(), encoded_root);
+    });
+    certify_state(&[]);
+    Ok(())
+}
+
+/// Take a new proof-of-liabilities snapshot of all ckALGO balances
+/// Requires the Operator role
+#[update]
+fn snapshot_liabilities() -> Result<LiabilityRoot, String> {
+    let caller_principal = caller();
+
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: snapshotting liabilities requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    rebuild_liability_tree()?;
+    Ok(LIABILITY_TREE.with(|tree| tree.borrow().root_info()))
+}
+
+/// Certified root of the latest liability snapshot
+#[query]
+fn get_liability_root_certified() -> Result<CertifiedLiabilityRoot, String> {
+    let (certificate, witness) = certified_witness(CERTIFIED_LIABILITY_ROOT_KEY)?;
+    Ok(CertifiedLiabilityRoot {
+        root: certified_value(CERTIFIED_LIABILITY_ROOT_KEY)?
+            .ok_or("No liability snapshot has been taken yet")?,
+        certificate,
+        witness,
+    })
+}
+
+/// Inclusion proof that an account's balance is counted in the latest snapshot
+#[query]
+fn get_liability_proof(account: Principal) -> Result<LiabilityProof, String> {
+    let account_str = account.to_text();
+
+    LIABILITY_TREE.with(|tree| {
+        let tree = tree.borrow();
+        if tree.levels.is_empty() {
+            return Err("No liability snapshot has been taken yet".to_string());
+        }
+        let leaf_index = *tree.leaf_index.get(&account_str).ok_or_else(|| {
+            format!("Account {} has no balance in the snapshot taken at {}", account_str, tree.snapshot_at)
+        })?;
+
+        let mut path = Vec::new();
+        let mut index = leaf_index;
+        for level in &tree.levels[..tree.levels.len() - 1] {
+            let sibling_index = index ^ 1;
+            let (hash, sum) = level[sibling_index];
+            path.push(LiabilityProofStep {
+                sibling: LiabilityNode { hash: hash.to_vec(), sum: Nat::from(sum) },
+                sibling_is_left: sibling_index < index,
+            });
+            index /= 2;
+        }
+
+        Ok(LiabilityProof {
+            account,
+            balance: Nat::from(tree.levels[0][leaf_index].1),
+            leaf_index: leaf_index as u64,
+            path,
+            root: tree.root_info(),
+        })
+    })
+}
+
+// ============================================================================
+// BRIDGE CORE FUNCTIONS
+// ============================================================================
+
+// REMOVED: generate_deposit_address — returned fake "BRIDGE..." strings.
+// Real Algorand custody addresses are derived by threshold_signer canister
+// (vj7ly-diaaa-aaaae-abvoq-cai) via derive_algorand_address().
+// Use register_custody_address() to register a real threshold-derived address.
+
+/// Admin function: register an existing custody address for a user
+/// Requires the DepositReporter role
+#[update]
+fn register_custody_address(custody_address: String, user: Principal) -> Result<String, String> {
+    let caller_principal = caller();
+
+    if !has_role(&caller_principal, Role::DepositReporter) {
+        return Err(format!(
+            "Unauthorized: registering custody addresses requires the DepositReporter role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_not_paused(PauseClass::DepositRegistration)?;
+
+    DEPOSIT_ADDRESSES.with(|addresses| {
+        addresses.borrow_mut().insert(custody_address.clone(), user);
+    });
+
+    Ok(format!("Custody address {} registered for user {}", custody_address, user.to_text()))
+}
+
+#[update]
+async fn register_pending_deposit(
+    user: Principal,
+    algorand_tx_id: String,
+    amount: Nat,
+    custody_address: String,
+    confirmations: u8
+) -> Result<String, String> {
+    let caller_principal = caller();
+
+    if !has_role(&caller_principal, Role::DepositReporter) {
+        return Err(format!(
+            "Unauthorized: registering deposits requires the DepositReporter role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_not_paused(PauseClass::DepositRegistration)?;
+
+    // CRITICAL FIX 1: Verify custody address belongs to the claimed user
+    let address_owner = DEPOSIT_ADDRESSES.with(|addresses| {
+        addresses.borrow().get(&custody_address)
+    });
+
+    match address_owner {
+        Some(owner) if owner == user => {
+            // Valid - custody address belongs to user
+        },
+        Some(owner) => {
+            return Err(format!(
+                "Security violation: Custody address {} belongs to {}, not {}",
+                custody_address, owner.to_text(), user.to_text()
+            ));
+        },
+        None => {
+            return Err(format!("Unknown custody address: {}", custody_address));
+        }
+    }
+
+    // CRITICAL FIX 3: Check BOTH pending and completed deposits for duplicates
+    let already_pending = PENDING_DEPOSITS.with(|deposits| {
+        deposits.borrow().contains_key(&algorand_tx_id)
+    });
+
+    let already_completed = DEPOSIT_TX_INDEX.with(|index| {
+        index.borrow().contains_key(&algorand_tx_id)
+    });
+
+    if already_pending || already_completed {
+        return Err(format!("Deposit {} already processed", algorand_tx_id));
+    }
+
+    // Validate amount (must be > 0)
+    if amount == 0u64 {
+        return Err("Deposit amount must be greater than 0".to_string());
+    }
+
+    // HIGH-PRIORITY FIX: Add minimum deposit validation (0.1 ALGO = 100,000 microALGO)
+    const MIN_DEPOSIT_MICROALGOS: u64 = 100_000;
+    const MAX_DEPOSIT_MICROALGOS: u64 = 1_000_000_000_000; // 1M ALGO
+
+    let amount_u64 = amount.0.to_u64().ok_or("Amount too large to process")?;
+
+    if amount_u64 < MIN_DEPOSIT_MICROALGOS {
+        return Err(format!("Minimum deposit is 0.1 ALGO (100,000 microALGO). Got: {} microALGO", amount_u64));
+    }
+
+    if amount_u64 > MAX_DEPOSIT_MICROALGOS {
+        return Err(format!("Maximum deposit is 1M ALGO. Got: {} microALGO", amount_u64));
+    }
+
+    // HIGH-PRIORITY FIX: Add max pending deposits cap to prevent spam attacks
+    const MAX_PENDING_DEPOSITS: usize = 10_000;
+
+    let pending_count = PENDING_DEPOSITS.with(|deposits| deposits.borrow().len());
+    if pending_count >= MAX_PENDING_DEPOSITS as u64 {
+        return Err(format!("Maximum pending deposits limit reached ({}). Please wait for confirmations.", MAX_PENDING_DEPOSITS));
+    }
+
+    // HIGH-PRIORITY FIX: Clarify that confirmations parameter is REQUIRED confirmations
+    // Validate required confirmations (3 for testnet, 6 for mainnet)
+    let required_confirmations = confirmations;
+    if required_confirmations != 3 && required_confirmations != 6 {
+        return Err(format!("Invalid required_confirmations: {} (must be 3 for testnet or 6 for mainnet)", required_confirmations));
+    }
+
+    // Create pending deposit record
+    let pending_deposit = PendingDeposit {
+        user,
+        algorand_tx_id: algorand_tx_id.clone(),
+        amount: amount.clone(),
+        timestamp: time(),
+        confirmations: 0, // Will be updated as confirmations increase
+        required_confirmations,
+        custody_address: Some(custody_address.clone()),
+    };
+
+    // Store in pending deposits
+    PENDING_DEPOSITS.with(|deposits| {
+        deposits.borrow_mut().insert(algorand_tx_id.clone(), StableCandid(pending_deposit));
+    });
+
+    // Also track the custody address mapping if not already present
+    DEPOSIT_ADDRESSES.with(|addresses| {
+        if !addresses.borrow().contains_key(&custody_address) {
+            addresses.borrow_mut().insert(custody_address.clone(), user);
+        }
+    });
+
+    Ok(format!("Deposit {} registered successfully for user {}", algorand_tx_id, user.to_text()))
+}
+
+#[update]
+async fn mint_after_deposit_confirmed(deposit_tx_id: String) -> Result<Nat, String> {
+    let caller_principal = caller();
+    
+    if !has_role(&caller_principal, Role::Minter) {
+        return Err(format!(
+            "Unauthorized: minting requires the Minter role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_not_paused(PauseClass::Mints)?;
+    
+    // Check if deposit exists and is confirmed
+    let deposit_opt = PENDING_DEPOSITS.with(|deposits| {
+        deposits.borrow().get(&deposit_tx_id).map(|d| d.0)
+    });
+    
+    let deposit = match deposit_opt {
+        Some(dep) if dep.confirmations >= dep.required_confirmations => dep,
+        Some(_) => return Err("Deposit not yet confirmed".to_string()),
+        None => return Err("Deposit not found".to_string()),
+    };
+    
+    // Check reserve health before minting
+    let reserve_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
+    if !reserve_healthy {
+        return Err("Cannot mint: reserve system unhealthy".to_string());
+    }
+    
+    // Mint ckALGO tokens
+    let user_str = deposit.user.to_text();
+    set_balance(&user_str, balance_of(&user_str) + deposit.amount.clone());
+    
+    // Update total supply
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = total.clone() + deposit.amount.clone();
+    });
+    
+    // Update locked reserves
+    LOCKED_ALGO_RESERVES.with(|reserves| {
+        let mut locked = reserves.borrow_mut();
+        *locked = locked.clone() + deposit.amount.clone();
+    });
+    
+    // Remove from pending deposits
+    PENDING_DEPOSITS.with(|deposits| {
+        deposits.borrow_mut().remove(&deposit_tx_id);
+    });
+    
+    // Track the ALGO now sitting on the custody address (source for consolidation sweeps)
+    if let Some(custody_address) = &deposit.custody_address {
+        set_custody_balance(custody_address, custody_balance(custody_address) + deposit.amount.clone());
+    }
+
+    // Record the deposit
+    let deposit_record = DepositRecord {
+        deposit_id: deposit_tx_id,
+        user: deposit.user,
+        custody_address: deposit.custody_address.clone().unwrap_or_default(),
+        amount: deposit.amount.clone(),
+        algorand_tx_id: deposit.algorand_tx_id,
+        confirmed_at: time(),
+        minted_ck_algo: deposit.amount.clone(),
+    };
+    
+    append_deposit_record(deposit_record);
+
+    certify_state(&[&user_str]);
+
+    Ok(deposit.amount)
+}
+
+/// TEMPORARY ARCHITECTURE: Update deposit confirmations
+///
+/// SECURITY WARNING: This function trusts the authorized backend to report
+/// accurate confirmation counts. In Phase 2, this will be replaced with
+/// canister-side verification via HTTP outcalls (ckETH pattern).
+///
+/// Requires the DepositReporter role.
+#[update]
+async fn update_deposit_confirmations(
+    algorand_tx_id: String,
+    confirmations: u8
+) -> Result<String, String> {
+    let caller_principal = caller();
+
+    if !has_role(&caller_principal, Role::DepositReporter) {
+        return Err(format!(
+            "Unauthorized: updating confirmations requires the DepositReporter role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    // Update confirmations in pending deposits
+    PENDING_DEPOSITS.with(|deposits| {
+        let mut deposits = deposits.borrow_mut();
+        if let Some(StableCandid(mut deposit)) = deposits.get(&algorand_tx_id) {
+            deposit.confirmations = confirmations;
+            let required_confirmations = deposit.required_confirmations;
+            deposits.insert(algorand_tx_id.clone(), StableCandid(deposit));
+            Ok(format!(
+                "Updated deposit {} to {} confirmations (required: {})",
+                algorand_tx_id, confirmations, required_confirmations
+            ))
+        } else {
+            Err(format!("Deposit {} not found in pending deposits", algorand_tx_id))
+        }
+    })
+}
+
+#[update]
+async fn redeem_ck_algo(amount: Nat, destination: String) -> Result<String, String> {
+    ensure_not_paused(PauseClass::Redemptions)?;
+    let user = caller();
+    let user_str = user.to_text();
+
+    // Check balance
+    let current_balance = balance_of(&user_str);
+
+    if current_balance < amount {
+        return Err("Insufficient ckALGO balance".to_string());
+    }
+
+    // Check reserve health
+    let reserve_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
+    if !reserve_healthy {
+        return Err("Cannot redeem: reserve system unhealthy".to_string());
+    }
+
+    // Withdrawals are paid from the hot wallet only
+    let reserved = reserve_hot_wallet_liquidity(&amount)?;
+
+    // Burn ckALGO tokens
+    set_balance(&user_str, current_balance.clone() - amount.clone());
+
+    // Update total supply
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = total.clone() - amount.clone();
+    });
+
+    // Update locked reserves
+    LOCKED_ALGO_RESERVES.with(|reserves| {
+        let mut locked = reserves.borrow_mut();
+        *locked = locked.clone() - amount.clone();
+    });
+
+    certify_state(&[&user_str]);
+    let redemption_id = record_redemption(user, &amount, &destination, reserved);
+
+    // Return withdrawal ID; the backend reports the payout with complete_redemption
+    Ok(format!("WITHDRAW_{}", redemption_id))
+}
+
+/// Admin function: redeem ckALGO on behalf of a user
+/// Used by backend to process redemption requests (burns user's tokens)
+/// Requires the Admin role; amounts at or above the approval threshold become
+/// a pending operation (see approve_admin_operation)
+#[update]
+async fn admin_redeem_ck_algo(
+    user: Principal,
+    amount: Nat,
+    destination: String
+) -> Result<String, String> {
+    let caller_principal = caller();
+
+    // Check authorization
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err(format!(
+            "Unauthorized: redeeming on behalf of users requires the Admin role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    ensure_not_paused(PauseClass::Redemptions)?;
+    let action = AdminAction::RedeemCkAlgo { user, amount: amount.clone(), destination: destination.clone() };
+    if let Some(pending) = propose_if_high_value(action, caller_principal) {
+        return Err(pending);
+    }
+    redeem_on_behalf(user, amount, destination)
+}
+
+fn redeem_on_behalf(user: Principal, amount: Nat, destination: String) -> Result<String, String> {
+    ensure_not_paused(PauseClass::Redemptions)?;
+    let user_str = user.to_text();
+
+    // Check user's balance
+    let current_balance = balance_of(&user_str);
+
+    if current_balance < amount {
+        return Err(format!(
+            "Insufficient ckALGO balance for user {}: has {}, requested {}",
+            user_str, current_balance, amount
+        ));
+    }
+
+    // Check reserve health
+    let reserve_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
+    if !reserve_healthy {
+        return Err("Cannot redeem: reserve system unhealthy".to_string());
+    }
+
+    // Withdrawals are paid from the hot wallet only
+    let reserved = reserve_hot_wallet_liquidity(&amount)?;
+
+    // Burn ckALGO tokens from user's balance
+    set_balance(&user_str, current_balance.clone() - amount.clone());
+
+    // Update total supply
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = total.clone() - amount.clone();
+    });
+
+    // Update locked reserves
+    LOCKED_ALGO_RESERVES.with(|reserves| {
+        let mut locked = reserves.borrow_mut();
+        *locked = locked.clone() - amount.clone();
+    });
+
+    certify_state(&[&user_str]);
+    let redemption_id = record_redemption(user, &amount, &destination, reserved);
+
+    // Return redemption ID with destination for tracking
+    Ok(format!("REDEEM_{}_{}", redemption_id, destination))
+}
+
+/// Admin function: transfer ckALGO from one principal to another
+/// Used by backend for X402 payments (transfers user's tokens to treasury)
+/// Requires the Admin role; amounts at or above the approval threshold become
+/// a pending operation (see approve_admin_operation)
+#[update]
+fn admin_transfer_ck_algo(
+    from_principal: Principal,
+    to_principal: Principal,
+    amount: Nat
+) -> Result<Nat, String> {
+    let caller_principal = caller();
+
+    // Check authorization
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err(format!(
+            "Unauthorized: admin transfers require the Admin role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    ensure_not_paused(PauseClass::AdminTransfers)?;
+    let action = AdminAction::TransferCkAlgo { from: from_principal, to: to_principal, amount: amount.clone() };
+    if let Some(pending) = propose_if_high_value(action, caller_principal) {
+        return Err(pending);
+    }
+    transfer_on_behalf(from_principal, to_principal, amount)
+}
+
+fn transfer_on_behalf(from_principal: Principal, to_principal: Principal, amount: Nat) -> Result<Nat, String> {
+    ensure_not_paused(PauseClass::AdminTransfers)?;
+    let from_str = from_principal.to_text();
+    let to_str = to_principal.to_text();
+
+    // Check sender's balance
+    let from_balance = balance_of(&from_str);
+
+    if from_balance < amount {
+        return Err(format!(
+            "Insufficient balance for principal {}: has {}, requested {}",
+            from_str, from_balance, amount
+        ));
+    }
+
+    // Perform transfer: deduct from sender, add to receiver
+    // Deduct from sender
+    set_balance(&from_str, from_balance.clone() - amount.clone());
+
+    // Add to receiver
+    set_balance(&to_str, balance_of(&to_str) + amount.clone());
+
+    certify_state(&[&from_str, &to_str]);
+
+    // Return transfer timestamp as transaction index
+    Ok(Nat::from(time()))
+}
+
+/// Admin function: sweep ckETH from main canister account to user's custody subaccount
+/// Used when user accidentally sends ckETH to main account instead of their custody subaccount
+/// Requires the Admin role; amounts at or above the approval threshold become
+/// a pending operation (see approve_admin_operation)
+#[update]
+async fn admin_sweep_cketh_to_custody(
+    user_principal: Principal,
+    amount: Nat
+) -> Result<Nat, String> {
+    let caller_principal = caller();
+
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err(format!(
+            "Unauthorized: sweeping ckETH requires the Admin role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    ensure_not_paused(PauseClass::AdminTransfers)?;
+    let action = AdminAction::SweepCkethToCustody { user: user_principal, amount: amount.clone() };
+    if let Some(pending) = propose_if_high_value(action, caller_principal) {
+        return Err(pending);
+    }
+    sweep_cketh_to_custody(user_principal, amount).await
+}
+
+async fn sweep_cketh_to_custody(user_principal: Principal, amount: Nat) -> Result<Nat, String> {
+    ensure_not_paused(PauseClass::AdminTransfers)?;
+    // Derive the user's custody subaccount
+    let custody_subaccount = derive_custody_subaccount(&user_principal);
+
+    // Get ckETH canister
+    let cketh_canister = Principal::from_text(CKETH_CANISTER_ID)
+        .map_err(|e| format!("Invalid ckETH canister ID: {}", e))?;
+
+    // Build transfer args: from main account (no subaccount) to custody subaccount
+    let transfer_args = TransferArgs {
+        from_subaccount: None,  // From main account
+        to: Account {
+            owner: ic_cdk::api::id(),  // Same canister
+            subaccount: Some(custody_subaccount),  // To custody subaccount
+        },
+        amount: amount.clone(),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+
+    // Inter-canister call to ckETH
+    let transfer_result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(cketh_canister, "icrc1_transfer", (transfer_args,)).await;
+
+    match transfer_result {
+        Ok((Ok(block_index),)) => {
+            ic_cdk::println!(
+                "Swept {} ckETH to custody subaccount for {}, block {}",
+                amount, user_principal, block_index
+            );
+            Ok(block_index)
+        }
+        Ok((Err(e),)) => Err(format!("ckETH transfer failed: {:?}", e)),
+        Err((code, msg)) => Err(format!("Inter-canister call failed: {:?} - {}", code, msg)),
+    }
+}
+
+#[query]
+fn get_reserve_ratio() -> ReserveStatus {
+    let locked_reserves = LOCKED_ALGO_RESERVES.with(|reserves| reserves.borrow().clone());
+    let total_supply = TOTAL_SUPPLY.with(|supply| supply.borrow().clone());
+    let is_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
+    let last_check = LAST_RESERVE_CHECK.with(|check| *check.borrow());
+    
+    let ratio = if total_supply > 0u64 {
+        // Convert to f64 for ratio calculation
+        let locked_f64 = locked_reserves.0.to_f64().unwrap_or(0.0);
+        let supply_f64 = total_supply.0.to_f64().unwrap_or(1.0);
+        locked_f64 / supply_f64
+    } else {
+        1.0
+    };
+    
+    ReserveStatus {
+        locked_algo_reserves: locked_reserves,
+        total_ck_algo_supply: total_supply,
+        reserve_ratio: ratio,
+        is_healthy,
+        last_verification: last_check,
+    }
+}
+
+#[query]
+fn get_user_deposits(user: Principal) -> Vec<DepositRecord> {
+    DEPOSIT_RECORDS.with(|records| {
+        records.borrow()
+            .iter()
+            .map(|record| record.0)
+            .filter(|record| record.user == user)
+            .collect()
+    })
+}
+
+// ============================================================================
+// ACCESS CONTROL
+// ============================================================================
+
+/// Roles held by a former AUTHORIZED_MINTERS entry (everything but Admin and Pauser)
+const LEGACY_MINTER_ROLES: [Role; 4] = [Role::Minter, Role::DepositReporter, Role::Operator, Role::SwapExecutor];
+
+/// Principals that can never hold a role: anyone can call as the anonymous
+/// principal, and the management canister never calls canisters
+fn is_invalid_role_holder(principal: &Principal) -> bool {
+    *principal == Principal::anonymous() || *principal == Principal::management_canister()
+}
+
+fn is_governance(principal: &Principal) -> bool {
+    GOVERNANCE_CANISTER.with(|g| *g.borrow() == Some(*principal))
+}
+
+/// Controllers, or the governance canister executing a proposal
+fn is_controller_or_governance(principal: &Principal) -> bool {
+    is_controller(principal) || is_governance(principal)
+}
+
+fn has_role(principal: &Principal, role: Role) -> bool {
+    if is_invalid_role_holder(principal) {
+        return false;
+    }
+    is_controller_or_governance(principal)
+        || ROLES.with(|roles| {
+            roles.borrow().get(principal).is_some_and(|held| held.contains(&role) || held.contains(&Role::Admin))
+        })
+}
+
+fn grant_legacy_minter_roles(principal: Principal) {
+    ROLES.with(|roles| roles.borrow_mut().entry(principal).or_default().extend(LEGACY_MINTER_ROLES));
+}
+
+fn grant_init_minters(args: InitArgs) {
+    for minter in args.minters {
+        if is_invalid_role_holder(&minter) {
+            ic_cdk::trap(&format!("Invalid minter in init args: {}", minter));
+        }
+        grant_legacy_minter_roles(minter);
+    }
+}
+
+fn strip_invalid_role_holders() {
+    ROLES.with(|roles| {
+        roles.borrow_mut().retain(|principal, held| {
+            let invalid = is_invalid_role_holder(principal);
+            if invalid {
+                ic_cdk::println!("Removed roles {:?} from invalid principal {}", held, principal);
+            }
+            !invalid
+        })
+    });
+}
+
+fn held_roles(principal: &Principal) -> Vec<Role> {
+    let mut held: Vec<Role> = ROLES.with(|roles| {
+        roles.borrow().get(principal).map(|r| r.iter().copied().collect()).unwrap_or_default()
+    });
+    held.sort();
+    held
+}
+
+fn check_grant_role(principal: &Principal, role: Role) -> Result<(), String> {
+    if is_invalid_role_holder(principal) {
+        return Err(format!("Roles cannot be granted to {}", principal));
+    }
+    if held_roles(principal).contains(&role) {
+        return Err(format!("{} already holds the {:?} role", principal, role));
+    }
+    Ok(())
+}
+
+fn check_revoke_role(principal: &Principal, role: Role) -> Result<(), String> {
+    if !held_roles(principal).contains(&role) {
+        return Err(format!("{} does not hold the {:?} role", principal, role));
+    }
+    Ok(())
+}
+
+/// Grant a role to a principal (controllers or governance)
+#[update]
+fn grant_role(principal: Principal, role: Role) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can grant roles".to_string());
+    }
+    ensure_no_state_export()?;
+    check_grant_role(&principal, role)?;
 
-    let already_completed = DEPOSIT_RECORDS.with(|records| {
-        records.borrow().iter().any(|r| r.algorand_tx_id == algorand_tx_id)
-    });
+    ROLES.with(|roles| roles.borrow_mut().entry(principal).or_default().insert(role));
+    Ok(format!("Granted {:?} to {}", role, principal))
+}
 
-    if already_pending || already_completed {
-        return Err(format!("Deposit {} already processed", algorand_tx_id));
+/// Revoke a role from a principal (controllers or governance)
+#[update]
+fn revoke_role(principal: Principal, role: Role) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can revoke roles".to_string());
     }
+    ensure_no_state_export()?;
+    check_revoke_role(&principal, role)?;
+
+    ROLES.with(|roles| {
+        let mut roles = roles.borrow_mut();
+        if let Some(held) = roles.get_mut(&principal) {
+            held.remove(&role);
+            if held.is_empty() {
+                roles.remove(&principal);
+            }
+        }
+    });
+    Ok(format!("Revoked {:?} from {}", role, principal))
+}
 
-    // Validate amount (must be > 0)
-    if amount == Nat::from(0u64) {
-        return Err("Deposit amount must be greater than 0".to_string());
-    }
+/// Explicit role assignments (controllers hold every role implicitly and are not listed)
+#[query]
+fn list_roles() -> Vec<RoleAssignment> {
+    let mut assignments: Vec<RoleAssignment> = ROLES.with(|roles| {
+        roles.borrow().iter().map(|(principal, held)| {
+            let mut roles: Vec<Role> = held.iter().copied().collect();
+            roles.sort();
+            RoleAssignment { principal: *principal, roles }
+        }).collect()
+    });
+    assignments.sort_by_key(|a| a.principal.to_text());
+    assignments
+}
 
-    // HIGH-PRIORITY FIX: Add minimum deposit validation (0.1 ALGO = 100,000 microALGO)
-    const MIN_DEPOSIT_MICROALGOS: u64 = 100_000;
-    const MAX_DEPOSIT_MICROALGOS: u64 = 1_000_000_000_000; // 1M ALGO
+// ============================================================================
+// INGRESS MESSAGE INSPECTION
+// ============================================================================
+//
+// Rejects ingress update calls before they reach consensus: anonymous callers
+// everywhere, callers without the required role on privileged methods, and
+// oversized payloads. This only filters ingress (inter-canister calls skip it),
+// so every method still performs its own authorization check.
+
+const DEFAULT_MAX_INGRESS_PAYLOAD: usize = 2 * 1024;
+
+enum IngressRequirement {
+    Authenticated,  // Any non-anonymous caller; the method checks the rest
+    Role(Role),
+    Controller,
+    Approver,
+}
 
-    let amount_u64 = amount.0.to_u64().ok_or("Amount too large to process")?;
+fn ingress_requirement(method: &str) -> IngressRequirement {
+    match method {
+        "mint_after_deposit_confirmed" => IngressRequirement::Role(Role::Minter),
 
-    if amount_u64 < MIN_DEPOSIT_MICROALGOS {
-        return Err(format!("Minimum deposit is 0.1 ALGO (100,000 microALGO). Got: {} microALGO", amount_u64));
-    }
+        "register_custody_address" | "register_pending_deposit" | "update_deposit_confirmations" => {
+            IngressRequirement::Role(Role::DepositReporter)
+        }
 
-    if amount_u64 > MAX_DEPOSIT_MICROALGOS {
-        return Err(format!("Maximum deposit is 1M ALGO. Got: {} microALGO", amount_u64));
+        "snapshot_liabilities" | "update_reserve_health" | "refresh_eth_algo_rate"
+        | "run_deposit_watcher" | "report_algorand_network_params"
+        | "run_custody_sweep" | "confirm_sweep" | "fail_sweep"
+        | "run_reserve_rebalance" | "complete_rebalance" | "fail_rebalance"
+        | "complete_redemption" | "fail_redemption" => {
+            IngressRequirement::Role(Role::Operator)
+        }
+
+        "pause_operations" => IngressRequirement::Role(Role::Pauser),
+
+        "swap_to_ckalgo" | "swap_cketh_to_ckalgo" | "request_swap_quote"
+        | "swap_deposit_to_ckalgo" | "swap_cketh_for_ckalgo_deposit" => {
+            IngressRequirement::Role(Role::SwapExecutor)
+        }
+
+        "admin_redeem_ck_algo" | "admin_transfer_ck_algo" | "admin_sweep_cketh_to_custody"
+        | "unpause_operations"
+        | "set_rate_guard_config" | "reset_rate_circuit_breaker" | "set_rate_cache_config"
+        | "set_swap_pair" | "set_swap_pair_enabled" | "set_swap_enabled" | "set_swap_fee_bps" | "set_swap_limits"
+        | "set_deposit_watcher_config" | "set_sweep_config"
+        | "set_reserve_policy" | "add_cold_wallet" | "remove_cold_wallet"
+        | "approve_rebalance" | "reject_rebalance" => IngressRequirement::Role(Role::Admin),
+
+        "grant_role" | "revoke_role" | "set_approval_config"
+        | "set_config_timelock_delay" | "cancel_config_change"
+        | "set_treasury_account" | "withdraw_treasury" | "set_governance_canister"
+        | "check_upgrade_compat" | "prepare_state_export" | "get_state_export_info" | "export_state" | "import_state" => {
+            IngressRequirement::Controller
+        }
+
+        "approve_admin_operation" => IngressRequirement::Approver,
+
+        _ => IngressRequirement::Authenticated,
     }
+}
 
-    // HIGH-PRIORITY FIX: Add max pending deposits cap to prevent spam attacks
-    const MAX_PENDING_DEPOSITS: usize = 10_000;
+fn max_ingress_payload(method: &str) -> usize {
+    match method {
+        "set_approval_config" => 16 * 1024,  // Approver list
+        "check_upgrade_compat" => 2 * 1024 * 1024,  // Saved state blob
+        "import_state" => 2 * 1024 * 1024,  // Snapshot page
+        _ => DEFAULT_MAX_INGRESS_PAYLOAD,
+    }
+}
 
-    let pending_count = PENDING_DEPOSITS.with(|deposits| deposits.borrow().len());
-    if pending_count >= MAX_PENDING_DEPOSITS {
-        return Err(format!("Maximum pending deposits limit reached ({}). Please wait for confirmations.", MAX_PENDING_DEPOSITS));
+fn check_ingress(method: &str, caller_principal: &Principal, payload_size: usize) -> Result<(), String> {
+    if *caller_principal == Principal::anonymous() {
+        return Err("Anonymous update calls are not accepted".to_string());
     }
 
-    // HIGH-PRIORITY FIX: Clarify that confirmations parameter is REQUIRED confirmations
-    // Validate required confirmations (3 for testnet, 6 for mainnet)
-    let required_confirmations = confirmations;
-    if required_confirmations != 3 && required_confirmations != 6 {
-        return Err(format!("Invalid required_confirmations: {} (must be 3 for testnet or 6 for mainnet)", required_confirmations));
+    let max_payload = max_ingress_payload(method);
+    if payload_size > max_payload {
+        return Err(format!("Payload of {} bytes exceeds the {} byte limit for {}", payload_size, max_payload, method));
     }
 
-    // Create pending deposit record
-    let pending_deposit = PendingDeposit {
-        user,
-        algorand_tx_id: algorand_tx_id.clone(),
-        amount: amount.clone(),
-        timestamp: time(),
-        confirmations: 0, // Will be updated as confirmations increase
-        required_confirmations,
+    let authorized = match ingress_requirement(method) {
+        IngressRequirement::Authenticated => true,
+        IngressRequirement::Role(role) => has_role(caller_principal, role),
+        IngressRequirement::Controller => is_controller(caller_principal),
+        IngressRequirement::Approver => {
+            APPROVAL_CONFIG.with(|c| c.borrow().approvers.contains(caller_principal))
+        }
     };
+    if !authorized {
+        return Err(format!("Caller {} is not authorized to call {}", caller_principal, method));
+    }
+    Ok(())
+}
 
-    // Store in pending deposits
-    PENDING_DEPOSITS.with(|deposits| {
-        deposits.borrow_mut().insert(algorand_tx_id.clone(), pending_deposit);
-    });
+#[inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    match check_ingress(&method, &caller(), ic_cdk::api::call::arg_data_raw_size()) {
+        Ok(()) => ic_cdk::api::call::accept_message(),
+        Err(reason) => ic_cdk::trap(&reason),
+    }
+}
 
-    // Also track the custody address mapping if not already present
-    DEPOSIT_ADDRESSES.with(|addresses| {
-        if !addresses.borrow().contains_key(&custody_address) {
-            addresses.borrow_mut().insert(custody_address.clone(), user);
-        }
-    });
+// ============================================================================
+// EMERGENCY PAUSE
+// ============================================================================
+//
+// Pausers (and Admins/controllers) can stop any operation class instantly;
+// only Admins can resume it. Pausing is never timelocked or approval-gated.
+
+const ALL_PAUSE_CLASSES: [PauseClass; 6] = [
+    PauseClass::Transfers,
+    PauseClass::Mints,
+    PauseClass::Redemptions,
+    PauseClass::Swaps,
+    PauseClass::DepositRegistration,
+    PauseClass::AdminTransfers,
+];
+
+fn pause_bit(class: PauseClass) -> u32 {
+    1 << (class as u32)
+}
 
-    Ok(format!("Deposit {} registered successfully for user {}", algorand_tx_id, user.to_text()))
+fn ensure_not_paused(class: PauseClass) -> Result<(), String> {
+    if PAUSE_BITMAP.with(|b| *b.borrow()) & pause_bit(class) == 0 {
+        return Ok(());
+    }
+    let reason = PAUSE_RECORDS.with(|r| r.borrow().get(&class).map(|rec| rec.reason.clone()))
+        .unwrap_or_default();
+    Err(format!("{:?} are paused: {}", class, reason))
+}
+
+fn check_pause_request(classes: &[PauseClass], reason: &str) -> Result<(), String> {
+    if classes.is_empty() {
+        return Err("No operation classes given".to_string());
+    }
+    if reason.trim().is_empty() {
+        return Err("A pause reason is required".to_string());
+    }
+    Ok(())
 }
 
+/// Pause one or more operation classes (Pauser role)
 #[update]
-async fn mint_after_deposit_confirmed(deposit_tx_id: String) -> Result<Nat, String> {
+fn pause_operations(classes: Vec<PauseClass>, reason: String) -> Result<PauseState, String> {
     let caller_principal = caller();
-    
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
-    });
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
-    
-    if !is_authorized && !is_controller {
-        return Err(format!("Unauthorized minting attempt from principal: {}", caller_principal));
+    if !has_role(&caller_principal, Role::Pauser) {
+        return Err("Pausing operations requires the Pauser role".to_string());
     }
-    
-    // Check if deposit exists and is confirmed
-    let deposit_opt = PENDING_DEPOSITS.with(|deposits| {
-        deposits.borrow().get(&deposit_tx_id).cloned()
+    check_pause_request(&classes, &reason)?;
+
+    let now = time();
+    for class in classes {
+        PAUSE_BITMAP.with(|b| *b.borrow_mut() |= pause_bit(class));
+        PAUSE_RECORDS.with(|r| {
+            r.borrow_mut().insert(class, PauseRecord {
+                class,
+                reason: reason.clone(),
+                paused_by: caller_principal,
+                paused_at: now,
+            })
+        });
+        ic_cdk::println!("{:?} paused by {}: {}", class, caller_principal, reason);
+    }
+    Ok(get_pause_state())
+}
+
+/// Resume one or more operation classes (Admin role)
+#[update]
+fn unpause_operations(classes: Vec<PauseClass>) -> Result<PauseState, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Unpausing operations requires the Admin role".to_string());
+    }
+
+    for class in classes {
+        PAUSE_BITMAP.with(|b| *b.borrow_mut() &= !pause_bit(class));
+        PAUSE_RECORDS.with(|r| r.borrow_mut().remove(&class));
+        ic_cdk::println!("{:?} unpaused by {}", class, caller_principal);
+    }
+    Ok(get_pause_state())
+}
+
+#[query]
+fn get_pause_state() -> PauseState {
+    let paused = PAUSE_RECORDS.with(|r| {
+        let records = r.borrow();
+        ALL_PAUSE_CLASSES.iter().filter_map(|class| records.get(class).cloned()).collect()
     });
-    
-    let deposit = match deposit_opt {
-        Some(dep) if dep.confirmations >= dep.required_confirmations => dep,
-        Some(_) => return Err("Deposit not yet confirmed".to_string()),
-        None => return Err("Deposit not found".to_string()),
-    };
-    
-    // Check reserve health before minting
-    let reserve_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
-    if !reserve_healthy {
-        return Err("Cannot mint: reserve system unhealthy".to_string());
+    PauseState {
+        bitmap: PAUSE_BITMAP.with(|b| *b.borrow()),
+        paused,
     }
-    
-    // Mint ckALGO tokens
-    let user_str = deposit.user.to_text();
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
-        let current_balance = balances_map.get(&user_str).unwrap_or(&Nat::from(0u64)).clone();
-        let new_balance = current_balance + deposit.amount.clone();
-        balances_map.insert(user_str, new_balance);
+}
+
+// ============================================================================
+// MULTI-PARTY APPROVAL
+// ============================================================================
+//
+// admin_transfer_ck_algo, admin_redeem_ck_algo and admin_sweep_cketh_to_custody
+// at or above the configured threshold are queued instead of executed. They run
+// once `required_approvals` distinct approvers other than the proposer have called
+// approve_admin_operation, and expire if that does not happen before expires_at.
+// Only approvals from principals still configured as approvers count when it
+// executes.
+//
+// The threshold applies to the amount plus everything the same proposer, or
+// anyone, moved from the same account without approval in the last
+// APPROVAL_WINDOW_SECS, so splitting a transfer does not avoid it.
+
+const MIN_APPROVAL_EXPIRY_SECS: u64 = 300;
+const MAX_APPROVAL_EXPIRY_SECS: u64 = 7 * 86_400;
+const APPROVAL_WINDOW_SECS: u64 = 86_400;
+
+/// (account whose funds move, amount, amount is wei)
+fn admin_action_target(action: &AdminAction) -> (Principal, &Nat, bool) {
+    match action {
+        AdminAction::TransferCkAlgo { from, amount, .. } => (*from, amount, false),
+        AdminAction::RedeemCkAlgo { user, amount, .. } => (*user, amount, false),
+        AdminAction::SweepCkethToCustody { user, amount } => (*user, amount, true),
+    }
+}
+
+/// Unapproved amounts of the same asset in the window: (by proposer, from target)
+fn unapproved_window_totals(proposer: Principal, target: Principal, cketh: bool, now: u64) -> (Nat, Nat) {
+    let window_start = now.saturating_sub(APPROVAL_WINDOW_SECS * 1_000_000_000);
+    UNAPPROVED_ADMIN_ACTIONS.with(|actions| {
+        actions.borrow().iter()
+            .filter(|a| a.cketh == cketh && a.timestamp >= window_start)
+            .fold((Nat::from(0u64), Nat::from(0u64)), |(by_proposer, from_target), a| (
+                if a.proposer == proposer { by_proposer + a.amount.clone() } else { by_proposer },
+                if a.target == target { from_target + a.amount.clone() } else { from_target },
+            ))
+    })
+}
+
+fn admin_action_needs_approval(action: &AdminAction, proposer: Principal, config: &ApprovalConfig, now: u64) -> bool {
+    if !config.enabled {
+        return false;
+    }
+    let (target, amount, cketh) = admin_action_target(action);
+    let threshold = if cketh { &config.cketh_threshold } else { &config.ckalgo_threshold };
+    let (by_proposer, from_target) = unapproved_window_totals(proposer, target, cketh, now);
+    by_proposer.max(from_target) + amount.clone() >= *threshold
+}
+
+/// Count an action that runs without approval towards the window totals
+fn record_unapproved_admin_action(action: &AdminAction, proposer: Principal, now: u64) {
+    let (target, amount, cketh) = admin_action_target(action);
+    let window_start = now.saturating_sub(APPROVAL_WINDOW_SECS * 1_000_000_000);
+    UNAPPROVED_ADMIN_ACTIONS.with(|actions| {
+        let mut actions = actions.borrow_mut();
+        actions.retain(|a| a.timestamp >= window_start);
+        actions.push(UnapprovedAdminAction { proposer, target, cketh, amount: amount.clone(), timestamp: now });
     });
-    
-    // Update total supply
-    TOTAL_SUPPLY.with(|supply| {
-        let mut total = supply.borrow_mut();
-        *total = total.clone() + deposit.amount.clone();
+}
+
+/// Queue `action` if it needs approval. Returns Some(message) when queued; the
+/// proposer cannot approve it, even as a configured approver.
+fn propose_if_high_value(action: AdminAction, proposer: Principal) -> Option<String> {
+    let config = APPROVAL_CONFIG.with(|c| c.borrow().clone());
+    let now = time();
+    if !admin_action_needs_approval(&action, proposer, &config, now) {
+        if config.enabled {
+            record_unapproved_admin_action(&action, proposer, now);
+        }
+        return None;
+    }
+
+    let expires_at = now + config.expiry_secs * 1_000_000_000;
+    let operation_id = ADMIN_OPERATIONS.with(|operations| {
+        let mut operations = operations.borrow_mut();
+        let operation_id = operations.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        operations.insert(operation_id, StableCandid(AdminOperation {
+            operation_id,
+            action,
+            proposed_by: proposer,
+            approvals: Vec::new(),
+            required_approvals: config.required_approvals,
+            status: AdminOperationStatus::Pending,
+            created_at: now,
+            expires_at,
+            updated_at: now,
+        }));
+        operation_id
     });
-    
-    // Update locked reserves
-    LOCKED_ALGO_RESERVES.with(|reserves| {
-        let mut locked = reserves.borrow_mut();
-        *locked = locked.clone() + deposit.amount.clone();
+
+    Some(format!(
+        "Pending approval: operation {} needs {} approvals before {}",
+        operation_id, config.required_approvals, expires_at
+    ))
+}
+
+fn admin_operation(operation_id: u64) -> Result<AdminOperation, String> {
+    ADMIN_OPERATIONS.with(|o| o.borrow().get(&operation_id).map(|operation| operation.0))
+        .ok_or(format!("Admin operation {} not found", operation_id))
+}
+
+fn update_admin_operation(operation_id: u64, f: impl FnOnce(&mut AdminOperation)) {
+    ADMIN_OPERATIONS.with(|operations| {
+        let mut operations = operations.borrow_mut();
+        if let Some(StableCandid(mut operation)) = operations.get(&operation_id) {
+            f(&mut operation);
+            operation.updated_at = time();
+            operations.insert(operation_id, StableCandid(operation));
+        }
     });
-    
-    // Remove from pending deposits
-    PENDING_DEPOSITS.with(|deposits| {
-        deposits.borrow_mut().remove(&deposit_tx_id);
+}
+
+/// Mark the operation Expired if it is still pending past its deadline
+fn expire_admin_operation(operation_id: u64) {
+    let now = time();
+    update_admin_operation(operation_id, |operation| {
+        if operation.status == AdminOperationStatus::Pending && now > operation.expires_at {
+            operation.status = AdminOperationStatus::Expired;
+        }
     });
-    
-    // Record the deposit
-    let deposit_record = DepositRecord {
-        deposit_id: deposit_tx_id,
-        user: deposit.user,
-        custody_address: "".to_string(), // Would be filled by backend
-        amount: deposit.amount.clone(),
-        algorand_tx_id: deposit.algorand_tx_id,
-        confirmed_at: time(),
-        minted_ck_algo: deposit.amount.clone(),
+}
+
+/// Approvals from principals that are still configured approvers, never the proposer's
+fn current_approval_count(operation: &AdminOperation) -> u32 {
+    APPROVAL_CONFIG.with(|c| {
+        let approvers = &c.borrow().approvers;
+        operation.approvals.iter()
+            .filter(|p| **p != operation.proposed_by && approvers.contains(p))
+            .count() as u32
+    })
+}
+
+async fn execute_admin_operation_if_approved(operation_id: u64) -> Result<AdminOperation, String> {
+    let operation = admin_operation(operation_id)?;
+    if operation.status != AdminOperationStatus::Pending
+        || current_approval_count(&operation) < operation.required_approvals
+    {
+        return Ok(operation);
+    }
+
+    // Executing is set before any await so a concurrent approval cannot run it twice
+    update_admin_operation(operation_id, |o| o.status = AdminOperationStatus::Executing);
+    let result = match operation.action {
+        AdminAction::TransferCkAlgo { from, to, amount } => {
+            transfer_on_behalf(from, to, amount).map(|index| format!("Transfer index {}", index))
+        }
+        AdminAction::RedeemCkAlgo { user, amount, destination } => redeem_on_behalf(user, amount, destination),
+        AdminAction::SweepCkethToCustody { user, amount } => {
+            sweep_cketh_to_custody(user, amount).await.map(|block| format!("ckETH block {}", block))
+        }
     };
-    
-    DEPOSIT_RECORDS.with(|records| {
-        records.borrow_mut().push(deposit_record);
+    update_admin_operation(operation_id, |o| {
+        o.status = match result {
+            Ok(result) => AdminOperationStatus::Executed { result },
+            Err(error) => AdminOperationStatus::Failed { error },
+        }
     });
-    
-    Ok(deposit.amount)
+    admin_operation(operation_id)
 }
 
-/// TEMPORARY ARCHITECTURE: Update deposit confirmations
-///
-/// SECURITY WARNING: This function trusts the authorized backend to report
-/// accurate confirmation counts. In Phase 2, this will be replaced with
-/// canister-side verification via HTTP outcalls (ckETH pattern).
-///
-/// Only authorized minters (backend principal 2vxsx-fae) can call this function.
+/// Configure multi-party approval (controllers or governance)
 #[update]
-async fn update_deposit_confirmations(
-    algorand_tx_id: String,
-    confirmations: u8
-) -> Result<String, String> {
+fn set_approval_config(config: ApprovalConfig) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can configure multi-party approval".to_string());
+    }
+    ensure_no_state_export()?;
+    check_approval_config(&config)?;
+
+    let summary = format!(
+        "Multi-party approval {}: {} of {} approvers, thresholds {} microALGO / {} wei, expiry {}s",
+        if config.enabled { "enabled" } else { "disabled" },
+        config.required_approvals, config.approvers.len(),
+        config.ckalgo_threshold, config.cketh_threshold, config.expiry_secs
+    );
+    APPROVAL_CONFIG.with(|c| *c.borrow_mut() = config);
+    Ok(summary)
+}
+
+fn check_approval_config(config: &ApprovalConfig) -> Result<(), String> {
+    let distinct: HashSet<Principal> = config.approvers.iter().copied().collect();
+    if distinct.len() != config.approvers.len() {
+        return Err("Approvers must be distinct".to_string());
+    }
+    if let Some(invalid) = config.approvers.iter().find(|p| is_invalid_role_holder(p)) {
+        return Err(format!("{} cannot be an approver", invalid));
+    }
+    if config.enabled && (config.required_approvals == 0 || config.required_approvals as usize > config.approvers.len()) {
+        return Err(format!(
+            "required_approvals must be between 1 and the number of approvers ({})",
+            config.approvers.len()
+        ));
+    }
+    if config.expiry_secs < MIN_APPROVAL_EXPIRY_SECS || config.expiry_secs > MAX_APPROVAL_EXPIRY_SECS {
+        return Err(format!(
+            "expiry_secs must be between {} and {}",
+            MIN_APPROVAL_EXPIRY_SECS, MAX_APPROVAL_EXPIRY_SECS
+        ));
+    }
+    Ok(())
+}
+
+#[query]
+fn get_approval_config() -> ApprovalConfig {
+    APPROVAL_CONFIG.with(|c| c.borrow().clone())
+}
+
+/// Approve a pending admin operation (configured approvers only)
+/// The approval that reaches the quorum executes the operation.
+#[update]
+async fn approve_admin_operation(operation_id: u64) -> Result<AdminOperation, String> {
+    let caller_principal = caller();
+    let is_approver = APPROVAL_CONFIG.with(|c| c.borrow().approvers.contains(&caller_principal));
+    if !is_approver {
+        return Err(format!(
+            "Unauthorized: only configured approvers can approve admin operations. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    expire_admin_operation(operation_id);
+    let operation = admin_operation(operation_id)?;
+    if operation.status != AdminOperationStatus::Pending {
+        return Err(format!("Operation {} is {:?}", operation_id, operation.status));
+    }
+    if operation.proposed_by == caller_principal {
+        return Err(format!("{} proposed operation {} and cannot approve it", caller_principal, operation_id));
+    }
+    if operation.approvals.contains(&caller_principal) {
+        return Err(format!("{} already approved operation {}", caller_principal, operation_id));
+    }
+
+    update_admin_operation(operation_id, |o| o.approvals.push(caller_principal));
+    execute_admin_operation_if_approved(operation_id).await
+}
+
+/// Cancel a pending admin operation (its proposer or controllers)
+#[update]
+fn cancel_admin_operation(operation_id: u64) -> Result<AdminOperation, String> {
     let caller_principal = caller();
+    expire_admin_operation(operation_id);
+    let operation = admin_operation(operation_id)?;
+    if caller_principal != operation.proposed_by && !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only the proposer or controllers can cancel operation {}. Caller: {}",
+            operation_id, caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+    if operation.status != AdminOperationStatus::Pending {
+        return Err(format!("Operation {} is {:?}", operation_id, operation.status));
+    }
+
+    update_admin_operation(operation_id, |o| o.status = AdminOperationStatus::Cancelled);
+    admin_operation(operation_id)
+}
+
+/// Admin operations, newest first. Pending operations past their deadline are
+/// reported as Expired.
+#[query]
+fn get_admin_operations(pending_only: bool, limit: Option<u32>) -> Vec<AdminOperation> {
+    let limit = limit.unwrap_or(100) as usize;
+    let now = time();
+    ADMIN_OPERATIONS.with(|operations| {
+        operations.borrow().iter().rev()
+            .map(|(_, StableCandid(mut operation))| {
+                if operation.status == AdminOperationStatus::Pending && now > operation.expires_at {
+                    operation.status = AdminOperationStatus::Expired;
+                }
+                operation
+            })
+            .filter(|operation| !pending_only || operation.status == AdminOperationStatus::Pending)
+            .take(limit)
+            .collect()
+    })
+}
+
+// ============================================================================
+// CONFIG TIMELOCK
+// ============================================================================
+//
+// Fee, limit, pair, swap-enable and reserve policy changes are queued and take
+// effect CONFIG_TIMELOCK_DELAY_SECS later, so integrators see them coming via
+// get_pending_config_changes. Emergency pauses (disabling swaps or a pair) skip
+// the queue. A one-shot timer fires at the earliest pending change; controllers
+// can cancel anything still pending. A zero delay applies changes immediately.
+
+const DEFAULT_CONFIG_TIMELOCK_SECS: u64 = 86_400;
+const MAX_CONFIG_TIMELOCK_SECS: u64 = 30 * 86_400;
+
+fn describe_config_change(change: &ConfigChange) -> String {
+    match change {
+        ConfigChange::SwapEnabled(enabled) => format!("swaps {}", if *enabled { "enabled" } else { "disabled" }),
+        ConfigChange::SwapFeeBps(fee_bps) => format!("ckETH swap fee {} bps", fee_bps),
+        ConfigChange::SwapLimits { min_cketh, max_cketh } => format!("ckETH swap limits [{}, {}]", min_cketh, max_cketh),
+        ConfigChange::SwapPair(pair) => format!(
+            "swap pair {} ({}): XRC {}, {} decimals, fee {} bps, limits [{}, {}], {}",
+            pair.symbol, pair.ledger, pair.xrc_symbol, pair.decimals, pair.fee_bps,
+            pair.min_amount, pair.max_amount,
+            if pair.enabled { "enabled" } else { "disabled" }
+        ),
+        ConfigChange::SwapPairEnabled { ledger, enabled } => {
+            format!("swap pair {} {}", ledger, if *enabled { "enabled" } else { "disabled" })
+        }
+        ConfigChange::ReservePolicy(policy) => format!(
+            "reserve policy {}: hot band [{}, {}], target {}",
+            if policy.enabled { "enabled" } else { "disabled" }, policy.hot_min, policy.hot_max, policy.hot_target
+        ),
+        ConfigChange::TimelockDelay(delay_secs) => format!("config timelock {}s", delay_secs),
+    }
+}
+
+/// Apply a change now, re-validating anything that may have changed while queued
+fn apply_config_change(change: &ConfigChange) -> Result<String, String> {
+    match change {
+        ConfigChange::SwapEnabled(enabled) => {
+            SWAP_ENABLED.with(|e| *e.borrow_mut() = *enabled);
+        }
+        ConfigChange::SwapFeeBps(fee_bps) => {
+            SWAP_PAIRS.with(|pairs| {
+                if let Some(pair) = pairs.borrow_mut().get_mut(&cketh_ledger()) {
+                    pair.fee_bps = *fee_bps;
+                }
+            });
+        }
+        ConfigChange::SwapLimits { min_cketh, max_cketh } => {
+            SWAP_PAIRS.with(|pairs| {
+                if let Some(pair) = pairs.borrow_mut().get_mut(&cketh_ledger()) {
+                    pair.min_amount = min_cketh.clone();
+                    pair.max_amount = max_cketh.clone();
+                }
+            });
+        }
+        ConfigChange::SwapPair(pair) => {
+            validate_swap_pair(pair)?;
+            SWAP_PAIRS.with(|pairs| pairs.borrow_mut().insert(pair.ledger, pair.clone()));
+        }
+        ConfigChange::SwapPairEnabled { ledger, enabled } => {
+            SWAP_PAIRS.with(|pairs| {
+                pairs.borrow_mut().get_mut(ledger).map(|p| p.enabled = *enabled)
+            }).ok_or(format!("Unsupported swap pair: {}", ledger))?;
+        }
+        ConfigChange::ReservePolicy(policy) => {
+            RESERVE_POLICY.with(|p| *p.borrow_mut() = policy.clone());
+            schedule_rebalance_timer();
+        }
+        ConfigChange::TimelockDelay(delay_secs) => {
+            CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow_mut() = *delay_secs);
+        }
+    }
+    certify_state(&[]);
+    Ok(format!("Applied {}", describe_config_change(change)))
+}
+
+/// Queue a change behind the timelock (or apply it now if the delay is zero)
+fn queue_config_change(change: ConfigChange, proposer: Principal) -> String {
+    let now = time();
+    let delay_secs = CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow());
+    let description = describe_config_change(&change);
+
+    let change_id = CONFIG_CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        let change_id = changes.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        changes.insert(change_id, StableCandid(PendingConfigChange {
+            change_id,
+            change: change.clone(),
+            proposed_by: proposer,
+            proposed_at: now,
+            effective_at: now + delay_secs * 1_000_000_000,
+            status: ConfigChangeStatus::Pending,
+            resolved_at: None,
+        }));
+        change_id
+    });
+
+    // Only this change: anything else due is left to the timer, in queue order
+    if delay_secs == 0 {
+        return match resolve_config_change(change_id, &change, now) {
+            ConfigChangeStatus::Applied { message } => message,
+            status => format!("Config change {}: {:?}", change_id, status),
+        };
+    }
+
+    schedule_config_timelock_timer();
+    format!("Queued config change {}: {} takes effect in {}s", change_id, description, delay_secs)
+}
 
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
+/// Apply every pending change whose delay has elapsed, in queue order.
+/// Held back while a state export is in progress and retried after
+/// STATE_EXPORT_RETRY_SECS.
+fn apply_due_config_changes() {
+    if state_export_in_progress() {
+        let timer_id = ic_cdk_timers::set_timer(Duration::from_secs(STATE_EXPORT_RETRY_SECS), apply_due_config_changes);
+        CONFIG_TIMELOCK_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+        return;
+    }
+    let now = time();
+    let due: Vec<(u64, ConfigChange)> = CONFIG_CHANGES.with(|changes| {
+        changes.borrow().iter()
+            .map(|(_, change)| change.0)
+            .filter(|c| c.status == ConfigChangeStatus::Pending && c.effective_at <= now)
+            .map(|c| (c.change_id, c.change))
+            .collect()
     });
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
 
-    if !is_authorized && !is_controller {
-        return Err(format!(
-            "Unauthorized: only authorized minters or controllers can update confirmations. Caller: {}",
-            caller_principal
-        ));
+    for (change_id, change) in due {
+        resolve_config_change(change_id, &change, now);
     }
+    schedule_config_timelock_timer();
+}
 
-    // Update confirmations in pending deposits
-    PENDING_DEPOSITS.with(|deposits| {
-        if let Some(deposit) = deposits.borrow_mut().get_mut(&algorand_tx_id) {
-            deposit.confirmations = confirmations;
-            Ok(format!(
-                "Updated deposit {} to {} confirmations (required: {})",
-                algorand_tx_id, confirmations, deposit.required_confirmations
-            ))
-        } else {
-            Err(format!("Deposit {} not found in pending deposits", algorand_tx_id))
+/// Apply one queued change and record whether it applied or failed re-validation
+fn resolve_config_change(change_id: u64, change: &ConfigChange, now: u64) -> ConfigChangeStatus {
+    let status = match apply_config_change(change) {
+        Ok(message) => ConfigChangeStatus::Applied { message },
+        Err(error) => ConfigChangeStatus::Failed { error },
+    };
+    ic_cdk::println!("Config change {}: {:?}", change_id, status);
+    CONFIG_CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        if let Some(StableCandid(mut c)) = changes.get(&change_id) {
+            c.status = status.clone();
+            c.resolved_at = Some(now);
+            changes.insert(change_id, StableCandid(c));
         }
-    })
+    });
+    status
 }
 
-#[update]
-async fn redeem_ck_algo(amount: Nat, _destination: String) -> Result<String, String> {
-    let user = caller();
-    let user_str = user.to_text();
+fn schedule_config_timelock_timer() {
+    if let Some(timer_id) = CONFIG_TIMELOCK_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
 
-    // Check balance
-    let current_balance = BALANCES.with(|balances| {
-        balances.borrow().get(&user_str).unwrap_or(&Nat::from(0u64)).clone()
+    let next = CONFIG_CHANGES.with(|changes| {
+        changes.borrow().iter()
+            .map(|(_, change)| change.0)
+            .filter(|c| c.status == ConfigChangeStatus::Pending)
+            .map(|c| c.effective_at)
+            .min()
     });
+    if let Some(effective_at) = next {
+        let delay = Duration::from_nanos(effective_at.saturating_sub(time()));
+        let timer_id = ic_cdk_timers::set_timer(delay, apply_due_config_changes);
+        CONFIG_TIMELOCK_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+    }
+}
 
-    if current_balance < amount {
-        return Err("Insufficient ckALGO balance".to_string());
+fn check_timelock_delay(delay_secs: u64) -> Result<(), String> {
+    if delay_secs > MAX_CONFIG_TIMELOCK_SECS {
+        return Err(format!("Timelock cannot exceed {}s", MAX_CONFIG_TIMELOCK_SECS));
     }
+    Ok(())
+}
 
-    // Check reserve health
-    let reserve_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
-    if !reserve_healthy {
-        return Err("Cannot redeem: reserve system unhealthy".to_string());
+fn pending_config_change(change_id: u64) -> Result<PendingConfigChange, String> {
+    let change = CONFIG_CHANGES.with(|changes| changes.borrow().get(&change_id).map(|change| change.0))
+        .ok_or(format!("Config change {} not found", change_id))?;
+    if change.status != ConfigChangeStatus::Pending {
+        return Err(format!("Config change {} is {:?}", change_id, change.status));
     }
+    Ok(change)
+}
 
-    // Burn ckALGO tokens
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
-        let new_balance = current_balance.clone() - amount.clone();
-        balances_map.insert(user_str, new_balance);
-    });
+/// Change the timelock delay (controllers or governance). The change itself waits out the current delay.
+#[update]
+fn set_config_timelock_delay(delay_secs: u64) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can change the config timelock".to_string());
+    }
+    ensure_no_state_export()?;
+    check_timelock_delay(delay_secs)?;
 
-    // Update total supply
-    TOTAL_SUPPLY.with(|supply| {
-        let mut total = supply.borrow_mut();
-        *total = total.clone() - amount.clone();
+    Ok(queue_config_change(ConfigChange::TimelockDelay(delay_secs), caller_principal))
+}
+
+/// Cancel a pending config change (controllers or governance)
+#[update]
+fn cancel_config_change(change_id: u64) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can cancel config changes".to_string());
+    }
+    ensure_no_state_export()?;
+    pending_config_change(change_id)?;
+
+    let description = CONFIG_CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        let StableCandid(mut c) = changes.get(&change_id).expect("pending config change exists");
+        c.status = ConfigChangeStatus::Cancelled { by: caller_principal };
+        c.resolved_at = Some(time());
+        let description = describe_config_change(&c.change);
+        changes.insert(change_id, StableCandid(c));
+        description
     });
 
-    // Update locked reserves
-    LOCKED_ALGO_RESERVES.with(|reserves| {
-        let mut locked = reserves.borrow_mut();
-        *locked = locked.clone() - amount.clone();
+    schedule_config_timelock_timer();
+    Ok(format!("Cancelled config change {}: {}", change_id, description))
+}
+
+/// Pending config changes, soonest first
+#[query]
+fn get_pending_config_changes() -> Vec<PendingConfigChange> {
+    let mut pending: Vec<PendingConfigChange> = CONFIG_CHANGES.with(|changes| {
+        changes.borrow().iter().map(|(_, change)| change.0).filter(|c| c.status == ConfigChangeStatus::Pending).collect()
     });
+    pending.sort_by_key(|c| (c.effective_at, c.change_id));
+    pending
+}
 
-    // Return withdrawal transaction ID (would be generated by backend)
-    Ok(format!("WITHDRAW_{}", time()))
+/// Resolved and pending config changes, newest first
+#[query]
+fn get_config_change_history(limit: Option<u32>) -> Vec<PendingConfigChange> {
+    let limit = limit.unwrap_or(100) as usize;
+    CONFIG_CHANGES.with(|changes| changes.borrow().iter().rev().take(limit).map(|(_, change)| change.0).collect())
 }
 
-/// Admin function: redeem ckALGO on behalf of a user
-/// Used by backend to process redemption requests (burns user's tokens)
-/// Only authorized minters or controllers can call this function
-#[update]
-async fn admin_redeem_ck_algo(
-    user: Principal,
-    amount: Nat,
-    destination: String
-) -> Result<String, String> {
-    let caller_principal = caller();
+#[query]
+fn get_config_timelock_delay() -> u64 {
+    CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow())
+}
 
-    // Check authorization
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
-    });
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
+// ============================================================================
+// GOVERNANCE (SNS generic-function proposals)
+// ============================================================================
+//
+// The governance canister may call every config function validated below,
+// like a controller. Each target method has a validate_<method> query with
+// the same arguments that runs the target's checks and renders a
+// human-readable diff, so it can be registered as an SNS generic nervous
+// system function (target_method_name = "set_swap_fee_bps",
+//  validator_method_name = "validate_set_swap_fee_bps", ...).
+//
+// Validators only read state. Timelocked changes still queue on execution.
+
+fn render_config_diff(title: &str, fields: &[(&str, String, String)]) -> String {
+    let mut out = title.to_string();
+    for (field, current, proposed) in fields {
+        if current == proposed {
+            out.push_str(&format!("\n  {}: {} (unchanged)", field, current));
+        } else {
+            out.push_str(&format!("\n  {}: {} -> {}", field, current, proposed));
+        }
+    }
+    out
+}
 
-    if !is_authorized && !is_controller {
-        return Err(format!(
-            "Unauthorized: only authorized minters or controllers can redeem on behalf of users. Caller: {}",
-            caller_principal
-        ));
+fn timelock_notice() -> String {
+    match CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow()) {
+        0 => "\nApplies immediately (config timelock is 0)".to_string(),
+        delay_secs => format!("\nQueued on execution; applies {}s later (config timelock)", delay_secs),
     }
+}
 
-    let user_str = user.to_text();
+fn opt_text<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_else(|| "none".to_string())
+}
 
-    // Check user's balance
-    let current_balance = BALANCES.with(|balances| {
-        balances.borrow().get(&user_str).unwrap_or(&Nat::from(0u64)).clone()
-    });
+fn roles_text(roles: &[Role]) -> String {
+    if roles.is_empty() {
+        return "none".to_string();
+    }
+    roles.iter().map(|r| format!("{:?}", r)).collect::<Vec<_>>().join(", ")
+}
 
-    if current_balance < amount {
-        return Err(format!(
-            "Insufficient ckALGO balance for user {}: has {}, requested {}",
-            user_str, current_balance, amount
-        ));
+fn is_paused(class: PauseClass) -> bool {
+    PAUSE_BITMAP.with(|b| *b.borrow()) & pause_bit(class) != 0
+}
+
+/// Set or clear the governance canister (controllers or the current governance canister)
+#[update]
+fn set_governance_canister(governance: Option<Principal>) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can set the governance canister".to_string());
     }
+    ensure_no_state_export()?;
+    check_governance_canister(governance)?;
 
-    // Check reserve health
-    let reserve_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
-    if !reserve_healthy {
-        return Err("Cannot redeem: reserve system unhealthy".to_string());
+    GOVERNANCE_CANISTER.with(|g| *g.borrow_mut() = governance);
+    Ok(format!("Governance canister set to {}", opt_text(governance)))
+}
+
+fn check_governance_canister(governance: Option<Principal>) -> Result<(), String> {
+    if let Some(principal) = governance {
+        if is_invalid_role_holder(&principal) {
+            return Err(format!("{} cannot be the governance canister", principal));
+        }
     }
+    Ok(())
+}
 
-    // Burn ckALGO tokens from user's balance
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
-        let new_balance = current_balance.clone() - amount.clone();
-        balances_map.insert(user_str.clone(), new_balance);
-    });
+#[query]
+fn get_governance_canister() -> Option<Principal> {
+    GOVERNANCE_CANISTER.with(|g| *g.borrow())
+}
 
-    // Update total supply
-    TOTAL_SUPPLY.with(|supply| {
-        let mut total = supply.borrow_mut();
-        *total = total.clone() - amount.clone();
-    });
+#[query]
+fn validate_set_governance_canister(governance: Option<Principal>) -> Result<String, String> {
+    check_governance_canister(governance)?;
+    Ok(render_config_diff("Set governance canister", &[
+        ("governance_canister", opt_text(get_governance_canister()), opt_text(governance)),
+    ]))
+}
 
-    // Update locked reserves
-    LOCKED_ALGO_RESERVES.with(|reserves| {
-        let mut locked = reserves.borrow_mut();
-        *locked = locked.clone() - amount.clone();
-    });
+// --- Fees, limits and the swap pair registry
 
-    // Return redemption ID with destination for tracking
-    Ok(format!("REDEEM_{}_{}", time(), destination))
+#[query]
+fn validate_set_swap_enabled(enabled: bool) -> Result<String, String> {
+    let current = SWAP_ENABLED.with(|e| *e.borrow());
+    let diff = render_config_diff("Enable/disable swaps", &[
+        ("enabled", current.to_string(), enabled.to_string()),
+    ]);
+    if enabled {
+        Ok(diff + &timelock_notice())
+    } else {
+        Ok(diff + "\nApplies immediately (emergency pause)")
+    }
 }
 
-/// Admin function: transfer ckALGO from one principal to another
-/// Used by backend for X402 payments (transfers user's tokens to treasury)
-/// Only authorized minters or controllers can call this function
-#[update]
-fn admin_transfer_ck_algo(
-    from_principal: Principal,
-    to_principal: Principal,
-    amount: Nat
-) -> Result<Nat, String> {
-    let caller_principal = caller();
+#[query]
+fn validate_set_swap_fee_bps(fee_bps: u64) -> Result<String, String> {
+    check_swap_fee_bps(fee_bps)?;
+    let pair = registered_swap_pair(&cketh_ledger())?;
+    Ok(render_config_diff("Set ckETH swap fee", &[
+        ("fee_bps", pair.fee_bps.to_string(), fee_bps.to_string()),
+    ]) + &timelock_notice())
+}
 
-    // Check authorization
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
-    });
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
+#[query]
+fn validate_set_swap_limits(min_cketh: Nat, max_cketh: Nat) -> Result<String, String> {
+    check_swap_limits_range(&min_cketh, &max_cketh)?;
+    let pair = registered_swap_pair(&cketh_ledger())?;
+    Ok(render_config_diff("Set ckETH swap limits (wei)", &[
+        ("min_amount", pair.min_amount.to_string(), min_cketh.to_string()),
+        ("max_amount", pair.max_amount.to_string(), max_cketh.to_string()),
+    ]) + &timelock_notice())
+}
 
-    if !is_authorized && !is_controller {
-        return Err(format!(
-            "Unauthorized: only authorized minters or controllers can perform admin transfers. Caller: {}",
-            caller_principal
-        ));
+#[query]
+fn validate_set_swap_pair(pair: SwapPair) -> Result<String, String> {
+    validate_swap_pair(&pair)?;
+    let current = SWAP_PAIRS.with(|pairs| pairs.borrow().get(&pair.ledger).cloned());
+    let title = match current {
+        Some(_) => format!("Update swap pair {} ({})", pair.symbol, pair.ledger),
+        None => format!("Register swap pair {} ({})", pair.symbol, pair.ledger),
+    };
+    let field = |name, f: fn(&SwapPair) -> String| (name, opt_text(current.as_ref().map(f)), f(&pair));
+    Ok(render_config_diff(&title, &[
+        field("symbol", |p| p.symbol.clone()),
+        field("xrc_symbol", |p| p.xrc_symbol.clone()),
+        field("decimals", |p| p.decimals.to_string()),
+        field("fee_bps", |p| p.fee_bps.to_string()),
+        field("min_amount", |p| p.min_amount.to_string()),
+        field("max_amount", |p| p.max_amount.to_string()),
+        field("enabled", |p| p.enabled.to_string()),
+    ]) + &timelock_notice())
+}
+
+#[query]
+fn validate_set_swap_pair_enabled(ledger: Principal, enabled: bool) -> Result<String, String> {
+    let pair = registered_swap_pair(&ledger)?;
+    let diff = render_config_diff(&format!("Enable/disable swap pair {} ({})", pair.symbol, ledger), &[
+        ("enabled", pair.enabled.to_string(), enabled.to_string()),
+    ]);
+    if enabled {
+        Ok(diff + &timelock_notice())
+    } else {
+        Ok(diff + "\nApplies immediately (emergency pause)")
     }
+}
 
-    let from_str = from_principal.to_text();
-    let to_str = to_principal.to_text();
+// --- Roles
 
-    // Check sender's balance
-    let from_balance = BALANCES.with(|balances| {
-        balances.borrow().get(&from_str).unwrap_or(&Nat::from(0u64)).clone()
-    });
+#[query]
+fn validate_grant_role(principal: Principal, role: Role) -> Result<String, String> {
+    check_grant_role(&principal, role)?;
+    let current = held_roles(&principal);
+    let mut proposed = current.clone();
+    proposed.push(role);
+    proposed.sort();
+    Ok(render_config_diff(&format!("Grant {:?} to {}", role, principal), &[
+        ("roles", roles_text(&current), roles_text(&proposed)),
+    ]))
+}
 
-    if from_balance < amount {
-        return Err(format!(
-            "Insufficient balance for principal {}: has {}, requested {}",
-            from_str, from_balance, amount
-        ));
-    }
+#[query]
+fn validate_revoke_role(principal: Principal, role: Role) -> Result<String, String> {
+    check_revoke_role(&principal, role)?;
+    let current = held_roles(&principal);
+    let proposed: Vec<Role> = current.iter().copied().filter(|r| *r != role).collect();
+    Ok(render_config_diff(&format!("Revoke {:?} from {}", role, principal), &[
+        ("roles", roles_text(&current), roles_text(&proposed)),
+    ]))
+}
 
-    // Perform transfer: deduct from sender, add to receiver
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
+// --- Pause
 
-        // Deduct from sender
-        let new_from_balance = from_balance.clone() - amount.clone();
-        balances_map.insert(from_str.clone(), new_from_balance);
+#[query]
+fn validate_pause_operations(classes: Vec<PauseClass>, reason: String) -> Result<String, String> {
+    check_pause_request(&classes, &reason)?;
+    let names: Vec<String> = classes.iter().map(|c| format!("{:?}", c)).collect();
+    let fields: Vec<(&str, String, String)> = classes.iter().zip(names.iter())
+        .map(|(class, name)| (name.as_str(), is_paused(*class).to_string(), "true".to_string()))
+        .collect();
+    Ok(render_config_diff("Pause operations (paused)", &fields) + &format!("\nReason: {}", reason))
+}
 
-        // Add to receiver
-        let to_balance = balances_map.get(&to_str).unwrap_or(&Nat::from(0u64)).clone();
-        let new_to_balance = to_balance + amount.clone();
-        balances_map.insert(to_str, new_to_balance);
-    });
+#[query]
+fn validate_unpause_operations(classes: Vec<PauseClass>) -> Result<String, String> {
+    let names: Vec<String> = classes.iter().map(|c| format!("{:?}", c)).collect();
+    let fields: Vec<(&str, String, String)> = classes.iter().zip(names.iter())
+        .map(|(class, name)| (name.as_str(), is_paused(*class).to_string(), "false".to_string()))
+        .collect();
+    Ok(render_config_diff("Unpause operations (paused)", &fields))
+}
 
-    // Return transfer timestamp as transaction index
-    Ok(Nat::from(time()))
+// --- Rate provider (XRC) guards and cache
+
+#[query]
+fn validate_set_rate_guard_config(config: RateGuardConfig) -> Result<String, String> {
+    check_rate_guard_config(&config)?;
+    let current = RATE_GUARD_CONFIG.with(|c| c.borrow().clone());
+    Ok(render_config_diff("Set rate quality guards", &[
+        ("min_sources", current.min_sources.to_string(), config.min_sources.to_string()),
+        ("max_deviation_bps", current.max_deviation_bps.to_string(), config.max_deviation_bps.to_string()),
+        ("max_age_secs", current.max_age_secs.to_string(), config.max_age_secs.to_string()),
+        ("max_jump_bps", current.max_jump_bps.to_string(), config.max_jump_bps.to_string()),
+        ("jump_window_secs", current.jump_window_secs.to_string(), config.jump_window_secs.to_string()),
+    ]))
 }
 
-/// Admin function: sweep ckETH from main canister account to user's custody subaccount
-/// Used when user accidentally sends ckETH to main account instead of their custody subaccount
-/// Only controllers can call this function
-#[update]
-async fn admin_sweep_cketh_to_custody(
-    user_principal: Principal,
-    amount: Nat
-) -> Result<Nat, String> {
-    let caller_principal = caller();
+#[query]
+fn validate_set_rate_cache_config(config: RateCacheConfig) -> Result<String, String> {
+    check_rate_cache_config(&config)?;
+    let current = RATE_CACHE_CONFIG.with(|c| c.borrow().clone());
+    Ok(render_config_diff("Set rate cache", &[
+        ("ttl_secs", current.ttl_secs.to_string(), config.ttl_secs.to_string()),
+        ("timer_refresh", current.timer_refresh.to_string(), config.timer_refresh.to_string()),
+    ]))
+}
 
-    // Only controllers can sweep (more restrictive than regular admin)
-    if !ic_cdk::api::is_controller(&caller_principal) {
-        return Err(format!(
-            "Unauthorized: only controllers can sweep ckETH. Caller: {}",
-            caller_principal
-        ));
-    }
+#[query]
+fn validate_reset_rate_circuit_breaker() -> Result<String, String> {
+    let breaker = RATE_CIRCUIT_BREAKER.with(|b| b.borrow().clone());
+    let baseline = LAST_ACCEPTED_RATES.with(|rates| rates.borrow().len());
+    Ok(render_config_diff("Reset rate circuit breaker", &[
+        ("circuit_breaker", opt_text(breaker), "none".to_string()),
+        ("baseline_rates", baseline.to_string(), "0".to_string()),
+    ]))
+}
 
-    // Derive the user's custody subaccount
-    let custody_subaccount = derive_custody_subaccount(&user_principal);
+// --- Deposit watcher, sweeps and reserves
 
-    // Get ckETH canister
-    let cketh_canister = Principal::from_text(CKETH_CANISTER_ID)
-        .map_err(|e| format!("Invalid ckETH canister ID: {}", e))?;
+#[query]
+fn validate_set_deposit_watcher_config(config: DepositWatcherConfig) -> Result<String, String> {
+    check_deposit_watcher_config(&config)?;
+    let current = DEPOSIT_WATCHER_CONFIG.with(|c| c.borrow().clone());
+    Ok(render_config_diff("Set deposit watcher", &[
+        ("enabled", current.enabled.to_string(), config.enabled.to_string()),
+        ("interval_secs", current.interval_secs.to_string(), config.interval_secs.to_string()),
+        ("max_agents_per_cycle", current.max_agents_per_cycle.to_string(), config.max_agents_per_cycle.to_string()),
+    ]))
+}
 
-    // Build transfer args: from main account (no subaccount) to custody subaccount
-    let transfer_args = TransferArgs {
-        from_subaccount: None,  // From main account
-        to: Account {
-            owner: ic_cdk::api::id(),  // Same canister
-            subaccount: Some(custody_subaccount),  // To custody subaccount
-        },
-        amount: amount.clone(),
-        fee: None,
-        memo: None,
-        created_at_time: None,
-    };
+#[query]
+fn validate_set_sweep_config(
+    enabled: bool,
+    hot_wallet_address: String,
+    threshold: Nat,
+    interval_secs: u64
+) -> Result<String, String> {
+    check_sweep_config(&hot_wallet_address, interval_secs)?;
+    let current = SWEEP_CONFIG.with(|c| c.borrow().clone());
+    Ok(render_config_diff("Set custody sweeps", &[
+        ("enabled", current.enabled.to_string(), enabled.to_string()),
+        ("hot_wallet_address", opt_text(current.hot_wallet_address), hot_wallet_address),
+        ("threshold", current.threshold.to_string(), threshold.to_string()),
+        ("interval_secs", current.interval_secs.to_string(), interval_secs.to_string()),
+    ]))
+}
 
-    // Inter-canister call to ckETH
-    let transfer_result: Result<(Result<Nat, TransferError>,), _> =
-        ic_cdk::call(cketh_canister, "icrc1_transfer", (transfer_args,)).await;
+/// The hot wallet owner derivation needs an inter-canister call, so it is only
+/// verified when the proposal executes.
+#[query]
+fn validate_set_reserve_policy(
+    enabled: bool,
+    hot_wallet_owner: Principal,
+    hot_min: Nat,
+    hot_target: Nat,
+    hot_max: Nat
+) -> Result<String, String> {
+    let hot_wallet = check_reserve_band(&hot_min, &hot_target, &hot_max)?;
+    let current = RESERVE_POLICY.with(|p| p.borrow().clone());
+    Ok(render_config_diff(&format!("Set reserve policy for hot wallet {}", hot_wallet), &[
+        ("enabled", current.enabled.to_string(), enabled.to_string()),
+        ("hot_wallet_owner", opt_text(current.hot_wallet_owner), hot_wallet_owner.to_string()),
+        ("hot_min", current.hot_min.to_string(), hot_min.to_string()),
+        ("hot_target", current.hot_target.to_string(), hot_target.to_string()),
+        ("hot_max", current.hot_max.to_string(), hot_max.to_string()),
+    ]) + &timelock_notice() + "\nThe hot wallet owner derivation is verified with threshold_signer on execution")
+}
 
-    match transfer_result {
-        Ok((Ok(block_index),)) => {
-            ic_cdk::println!(
-                "Swept {} ckETH to custody subaccount for {}, block {}",
-                amount, user_principal, block_index
-            );
-            Ok(block_index)
-        }
-        Ok((Err(e),)) => Err(format!("ckETH transfer failed: {:?}", e)),
-        Err((code, msg)) => Err(format!("Inter-canister call failed: {:?} - {}", code, msg)),
-    }
+#[query]
+fn validate_add_cold_wallet(address: String, label: String) -> Result<String, String> {
+    check_add_cold_wallet(&address)?;
+    let count = COLD_WALLETS.with(|wallets| wallets.borrow().len());
+    Ok(render_config_diff(&format!("Add cold wallet {} ({})", address, label), &[
+        ("cold_wallets", count.to_string(), (count + 1).to_string()),
+    ]))
 }
 
 #[query]
-fn get_reserve_ratio() -> ReserveStatus {
-    let locked_reserves = LOCKED_ALGO_RESERVES.with(|reserves| reserves.borrow().clone());
-    let total_supply = TOTAL_SUPPLY.with(|supply| supply.borrow().clone());
-    let is_healthy = RESERVE_HEALTH_STATUS.with(|health| *health.borrow());
-    let last_check = LAST_RESERVE_CHECK.with(|check| *check.borrow());
-    
-    let ratio = if total_supply > Nat::from(0u64) {
-        // Convert to f64 for ratio calculation
-        let locked_f64 = locked_reserves.0.to_f64().unwrap_or(0.0);
-        let supply_f64 = total_supply.0.to_f64().unwrap_or(1.0);
-        locked_f64 / supply_f64
-    } else {
-        1.0
+fn validate_remove_cold_wallet(address: String) -> Result<String, String> {
+    let wallet = check_remove_cold_wallet(&address)?;
+    let count = COLD_WALLETS.with(|wallets| wallets.borrow().len());
+    Ok(render_config_diff(&format!("Remove cold wallet {} ({})", wallet.address, wallet.label), &[
+        ("cold_wallets", count.to_string(), (count - 1).to_string()),
+    ]))
+}
+
+// --- Approvals, timelock and treasury
+
+#[query]
+fn validate_set_approval_config(config: ApprovalConfig) -> Result<String, String> {
+    check_approval_config(&config)?;
+    let current = APPROVAL_CONFIG.with(|c| c.borrow().clone());
+    let approvers_text = |approvers: &[Principal]| {
+        if approvers.is_empty() {
+            return "none".to_string();
+        }
+        approvers.iter().map(|p| p.to_text()).collect::<Vec<_>>().join(", ")
     };
-    
-    ReserveStatus {
-        locked_algo_reserves: locked_reserves,
-        total_ck_algo_supply: total_supply,
-        reserve_ratio: ratio,
-        is_healthy,
-        last_verification: last_check,
-    }
+    Ok(render_config_diff("Set multi-party approval", &[
+        ("enabled", current.enabled.to_string(), config.enabled.to_string()),
+        ("approvers", approvers_text(&current.approvers), approvers_text(&config.approvers)),
+        ("required_approvals", current.required_approvals.to_string(), config.required_approvals.to_string()),
+        ("ckalgo_threshold", current.ckalgo_threshold.to_string(), config.ckalgo_threshold.to_string()),
+        ("cketh_threshold", current.cketh_threshold.to_string(), config.cketh_threshold.to_string()),
+        ("expiry_secs", current.expiry_secs.to_string(), config.expiry_secs.to_string()),
+    ]))
+}
+
+#[query]
+fn validate_set_config_timelock_delay(delay_secs: u64) -> Result<String, String> {
+    check_timelock_delay(delay_secs)?;
+    Ok(render_config_diff("Set config timelock", &[
+        ("delay_secs", get_config_timelock_delay().to_string(), delay_secs.to_string()),
+    ]) + &timelock_notice())
 }
 
 #[query]
-fn get_user_deposits(user: Principal) -> Vec<DepositRecord> {
-    DEPOSIT_RECORDS.with(|records| {
-        records.borrow()
-            .iter()
-            .filter(|record| record.user == user)
-            .cloned()
-            .collect()
-    })
+fn validate_cancel_config_change(change_id: u64) -> Result<String, String> {
+    let change = pending_config_change(change_id)?;
+    Ok(format!(
+        "Cancel config change {}: {} (due at {})",
+        change_id, describe_config_change(&change.change), change.effective_at
+    ))
+}
+
+#[query]
+fn validate_set_treasury_account(account: Option<Principal>) -> Result<String, String> {
+    check_treasury_account(account)?;
+    let proposed = account.unwrap_or_else(ic_cdk::api::id);
+    Ok(render_config_diff("Set swap fee treasury account", &[
+        ("treasury_account", treasury_principal().to_text(), proposed.to_text()),
+    ]))
 }
 
 // ============================================================================
@@ -1028,13 +3380,13 @@ fn update_reserve_health(is_healthy: bool) -> Result<String, String> {
     let caller_principal = caller();
     
     // Check authorization
-    let is_authorized = AUTHORIZED_MINTERS.with(|minters| {
-        minters.borrow().contains(&caller_principal)
-    });
-    
-    if !is_authorized {
-        return Err("Unauthorized".to_string());
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: updating reserve health requires the Operator role. Caller: {}",
+            caller_principal
+        ));
     }
+    ensure_no_state_export()?;
     
     RESERVE_HEALTH_STATUS.with(|health| {
         *health.borrow_mut() = is_healthy;
@@ -1043,7 +3395,9 @@ fn update_reserve_health(is_healthy: bool) -> Result<String, String> {
     LAST_RESERVE_CHECK.with(|check| {
         *check.borrow_mut() = time();
     });
-    
+
+    certify_state(&[]);
+
     Ok(format!("Reserve health updated to: {}", is_healthy))
 }
 
@@ -1052,578 +3406,1388 @@ fn get_canister_status() -> String {
     let reserve_status = get_reserve_ratio();
     let total_deposits = DEPOSIT_RECORDS.with(|records| records.borrow().len());
     let pending_deposits = PENDING_DEPOSITS.with(|pending| pending.borrow().len());
+    let pause_state = get_pause_state();
+    let paused = if pause_state.paused.is_empty() {
+        "none".to_string()
+    } else {
+        pause_state.paused.iter()
+            .map(|rec| format!("{:?} ({})", rec.class, rec.reason))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
 
     format!(
-        "Simplified Bridge Status: {} deposits, {} pending, {:.2}% reserve ratio, healthy: {}",
+        "Simplified Bridge Status: {} deposits, {} pending, {:.2}% reserve ratio, healthy: {}, pause bitmap: {:#04x}, paused: {}",
         total_deposits,
         pending_deposits,
         reserve_status.reserve_ratio * 100.0,
-        reserve_status.is_healthy
+        reserve_status.is_healthy,
+        pause_state.bitmap,
+        paused
     )
 }
 
-// ============================================================================
-// SWAP FUNCTIONS (ckETH -> ckALGO)
-// ============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Helper: Convert Nat to f64 for calculations
-fn nat_to_f64(n: &Nat) -> f64 {
-    n.0.to_f64().unwrap_or(0.0)
-}
+    /// Off-chain stand-ins for the caller and clock, so update methods can run in tests
+    pub(crate) mod env {
+        use candid::Principal;
+        use std::cell::Cell;
 
-/// Get current ETH/ALGO exchange rate from the Exchange Rate Canister (XRC)
-///
-/// Returns ETH/ALGO rate (how many ALGO per 1 ETH)
-/// NO FALLBACK: If XRC unavailable, reject the request
-async fn get_eth_algo_rate() -> Result<f64, String> {
-    let xrc_canister = Principal::from_text(XRC_CANISTER_ID)
-        .map_err(|e| format!("Invalid XRC canister ID: {}", e))?;
-
-    // Get ETH/USD rate
-    let eth_usd_request = GetExchangeRateRequest {
-        base_asset: Asset {
-            symbol: "ETH".to_string(),
-            class: AssetClass::Cryptocurrency,
-        },
-        quote_asset: Asset {
-            symbol: "USD".to_string(),
-            class: AssetClass::FiatCurrency,
-        },
-        timestamp: None,
-    };
+        thread_local! {
+            static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+            static NOW: Cell<u64> = const { Cell::new(1_700_000_000_000_000_000) };
+            static CONTROLLER: Cell<Option<Principal>> = const { Cell::new(None) };
+        }
 
-    // XRC requires cycles - send 1B cycles (1T = 1 ICP, 1B = 0.001 ICP)
-    const XRC_CYCLES: u128 = 1_000_000_000; // 1B cycles
-
-    let eth_usd_result: Result<(GetExchangeRateResult,), _> = ic_cdk::api::call::call_with_payment128(
-        xrc_canister,
-        "get_exchange_rate",
-        (eth_usd_request,),
-        XRC_CYCLES
-    ).await;
-
-    // Get ALGO/USD rate
-    let algo_usd_request = GetExchangeRateRequest {
-        base_asset: Asset {
-            symbol: "ALGO".to_string(),
-            class: AssetClass::Cryptocurrency,
-        },
-        quote_asset: Asset {
-            symbol: "USD".to_string(),
-            class: AssetClass::FiatCurrency,
-        },
-        timestamp: None,
-    };
+        pub fn caller() -> Principal {
+            CALLER.with(Cell::get)
+        }
 
-    let algo_usd_result: Result<(GetExchangeRateResult,), _> = ic_cdk::api::call::call_with_payment128(
-        xrc_canister,
-        "get_exchange_rate",
-        (algo_usd_request,),
-        XRC_CYCLES
-    ).await;
-
-    match (eth_usd_result, algo_usd_result) {
-        (Ok((Ok(eth_rate),)), Ok((Ok(algo_rate),))) => {
-            // XRC uses rate with decimals specified in metadata (typically 9)
-            let eth_decimals = eth_rate.metadata.decimals;
-            let algo_decimals = algo_rate.metadata.decimals;
-
-            let eth_usd = eth_rate.rate as f64 / 10_f64.powi(eth_decimals as i32);
-            let algo_usd = algo_rate.rate as f64 / 10_f64.powi(algo_decimals as i32);
-
-            if algo_usd > 0.0 {
-                // ETH/ALGO = (ETH/USD) / (ALGO/USD)
-                Ok(eth_usd / algo_usd)
-            } else {
-                Err("ALGO/USD rate is zero".to_string())
-            }
+        pub fn time() -> u64 {
+            NOW.with(Cell::get)
         }
-        (Ok((Err(e),)), _) => {
-            Err(format!("Failed to get ETH/USD rate: {:?}", e))
+
+        pub fn is_controller(principal: &Principal) -> bool {
+            CONTROLLER.with(Cell::get) == Some(*principal)
         }
-        (_, Ok((Err(e),))) => {
-            Err(format!("Failed to get ALGO/USD rate: {:?}", e))
+
+        pub fn set_certified_data(_data: &[u8]) {}
+
+        pub fn set_caller(principal: Principal) {
+            CALLER.with(|c| c.set(principal));
         }
-        (Err((code, msg)), _) => {
-            Err(format!("XRC call failed for ETH/USD: {:?} - {}", code, msg))
+
+        pub fn set_controller(principal: Principal) {
+            CONTROLLER.with(|c| c.set(Some(principal)));
         }
-        (_, Err((code, msg))) => {
-            Err(format!("XRC call failed for ALGO/USD: {:?} - {}", code, msg))
+
+        pub fn advance_time(nanos: u64) {
+            NOW.with(|now| now.set(now.get() + nanos));
         }
     }
-}
 
-/// Swap ckETH for ckALGO
-///
-/// SECURITY: Only callable by authorized principals (backend, controllers)
-///
-/// Flow:
-/// 1. Verify caller is authorized
-/// 2. Verify swap is enabled and amount within limits
-/// 3. Get current ETH/ALGO rate from XRC (NO fallback)
-/// 4. Call ckETH.icrc2_transfer_from(user, this_canister, amount)
-/// 5. Calculate ckALGO output (minus fee)
-/// 6. Mint ckALGO to user
-/// 7. Record swap, update reserves
-#[update]
-async fn swap_cketh_to_ckalgo(
-    user: Principal,
-    cketh_amount: Nat,
-    min_ckalgo_out: Option<Nat>  // Slippage protection
-) -> Result<SwapResult, String> {
-    // Authorization check
-    let caller_principal = caller();
-    let is_authorized = AUTHORIZED_MINTERS.with(|m| m.borrow().contains(&caller_principal));
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
+    /// pre_upgrade output of each StableStorage version. Every fixture was seeded
+    /// with the same state: alice 200_000 and bob 100_000 ckALGO (total supply and
+    /// locked reserves 300_000), one completed deposit to the "CUSTODY" address,
+    /// one processed swap deposit key ("42") and one legacy minter. From v2 on the
+    /// balances, deposits, addresses and processed keys live in stable structures
+    /// instead. See tests/fixtures/README.md for how each one is generated.
+    const FIXTURES: [(u32, &[u8]); 2] = [
+        (1, include_bytes!("../tests/fixtures/stable_storage_v1.bin")),
+        (2, include_bytes!("../tests/fixtures/stable_storage_v2.bin")),
+    ];
+
+    fn current_fixture() -> &'static [u8] {
+        FIXTURES[STATE_VERSION as usize - 1].1
+    }
 
-    if !is_authorized && !is_controller {
-        return Err(format!(
-            "Unauthorized: only authorized minters can execute swaps. Caller: {}",
-            caller_principal
-        ));
+    fn alice() -> Principal {
+        Principal::from_slice(&[1; 29])
     }
 
-    // Check swap enabled
-    let enabled = SWAP_ENABLED.with(|e| *e.borrow());
-    if !enabled {
-        return Err("Swaps are currently disabled".to_string());
+    fn bob() -> Principal {
+        Principal::from_slice(&[2; 29])
     }
 
-    // Validate amount within limits
-    let min_swap = MIN_SWAP_CKETH.with(|m| m.borrow().clone());
-    let max_swap = MAX_SWAP_CKETH.with(|m| m.borrow().clone());
+    fn minter() -> Principal {
+        Principal::from_slice(&[3; 29])
+    }
 
-    if cketh_amount < min_swap {
-        return Err(format!(
-            "Swap amount {} below minimum {} (0.0001 ETH)",
-            cketh_amount, min_swap
-        ));
+    fn migrate_fixture(version: u32, bytes: &[u8]) -> MigratedState {
+        decode_versioned_state(bytes, version == 1)
+            .unwrap_or_else(|e| panic!("v{} fixture: {}", version, e))
     }
-    if cketh_amount > max_swap {
-        return Err(format!(
-            "Swap amount {} exceeds maximum {} (1 ETH)",
-            cketh_amount, max_swap
-        ));
+
+    /// Writes the STATE_VERSION fixture from the seed state described on FIXTURES:
+    /// cargo test -p simplified_bridge write_state_fixture -- --ignored
+    #[test]
+    #[ignore]
+    fn write_state_fixture() {
+        credit(alice(), 200_000);
+        credit(bob(), 100_000);
+        DEPOSIT_ADDRESSES.with(|a| a.borrow_mut().insert("CUSTODY".to_string(), alice()));
+        PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow_mut().insert("42".to_string()));
+        grant_legacy_minter_roles(minter());
+
+        let path = format!("{}/tests/fixtures/stable_storage_v{}.bin", env!("CARGO_MANIFEST_DIR"), STATE_VERSION);
+        std::fs::write(&path, encode_state_envelope(&snapshot_heap_state())).unwrap();
+    }
+
+    #[test]
+    fn fixtures_cover_every_version() {
+        let versions: Vec<u32> = FIXTURES.iter().map(|(version, _)| *version).collect();
+        assert_eq!(versions, (1..=STATE_VERSION).collect::<Vec<_>>());
     }
 
-    // Get exchange rate from XRC (NO FALLBACK - reject if unavailable)
-    let rate = get_eth_algo_rate().await?;
+    #[test]
+    fn every_fixture_is_dated_to_its_version() {
+        for (version, bytes) in FIXTURES {
+            assert_eq!(migrate_fixture(version, bytes).source_version, version);
+        }
+    }
 
-    // Calculate output
-    // ckETH has 18 decimals, ckALGO has 6 decimals
-    // cketh_amount is in wei (1e-18 ETH)
-    // rate is ETH/ALGO (how many ALGO per ETH)
-    // output should be in microALGO (1e-6 ALGO)
-    let cketh_as_f64 = nat_to_f64(&cketh_amount);
-    let eth_amount = cketh_as_f64 / 1e18;  // Convert wei to ETH
-    let algo_amount = eth_amount * rate;    // ETH to ALGO
-    let micro_algo = algo_amount * 1e6;     // ALGO to microALGO
+    #[test]
+    fn every_fixture_migrates_to_current_version() {
+        for (version, bytes) in FIXTURES {
+            let migrated = migrate_fixture(version, bytes);
+            let state = &migrated.state;
 
-    // Apply fee
-    let fee_bps = SWAP_FEE_BPS.with(|f| *f.borrow());
-    let fee_amount = micro_algo * (fee_bps as f64 / 10_000.0);
-    let net_micro_algo = micro_algo - fee_amount;
+            assert_eq!(state.total_supply, Nat::from(300_000u64), "v{}", version);
+            assert_eq!(state.locked_algo_reserves, Nat::from(300_000u64), "v{}", version);
+            assert!(state.swap_pairs.iter().any(|p| p.ledger == cketh_ledger()), "v{}", version);
 
-    if net_micro_algo <= 0.0 {
-        return Err("Output amount too small after fee".to_string());
+            let minter_roles = state.roles.iter().find(|(p, _)| *p == minter()).map(|(_, r)| r.clone()).unwrap_or_default();
+            assert!(LEGACY_MINTER_ROLES.iter().all(|role| minter_roles.contains(role)), "v{}", version);
+            assert!(!minter_roles.contains(&Role::Admin), "v{}", version);
+        }
     }
 
-    // Bounds check before u64 cast (P2 fix: prevent truncation/overflow)
-    if net_micro_algo > (u64::MAX as f64) {
-        return Err(format!(
-            "Output amount {} exceeds maximum representable value",
-            net_micro_algo
-        ));
+    #[test]
+    fn legacy_fixture_carries_unbounded_collections() {
+        let legacy = migrate_fixture(1, FIXTURES[0].1).collections;
+
+        let mut balances = legacy.balances;
+        balances.sort();
+        let mut expected = vec![
+            (alice().to_text(), Nat::from(200_000u64)),
+            (bob().to_text(), Nat::from(100_000u64)),
+        ];
+        expected.sort();
+        assert_eq!(balances, expected);
+        assert_eq!(legacy.deposit_records.len(), 1);
+        assert_eq!(legacy.deposit_records[0].user, alice());
+        assert_eq!(legacy.deposit_addresses, vec![("CUSTODY".to_string(), alice())]);
+        assert_eq!(legacy.processed_swap_deposits, vec!["42".to_string()]);
     }
-    if fee_amount > (u64::MAX as f64) {
-        return Err(format!(
-            "Fee amount {} exceeds maximum representable value",
-            fee_amount
-        ));
+
+    #[test]
+    fn processed_deposit_log_is_rebuilt_from_v1() {
+        let log = migrate_fixture(1, FIXTURES[0].1).collections.processed_swap_deposit_log;
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].tx_id, "42");
+        assert_eq!(log[0].pair, cketh_ledger());
+        assert!(matches!(log[0].result, ProcessedDepositResult::Unknown));
     }
 
-    let ckalgo_out = Nat::from(net_micro_algo.floor() as u64);
-    let fee_nat = Nat::from(fee_amount.floor() as u64);
+    #[test]
+    fn inline_collections_move_into_stable_structures() {
+        let MigratedState { state, mut collections, .. } = migrate_fixture(1, FIXTURES[0].1);
+        collections.custody_balances = vec![("CUSTODY".to_string(), Nat::from(7u64))];
+        restore_heap_state(state);
+        restore_state_collections(collections);
+
+        assert_eq!(DEPOSIT_ADDRESSES.with(|a| a.borrow().get(&"CUSTODY".to_string())), Some(alice()));
+        assert_eq!(custody_balance("CUSTODY"), Nat::from(7u64));
+        assert_eq!(balance_of(&alice().to_text()), Nat::from(200_000u64));
+
+        // Snapshots page them out
+        let page = |collection| {
+            let data = read_state_page(collection, None).unwrap().data;
+            decode_state_page(collection, &data).unwrap().0
+        };
+        assert_eq!(page(StateCollection::DepositAddresses).deposit_addresses, vec![("CUSTODY".to_string(), alice())]);
+        assert_eq!(page(StateCollection::CustodyBalances).custody_balances, vec![("CUSTODY".to_string(), Nat::from(7u64))]);
+    }
 
-    // Slippage check
-    if let Some(min_out) = min_ckalgo_out {
-        if ckalgo_out < min_out {
-            return Err(format!(
-                "Slippage exceeded: output {} < minimum {}",
-                ckalgo_out, min_out
-            ));
+    #[test]
+    fn completed_deposits_are_indexed_by_algorand_tx_id() {
+        let collections = migrate_fixture(1, FIXTURES[0].1).collections;
+        let restored_tx = collections.deposit_records[0].algorand_tx_id.clone();
+        restore_state_collections(collections);
+        assert_eq!(DEPOSIT_TX_INDEX.with(|i| i.borrow().get(&restored_tx)), Some(0));
+
+        // Records appended before the index existed are picked up on upgrade
+        DEPOSIT_RECORDS.with(|r| r.borrow().append(&StableCandid(DepositRecord {
+            deposit_id: "DEP1".to_string(),
+            user: alice(),
+            custody_address: "CUSTODY".to_string(),
+            amount: Nat::from(100_000u64),
+            algorand_tx_id: "UNINDEXED".to_string(),
+            confirmed_at: time(),
+            minted_ck_algo: Nat::from(100_000u64),
+        })).unwrap());
+        index_deposit_records();
+        assert_eq!(DEPOSIT_TX_INDEX.with(|i| i.borrow().get(&"UNINDEXED".to_string())), Some(1));
+
+        grant(operator(), &[Role::DepositReporter]);
+        env::set_caller(operator());
+        let duplicate = run_now(register_pending_deposit(alice(), "UNINDEXED".to_string(), Nat::from(100_000u64), "CUSTODY".to_string(), 0));
+        assert_eq!(duplicate.unwrap_err(), "Deposit UNINDEXED already processed");
+    }
+
+    #[test]
+    fn migrations_are_reported_per_step() {
+        let migrated = migrate_fixture(1, FIXTURES[0].1);
+        let steps: Vec<&str> = migrated.migrations.iter().map(|m| m.split(':').next().unwrap()).collect();
+        assert_eq!(steps, ["v1 -> v2"]);
+
+        assert!(migrate_fixture(STATE_VERSION, current_fixture()).migrations.is_empty());
+    }
+
+    #[test]
+    fn legacy_blob_tolerates_page_padding() {
+        let mut padded = FIXTURES[0].1.to_vec();
+        padded.resize(64 * 1024, 0);
+        assert_eq!(migrate_fixture(1, &padded).source_version, 1);
+    }
+
+    #[test]
+    fn envelope_round_trips_at_current_version() {
+        for (version, bytes) in FIXTURES {
+            let state = migrate_fixture(version, bytes).state;
+            let envelope = encode_state_envelope(&state);
+            let restored = decode_versioned_state(&envelope, false).expect("envelope decodes");
+
+            assert_eq!(restored.source_version, STATE_VERSION);
+            assert!(restored.migrations.is_empty());
+            assert_eq!(encode_state_envelope(&restored.state), envelope, "v{}", version);
         }
     }
 
-    // Execute: Pull ckETH from user via ICRC-2 transfer_from
-    let cketh_canister = Principal::from_text(CKETH_CANISTER_ID)
-        .map_err(|e| format!("Invalid ckETH canister ID: {}", e))?;
-    let this_canister = ic_cdk::api::id();
+    #[test]
+    fn newer_state_version_is_rejected() {
+        let state = migrate_fixture(STATE_VERSION, current_fixture()).state;
+        let envelope = candid::encode_one(StateEnvelope {
+            version: STATE_VERSION + 1,
+            state: candid::encode_one(&state).unwrap(),
+        }).unwrap();
 
-    let transfer_args = TransferFromArgs {
-        from: Account {
-            owner: user,
-            subaccount: None,
-        },
-        to: Account {
-            owner: this_canister,
-            subaccount: None,
-        },
-        amount: cketh_amount.clone(),
-        fee: None,
-        memo: None,
-        created_at_time: None,
-        spender_subaccount: None,
-    };
+        let err = decode_versioned_state(&envelope, false).err().expect("future version rejected");
+        assert!(err.contains("not supported"), "{}", err);
+    }
 
-    // Inter-canister call to ckETH
-    let transfer_result: Result<(Result<Nat, TransferFromError>,), _> =
-        ic_cdk::call(cketh_canister, "icrc2_transfer_from", (transfer_args,)).await;
+    fn enter_maintenance() {
+        grant(operator(), &[Role::Pauser]);
+        env::set_caller(operator());
+        pause_operations(ALL_PAUSE_CLASSES.to_vec(), "state snapshot".to_string()).unwrap();
+    }
 
-    match transfer_result {
-        Ok((Ok(block_index),)) => {
-            // ckETH received! Now mint ckALGO
-            let user_str = user.to_text();
-
-            // Mint ckALGO to user
-            BALANCES.with(|balances| {
-                let mut balances_map = balances.borrow_mut();
-                let current = balances_map.get(&user_str).unwrap_or(&Nat::from(0u64)).clone();
-                balances_map.insert(user_str.clone(), current + ckalgo_out.clone());
-            });
+    /// Every page of the prepared export, in order
+    fn export_pages() -> Vec<StateExportPage> {
+        STATE_EXPORT.with(|e| {
+            let export = e.borrow();
+            let export = export.as_ref().expect("export prepared");
+            let mut pages = Vec::new();
+            let mut next = Some((StateCollection::Heap, None));
+            while let Some((collection, cursor)) = next {
+                let page = export_page(export, collection, cursor).unwrap();
+                next = following_page(collection, page.next_cursor.clone());
+                pages.push(page);
+            }
+            pages
+        })
+    }
 
-            // Update total supply
-            TOTAL_SUPPLY.with(|supply| {
-                let mut total = supply.borrow_mut();
-                *total = total.clone() + ckalgo_out.clone();
-            });
+    /// Export the current state: its snapshot hash and pages
+    fn export_snapshot() -> (Vec<u8>, Vec<StateExportPage>) {
+        freeze_state_export().unwrap();
+        assert!(hash_next_export_pages(usize::MAX));
+        let sha256 = STATE_EXPORT.with(|e| e.borrow().as_ref().unwrap().info.sha256.clone());
+        (sha256.expect("every page hashed"), export_pages())
+    }
 
-            // Track ckETH-backed ckALGO separately (NOT backed by ALGO reserves)
-            CKETH_BACKED_CKALGO.with(|backed| {
-                let mut total = backed.borrow_mut();
-                *total = total.clone() + ckalgo_out.clone();
-            });
+    fn import_pages(snapshot_sha256: &[u8], pages: Vec<StateExportPage>) -> Result<StateImportStatus, String> {
+        let mut status = None;
+        for page in pages {
+            status = Some(receive_state_page(snapshot_sha256.to_vec(), page)?.0);
+        }
+        Ok(status.expect("no pages"))
+    }
 
-            // Track total ckETH received
-            TOTAL_CKETH_RECEIVED.with(|received| {
-                let mut total = received.borrow_mut();
-                *total = total.clone() + cketh_amount.clone();
+    #[test]
+    fn state_snapshot_round_trips_in_pages() {
+        let accounts: Vec<Principal> = (0..=STATE_PAGE_ENTRIES as u32)
+            .map(|n| Principal::from_slice(&n.to_be_bytes()))
+            .collect();
+        for account in &accounts {
+            credit(*account, 1_000);
+        }
+        for n in 0..=STATE_PAGE_ENTRIES {
+            PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow_mut().insert(n.to_string()));
+            append_deposit_record(DepositRecord {
+                deposit_id: format!("DEP{}", n),
+                user: alice(),
+                custody_address: "CUSTODY".to_string(),
+                amount: Nat::from(1u64),
+                algorand_tx_id: format!("TX{}", n),
+                confirmed_at: time(),
+                minted_ck_algo: Nat::from(1u64),
             });
+        }
+        enter_maintenance();
 
-            // Record swap for audit trail
-            let record = SwapRecord {
-                user,
-                cketh_in: cketh_amount.clone(),
-                ckalgo_out: ckalgo_out.clone(),
-                rate_used: rate,
-                fee_collected: fee_nat,
-                timestamp: time(),
-                tx_id: format!("SWAP_{}", block_index),
-            };
+        freeze_state_export().unwrap();
+        let mut batches = 1;
+        while !hash_next_export_pages(2) {
+            batches += 1;
+        }
+        let info = STATE_EXPORT.with(|e| e.borrow().as_ref().unwrap().info.clone());
+        let pages = export_pages();
+
+        // Balances, processed deposits and deposit records take two pages each
+        assert_eq!(pages.len(), STATE_COLLECTIONS.len() + 3);
+        assert_eq!(info.pages, pages.len() as u64);
+        assert_eq!(batches, pages.len().div_ceil(2));
+        let mut hasher = Sha256::new();
+        for page in &pages {
+            assert_eq!(Sha256::digest(&page.data).to_vec(), page.sha256);
+            fold_page_hash(&mut hasher, page.collection, &page.sha256);
+        }
+        let sha256 = info.sha256.expect("every page hashed");
+        assert_eq!(hasher.finalize().to_vec(), sha256);
+
+        // A new thread starts from empty state, like a reinstalled canister
+        let total_supply = TOTAL_SUPPLY.with(|s| s.borrow().clone());
+        std::thread::spawn(move || {
+            enter_maintenance();
+            let last = pages.last().unwrap().clone();
+            let status = import_pages(&sha256, pages).unwrap();
+
+            assert!(status.applied);
+            assert_eq!(status.next_collection, None);
+            assert_eq!(status.accounts, Some(accounts.len() as u64));
+            assert_eq!(status.total_supply, Some(total_supply));
+            assert_eq!(balance_of(&accounts[STATE_PAGE_ENTRIES].to_text()), Nat::from(1_000u64));
+            assert_eq!(PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow().len()), STATE_PAGE_ENTRIES as u64 + 1);
+            assert_eq!(DEPOSIT_RECORDS.with(|r| r.borrow().len()), STATE_PAGE_ENTRIES as u64 + 1);
+            assert!(in_maintenance_mode());
+
+            // Resending the last page does not apply it again
+            let (status, applied) = receive_state_page(sha256, last).unwrap();
+            assert!(status.applied && !applied);
+        }).join().unwrap();
+    }
 
-            SWAP_RECORDS.with(|records| records.borrow_mut().push(record));
+    #[test]
+    fn state_import_checks_each_page_and_the_snapshot_hash() {
+        credit(alice(), 1_000);
+        enter_maintenance();
+        let (sha256, pages) = export_snapshot();
+
+        let out_of_order = pages.clone();
+        std::thread::spawn(move || {
+            enter_maintenance();
+            let mut tampered = out_of_order[0].clone();
+            tampered.data.push(0);
+            assert!(receive_state_page(sha256.clone(), tampered).unwrap_err().contains("SHA-256"));
+            assert!(receive_state_page(sha256.clone(), out_of_order[1].clone()).unwrap_err().contains("Heap page"));
+
+            receive_state_page(sha256.clone(), out_of_order[0].clone()).unwrap();
+            assert!(receive_state_page(sha256, out_of_order[2].clone()).unwrap_err().contains("in order"));
+        }).join().unwrap();
+
+        // The snapshot hash is checked once every page is in
+        std::thread::spawn(move || {
+            enter_maintenance();
+            let err = import_pages(&[0; 32], pages).unwrap_err();
+            assert!(err.contains("SHA-256 does not match"), "{}", err);
+            assert_eq!(TOTAL_SUPPLY.with(|s| s.borrow().clone()), Nat::from(0u64));
+        }).join().unwrap();
+    }
 
-            Ok(SwapResult {
-                cketh_in: cketh_amount,
-                ckalgo_out,
-                rate_used: rate,
-                cketh_block_index: block_index,
-            })
+    #[test]
+    fn state_import_rejects_supply_mismatch_and_duplicates() {
+        set_balance(&alice().to_text(), Nat::from(5u64));
+        enter_maintenance();
+        let (sha256, pages) = export_snapshot();
+        std::thread::spawn(move || {
+            enter_maintenance();
+            assert!(import_pages(&sha256, pages).unwrap_err().contains("total supply"));
+        }).join().unwrap();
+
+        let mut sum = Nat::from(0u64);
+        let bob_twice = [(bob().to_text(), Nat::from(1u64)), (bob().to_text(), Nat::from(2u64))];
+        assert!(add_imported_balances(&mut sum, &bob_twice).unwrap_err().contains("twice"));
+        let alice_again = [(alice().to_text(), Nat::from(1u64))];
+        assert!(add_imported_balances(&mut sum, &alice_again).unwrap_err().contains("twice"));
+    }
+
+    #[test]
+    fn state_import_needs_every_collection_empty() {
+        enter_maintenance();
+        let (sha256, pages) = export_snapshot();
+        std::thread::spawn(move || {
+            enter_maintenance();
+            PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow_mut().insert("42".to_string()));
+            assert!(!is_fresh_canister());
+            assert!(import_pages(&sha256, pages).unwrap_err().contains("fresh canister"));
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn state_export_needs_maintenance_mode_throughout() {
+        assert!(freeze_state_export().unwrap_err().contains("maintenance mode"));
+        enter_maintenance();
+        freeze_state_export().unwrap();
+        assert!(freeze_state_export().unwrap_err().contains("still being hashed"));
+
+        grant(operator(), &[Role::Admin]);
+        unpause_operations(vec![PauseClass::Swaps]).unwrap();
+        assert!(hash_next_export_pages(STATE_EXPORT_HASH_BATCH_PAGES));
+        assert!(STATE_EXPORT.with(|e| e.borrow().is_none()));
+    }
+
+    #[test]
+    fn state_export_freezes_endpoints_that_ignore_pauses() {
+        enter_maintenance();
+        freeze_state_export().unwrap();
+
+        env::set_caller(alice());
+        let err = set_auto_swap_preference(alice(), 100, true).unwrap_err();
+        assert!(err.contains("frozen for an export"), "{}", err);
+        assert!(AUTO_SWAP_PREFERENCES.with(|p| p.borrow().is_empty()));
+
+        grant(operator(), &[Role::Admin]);
+        env::set_caller(operator());
+        unpause_operations(vec![PauseClass::Swaps]).unwrap();
+        env::set_caller(alice());
+        set_auto_swap_preference(alice(), 100, true).unwrap();
+    }
+
+    #[test]
+    fn legacy_blob_imports_as_the_heap_page() {
+        enter_maintenance();
+        freeze_state_export().unwrap();
+        STATE_EXPORT.with(|e| e.borrow_mut().as_mut().unwrap().heap = FIXTURES[0].1.to_vec());
+        assert!(hash_next_export_pages(usize::MAX));
+        let sha256 = STATE_EXPORT.with(|e| e.borrow().as_ref().unwrap().info.sha256.clone()).unwrap();
+
+        let status = import_pages(&sha256, export_pages()).unwrap();
+        assert_eq!(status.source_version, Some(1));
+        assert_eq!(status.total_supply, Some(Nat::from(300_000u64)));
+        assert_eq!(balance_of(&alice().to_text()), Nat::from(200_000u64));
+        assert_eq!(DEPOSIT_ADDRESSES.with(|a| a.borrow().get(&"CUSTODY".to_string())), Some(alice()));
+        assert!(in_maintenance_mode());
+    }
+
+    #[test]
+    fn corrupt_state_is_an_error_not_a_panic() {
+        assert!(decode_versioned_state(b"DIDL\x00\x01\x71\x03abc", true).is_err());
+        assert!(decode_versioned_state(&[0xff; 32], false).is_err());
+    }
+
+    fn operator() -> Principal {
+        Principal::from_slice(&[4; 29])
+    }
+
+    fn grant(principal: Principal, roles: &[Role]) {
+        ROLES.with(|r| r.borrow_mut().entry(principal).or_default().extend(roles.iter().copied()));
+    }
+
+    fn credit(account: Principal, amount: u64) {
+        set_balance(&account.to_text(), balance_of(&account.to_text()) + Nat::from(amount));
+        TOTAL_SUPPLY.with(|s| *s.borrow_mut() += Nat::from(amount));
+        LOCKED_ALGO_RESERVES.with(|r| *r.borrow_mut() += Nat::from(amount));
+        certify_state(&[&account.to_text()]);
+    }
+
+    fn certified_bytes(key: &[u8]) -> Option<Vec<u8>> {
+        CERTIFIED_STATE.with(|tree| tree.borrow().get(key).cloned())
+    }
+
+    /// Every certified leaf must equal the value its uncertified query returns now
+    fn assert_certified_matches_live(accounts: &[Principal], mutator: &str) {
+        for account in accounts {
+            let certified: Nat = certified_value(&certified_balance_key(&account.to_text()))
+                .unwrap()
+                .unwrap_or_else(|| Nat::from(0u64));
+            assert_eq!(certified, icrc1_balance_of(*account), "{}: balance of {}", mutator, account);
         }
-        Ok((Err(e),)) => Err(format!("ckETH transfer_from failed: {:?}", e)),
-        Err((code, msg)) => Err(format!("Inter-canister call to ckETH failed: {:?} - {}", code, msg)),
+        assert_eq!(
+            certified_bytes(CERTIFIED_RESERVE_STATUS_KEY),
+            Some(candid::encode_one(get_reserve_ratio()).unwrap()),
+            "{}: reserve status", mutator
+        );
+        assert_eq!(
+            certified_bytes(CERTIFIED_SWAP_CONFIG_KEY),
+            Some(candid::encode_one(get_swap_config()).unwrap()),
+            "{}: swap config", mutator
+        );
     }
-}
 
-// ============================================================================
-// SWAP ADMIN FUNCTIONS
-// ============================================================================
+    fn run_now<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        match future.as_mut().poll(&mut cx) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("future awaited an inter-canister call"),
+        }
+    }
 
-/// Enable/disable swaps (controllers only)
-#[update]
-fn set_swap_enabled(enabled: bool) -> Result<String, String> {
-    let caller_principal = caller();
-    if !ic_cdk::api::is_controller(&caller_principal) {
-        return Err("Only controllers can enable/disable swaps".to_string());
+    #[test]
+    fn every_mutator_recertifies() {
+        let accounts = [alice(), bob(), minter(), operator()];
+        grant(minter(), &LEGACY_MINTER_ROLES);
+        grant(operator(), &[Role::Operator, Role::Admin]);
+        TREASURY_ACCOUNT.with(|t| *t.borrow_mut() = Some(operator()));
+        certify_state(&[]);
+        credit(alice(), 500_000);
+        assert_certified_matches_live(&accounts, "setup");
+
+        env::set_caller(alice());
+        icrc1_transfer(bob(), Nat::from(1_000u64)).unwrap();
+        assert_certified_matches_live(&accounts, "icrc1_transfer");
+
+        PENDING_DEPOSITS.with(|d| d.borrow_mut().insert("DEP2".to_string(), StableCandid(PendingDeposit {
+            user: bob(),
+            algorand_tx_id: "ALGOTX".to_string(),
+            amount: Nat::from(7_000u64),
+            timestamp: time(),
+            confirmations: 6,
+            required_confirmations: 6,
+            custody_address: None,
+        })));
+        env::set_caller(minter());
+        run_now(mint_after_deposit_confirmed("DEP2".to_string())).unwrap();
+        assert_certified_matches_live(&accounts, "mint_after_deposit_confirmed");
+
+        redeem_on_behalf(alice(), Nat::from(2_000u64), "ALGOADDR".to_string()).unwrap();
+        assert_certified_matches_live(&accounts, "redeem_on_behalf");
+
+        transfer_on_behalf(alice(), bob(), Nat::from(3_000u64)).unwrap();
+        assert_certified_matches_live(&accounts, "transfer_on_behalf");
+
+        let pair = default_swap_pairs().remove(&cketh_ledger()).unwrap();
+        credit_swap(&pair, bob(), Nat::from(10u64), SwapPrice {
+            amount_out: Nat::from(900u64),
+            fee: Nat::from(100u64),
+            rate: RateFraction { numerator: Nat::from(1u64), denominator: Nat::from(1u64) },
+            quote: None,
+        }, "SWAPTX".to_string(), Nat::from(1u64));
+        assert_certified_matches_live(&accounts, "credit_swap");
+
+        env::set_caller(operator());
+        update_reserve_health(false).unwrap();
+        assert_certified_matches_live(&accounts, "update_reserve_health");
+
+        for change in [ConfigChange::SwapEnabled(true), ConfigChange::SwapFeeBps(42)] {
+            apply_config_change(&change).unwrap();
+            assert_certified_matches_live(&accounts, &describe_config_change(&change));
+        }
+
+        IN_FLIGHT_SWEEPS.with(|f| *f.borrow_mut() = Nat::from(1_010u64));
+        SWEEP_RECORDS.with(|r| r.borrow_mut().insert(0, StableCandid(SweepRecord {
+            sweep_id: 0,
+            custody_address: "CUSTODY".to_string(),
+            owner: alice(),
+            destination: "HOT".to_string(),
+            amount: Nat::from(1_000u64),
+            fee: Nat::from(10u64),
+            algorand_tx_id: "SWEEPTX".to_string(),
+            signed_transaction: vec![],
+            first_valid: 1,
+            last_valid: 2,
+            status: SweepStatus::Signed,
+            created_at: time(),
+            updated_at: time(),
+            error: None,
+        })));
+        confirm_sweep(0).unwrap();
+        assert_certified_matches_live(&accounts, "confirm_sweep");
+
+        IN_FLIGHT_REBALANCE.with(|f| *f.borrow_mut() = Nat::from(510u64));
+        COLD_WALLETS.with(|w| w.borrow_mut().push(ColdWallet {
+            address: "COLD".to_string(),
+            label: "cold".to_string(),
+            balance: Nat::from(0u64),
+        }));
+        REBALANCE_PROPOSALS.with(|p| p.borrow_mut().insert(0, StableCandid(RebalanceProposal {
+            proposal_id: 0,
+            direction: RebalanceDirection::HotToCold,
+            cold_address: "COLD".to_string(),
+            amount: Nat::from(500u64),
+            fee: Nat::from(10u64),
+            status: RebalanceStatus::Signed,
+            reason: "test".to_string(),
+            created_at: time(),
+            updated_at: time(),
+            approved_by: None,
+            algorand_tx_id: Some("REBALTX".to_string()),
+            signed_transaction: None,
+            error: None,
+        })));
+        complete_rebalance(0, "REBALTX".to_string()).unwrap();
+        assert_certified_matches_live(&accounts, "complete_rebalance");
+
+        fail_redemption(0, "payout failed".to_string()).unwrap();
+        assert_certified_matches_live(&accounts, "fail_redemption");
     }
 
-    SWAP_ENABLED.with(|e| *e.borrow_mut() = enabled);
-    Ok(format!("Swaps {}", if enabled { "enabled" } else { "disabled" }))
-}
+    #[test]
+    fn certified_queries_serve_the_certified_leaf() {
+        credit(alice(), 1_000);
+        certify_state(&[]);
 
-/// Set swap fee (controllers only, max 5%)
-#[update]
-fn set_swap_fee_bps(fee_bps: u64) -> Result<String, String> {
-    let caller_principal = caller();
-    if !ic_cdk::api::is_controller(&caller_principal) {
-        return Err("Only controllers can set swap fee".to_string());
+        // Change live state without recertifying: the certified leaf must not move
+        set_balance(&alice().to_text(), Nat::from(5u64));
+        SWAP_ENABLED.with(|e| *e.borrow_mut() = true);
+
+        let balance: Option<Nat> = certified_value(&certified_balance_key(&alice().to_text())).unwrap();
+        assert_eq!(balance, Some(Nat::from(1_000u64)));
+        let config: SwapConfig = certified_value(CERTIFIED_SWAP_CONFIG_KEY).unwrap().unwrap();
+        assert!(!config.enabled);
+        assert_eq!(certified_value::<Nat>(&certified_balance_key(&bob().to_text())).unwrap(), None);
     }
 
-    if fee_bps > 500 {
-        return Err("Fee cannot exceed 5% (500 bps)".to_string());
+    #[test]
+    fn recertification_resumes_in_batches() {
+        let accounts: Vec<Principal> = (10..15).map(|n| Principal::from_slice(&[n; 29])).collect();
+        for account in &accounts {
+            credit(*account, 1_000);
+        }
+        CERTIFIED_STATE.with(|tree| *tree.borrow_mut() = RbTree::new());
+        RECERTIFY_IN_PROGRESS.with(|r| *r.borrow_mut() = true);
+
+        let mut batches = 1;
+        while !recertify_next_batch(2) {
+            batches += 1;
+            assert!(RECERTIFY_IN_PROGRESS.with(|r| *r.borrow()));
+            let certified = accounts.iter()
+                .filter(|a| certified_value::<Nat>(&certified_balance_key(&a.to_text())).unwrap().is_some())
+                .count();
+            assert_eq!(certified, 2 * (batches - 1));
+            let pending = accounts.iter().filter(|a| is_recertifying_account(&a.to_text())).count();
+            assert_eq!(pending, accounts.len() - certified);
+        }
+
+        assert_eq!(batches, 3);
+        assert!(accounts.iter().all(|a| !is_recertifying_account(&a.to_text())));
+        for account in &accounts {
+            let balance: Option<Nat> = certified_value(&certified_balance_key(&account.to_text())).unwrap();
+            assert_eq!(balance, Some(Nat::from(1_000u64)));
+        }
     }
 
-    SWAP_FEE_BPS.with(|f| *f.borrow_mut() = fee_bps);
-    Ok(format!("Swap fee set to {} bps ({}%)", fee_bps, fee_bps as f64 / 100.0))
-}
+    fn fold_liability_proof(proof: &LiabilityProof) -> ([u8; 32], u128) {
+        let balance = proof.balance.0.to_u128().unwrap();
+        let mut node = (liability_leaf_hash(&proof.account.to_text(), balance), balance);
+        for step in &proof.path {
+            let sibling = (
+                <[u8; 32]>::try_from(step.sibling.hash.as_slice()).unwrap(),
+                step.sibling.sum.0.to_u128().unwrap(),
+            );
+            let hash = if step.sibling_is_left {
+                liability_node_hash(&sibling, &node)
+            } else {
+                liability_node_hash(&node, &sibling)
+            };
+            node = (hash, node.1 + sibling.1);
+        }
+        node
+    }
 
-/// Set swap limits (controllers only)
-#[update]
-fn set_swap_limits(min_cketh: Nat, max_cketh: Nat) -> Result<String, String> {
-    let caller_principal = caller();
-    if !ic_cdk::api::is_controller(&caller_principal) {
-        return Err("Only controllers can set swap limits".to_string());
+    #[test]
+    fn liability_proofs_fold_to_the_certified_root() {
+        let holders = [alice(), bob(), minter(), operator(), Principal::from_slice(&[5; 29])];
+        for (i, holder) in holders.iter().enumerate() {
+            credit(*holder, 1_000 * (i as u64 + 1));
+        }
+        assert!(get_liability_proof(alice()).is_err(), "no snapshot yet");
+
+        rebuild_liability_tree().unwrap();
+        let root: LiabilityRoot = certified_value(CERTIFIED_LIABILITY_ROOT_KEY).unwrap().unwrap();
+        assert_eq!(root.leaf_count, 5);
+        assert_eq!(root.total_liabilities, Nat::from(15_000u64));
+
+        for holder in holders {
+            let proof = get_liability_proof(holder).unwrap();
+            let (hash, sum) = fold_liability_proof(&proof);
+            assert_eq!(hash.to_vec(), root.root_hash, "{}", holder);
+            assert_eq!(Nat::from(sum), root.total_liabilities, "{}", holder);
+        }
+
+        // A proof for a tampered balance no longer reaches the root
+        let mut proof = get_liability_proof(bob()).unwrap();
+        proof.balance += Nat::from(1u64);
+        assert_ne!(fold_liability_proof(&proof).0.to_vec(), root.root_hash);
     }
 
-    if min_cketh >= max_cketh {
-        return Err("Minimum must be less than maximum".to_string());
+    #[test]
+    fn liability_overflow_is_an_error() {
+        set_balance(&alice().to_text(), Nat::from(u128::MAX));
+        set_balance(&bob().to_text(), Nat::from(1u64));
+        assert!(rebuild_liability_tree().unwrap_err().contains("Total liabilities"));
+
+        set_balance(&alice().to_text(), Nat::from(u128::MAX) + Nat::from(1u64));
+        assert!(rebuild_liability_tree().unwrap_err().contains("exceeds"));
     }
 
-    MIN_SWAP_CKETH.with(|m| *m.borrow_mut() = min_cketh.clone());
-    MAX_SWAP_CKETH.with(|m| *m.borrow_mut() = max_cketh.clone());
+    #[test]
+    fn msgpack_uint_uses_the_smallest_encoding() {
+        let encode = |value: u64| {
+            let mut out = Vec::new();
+            msgpack_uint(&mut out, value);
+            out
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(0x7f), [0x7f]);
+        assert_eq!(encode(0x80), [0xcc, 0x80]);
+        assert_eq!(encode(0xff), [0xcc, 0xff]);
+        assert_eq!(encode(0x100), [0xcd, 0x01, 0x00]);
+        assert_eq!(encode(0xffff), [0xcd, 0xff, 0xff]);
+        assert_eq!(encode(0x1_0000), [0xce, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(encode(u32::MAX as u64 + 1), [0xcf, 0, 0, 0, 0x01, 0, 0, 0, 0]);
+    }
 
-    Ok(format!("Swap limits set: min={}, max={}", min_cketh, max_cketh))
-}
+    #[test]
+    fn msgpack_str_and_bin_headers() {
+        let mut out = Vec::new();
+        msgpack_str(&mut out, "amt");
+        assert_eq!(out, b"\xa3amt");
+
+        let long = "x".repeat(32);
+        out.clear();
+        msgpack_str(&mut out, &long);
+        assert_eq!(out[..2], [0xd9, 32]);
+        assert_eq!(out.len(), 34);
+
+        out.clear();
+        msgpack_bin(&mut out, &[7; 32]);
+        assert_eq!(out[..2], [0xc4, 32]);
+        assert_eq!(out.len(), 34);
+
+        out.clear();
+        msgpack_bin(&mut out, &[7; 300]);
+        assert_eq!(out[..3], [0xc5, 0x01, 0x2c]);
+    }
 
-/// Query swap configuration
-#[query]
-fn get_swap_config() -> SwapConfig {
-    SwapConfig {
-        enabled: SWAP_ENABLED.with(|e| *e.borrow()),
-        fee_bps: SWAP_FEE_BPS.with(|f| *f.borrow()),
-        min_cketh: MIN_SWAP_CKETH.with(|m| m.borrow().clone()),
-        max_cketh: MAX_SWAP_CKETH.with(|m| m.borrow().clone()),
-        cketh_backed_ckalgo: CKETH_BACKED_CKALGO.with(|b| b.borrow().clone()),
-        total_cketh_received: TOTAL_CKETH_RECEIVED.with(|t| t.borrow().clone()),
+    #[test]
+    fn payment_encoding_is_canonical() {
+        let genesis_hash = [9u8; 32];
+        let payment = AlgorandPayment {
+            sender: [1; 32],
+            receiver: [2; 32],
+            amount: 1,
+            fee: 1_000,
+            first_valid: 1,
+            last_valid: 1_001,
+            genesis_id: "testnet-v1.0",
+            genesis_hash: &genesis_hash,
+        };
+
+        let mut expected = vec![0x89];
+        expected.extend_from_slice(b"\xa3amt\x01");
+        expected.extend_from_slice(b"\xa3fee\xcd\x03\xe8");
+        expected.extend_from_slice(b"\xa2fv\x01");
+        expected.extend_from_slice(b"\xa3gen\xactestnet-v1.0");
+        expected.extend_from_slice(b"\xa2gh\xc4\x20");
+        expected.extend_from_slice(&genesis_hash);
+        expected.extend_from_slice(b"\xa2lv\xcd\x03\xe9");
+        expected.extend_from_slice(b"\xa3rcv\xc4\x20");
+        expected.extend_from_slice(&[2; 32]);
+        expected.extend_from_slice(b"\xa3snd\xc4\x20");
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend_from_slice(b"\xa4type\xa3pay");
+        let encoded = payment.encode();
+        assert_eq!(encoded, expected);
+
+        assert_eq!(algorand_bytes_to_sign(&encoded)[..2], *b"TX");
+        assert_eq!(algorand_transaction_id(&encoded).len(), 52);
+
+        let signed = algorand_signed_transaction(&encoded, &[5; 64]);
+        assert_eq!(signed[..7], *b"\x82\xa3sig\xc4\x40");
+        assert!(signed.ends_with(&encoded));
     }
-}
 
-/// Query swap history (most recent first)
-#[query]
-fn get_swap_records(limit: Option<u32>) -> Vec<SwapRecord> {
-    let limit = limit.unwrap_or(100) as usize;
-    SWAP_RECORDS.with(|records| {
-        let r = records.borrow();
-        r.iter().rev().take(limit).cloned().collect()
-    })
-}
+    #[test]
+    fn base32_matches_rfc4648_without_padding() {
+        let vectors = [
+            ("", ""), ("f", "MY"), ("fo", "MZXQ"), ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"), ("fooba", "MZXW6YTB"), ("foobar", "MZXW6YTBOI"),
+        ];
+        for (input, expected) in vectors {
+            assert_eq!(algorand_base32_encode(input.as_bytes()), expected, "{:?}", input);
+        }
+    }
 
-/// Get current ETH/ALGO rate (exposed for quote calculations)
-/// Note: This is async and requires an update call
-#[update]
-async fn get_current_eth_algo_rate() -> Result<f64, String> {
-    get_eth_algo_rate().await
-}
+    #[test]
+    fn algorand_addresses_decode_and_verify_checksum() {
+        const ZERO_ADDRESS: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAY5HFKQ";
+        assert_eq!(decode_algorand_address(ZERO_ADDRESS).unwrap(), [0; 32]);
+
+        // Round trip: base32(public key || last 4 bytes of SHA-512/256(public key))
+        let public_key = [0xab; 32];
+        let mut with_checksum = public_key.to_vec();
+        with_checksum.extend_from_slice(&Sha512_256::digest(public_key)[28..]);
+        let address = algorand_base32_encode(&with_checksum);
+        assert_eq!(address.len(), 58);
+        assert_eq!(decode_algorand_address(&address).unwrap(), public_key);
+
+        let mut tampered = address.into_bytes();
+        tampered[0] = if tampered[0] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(decode_algorand_address(&tampered).unwrap_err().contains("checksum"));
+
+        assert!(decode_algorand_address(&ZERO_ADDRESS[1..]).unwrap_err().contains("length"));
+        let lowercase = ZERO_ADDRESS.to_lowercase();
+        assert!(decode_algorand_address(&lowercase).unwrap_err().contains("character"));
+    }
 
-// ============================================================================
-// DEPOSIT-BASED SWAP FUNCTIONS (Autonomous Agent Flow)
-// ============================================================================
+    #[test]
+    fn sweeps_fail_only_after_last_valid() {
+        grant(operator(), &[Role::Operator]);
+        env::set_caller(operator());
+        CUSTODY_BALANCES.with(|b| b.borrow_mut().insert("CUSTODY".to_string(), StableCandid(Nat::from(0u64))));
+        IN_FLIGHT_SWEEPS.with(|f| *f.borrow_mut() = Nat::from(1_010u64));
+        SWEEP_RECORDS.with(|r| r.borrow_mut().insert(0, StableCandid(SweepRecord {
+            sweep_id: 0,
+            custody_address: "CUSTODY".to_string(),
+            owner: alice(),
+            destination: "HOT".to_string(),
+            amount: Nat::from(1_000u64),
+            fee: Nat::from(10u64),
+            algorand_tx_id: "SWEEPTX".to_string(),
+            signed_transaction: vec![],
+            first_valid: 100,
+            last_valid: 1_100,
+            status: SweepStatus::Signed,
+            created_at: time(),
+            updated_at: time(),
+            error: None,
+        })));
+
+        assert!(fail_sweep(0, 1_100, "expired".to_string()).unwrap_err().contains("may still land"));
+        fail_sweep(0, 1_101, "expired".to_string()).unwrap();
+        assert_eq!(SWEEP_RECORDS.with(|r| r.borrow().get(&0).unwrap().0.status), SweepStatus::Failed);
+        assert_eq!(IN_FLIGHT_SWEEPS.with(|f| f.borrow().clone()), Nat::from(0u64));
+    }
 
-/// Derive custody subaccount for a principal (for ckETH deposits)
-///
-/// Agent should transfer ckETH to:
-///   Account { owner: simplified_bridge_canister, subaccount: Some(result) }
-///
-/// The subaccount is SHA256(principal.as_slice())[0:32]
-fn derive_custody_subaccount(principal: &Principal) -> [u8; 32] {
-    let mut hasher = Sha256::new();
-    hasher.update(principal.as_slice());
-    let result = hasher.finalize();
-    let mut subaccount = [0u8; 32];
-    subaccount.copy_from_slice(&result[..32]);
-    subaccount
-}
+    #[test]
+    fn failed_redemption_releases_hot_wallet_liquidity() {
+        grant(operator(), &[Role::Operator, Role::Admin]);
+        env::set_caller(operator());
+        credit(alice(), 5_000);
+        RESERVE_POLICY.with(|p| *p.borrow_mut() = ReservePolicy {
+            enabled: true,
+            hot_wallet_owner: None,
+            hot_min: Nat::from(0u64),
+            hot_target: Nat::from(5_000u64),
+            hot_max: Nat::from(10_000u64),
+        });
+        HOT_WALLET_BALANCE.with(|h| *h.borrow_mut() = Nat::from(5_000u64));
+
+        assert_eq!(redeem_on_behalf(alice(), Nat::from(2_000u64), "ALGOADDR".to_string()).unwrap(), "REDEEM_0_ALGOADDR");
+        assert_eq!(HOT_WALLET_BALANCE.with(|h| h.borrow().clone()), Nat::from(3_000u64));
+        assert_eq!(get_pending_redemptions()[0].hot_wallet_reserved, Nat::from(2_000u64));
+
+        fail_redemption(0, "payout rejected".to_string()).unwrap();
+        assert_eq!(HOT_WALLET_BALANCE.with(|h| h.borrow().clone()), Nat::from(5_000u64));
+        assert_eq!(icrc1_balance_of(alice()), Nat::from(5_000u64));
+        assert_eq!(TOTAL_SUPPLY.with(|s| s.borrow().clone()), Nat::from(5_000u64));
+        assert_eq!(LOCKED_ALGO_RESERVES.with(|r| r.borrow().clone()), Nat::from(5_000u64));
+        assert!(get_pending_redemptions().is_empty());
+        assert!(fail_redemption(0, "again".to_string()).unwrap_err().contains("already"));
+
+        redeem_on_behalf(alice(), Nat::from(1_000u64), "ALGOADDR".to_string()).unwrap();
+        complete_redemption(1, "PAYOUTTX".to_string()).unwrap();
+        assert_eq!(HOT_WALLET_BALANCE.with(|h| h.borrow().clone()), Nat::from(4_000u64));
+        assert!(fail_redemption(1, "late".to_string()).is_err());
+    }
 
-/// Get custody subaccount for ckETH deposits (query)
-///
-/// Returns the 32-byte subaccount that the agent should deposit ckETH to.
-/// Full deposit account: { owner: this_canister, subaccount: result }
-#[query]
-fn get_swap_custody_subaccount(principal: Principal) -> Vec<u8> {
-    derive_custody_subaccount(&principal).to_vec()
-}
+    fn rate(numerator: u64, denominator: u64) -> RateFraction {
+        RateFraction { numerator: Nat::from(numerator), denominator: Nat::from(denominator) }
+    }
 
-/// Check if a swap deposit has already been processed (anti-replay)
-#[query]
-fn is_swap_deposit_processed(tx_id: String) -> bool {
-    PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow().contains(&tx_id))
-}
+    #[test]
+    fn swap_output_rounds_net_down_and_fee_absorbs_remainder() {
+        // 1000 base units of a 6-decimal token at 1/3 ALGO: exact gross 333.33 microALGO
+        let (net, fee) = compute_swap_output(&Nat::from(1_000u64), 6, &rate(1, 3), 30).unwrap();
+        assert_eq!(net, Nat::from(332u64));  // floor(332.33)
+        assert_eq!(fee, Nat::from(1u64));     // floor(333.33) - 332
+
+        for amount in [1u64, 7, 999, 1_000, 123_457, 9_999_999] {
+            for fee_bps in [0u64, 1, 30, 9_999, 10_000] {
+                let (net, fee) = compute_swap_output(&Nat::from(amount), 6, &rate(7, 3), fee_bps).unwrap();
+                let gross = Nat::from(amount) * Nat::from(7u64) / Nat::from(3u64);
+                assert_eq!(net.clone() + fee, gross, "amount {} fee {}", amount, fee_bps);
+                // net * d * 10^dec * 10000 <= amount * n * 10^6 * (10000 - fee_bps)
+                assert!(
+                    net * Nat::from(3u64 * 10_000) <= Nat::from(amount) * Nat::from(7u64) * Nat::from(10_000 - fee_bps),
+                    "amount {} fee {}", amount, fee_bps
+                );
+            }
+        }
+    }
 
-/// Get list of processed swap deposits (for debugging/audit)
-#[query]
-fn get_processed_swap_deposits(limit: Option<u32>) -> Vec<String> {
-    let limit = limit.unwrap_or(100) as usize;
-    PROCESSED_SWAP_DEPOSITS.with(|d| {
-        d.borrow().iter().take(limit).cloned().collect()
-    })
-}
+    #[test]
+    fn swap_output_fee_boundaries() {
+        let amount = Nat::from(1_000_000u64);
+        let (net, fee) = compute_swap_output(&amount, 6, &rate(5, 2), 0).unwrap();
+        assert_eq!((net, fee), (Nat::from(2_500_000u64), Nat::from(0u64)));
 
-/// Swap ckETH for ckALGO - Deposit-Based (Autonomous Agent Flow)
-///
-/// SECURITY: Only callable by authorized principals (backend, controllers)
-/// Backend MUST verify ckETH deposit BEFORE calling this function.
-///
-/// Flow:
-/// 1. Agent transfers ckETH to custody subaccount (standard ICRC-1 transfer)
-/// 2. Agent calls backend POST /swap/execute with tx_id
-/// 3. Backend verifies ckETH balance in custody subaccount
-/// 4. Backend calls this function with deposit details
-/// 5. Canister verifies deposit not already processed (anti-replay)
-/// 6. Canister gets exchange rate from XRC
-/// 7. Canister mints ckALGO to agent's principal
-/// 8. Canister marks deposit as processed
-///
-/// NOTE: This function does NOT call ckETH canister to verify balance.
-/// Backend is trusted to verify the deposit before calling.
-#[update]
-async fn swap_cketh_for_ckalgo_deposit(
-    agent_principal: Principal,
-    cketh_amount: Nat,
-    cketh_tx_id: String,
-    min_ckalgo_out: Option<Nat>
-) -> Result<SwapResult, String> {
-    // 1. Authorization check
-    let caller_principal = caller();
-    let is_authorized = AUTHORIZED_MINTERS.with(|m| m.borrow().contains(&caller_principal));
-    let is_controller = ic_cdk::api::is_controller(&caller_principal);
+        let (net, fee) = compute_swap_output(&amount, 6, &rate(5, 2), BPS_DENOMINATOR).unwrap();
+        assert_eq!((net, fee), (Nat::from(0u64), Nat::from(2_500_000u64)));
 
-    if !is_authorized && !is_controller {
-        return Err(format!(
-            "Unauthorized: only authorized principals can execute deposit swaps. Caller: {}",
-            caller_principal
-        ));
+        assert!(compute_swap_output(&amount, 6, &rate(5, 2), BPS_DENOMINATOR + 1).unwrap_err().contains("exceeds 100%"));
     }
 
-    // 2. Check swap enabled
-    let enabled = SWAP_ENABLED.with(|e| *e.borrow());
-    if !enabled {
-        return Err("Swaps are currently disabled".to_string());
+    #[test]
+    fn swap_output_scales_with_input_decimals() {
+        // One whole token at 2.5 ALGO per token is 2.5 ALGO whatever its decimals
+        for decimals in [6u32, 8, 18] {
+            let one_token = pow10(decimals);
+            let (net, fee) = compute_swap_output(&one_token, decimals, &rate(5, 2), 30).unwrap();
+            assert_eq!(net, Nat::from(2_492_500u64), "decimals {}", decimals);
+            assert_eq!(fee, Nat::from(7_500u64), "decimals {}", decimals);
+        }
+
+        // 1 wei of an 18-decimal token is worth far less than a microALGO
+        let (net, fee) = compute_swap_output(&Nat::from(1u64), 18, &rate(5, 2), 30).unwrap();
+        assert_eq!((net, fee), (Nat::from(0u64), Nat::from(0u64)));
     }
 
-    // 3. Check for duplicate tx_id (anti-replay protection)
-    let is_duplicate = PROCESSED_SWAP_DEPOSITS.with(|deposits| {
-        deposits.borrow().contains(&cketh_tx_id)
-    });
-    if is_duplicate {
-        return Err(format!("Deposit {} already processed", cketh_tx_id));
+    #[test]
+    fn swap_output_with_zero_numerator_or_amount_is_zero() {
+        let (net, fee) = compute_swap_output(&Nat::from(1_000_000u64), 6, &rate(0, 1), 30).unwrap();
+        assert_eq!((net, fee), (Nat::from(0u64), Nat::from(0u64)));
+
+        let (net, fee) = compute_swap_output(&Nat::from(0u64), 18, &rate(5, 2), 30).unwrap();
+        assert_eq!((net, fee), (Nat::from(0u64), Nat::from(0u64)));
+
+        assert!(compute_swap_output(&Nat::from(1u64), 6, &rate(1, 0), 30).unwrap_err().contains("denominator"));
     }
 
-    // 4. Validate amount within limits
-    let min_swap = MIN_SWAP_CKETH.with(|m| m.borrow().clone());
-    let max_swap = MAX_SWAP_CKETH.with(|m| m.borrow().clone());
+    #[test]
+    fn swap_quotes_are_single_use_and_restored_on_failure() {
+        let pair = default_swap_pairs().remove(&cketh_ledger()).unwrap();
+        let quote = SwapQuote {
+            quote_id: 7,
+            pair: pair.ledger,
+            amount_in: Nat::from(1_000u64),
+            amount_out: Nat::from(900u64),
+            fee: Nat::from(3u64),
+            fee_bps: pair.fee_bps,
+            rate: rate(9, 10),
+            created_at: time(),
+            expires_at: time() + SWAP_QUOTE_TTL_NS,
+        };
+        SWAP_QUOTES.with(|quotes| quotes.borrow_mut().insert(7, quote));
+        let resolve = |amount_in: u64, min_out: Option<u64>| {
+            run_now(resolve_swap_price(&pair, &Nat::from(amount_in), Some(7), min_out.map(Nat::from).as_ref()))
+        };
+
+        // Rejections before any funds move leave the quote usable
+        assert!(resolve(999, None).err().unwrap().contains("Quote 7 is for"));
+        assert!(resolve(1_000, Some(901)).err().unwrap().contains("Slippage"));
+        assert!(get_swap_quote(7).is_some());
+
+        let price = resolve(1_000, Some(900)).ok().unwrap();
+        assert_eq!(price.amount_out, Nat::from(900u64));
+        assert!(resolve(1_000, None).err().unwrap().contains("already used"));
+
+        // A swap that fails afterwards puts it back while it is still valid
+        restore_swap_quote(price.quote.clone());
+        assert!(get_swap_quote(7).is_some());
+        env::advance_time(SWAP_QUOTE_TTL_NS);
+        assert!(resolve(1_000, None).err().unwrap().contains("expired"));
+        restore_swap_quote(price.quote);
+        assert!(SWAP_QUOTES.with(|quotes| quotes.borrow().is_empty()));
+    }
 
-    if cketh_amount < min_swap {
-        return Err(format!(
-            "Swap amount {} below minimum {} (0.0001 ETH)",
-            cketh_amount, min_swap
-        ));
+    fn xrc_rate(rate: u64, sources: u64, standard_deviation: u64, age_secs: u64) -> ExchangeRate {
+        ExchangeRate {
+            base_asset: Asset { symbol: "ETH".to_string(), class: AssetClass::Cryptocurrency },
+            quote_asset: Asset { symbol: "USD".to_string(), class: AssetClass::FiatCurrency },
+            timestamp: time() / 1_000_000_000 - age_secs,
+            rate,
+            metadata: ExchangeRateMetadata {
+                decimals: 9,
+                base_asset_num_received_rates: sources,
+                base_asset_num_queried_sources: 5,
+                quote_asset_num_received_rates: 5,
+                quote_asset_num_queried_sources: 5,
+                standard_deviation,
+                forex_timestamp: None,
+            },
+        }
     }
-    if cketh_amount > max_swap {
-        return Err(format!(
-            "Swap amount {} exceeds maximum {} (1 ETH)",
-            cketh_amount, max_swap
-        ));
+
+    #[test]
+    fn rate_guard_rejects_thin_dispersed_and_stale_rates() {
+        // Defaults: at least 3 sources, deviation up to 200 bps, at most 300s old
+        check_rate_quality("ETH/USD", &xrc_rate(1_000_000, 3, 20_000, 300)).unwrap();
+
+        let rejected = |rate: ExchangeRate| check_rate_quality("ETH/USD", &rate).unwrap_err();
+        assert!(rejected(xrc_rate(1_000_000, 2, 0, 0)).contains("only 2 of 5 sources"));
+        assert!(rejected(xrc_rate(1_000_000, 5, 20_001, 0)).contains("standard deviation"));
+        assert!(rejected(xrc_rate(0, 5, 0, 0)).contains("standard deviation"));
+        assert!(rejected(xrc_rate(1_000_000, 5, 0, 301)).contains("301s old"));
+        assert_eq!(RATE_REJECTIONS.with(|r| r.borrow().len()), 4);
     }
 
-    // 5. Get exchange rate from XRC (NO FALLBACK - reject if unavailable)
-    let rate = get_eth_algo_rate().await?;
+    #[test]
+    fn rate_jump_trips_the_circuit_breaker_until_reset() {
+        let accept = |rate: RateFraction| {
+            LAST_ACCEPTED_RATES.with(|rates| {
+                rates.borrow_mut().insert("ETH".to_string(), AcceptedRate { rate, accepted_at: time() })
+            });
+        };
+        accept(rate(100, 1));
+
+        // Default max_jump_bps is 10%
+        check_rate_jump("ETH", &rate(110, 1)).unwrap();
+        check_rate_jump("ETH", &rate(90, 1)).unwrap();
+        assert!(check_rate_jump("ETH", &rate(111, 1)).unwrap_err().contains("circuit breaker tripped"));
+        assert!(RATE_CIRCUIT_BREAKER.with(|b| b.borrow().is_some()));
+
+        // Pricing stops until an Admin resets it, which also clears the baseline
+        assert!(run_now(get_cached_rate("ETH")).unwrap_err().contains("Rate circuit breaker tripped"));
+        grant(operator(), &[Role::Admin]);
+        env::set_caller(operator());
+        assert!(reset_rate_circuit_breaker().unwrap().contains("was: ETH/ALGO moved"));
+        assert!(RATE_CIRCUIT_BREAKER.with(|b| b.borrow().is_none()));
+        assert!(LAST_ACCEPTED_RATES.with(|rates| rates.borrow().is_empty()));
+
+        // A baseline older than jump_window_secs is not compared against
+        accept(rate(100, 1));
+        env::advance_time(3_601 * 1_000_000_000);
+        check_rate_jump("ETH", &rate(1_000, 1)).unwrap();
+    }
 
-    // 6. Calculate output
-    // ckETH has 18 decimals, ckALGO has 6 decimals
-    // cketh_amount is in wei (1e-18 ETH)
-    // rate is ETH/ALGO (how many ALGO per ETH)
-    // output should be in microALGO (1e-6 ALGO)
-    let cketh_as_f64 = nat_to_f64(&cketh_amount);
-    let eth_amount = cketh_as_f64 / 1e18;  // Convert wei to ETH
-    let algo_amount = eth_amount * rate;    // ETH to ALGO
-    let micro_algo = algo_amount * 1e6;     // ALGO to microALGO
+    #[test]
+    fn swap_pairs_are_looked_up_by_ledger_with_per_ledger_deposit_keys() {
+        let usdc = SwapPair {
+            ledger: Principal::from_slice(&[9; 29]),
+            symbol: "ckUSDC".to_string(),
+            xrc_symbol: "USDC".to_string(),
+            decimals: 6,
+            fee_bps: 30,
+            min_amount: Nat::from(1_000u64),
+            max_amount: Nat::from(1_000_000_000u64),
+            enabled: true,
+        };
+        SWAP_ENABLED.with(|e| *e.borrow_mut() = true);
+        assert!(active_swap_pair(&usdc.ledger).unwrap_err().contains("Unsupported swap pair"));
+
+        SWAP_PAIRS.with(|pairs| pairs.borrow_mut().insert(usdc.ledger, usdc.clone()));
+        assert_eq!(active_swap_pair(&usdc.ledger).unwrap().symbol, "ckUSDC");
+        assert_eq!(active_swap_pair(&cketh_ledger()).unwrap().decimals, CKETH_DECIMALS);
+
+        SWAP_PAIRS.with(|pairs| pairs.borrow_mut().get_mut(&usdc.ledger).unwrap().enabled = false);
+        assert!(active_swap_pair(&usdc.ledger).unwrap_err().contains("ckUSDC are currently disabled"));
+        SWAP_ENABLED.with(|e| *e.borrow_mut() = false);
+        assert!(active_swap_pair(&cketh_ledger()).unwrap_err().contains("Swaps are currently disabled"));
+
+        // ckETH keeps bare block indexes; the same index on another ledger is a different deposit
+        assert_eq!(swap_deposit_key(&cketh_ledger(), "42"), "42");
+        assert_eq!(swap_deposit_key(&usdc.ledger, "42"), format!("{}:42", usdc.ledger));
+
+        // Decimals are fixed once the pair has backing
+        let mut redecimalled = usdc.clone();
+        redecimalled.decimals = 8;
+        validate_swap_pair(&redecimalled).unwrap();
+        SWAP_BACKING.with(|b| b.borrow_mut().insert(usdc.ledger, SwapBacking {
+            total_received: Nat::from(1u64),
+            ckalgo_minted: Nat::from(0u64),
+        }));
+        assert!(validate_swap_pair(&redecimalled).unwrap_err().contains("Cannot change decimals"));
+        validate_swap_pair(&usdc).unwrap();
+    }
 
-    // Apply fee
-    let fee_bps = SWAP_FEE_BPS.with(|f| *f.borrow());
-    let fee_amount = micro_algo * (fee_bps as f64 / 10_000.0);
-    let net_micro_algo = micro_algo - fee_amount;
+    #[test]
+    fn deposit_blocks_must_be_transfers_into_the_agents_custody_subaccount() {
+        let pair = default_swap_pairs().remove(&cketh_ledger()).unwrap();
+        let bridge = Principal::from_slice(&[8; 29]);
+        let block = parse_deposit_block_index(" 042").unwrap();
+        assert_eq!(block, Nat::from(42u64));
+        assert!(parse_deposit_block_index("0xabc").unwrap_err().contains("expected a ledger block index"));
+
+        let block_paying = |owner: Principal, agent: Principal| LedgerTransaction {
+            kind: "transfer".to_string(),
+            transfer: Some(LedgerTransfer {
+                from: Account { owner: bob(), subaccount: None },
+                to: Account { owner, subaccount: Some(derive_custody_subaccount(&agent)) },
+                amount: Nat::from(5u64),
+            }),
+            timestamp: time(),
+        };
+        let check = |tx| check_custody_deposit(&pair, &alice(), &block, tx, bridge);
+
+        assert_eq!(check(block_paying(bridge, alice())).unwrap().amount, Nat::from(5u64));
+        assert!(check(block_paying(bridge, bob())).unwrap_err().contains("does not pay the custody subaccount"));
+        assert!(check(block_paying(bob(), alice())).unwrap_err().contains("does not pay the custody subaccount"));
+
+        let mut mint = block_paying(bridge, alice());
+        mint.kind = "mint".to_string();
+        assert!(check(mint).unwrap_err().contains("is a mint, not a transfer"));
+        let mut empty = block_paying(bridge, alice());
+        empty.transfer = None;
+        assert!(check(empty).unwrap_err().contains("not a transfer"));
+    }
 
-    if net_micro_algo <= 0.0 {
-        return Err("Output amount too small after fee".to_string());
+    #[test]
+    fn reverse_swap_output_rounds_down_after_the_fee() {
+        // 1 ALGO at 5/2 ALGO per ckETH, 30 bps: fee 3000 microALGO, 0.997 ALGO -> 0.3988 ckETH
+        let (out, fee) = compute_reverse_swap_output(&Nat::from(1_000_000u64), 18, &rate(5, 2), 30).unwrap();
+        assert_eq!(fee, Nat::from(3_000u64));
+        assert_eq!(out, Nat::from(398_800_000_000_000_000u64));
+
+        // 1000 microALGO at 3 ALGO per token (6 decimals): 333.33 base units -> 333
+        let (out, fee) = compute_reverse_swap_output(&Nat::from(1_000u64), 6, &rate(3, 1), 0).unwrap();
+        assert_eq!((out, fee), (Nat::from(333u64), Nat::from(0u64)));
+
+        for ckalgo in [1u64, 7, 999, 123_457, 9_999_999] {
+            for fee_bps in [0u64, 1, 30, 9_999] {
+                let (out, fee) = compute_reverse_swap_output(&Nat::from(ckalgo), 6, &rate(7, 3), fee_bps).unwrap();
+                assert_eq!(fee, Nat::from(ckalgo * fee_bps / 10_000), "ckalgo {} fee {}", ckalgo, fee_bps);
+                // out * n <= (ckalgo - fee) * d, and one more base unit would exceed it
+                let net = Nat::from(ckalgo) - fee;
+                assert!(out.clone() * Nat::from(7u64) <= net.clone() * Nat::from(3u64), "ckalgo {} fee {}", ckalgo, fee_bps);
+                assert!((out + Nat::from(1u64)) * Nat::from(7u64) > net * Nat::from(3u64), "ckalgo {} fee {}", ckalgo, fee_bps);
+            }
+        }
     }
 
-    // Bounds check before u64 cast (P2 fix: prevent truncation/overflow)
-    if net_micro_algo > (u64::MAX as f64) {
-        return Err(format!(
-            "Output amount {} exceeds maximum representable value",
-            net_micro_algo
-        ));
+    #[test]
+    fn reverse_swap_output_fee_boundaries_and_invalid_inputs() {
+        let amount = Nat::from(1_000_000u64);
+        let (out, fee) = compute_reverse_swap_output(&amount, 6, &rate(2, 1), BPS_DENOMINATOR).unwrap();
+        assert_eq!((out, fee), (Nat::from(0u64), amount.clone()));
+
+        let (out, fee) = compute_reverse_swap_output(&Nat::from(0u64), 18, &rate(2, 1), 30).unwrap();
+        assert_eq!((out, fee), (Nat::from(0u64), Nat::from(0u64)));
+
+        assert!(compute_reverse_swap_output(&amount, 6, &rate(2, 1), BPS_DENOMINATOR + 1).unwrap_err().contains("exceeds 100%"));
+        assert!(compute_reverse_swap_output(&amount, 6, &rate(0, 1), 30).unwrap_err().contains("numerator"));
     }
-    if fee_amount > (u64::MAX as f64) {
-        return Err(format!(
-            "Fee amount {} exceeds maximum representable value",
-            fee_amount
-        ));
+
+    #[test]
+    fn reverse_swap_round_trip_never_gains() {
+        // Swapping in and straight back out can only lose to fees and rounding
+        for amount in [1_000u64, 123_456_789, 10u64.pow(18)] {
+            let (ckalgo, _) = compute_swap_output(&Nat::from(amount), 18, &rate(5, 2), 30).unwrap();
+            let (back, _) = compute_reverse_swap_output(&ckalgo, 18, &rate(5, 2), 30).unwrap();
+            assert!(back <= amount, "amount {}", amount);
+        }
     }
 
-    let ckalgo_out = Nat::from(net_micro_algo.floor() as u64);
-    let fee_nat = Nat::from(fee_amount.floor() as u64);
+    #[test]
+    fn rate_cache_ttl_is_capped_by_max_age() {
+        RATE_GUARD_CONFIG.with(|c| c.borrow_mut().max_age_secs = 300);
+        let config = |ttl_secs| RateCacheConfig { ttl_secs, timer_refresh: false };
+        assert!(check_rate_cache_config(&config(300)).is_ok());
+        assert!(check_rate_cache_config(&config(301)).unwrap_err().contains("max_age_secs"));
+
+        // A TTL accepted before max_age_secs was lowered is capped when read
+        RATE_CACHE_CONFIG.with(|c| *c.borrow_mut() = config(600));
+        LAST_ACCEPTED_RATES.with(|rates| rates.borrow_mut().insert(ETH_XRC_SYMBOL.to_string(), AcceptedRate {
+            rate: rate(5, 2),
+            accepted_at: time() - 200 * 1_000_000_000,
+        }));
+        assert!(cached_rate_if_fresh(ETH_XRC_SYMBOL).is_some());
+
+        RATE_GUARD_CONFIG.with(|c| c.borrow_mut().max_age_secs = 100);
+        assert!(cached_rate_if_fresh(ETH_XRC_SYMBOL).is_none());
+        let view = cached_rate_view(ETH_XRC_SYMBOL).unwrap();
+        assert_eq!(view.ttl_secs, 100);
+        assert!(!view.fresh);
+    }
 
-    // 7. Slippage check
-    if let Some(min_out) = min_ckalgo_out {
-        if ckalgo_out < min_out {
-            return Err(format!(
-                "Slippage exceeded: output {} < minimum {}",
-                ckalgo_out, min_out
-            ));
+    #[test]
+    fn deposit_watcher_rotates_through_all_agents() {
+        let agents: Vec<(Principal, ())> = (1..=5u8).map(|i| (Principal::from_slice(&[i; 29]), ())).collect();
+        let ids = |batch: &[(Principal, ())]| batch.iter().map(|(a, _)| a.as_slice()[0]).collect::<Vec<_>>();
+
+        let mut cursor = None;
+        let mut visited = Vec::new();
+        for _ in 0..3 {
+            let batch = next_agent_batch(agents.iter().rev().cloned().collect(), cursor, 2);
+            cursor = batch.last().map(|(a, _)| *a);
+            visited.push(ids(&batch));
         }
+        assert_eq!(visited, [vec![1, 2], vec![3, 4], vec![5, 1]]);
+
+        // A cursor whose agent has since left still resumes at the next one
+        let remaining: Vec<_> = agents.iter().filter(|(a, _)| a.as_slice()[0] != 3).cloned().collect();
+        let batch = next_agent_batch(remaining, Some(Principal::from_slice(&[3; 29])), 2);
+        assert_eq!(ids(&batch), [4, 5]);
     }
 
-    // 8. Mark deposit as processed BEFORE minting (prevents double-processing on retry)
-    PROCESSED_SWAP_DEPOSITS.with(|deposits| {
-        deposits.borrow_mut().insert(cketh_tx_id.clone());
-    });
+    #[test]
+    fn deposits_below_the_watcher_credit_cannot_be_claimed_individually() {
+        let ledger = Principal::from_slice(&[9; 29]);
+        assert!(check_deposit_not_bulk_credited(&ledger, &alice(), &Nat::from(5u64)).is_ok());
 
-    // 9. Mint ckALGO to agent
-    let agent_str = agent_principal.to_text();
+        CUSTODY_CREDITED_THROUGH.with(|c| c.borrow_mut().insert((ledger, alice()), StableCandid(Nat::from(10u64))));
+        assert!(check_deposit_not_bulk_credited(&ledger, &alice(), &Nat::from(9u64)).is_err());
+        assert!(check_deposit_not_bulk_credited(&ledger, &alice(), &Nat::from(10u64)).is_ok());
+        // Another agent's custody account is unaffected
+        assert!(check_deposit_not_bulk_credited(&ledger, &bob(), &Nat::from(9u64)).is_ok());
+    }
 
-    BALANCES.with(|balances| {
-        let mut balances_map = balances.borrow_mut();
-        let current = balances_map.get(&agent_str).unwrap_or(&Nat::from(0u64)).clone();
-        balances_map.insert(agent_str.clone(), current + ckalgo_out.clone());
-    });
+    #[test]
+    fn treasury_surplus_excludes_the_value_of_outstanding_ckalgo() {
+        // 2 ALGO per ckETH: 1.5 ALGO outstanding is worth 0.75 ckETH of the 1 ckETH held
+        let backing = SwapBacking {
+            total_received: pow10(18),
+            ckalgo_minted: Nat::from(1_500_000u64),
+        };
+        let surplus = treasury_backing_surplus(&backing, 18, &rate(2, 1)).unwrap();
+        assert_eq!(surplus, Nat::from(250_000_000_000_000_000u64));
+
+        // After the rate halves the outstanding ckALGO is worth more than is held
+        assert_eq!(treasury_backing_surplus(&backing, 18, &rate(1, 1)).unwrap(), Nat::from(0u64));
+
+        let idle = SwapBacking { total_received: Nat::from(42u64), ckalgo_minted: Nat::from(0u64) };
+        assert_eq!(treasury_backing_surplus(&idle, 18, &rate(2, 1)).unwrap(), Nat::from(42u64));
+    }
 
-    // 10. Update total supply
-    TOTAL_SUPPLY.with(|supply| {
-        let mut total = supply.borrow_mut();
-        *total = total.clone() + ckalgo_out.clone();
-    });
+    #[test]
+    fn admin_implies_every_role_and_controllers_hold_them_implicitly() {
+        grant(alice(), &[Role::Minter]);
+        assert!(has_role(&alice(), Role::Minter));
+        assert!(!has_role(&alice(), Role::Operator));
+        assert!(!has_role(&alice(), Role::Admin));
 
-    // 11. Track ckETH-backed ckALGO separately (NOT backed by ALGO reserves)
-    CKETH_BACKED_CKALGO.with(|backed| {
-        let mut total = backed.borrow_mut();
-        *total = total.clone() + ckalgo_out.clone();
-    });
+        grant(bob(), &[Role::Admin]);
+        for role in [Role::Minter, Role::DepositReporter, Role::Operator, Role::Pauser, Role::SwapExecutor, Role::Admin] {
+            assert!(has_role(&bob(), role), "{:?}", role);
+        }
 
-    // 12. Track total ckETH received
-    TOTAL_CKETH_RECEIVED.with(|received| {
-        let mut total = received.borrow_mut();
-        *total = total.clone() + cketh_amount.clone();
-    });
+        // Controllers and governance hold every role without an assignment
+        env::set_controller(minter());
+        GOVERNANCE_CANISTER.with(|g| *g.borrow_mut() = Some(operator()));
+        assert!(has_role(&minter(), Role::Pauser));
+        assert!(has_role(&operator(), Role::Admin));
+        assert!(held_roles(&minter()).is_empty());
+
+        // A stale assignment never makes the anonymous principal a role holder
+        grant(Principal::anonymous(), &[Role::Admin]);
+        assert!(!has_role(&Principal::anonymous(), Role::Minter));
+        env::set_caller(minter());
+        assert!(grant_role(Principal::anonymous(), Role::Minter).unwrap_err().contains("cannot be granted"));
+        assert!(grant_role(alice(), Role::Minter).unwrap_err().contains("already holds"));
+        revoke_role(alice(), Role::Minter).unwrap();
+        assert!(!has_role(&alice(), Role::Minter));
+    }
 
-    // 13. Record swap for audit trail
-    let record = SwapRecord {
-        user: agent_principal,
-        cketh_in: cketh_amount.clone(),
-        ckalgo_out: ckalgo_out.clone(),
-        rate_used: rate,
-        fee_collected: fee_nat,
-        timestamp: time(),
-        tx_id: format!("DEPOSIT_SWAP_{}", cketh_tx_id),
-    };
+    #[test]
+    fn zero_delay_applies_only_the_queued_change() {
+        CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow_mut() = 0);
+        SWAP_ENABLED.with(|e| *e.borrow_mut() = true);
+        // Queued under the old delay and due, but the timer has not fired yet
+        CONFIG_CHANGES.with(|c| c.borrow_mut().insert(0, StableCandid(PendingConfigChange {
+            change_id: 0,
+            change: ConfigChange::SwapEnabled(false),
+            proposed_by: operator(),
+            proposed_at: time(),
+            effective_at: time(),
+            status: ConfigChangeStatus::Pending,
+            resolved_at: None,
+        })));
+
+        let message = queue_config_change(ConfigChange::TimelockDelay(60), operator());
+        assert_eq!(message, "Applied config timelock 60s");
+        assert_eq!(CONFIG_TIMELOCK_DELAY_SECS.with(|d| *d.borrow()), 60);
+        assert!(SWAP_ENABLED.with(|e| *e.borrow()));
+        let status = |id| CONFIG_CHANGES.with(|c| c.borrow().get(&id).unwrap().0.status);
+        assert_eq!(status(0), ConfigChangeStatus::Pending);
+        assert!(matches!(status(1), ConfigChangeStatus::Applied { .. }));
+    }
 
-    SWAP_RECORDS.with(|records| records.borrow_mut().push(record));
+    #[test]
+    fn pausing_a_class_stops_only_that_class_until_an_admin_resumes_it() {
+        grant(operator(), &[Role::Pauser]);
+        grant(bob(), &[Role::Admin]);
+        credit(alice(), 1_000);
+        env::set_caller(operator());
+        assert!(pause_operations(vec![PauseClass::Transfers], " ".to_string()).is_err());
+        let state = pause_operations(vec![PauseClass::Transfers, PauseClass::Swaps], "incident 7".to_string()).unwrap();
+        assert_eq!(state.bitmap, pause_bit(PauseClass::Transfers) | pause_bit(PauseClass::Swaps));
+
+        for class in ALL_PAUSE_CLASSES {
+            let paused = matches!(class, PauseClass::Transfers | PauseClass::Swaps);
+            assert_eq!(ensure_not_paused(class).is_err(), paused, "{:?}", class);
+        }
+        env::set_caller(alice());
+        assert_eq!(icrc1_transfer(bob(), Nat::from(10u64)).unwrap_err(), "Transfers are paused: incident 7");
+        assert!(active_swap_pair(&cketh_ledger()).unwrap_err().contains("Swaps are paused"));
+
+        // Pausers cannot resume; Admins resume one class at a time
+        env::set_caller(operator());
+        assert!(unpause_operations(vec![PauseClass::Transfers]).is_err());
+        env::set_caller(bob());
+        unpause_operations(vec![PauseClass::Transfers]).unwrap();
+        env::set_caller(alice());
+        assert!(icrc1_transfer(bob(), Nat::from(10u64)).is_ok());
+        assert!(ensure_not_paused(PauseClass::Swaps).is_err());
+    }
 
-    // 14. Return result
-    Ok(SwapResult {
-        cketh_in: cketh_amount,
-        ckalgo_out,
-        rate_used: rate,
-        cketh_block_index: Nat::from(0u64), // Not applicable for deposit-based swap
-    })
-}
\ No newline at end of file
+    fn enable_approvals(approvers: Vec<Principal>, required_approvals: u32, ckalgo_threshold: u64) {
+        APPROVAL_CONFIG.with(|c| *c.borrow_mut() = ApprovalConfig {
+            enabled: true,
+            approvers,
+            required_approvals,
+            ckalgo_threshold: Nat::from(ckalgo_threshold),
+            ..ApprovalConfig::default()
+        });
+    }
+
+    #[test]
+    fn split_admin_transfers_count_towards_the_approval_threshold() {
+        enable_approvals(vec![bob(), minter()], 1, 1_000);
+        grant(operator(), &[Role::Admin]);
+        credit(alice(), 5_000);
+        env::set_caller(operator());
+
+        // 600 + 300 stay under the 1000 threshold, the next 300 would not
+        assert!(admin_transfer_ck_algo(alice(), operator(), Nat::from(600u64)).is_ok());
+        assert!(admin_transfer_ck_algo(alice(), operator(), Nat::from(300u64)).is_ok());
+        let pending = admin_transfer_ck_algo(alice(), operator(), Nat::from(300u64)).unwrap_err();
+        assert!(pending.starts_with("Pending approval"), "{}", pending);
+
+        // Outside the window the earlier transfers no longer count
+        env::advance_time(APPROVAL_WINDOW_SECS * 1_000_000_000 + 1);
+        assert!(admin_transfer_ck_algo(alice(), operator(), Nat::from(300u64)).is_ok());
+    }
+
+    #[test]
+    fn approvals_from_removed_approvers_do_not_execute() {
+        enable_approvals(vec![bob(), minter()], 2, 1_000);
+        grant(operator(), &[Role::Admin]);
+        credit(alice(), 5_000);
+        env::set_caller(operator());
+        assert!(admin_transfer_ck_algo(alice(), operator(), Nat::from(2_000u64)).is_err());
+        let operation_id = ADMIN_OPERATIONS.with(|o| o.borrow().len() - 1);
+
+        env::set_caller(bob());
+        run_now(approve_admin_operation(operation_id)).unwrap();
+        // bob is removed before the second approval arrives
+        enable_approvals(vec![minter(), alice()], 2, 1_000);
+        env::set_caller(minter());
+        let operation = run_now(approve_admin_operation(operation_id)).unwrap();
+        assert_eq!(operation.status, AdminOperationStatus::Pending);
+        assert_eq!(balance_of(&alice().to_text()), Nat::from(5_000u64));
+
+        env::set_caller(alice());
+        let operation = run_now(approve_admin_operation(operation_id)).unwrap();
+        assert!(matches!(operation.status, AdminOperationStatus::Executed { .. }), "{:?}", operation.status);
+        assert_eq!(balance_of(&alice().to_text()), Nat::from(3_000u64));
+    }
+
+    #[test]
+    fn proposers_cannot_approve_their_own_operations() {
+        enable_approvals(vec![operator(), bob()], 1, 1_000);
+        grant(operator(), &[Role::Admin]);
+        credit(alice(), 5_000);
+        env::set_caller(operator());
+        assert!(admin_transfer_ck_algo(alice(), operator(), Nat::from(2_000u64)).is_err());
+        let operation_id = ADMIN_OPERATIONS.with(|o| o.borrow().len() - 1);
+
+        let rejected = run_now(approve_admin_operation(operation_id)).unwrap_err();
+        assert!(rejected.contains("cannot approve it"), "{}", rejected);
+        assert_eq!(balance_of(&alice().to_text()), Nat::from(5_000u64));
+
+        // An approval recorded for the proposer does not count towards M either
+        update_admin_operation(operation_id, |o| o.approvals.push(operator()));
+        assert_eq!(current_approval_count(&admin_operation(operation_id).unwrap()), 0);
+
+        env::set_caller(bob());
+        let operation = run_now(approve_admin_operation(operation_id)).unwrap();
+        assert!(matches!(operation.status, AdminOperationStatus::Executed { .. }), "{:?}", operation.status);
+        assert_eq!(balance_of(&alice().to_text()), Nat::from(3_000u64));
+    }
+}
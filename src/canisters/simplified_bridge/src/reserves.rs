@@ -0,0 +1,760 @@
+use super::*;
+
+// ============================================================================
+// HOT/COLD RESERVE TYPES
+// ============================================================================
+
+/// Offline reserve address. Its key is NOT held by threshold_signer, so funds
+/// only leave it through an operator-executed, controller-approved rebalance.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ColdWallet {
+    pub address: String,
+    pub label: String,
+    pub balance: Nat,
+}
+
+/// Band the hot wallet balance should stay within (microALGO)
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ReservePolicy {
+    pub enabled: bool,
+    pub hot_wallet_owner: Option<Principal>,  // Principal whose threshold key controls the hot wallet
+    pub hot_min: Nat,
+    pub hot_target: Nat,
+    pub hot_max: Nat,
+}
+
+impl Default for ReservePolicy {
+    fn default() -> Self {
+        ReservePolicy {
+            enabled: false,
+            hot_wallet_owner: None,
+            hot_min: Nat::from(1_000_000_000u64),    // 1,000 ALGO
+            hot_target: Nat::from(5_000_000_000u64), // 5,000 ALGO
+            hot_max: Nat::from(10_000_000_000u64),   // 10,000 ALGO
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum RebalanceDirection {
+    HotToCold,
+    ColdToHot,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum RebalanceStatus {
+    Proposed,         // Hot -> cold, waiting to be signed by the next rebalance run
+    PendingApproval,  // Cold -> hot, waiting for a controller
+    Approved,         // Cold -> hot, waiting for cold key holders to send the funds
+    Signed,           // Hot -> cold, signed and waiting for backend submission
+    Completed,
+    Rejected,
+    Failed,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RebalanceProposal {
+    pub proposal_id: u64,
+    pub direction: RebalanceDirection,
+    pub cold_address: String,
+    pub amount: Nat,
+    pub fee: Nat,
+    pub status: RebalanceStatus,
+    pub reason: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub approved_by: Option<Principal>,
+    pub algorand_tx_id: Option<String>,
+    pub signed_transaction: Option<Vec<u8>>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum RedemptionStatus {
+    Pending,    // ckALGO burned, waiting for the backend to pay out ALGO
+    Completed,
+    Failed,     // Payout failed: ckALGO re-minted and hot wallet liquidity released
+}
+
+/// A ckALGO burn waiting for its ALGO payout from the hot wallet
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Redemption {
+    pub redemption_id: u64,
+    pub user: Principal,
+    pub amount: Nat,
+    pub destination: String,
+    pub hot_wallet_reserved: Nat,  // Taken from HOT_WALLET_BALANCE (0 while the reserve policy is off)
+    pub status: RedemptionStatus,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub algorand_tx_id: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ReserveSplit {
+    pub policy: ReservePolicy,
+    pub hot_wallet_address: Option<String>,
+    pub hot_balance: Nat,
+    pub cold_wallets: Vec<ColdWallet>,
+    pub cold_total: Nat,
+    pub custody_total: Nat,
+    pub in_flight_sweeps: Nat,
+    pub in_flight_rebalance: Nat,
+    pub locked_algo_reserves: Nat,
+}
+
+// ============================================================================
+// HOT/COLD RESERVE SPLIT
+// ============================================================================
+//
+// LOCKED_ALGO_RESERVES = custody + in-flight sweeps + hot + in-flight rebalance + cold.
+// When the hot balance leaves [hot_min, hot_max] a rebalance proposal towards
+// hot_target is created. Hot -> cold moves are signed by threshold_signer on the
+// next rebalance run; cold -> hot moves need a controller's approval because the
+// cold keys are held offline and the transfer is executed by operators.
+
+pub(crate) const RESERVE_REBALANCE_INTERVAL_SECS: u64 = 15 * 60;
+
+/// Run rebalances on their own interval while the reserve policy is enabled
+pub(crate) fn schedule_rebalance_timer() {
+    if let Some(timer_id) = REBALANCE_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+
+    if !RESERVE_POLICY.with(|p| p.borrow().enabled) {
+        return;
+    }
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(RESERVE_REBALANCE_INTERVAL_SECS), || {
+        ic_cdk::spawn(async {
+            if let Err(e) = run_rebalance_cycle().await {
+                ic_cdk::println!("Reserve rebalance skipped: {}", e);
+            }
+        })
+    });
+    REBALANCE_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+/// Reserve hot wallet liquidity for a withdrawal and return the amount taken
+/// (zero until the policy is enabled). Pair with release_hot_wallet_liquidity
+/// if the withdrawal does not go through.
+pub(crate) fn reserve_hot_wallet_liquidity(amount: &Nat) -> Result<Nat, String> {
+    if !RESERVE_POLICY.with(|p| p.borrow().enabled) {
+        return Ok(Nat::from(0u64));
+    }
+
+    let hot_balance = HOT_WALLET_BALANCE.with(|h| h.borrow().clone());
+    if hot_balance < *amount {
+        evaluate_reserve_policy();
+        return Err(format!(
+            "Insufficient hot wallet liquidity: has {}, requested {}. A cold -> hot rebalance has been requested",
+            hot_balance, amount
+        ));
+    }
+
+    HOT_WALLET_BALANCE.with(|h| *h.borrow_mut() = hot_balance - amount.clone());
+    evaluate_reserve_policy();
+    Ok(amount.clone())
+}
+
+pub(crate) fn release_hot_wallet_liquidity(reserved: &Nat) {
+    if reserved.0.is_zero() {
+        return;
+    }
+    HOT_WALLET_BALANCE.with(|h| {
+        let mut hot = h.borrow_mut();
+        *hot = hot.clone() + reserved.clone();
+    });
+    evaluate_reserve_policy();
+}
+
+pub(crate) fn has_open_rebalance() -> bool {
+    REBALANCE_PROPOSALS.with(|proposals| {
+        proposals.borrow().iter().any(|(_, p)| matches!(
+            p.0.status,
+            RebalanceStatus::Proposed | RebalanceStatus::PendingApproval | RebalanceStatus::Approved | RebalanceStatus::Signed
+        ))
+    })
+}
+
+/// Create a rebalance proposal if the hot balance is outside the policy band
+pub(crate) fn evaluate_reserve_policy() -> Option<u64> {
+    let policy = RESERVE_POLICY.with(|p| p.borrow().clone());
+    if !policy.enabled || has_open_rebalance() {
+        return None;
+    }
+
+    let hot_balance = HOT_WALLET_BALANCE.with(|h| h.borrow().clone());
+    let cold_wallets = COLD_WALLETS.with(|c| c.borrow().clone());
+
+    let (direction, cold_address, amount, reason) = if hot_balance > policy.hot_max {
+        let cold = cold_wallets.first()?;
+        (
+            RebalanceDirection::HotToCold,
+            cold.address.clone(),
+            hot_balance.clone() - policy.hot_target.clone(),
+            format!("Hot balance {} above maximum {}", hot_balance, policy.hot_max),
+        )
+    } else if hot_balance < policy.hot_min {
+        let cold = cold_wallets.iter().max_by(|a, b| a.balance.cmp(&b.balance))?;
+        let needed = policy.hot_target.clone() - hot_balance.clone();
+        let amount = if cold.balance < needed { cold.balance.clone() } else { needed };
+        if amount.0.is_zero() {
+            return None;
+        }
+        (
+            RebalanceDirection::ColdToHot,
+            cold.address.clone(),
+            amount,
+            format!("Hot balance {} below minimum {}", hot_balance, policy.hot_min),
+        )
+    } else {
+        return None;
+    };
+
+    let status = match direction {
+        RebalanceDirection::HotToCold => RebalanceStatus::Proposed,
+        RebalanceDirection::ColdToHot => RebalanceStatus::PendingApproval,
+    };
+
+    let now = time();
+    let proposal_id = REBALANCE_PROPOSALS.with(|proposals| {
+        let mut proposals = proposals.borrow_mut();
+        let proposal_id = proposals.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        proposals.insert(proposal_id, StableCandid(RebalanceProposal {
+            proposal_id,
+            direction,
+            cold_address,
+            amount,
+            fee: Nat::from(0u64),
+            status,
+            reason,
+            created_at: now,
+            updated_at: now,
+            approved_by: None,
+            algorand_tx_id: None,
+            signed_transaction: None,
+            error: None,
+        }));
+        proposal_id
+    });
+
+    Some(proposal_id)
+}
+
+/// Evaluate the policy and sign any proposed hot -> cold move
+pub(crate) async fn run_rebalance_cycle() -> Result<Option<u64>, String> {
+    ensure_no_state_export()?;
+    if !RESERVE_POLICY.with(|p| p.borrow().enabled) {
+        return Err("Hot/cold reserve policy is disabled".to_string());
+    }
+
+    evaluate_reserve_policy();
+
+    let proposed = REBALANCE_PROPOSALS.with(|proposals| {
+        proposals.borrow()
+            .iter()
+            .map(|(_, p)| p.0)
+            .find(|p| p.status == RebalanceStatus::Proposed)
+    });
+
+    match proposed {
+        Some(proposal) => sign_hot_to_cold(proposal).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+pub(crate) async fn sign_hot_to_cold(proposal: RebalanceProposal) -> Result<u64, String> {
+    let policy = RESERVE_POLICY.with(|p| p.borrow().clone());
+    let config = SWEEP_CONFIG.with(|c| c.borrow().clone());
+
+    let owner = policy.hot_wallet_owner.ok_or("Hot wallet owner not configured")?;
+    let hot_wallet = config.hot_wallet_address.ok_or("Hot wallet address not configured")?;
+    let network = config.network.ok_or("Algorand network params not reported")?;
+    if time().saturating_sub(network.reported_at) > NETWORK_PARAMS_MAX_AGE_NS {
+        return Err(format!("Algorand network params are stale (reported at {})", network.reported_at));
+    }
+
+    let payment = AlgorandPayment {
+        sender: decode_algorand_address(&hot_wallet)?,
+        receiver: decode_algorand_address(&proposal.cold_address)?,
+        amount: proposal.amount.0.to_u64().ok_or("Rebalance amount too large")?,
+        fee: network.min_fee,
+        first_valid: network.last_round,
+        last_valid: network.last_round + ALGORAND_MAX_VALIDITY_ROUNDS,
+        genesis_id: &network.genesis_id,
+        genesis_hash: &network.genesis_hash,
+    };
+    let encoded_txn = payment.encode();
+    let fee = Nat::from(network.min_fee);
+    let debit = proposal.amount.clone() + fee.clone();
+
+    let hot_balance = HOT_WALLET_BALANCE.with(|h| h.borrow().clone());
+    if hot_balance < debit {
+        return Err(format!("Hot balance {} cannot cover rebalance of {}", hot_balance, debit));
+    }
+
+    // Move the amount to in-flight before awaiting the signer
+    HOT_WALLET_BALANCE.with(|h| *h.borrow_mut() = hot_balance - debit.clone());
+    IN_FLIGHT_REBALANCE.with(|f| {
+        let mut in_flight = f.borrow_mut();
+        *in_flight = in_flight.clone() + debit.clone();
+    });
+
+    let signer = Principal::from_text(THRESHOLD_SIGNER_CANISTER_ID)
+        .map_err(|e| format!("Invalid threshold signer canister ID: {}", e))?;
+    let sign_result: Result<(Result<SignedTransaction, SigningError>,), _> = ic_cdk::call(
+        signer,
+        "sign_algorand_transaction",
+        (owner, algorand_bytes_to_sign(&encoded_txn)),
+    ).await;
+
+    let signature = match sign_result {
+        Ok((Ok(signed),)) => signed.signature,
+        Ok((Err(e),)) => {
+            release_in_flight_rebalance(&debit);
+            return Err(format!("Threshold signing failed: {} (code {})", e.message, e.code));
+        }
+        Err((code, msg)) => {
+            release_in_flight_rebalance(&debit);
+            return Err(format!("Inter-canister call to threshold signer failed: {:?} - {}", code, msg));
+        }
+    };
+
+    update_rebalance_proposal(proposal.proposal_id, |p| {
+        p.status = RebalanceStatus::Signed;
+        p.fee = fee;
+        p.algorand_tx_id = Some(algorand_transaction_id(&encoded_txn));
+        p.signed_transaction = Some(algorand_signed_transaction(&encoded_txn, &signature));
+        p.updated_at = time();
+        Ok(())
+    })?;
+
+    Ok(proposal.proposal_id)
+}
+
+/// Apply `f` to a rebalance proposal, writing it back only if `f` succeeds
+pub(crate) fn update_rebalance_proposal<T>(
+    proposal_id: u64,
+    f: impl FnOnce(&mut RebalanceProposal) -> Result<T, String>,
+) -> Result<T, String> {
+    REBALANCE_PROPOSALS.with(|proposals| {
+        let mut proposals = proposals.borrow_mut();
+        let mut proposal = proposals.get(&proposal_id)
+            .ok_or(format!("Rebalance {} not found", proposal_id))?.0;
+        let result = f(&mut proposal)?;
+        proposals.insert(proposal_id, StableCandid(proposal));
+        Ok(result)
+    })
+}
+
+/// Return an in-flight hot -> cold amount to the hot wallet balance
+pub(crate) fn release_in_flight_rebalance(debit: &Nat) {
+    IN_FLIGHT_REBALANCE.with(|f| {
+        let mut in_flight = f.borrow_mut();
+        *in_flight = in_flight.clone() - debit.clone();
+    });
+    HOT_WALLET_BALANCE.with(|h| {
+        let mut hot = h.borrow_mut();
+        *hot = hot.clone() + debit.clone();
+    });
+}
+
+/// Checks the band and returns the configured hot wallet address
+pub(crate) fn check_reserve_band(hot_min: &Nat, hot_target: &Nat, hot_max: &Nat) -> Result<String, String> {
+    if !(hot_min <= hot_target && hot_target <= hot_max) {
+        return Err("Policy band must satisfy hot_min <= hot_target <= hot_max".to_string());
+    }
+    SWEEP_CONFIG.with(|c| c.borrow().hot_wallet_address.clone())
+        .ok_or("Configure the hot wallet address with set_sweep_config first".to_string())
+}
+
+/// Configure the hot wallet band (Admin role, timelocked)
+///
+/// Verifies with threshold_signer that `hot_wallet_owner` actually derives the
+/// configured hot wallet address before queueing the policy.
+#[update]
+pub(crate) async fn set_reserve_policy(
+    enabled: bool,
+    hot_wallet_owner: Principal,
+    hot_min: Nat,
+    hot_target: Nat,
+    hot_max: Nat
+) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Setting the reserve policy requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    let hot_wallet = check_reserve_band(&hot_min, &hot_target, &hot_max)?;
+
+    let signer = Principal::from_text(THRESHOLD_SIGNER_CANISTER_ID)
+        .map_err(|e| format!("Invalid threshold signer canister ID: {}", e))?;
+    let derived: Result<(Result<AlgorandAddress, SigningError>,), _> =
+        ic_cdk::call(signer, "derive_algorand_address", (hot_wallet_owner,)).await;
+
+    match derived {
+        Ok((Ok(address),)) if address.address == hot_wallet => {}
+        Ok((Ok(address),)) => {
+            return Err(format!(
+                "Hot wallet owner {} derives {}, not the configured hot wallet {}",
+                hot_wallet_owner, address.address, hot_wallet
+            ));
+        }
+        Ok((Err(e),)) => return Err(format!("Address derivation failed: {}", e.message)),
+        Err((code, msg)) => {
+            return Err(format!("Inter-canister call to threshold signer failed: {:?} - {}", code, msg));
+        }
+    }
+
+    let policy = ReservePolicy {
+        enabled,
+        hot_wallet_owner: Some(hot_wallet_owner),
+        hot_min,
+        hot_target,
+        hot_max,
+    };
+    Ok(queue_config_change(ConfigChange::ReservePolicy(policy), caller_principal))
+}
+
+pub(crate) fn check_add_cold_wallet(address: &str) -> Result<(), String> {
+    decode_algorand_address(address)?;
+    if COLD_WALLETS.with(|wallets| wallets.borrow().iter().any(|w| w.address == address)) {
+        return Err(format!("Cold wallet {} already registered", address));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_remove_cold_wallet(address: &str) -> Result<ColdWallet, String> {
+    let wallet = COLD_WALLETS.with(|wallets| wallets.borrow().iter().find(|w| w.address == address).cloned())
+        .ok_or(format!("Cold wallet {} not found", address))?;
+    if !wallet.balance.0.is_zero() {
+        return Err(format!("Cold wallet {} still holds {} microALGO", address, wallet.balance));
+    }
+    Ok(wallet)
+}
+
+/// Register an offline cold reserve address (Admin role)
+#[update]
+pub(crate) fn add_cold_wallet(address: String, label: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Adding cold wallets requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_add_cold_wallet(&address)?;
+
+    COLD_WALLETS.with(|wallets| {
+        wallets.borrow_mut().push(ColdWallet {
+            address: address.clone(),
+            label: label.clone(),
+            balance: Nat::from(0u64),
+        })
+    });
+    Ok(format!("Cold wallet {} ({}) registered", address, label))
+}
+
+/// Remove an empty cold reserve address (Admin role)
+#[update]
+pub(crate) fn remove_cold_wallet(address: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Removing cold wallets requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_remove_cold_wallet(&address)?;
+
+    COLD_WALLETS.with(|wallets| wallets.borrow_mut().retain(|w| w.address != address));
+    Ok(format!("Cold wallet {} removed", address))
+}
+
+/// Evaluate the policy and sign any pending hot -> cold move now
+/// Requires the Operator role
+#[update]
+pub(crate) async fn run_reserve_rebalance() -> Result<Option<u64>, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: running rebalances requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    run_rebalance_cycle().await
+}
+
+/// Approve a cold -> hot rebalance (Admin role)
+#[update]
+pub(crate) fn approve_rebalance(proposal_id: u64) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Approving cold -> hot rebalances requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    update_rebalance_proposal(proposal_id, |proposal| {
+        if proposal.status != RebalanceStatus::PendingApproval {
+            return Err(format!("Rebalance {} is {:?}, not pending approval", proposal_id, proposal.status));
+        }
+        proposal.status = RebalanceStatus::Approved;
+        proposal.approved_by = Some(caller_principal);
+        proposal.updated_at = time();
+        Ok(format!(
+            "Rebalance {} approved: move {} microALGO from {} to the hot wallet",
+            proposal_id, proposal.amount, proposal.cold_address
+        ))
+    })
+}
+
+/// Reject a proposal that has not moved funds yet (Admin role)
+#[update]
+pub(crate) fn reject_rebalance(proposal_id: u64, reason: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Rejecting rebalances requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    update_rebalance_proposal(proposal_id, |proposal| {
+        if !matches!(
+            proposal.status,
+            RebalanceStatus::Proposed | RebalanceStatus::PendingApproval | RebalanceStatus::Approved
+        ) {
+            return Err(format!("Rebalance {} is {:?} and can no longer be rejected", proposal_id, proposal.status));
+        }
+        proposal.status = RebalanceStatus::Rejected;
+        proposal.error = Some(reason.clone());
+        proposal.updated_at = time();
+        Ok(format!("Rebalance {} rejected: {}", proposal_id, reason))
+    })
+}
+
+/// Report a rebalance transfer as confirmed on Algorand
+/// Requires the Operator role
+#[update]
+pub(crate) fn complete_rebalance(proposal_id: u64, algorand_tx_id: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: completing rebalances requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    let proposal = update_rebalance_proposal(proposal_id, |proposal| {
+        let ready = match proposal.direction {
+            RebalanceDirection::HotToCold => proposal.status == RebalanceStatus::Signed,
+            RebalanceDirection::ColdToHot => proposal.status == RebalanceStatus::Approved,
+        };
+        if !ready {
+            return Err(format!("Rebalance {} is {:?} and cannot be completed", proposal_id, proposal.status));
+        }
+        if let Some(expected) = &proposal.algorand_tx_id {
+            if *expected != algorand_tx_id {
+                return Err(format!("Transaction {} does not match signed rebalance {}", algorand_tx_id, expected));
+            }
+        }
+        proposal.status = RebalanceStatus::Completed;
+        proposal.algorand_tx_id = Some(algorand_tx_id.clone());
+        proposal.updated_at = time();
+        Ok(proposal.clone())
+    })?;
+
+    match proposal.direction {
+        RebalanceDirection::HotToCold => {
+            IN_FLIGHT_REBALANCE.with(|f| {
+                let mut in_flight = f.borrow_mut();
+                *in_flight = in_flight.clone() - proposal.amount.clone() - proposal.fee.clone();
+            });
+            COLD_WALLETS.with(|wallets| {
+                if let Some(w) = wallets.borrow_mut().iter_mut().find(|w| w.address == proposal.cold_address) {
+                    w.balance = w.balance.clone() + proposal.amount.clone();
+                }
+            });
+            LOCKED_ALGO_RESERVES.with(|reserves| {
+                let mut locked = reserves.borrow_mut();
+                *locked = locked.clone() - proposal.fee.clone();
+            });
+        }
+        RebalanceDirection::ColdToHot => {
+            COLD_WALLETS.with(|wallets| {
+                if let Some(w) = wallets.borrow_mut().iter_mut().find(|w| w.address == proposal.cold_address) {
+                    w.balance = w.balance.clone() - proposal.amount.clone();
+                }
+            });
+            HOT_WALLET_BALANCE.with(|h| {
+                let mut hot = h.borrow_mut();
+                *hot = hot.clone() + proposal.amount.clone();
+            });
+        }
+    }
+    certify_state(&[]);
+
+    Ok(format!("Rebalance {} completed ({})", proposal_id, algorand_tx_id))
+}
+
+/// Report a signed hot -> cold transfer as failed and release its amount
+/// Requires the Operator role
+#[update]
+pub(crate) fn fail_rebalance(proposal_id: u64, reason: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: failing rebalances requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    let proposal = update_rebalance_proposal(proposal_id, |proposal| {
+        if proposal.status != RebalanceStatus::Signed {
+            return Err(format!("Rebalance {} is {:?}, not signed", proposal_id, proposal.status));
+        }
+        proposal.status = RebalanceStatus::Failed;
+        proposal.error = Some(reason.clone());
+        proposal.updated_at = time();
+        Ok(proposal.clone())
+    })?;
+
+    release_in_flight_rebalance(&(proposal.amount + proposal.fee));
+
+    Ok(format!("Rebalance {} marked failed: {}", proposal_id, reason))
+}
+
+pub(crate) fn record_redemption(user: Principal, amount: &Nat, destination: &str, hot_wallet_reserved: Nat) -> u64 {
+    let now = time();
+    REDEMPTIONS.with(|redemptions| {
+        let mut redemptions = redemptions.borrow_mut();
+        let redemption_id = redemptions.last_key_value().map(|(id, _)| id + 1).unwrap_or(0);
+        redemptions.insert(redemption_id, StableCandid(Redemption {
+            redemption_id,
+            user,
+            amount: amount.clone(),
+            destination: destination.to_string(),
+            hot_wallet_reserved,
+            status: RedemptionStatus::Pending,
+            created_at: now,
+            updated_at: now,
+            algorand_tx_id: None,
+            error: None,
+        }));
+        redemption_id
+    })
+}
+
+/// Move a pending redemption to `status`, returning it as it was while pending
+pub(crate) fn resolve_redemption(
+    redemption_id: u64,
+    status: RedemptionStatus,
+    algorand_tx_id: Option<String>,
+    error: Option<String>,
+) -> Result<Redemption, String> {
+    REDEMPTIONS.with(|redemptions| {
+        let mut redemptions = redemptions.borrow_mut();
+        let mut redemption = redemptions.get(&redemption_id)
+            .ok_or(format!("Redemption {} not found", redemption_id))?.0;
+        if redemption.status != RedemptionStatus::Pending {
+            return Err(format!("Redemption {} is already {:?}", redemption_id, redemption.status));
+        }
+        let pending = redemption.clone();
+        redemption.status = status;
+        redemption.algorand_tx_id = algorand_tx_id;
+        redemption.error = error;
+        redemption.updated_at = time();
+        redemptions.insert(redemption_id, StableCandid(redemption));
+        Ok(pending)
+    })
+}
+
+/// Report the ALGO payout of a redemption as confirmed
+/// Requires the Operator role
+#[update]
+pub(crate) fn complete_redemption(redemption_id: u64, algorand_tx_id: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: completing redemptions requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    resolve_redemption(redemption_id, RedemptionStatus::Completed, Some(algorand_tx_id.clone()), None)?;
+    Ok(format!("Redemption {} completed ({})", redemption_id, algorand_tx_id))
+}
+
+/// Report that a redemption's ALGO payout failed: the burned ckALGO is minted
+/// back to the user and the reserved hot wallet liquidity is released
+/// Requires the Operator role
+#[update]
+pub(crate) fn fail_redemption(redemption_id: u64, reason: String) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: failing redemptions requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    let redemption = resolve_redemption(redemption_id, RedemptionStatus::Failed, None, Some(reason.clone()))?;
+
+    let user_str = redemption.user.to_text();
+    set_balance(&user_str, balance_of(&user_str) + redemption.amount.clone());
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = total.clone() + redemption.amount.clone();
+    });
+    LOCKED_ALGO_RESERVES.with(|reserves| {
+        let mut locked = reserves.borrow_mut();
+        *locked = locked.clone() + redemption.amount.clone();
+    });
+    release_hot_wallet_liquidity(&redemption.hot_wallet_reserved);
+    certify_state(&[&user_str]);
+
+    Ok(format!("Redemption {} failed, {} ckALGO returned to {}: {}", redemption_id, redemption.amount, user_str, reason))
+}
+
+/// Redemptions waiting for their ALGO payout
+#[query]
+pub(crate) fn get_pending_redemptions() -> Vec<Redemption> {
+    REDEMPTIONS.with(|redemptions| {
+        redemptions.borrow()
+            .iter()
+            .map(|(_, redemption)| redemption.0)
+            .filter(|r| r.status == RedemptionStatus::Pending)
+            .collect()
+    })
+}
+
+#[query]
+pub(crate) fn get_reserve_split() -> ReserveSplit {
+    let cold_wallets = COLD_WALLETS.with(|c| c.borrow().clone());
+    let cold_total = cold_wallets.iter().fold(Nat::from(0u64), |acc, w| acc + w.balance.clone());
+
+    ReserveSplit {
+        policy: RESERVE_POLICY.with(|p| p.borrow().clone()),
+        hot_wallet_address: SWEEP_CONFIG.with(|c| c.borrow().hot_wallet_address.clone()),
+        hot_balance: HOT_WALLET_BALANCE.with(|h| h.borrow().clone()),
+        cold_wallets,
+        cold_total,
+        custody_total: CUSTODY_BALANCES.with(|b| {
+            b.borrow().values().fold(Nat::from(0u64), |acc, v| acc + v.0)
+        }),
+        in_flight_sweeps: IN_FLIGHT_SWEEPS.with(|f| f.borrow().clone()),
+        in_flight_rebalance: IN_FLIGHT_REBALANCE.with(|f| f.borrow().clone()),
+        locked_algo_reserves: LOCKED_ALGO_RESERVES.with(|r| r.borrow().clone()),
+    }
+}
+
+/// Query rebalance proposals (most recent first)
+#[query]
+pub(crate) fn get_rebalance_proposals(limit: Option<u32>) -> Vec<RebalanceProposal> {
+    let limit = limit.unwrap_or(100) as usize;
+    REBALANCE_PROPOSALS.with(|proposals| {
+        proposals.borrow().iter().rev().take(limit).map(|(_, proposal)| proposal.0).collect()
+    })
+}
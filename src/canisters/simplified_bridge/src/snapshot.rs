@@ -0,0 +1,753 @@
+use super::*;
+
+// ============================================================================
+// STATE SNAPSHOT TYPES
+// ============================================================================
+
+/// Entries per export_state page. No entry is more than a few KB, so a page
+/// stays well under the ingress limit when it is passed on to import_state.
+pub(crate) const STATE_PAGE_ENTRIES: usize = 500;
+
+/// Export pages hashed per timer tick after prepare_state_export
+pub(crate) const STATE_EXPORT_HASH_BATCH_PAGES: usize = 20;
+
+/// Delay before a timelocked config change held back by an export is retried
+pub(crate) const STATE_EXPORT_RETRY_SECS: u64 = 60;
+
+/// A section of a snapshot. Snapshots are paged in this order: the heap state
+/// as a single page, then each stable structure collection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum StateCollection {
+    Heap,
+    Balances,
+    PendingDeposits,
+    DepositRecords,
+    SwapRecords,
+    ProcessedSwapDeposits,
+    ProcessedSwapDepositLog,
+    TreasuryWithdrawals,
+    Redemptions,
+    DepositAddresses,
+    CustodyBalances,
+    SweepRecords,
+    AutoSwapPreferences,
+    CustodyCredited,
+    CustodyCreditedThrough,
+    SwapRefunds,
+    AdminOperations,
+    ConfigChanges,
+    RebalanceProposals,
+}
+
+pub(crate) const STATE_COLLECTIONS: [StateCollection; 19] = [
+    StateCollection::Heap,
+    StateCollection::Balances,
+    StateCollection::PendingDeposits,
+    StateCollection::DepositRecords,
+    StateCollection::SwapRecords,
+    StateCollection::ProcessedSwapDeposits,
+    StateCollection::ProcessedSwapDepositLog,
+    StateCollection::TreasuryWithdrawals,
+    StateCollection::Redemptions,
+    StateCollection::DepositAddresses,
+    StateCollection::CustodyBalances,
+    StateCollection::SweepRecords,
+    StateCollection::AutoSwapPreferences,
+    StateCollection::CustodyCredited,
+    StateCollection::CustodyCreditedThrough,
+    StateCollection::SwapRefunds,
+    StateCollection::AdminOperations,
+    StateCollection::ConfigChanges,
+    StateCollection::RebalanceProposals,
+];
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct StateSnapshotInfo {
+    pub version: u32,
+    pub created_at: u64,  // Identifies the snapshot across export_state pages
+    pub pages: u64,  // Hashed so far
+    pub entries: u64,
+    // Over every page's collection and SHA-256 in order, once all are hashed
+    pub sha256: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct StateExportPage {
+    pub created_at: u64,
+    pub collection: StateCollection,
+    pub cursor: Option<Vec<u8>>,
+    pub next_cursor: Option<Vec<u8>>,  // None on the collection's last page
+    pub entries: u64,
+    pub data: Vec<u8>,  // Candid-encoded entries; the StateEnvelope for Heap
+    pub sha256: Vec<u8>,  // Of data
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct StateImportStatus {
+    pub pages: u64,
+    pub entries: u64,
+    // The page import_state expects next; None once the snapshot is applied
+    pub next_collection: Option<StateCollection>,
+    pub next_cursor: Option<Vec<u8>>,
+    pub applied: bool,
+    pub source_version: Option<u32>,  // Set by the Heap page
+    // Set once the last page is verified and applied
+    pub accounts: Option<u64>,
+    pub total_supply: Option<Nat>,
+}
+
+/// Snapshot being exported: the heap state frozen by prepare_state_export and
+/// the page hashes folded into the snapshot hash so far
+pub struct StateExport {
+    pub info: StateSnapshotInfo,
+    pub maintenance_since: u64,  // The export is void once maintenance mode is left
+    pub heap: Vec<u8>,
+    pub hasher: Sha256,
+    pub next_page: Option<(StateCollection, Option<Vec<u8>>)>,  // Next page to hash
+}
+
+/// Snapshot pages applied so far by import_state
+pub struct StateImport {
+    pub snapshot_sha256: Vec<u8>,
+    pub created_at: u64,
+    pub hasher: Sha256,
+    pub heap: Option<StableStorage>,  // Restored after the last page
+    pub balance_sum: Nat,
+    pub last_page: Option<(StateCollection, Option<Vec<u8>>, Vec<u8>)>,  // Accepted again as a retry
+    pub status: StateImportStatus,
+}
+
+// CRITICAL FIX 2: Stable storage structure for canister upgrades
+// Only the heap state: the collections in stable structures (see STABLE MEMORY
+// LAYOUT) are never serialized. The few collections kept here stay bounded:
+// unapproved admin actions are pruned to APPROVAL_WINDOW_SECS, pending reverse
+// swaps are capped at MAX_PENDING_REVERSE_SWAPS, treasury fees and swap backing
+// hold one entry per pair and rate rejections keep the last MAX_RATE_REJECTIONS.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct StableStorage {
+    pub total_supply: Nat,
+    pub locked_algo_reserves: Nat,
+    pub reserve_health_status: bool,
+    pub last_reserve_check: u64,
+    pub roles: Vec<(Principal, Vec<Role>)>,
+    pub approval_config: ApprovalConfig,
+    pub unapproved_admin_actions: Vec<UnapprovedAdminAction>,
+    pub config_timelock_delay_secs: u64,
+    pub pause_bitmap: u32,
+    pub pause_records: Vec<PauseRecord>,
+    pub governance_canister: Option<Principal>,
+    // Swap pair registry and reverse swaps
+    pub swap_enabled: bool,
+    pub swap_pairs: Vec<SwapPair>,
+    pub swap_backing: Vec<(Principal, SwapBacking)>,
+    pub pending_reverse_swaps: Vec<PendingReverseSwap>,
+    pub next_reverse_swap_id: u64,
+    // XRC rate quality guards and cache
+    pub rate_guard_config: RateGuardConfig,
+    pub last_accepted_rates: Vec<(String, AcceptedRate)>,
+    pub rate_circuit_breaker: Option<String>,
+    pub rate_rejections: Vec<RateRejection>,
+    pub rate_cache_config: RateCacheConfig,
+    // Custody deposit watcher
+    pub deposit_watcher_config: DepositWatcherConfig,
+    pub deposit_watcher_cursor: Option<Principal>,
+    // Swap fee treasury
+    pub treasury_account: Option<Principal>,
+    pub treasury_fees: Vec<(Principal, Nat)>,
+    // Custody sweep state
+    pub sweep_config: SweepConfig,
+    pub in_flight_sweeps: Nat,
+    pub hot_wallet_balance: Nat,
+    // Hot/cold reserve split
+    pub reserve_policy: ReservePolicy,
+    pub cold_wallets: Vec<ColdWallet>,
+    pub in_flight_rebalance: Nat,
+}
+
+/// StableStorage as of v1, the single stable_save blob: every collection
+/// inline and the swap settings as flat ckETH fields. Only decoded to migrate.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StableStorageV1 {
+    pub pending_deposits: Vec<(String, PendingDeposit)>,
+    pub deposit_records: Vec<DepositRecord>,
+    pub deposit_addresses: Vec<(String, Principal)>,
+    pub balances: Vec<(String, Nat)>,
+    pub total_supply: Nat,
+    pub locked_algo_reserves: Nat,
+    pub authorized_minters: Vec<Principal>,
+    pub reserve_health_status: bool,
+    pub last_reserve_check: u64,
+    pub swap_enabled: Option<bool>,
+    pub swap_fee_bps: Option<u64>,
+    pub min_swap_cketh: Option<Nat>,
+    pub max_swap_cketh: Option<Nat>,
+    pub swap_records: Option<Vec<SwapRecord>>,
+    pub cketh_backed_ckalgo: Option<Nat>,
+    pub total_cketh_received: Option<Nat>,
+    pub processed_swap_deposits: Option<Vec<String>>,
+}
+
+// ============================================================================
+// STATE SNAPSHOTS (disaster recovery)
+// ============================================================================
+//
+// A snapshot is the heap StateEnvelope plus every stable structure collection,
+// so a reinstalled canister can be rebuilt from it. It moves in pages of at
+// most STATE_PAGE_ENTRIES, one collection at a time in STATE_COLLECTIONS order,
+// each page carrying the cursor of the next, so no call handles more than one
+// page. A page's SHA-256 covers its data; the snapshot hash chains the
+// collection and SHA-256 of every page in order.
+//
+// Export only runs in maintenance mode (every PauseClass paused). That stops
+// the paused operations, but settlements, config changes and the timers would
+// still write; while an export holds, ensure_no_state_export refuses them, so
+// every page comes from the moment the export was prepared. It holds until
+// maintenance mode ends. prepare_state_export freezes the heap state and hashes
+// the pages in timer batches; get_state_export_info returns the snapshot hash
+// through consensus once that is done. export_state serves the pages as cheap
+// queries, to be checked against that hash.
+//
+// import_state only starts on a fresh canister in maintenance mode. It takes
+// the pages in order along with the source's snapshot hash, checks each page
+// against its SHA-256 and applies it straight into the stable structures. The
+// last page checks the snapshot hash and total supply, then restores the heap
+// state and leaves the canister paused until an Admin unpauses it. A failed
+// check leaves a partial import behind: reinstall the canister and start over.
+
+/// No ckALGO has been issued and every collection in STATE_COLLECTIONS is empty
+pub(crate) fn is_fresh_canister() -> bool {
+    TOTAL_SUPPLY.with(|s| s.borrow().0.is_zero()) && STATE_COLLECTIONS.iter().all(|collection| collection_is_empty(*collection))
+}
+
+/// Whether a stable structure collection holds no entries. The heap state
+/// always has a value, so it counts as empty here.
+pub(crate) fn collection_is_empty(collection: StateCollection) -> bool {
+    match collection {
+        StateCollection::Heap => true,
+        StateCollection::Balances => BALANCES.with(|b| b.borrow().is_empty()),
+        StateCollection::PendingDeposits => PENDING_DEPOSITS.with(|d| d.borrow().is_empty()),
+        StateCollection::DepositRecords => DEPOSIT_RECORDS.with(|r| r.borrow().is_empty()),
+        StateCollection::SwapRecords => SWAP_RECORDS.with(|r| r.borrow().is_empty()),
+        StateCollection::ProcessedSwapDeposits => PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow().is_empty()),
+        StateCollection::ProcessedSwapDepositLog => PROCESSED_SWAP_DEPOSIT_LOG.with(|l| l.borrow().is_empty()),
+        StateCollection::TreasuryWithdrawals => TREASURY_WITHDRAWALS.with(|w| w.borrow().is_empty()),
+        StateCollection::Redemptions => REDEMPTIONS.with(|r| r.borrow().is_empty()),
+        StateCollection::DepositAddresses => DEPOSIT_ADDRESSES.with(|a| a.borrow().is_empty()),
+        StateCollection::CustodyBalances => CUSTODY_BALANCES.with(|b| b.borrow().is_empty()),
+        StateCollection::SweepRecords => SWEEP_RECORDS.with(|r| r.borrow().is_empty()),
+        StateCollection::AutoSwapPreferences => AUTO_SWAP_PREFERENCES.with(|p| p.borrow().is_empty()),
+        StateCollection::CustodyCredited => CUSTODY_CREDITED.with(|c| c.borrow().is_empty()),
+        StateCollection::CustodyCreditedThrough => CUSTODY_CREDITED_THROUGH.with(|c| c.borrow().is_empty()),
+        StateCollection::SwapRefunds => SWAP_REFUNDS.with(|r| r.borrow().is_empty()),
+        StateCollection::AdminOperations => ADMIN_OPERATIONS.with(|o| o.borrow().is_empty()),
+        StateCollection::ConfigChanges => CONFIG_CHANGES.with(|c| c.borrow().is_empty()),
+        StateCollection::RebalanceProposals => REBALANCE_PROPOSALS.with(|p| p.borrow().is_empty()),
+    }
+}
+
+
+pub(crate) fn in_maintenance_mode() -> bool {
+    ALL_PAUSE_CLASSES.iter().all(|class| is_paused(*class))
+}
+
+/// When the current maintenance mode began (the latest pause), or None outside it
+pub(crate) fn maintenance_since() -> Option<u64> {
+    if !in_maintenance_mode() {
+        return None;
+    }
+    Some(PAUSE_RECORDS.with(|r| r.borrow().values().map(|record| record.paused_at).max().unwrap_or(0)))
+}
+
+/// True while a prepared export holds, which is until maintenance mode ends
+pub(crate) fn state_export_in_progress() -> bool {
+    STATE_EXPORT.with(|e| e.borrow().as_ref().is_some_and(|export| check_export_current(export).is_ok()))
+}
+
+/// Refuse a state change while an export is in progress. Every mutating
+/// endpoint without a pause check, and every timer, calls this.
+pub(crate) fn ensure_no_state_export() -> Result<(), String> {
+    if state_export_in_progress() {
+        return Err("State is frozen for an export: unpause to leave maintenance mode first".to_string());
+    }
+    Ok(())
+}
+
+/// One page of a collection: its candid-encoded entries and, if it is full,
+/// the cursor of the page after it
+pub(crate) struct StatePage {
+    pub(crate) data: Vec<u8>,
+    pub(crate) entries: u64,
+    pub(crate) next_cursor: Option<Vec<u8>>,
+}
+
+impl StatePage {
+    fn new<T: CandidType>(entries: Vec<T>, next_cursor: Option<Vec<u8>>) -> Self {
+        StatePage {
+            data: candid::encode_one(&entries).expect("Failed to encode state page"),
+            entries: entries.len() as u64,
+            next_cursor,
+        }
+    }
+}
+
+pub(crate) fn encode_cursor<K: CandidType>(key: &K) -> Vec<u8> {
+    candid::encode_one(key).expect("Failed to encode cursor")
+}
+
+pub(crate) fn decode_cursor<K: CandidType + for<'de> Deserialize<'de>>(cursor: &[u8]) -> Result<K, String> {
+    candid::decode_one(cursor).map_err(|e| format!("Invalid cursor: {}", e))
+}
+
+/// Map entries after the key in `cursor`. A full page's cursor is its last key.
+pub(crate) fn map_page<K, V, T>(
+    map: &StableBTreeMap<K, V, Memory>,
+    cursor: Option<&[u8]>,
+    entry: impl Fn(K, V) -> T,
+) -> Result<StatePage, String>
+where
+    K: Storable + Ord + Clone + CandidType + for<'de> Deserialize<'de>,
+    V: Storable,
+    T: CandidType,
+{
+    let rows: Vec<(K, V)> = match cursor {
+        Some(cursor) => map.range((Excluded(decode_cursor::<K>(cursor)?), Unbounded)).take(STATE_PAGE_ENTRIES).collect(),
+        None => map.iter().take(STATE_PAGE_ENTRIES).collect(),
+    };
+    let next_cursor = if rows.len() == STATE_PAGE_ENTRIES { rows.last().map(|(key, _)| encode_cursor(key)) } else { None };
+    Ok(StatePage::new(rows.into_iter().map(|(key, value)| entry(key, value)).collect(), next_cursor))
+}
+
+/// Set keys after the one in `cursor`
+pub(crate) fn set_page(set: &StableBTreeSet<String, Memory>, cursor: Option<&[u8]>) -> Result<StatePage, String> {
+    let keys: Vec<String> = match cursor {
+        Some(cursor) => set.range((Excluded(decode_cursor::<String>(cursor)?), Unbounded)).take(STATE_PAGE_ENTRIES).collect(),
+        None => set.iter().take(STATE_PAGE_ENTRIES).collect(),
+    };
+    let next_cursor = if keys.len() == STATE_PAGE_ENTRIES { keys.last().map(encode_cursor) } else { None };
+    Ok(StatePage::new(keys, next_cursor))
+}
+
+/// Log entries from the index in `cursor`. A full page's cursor is the index after it.
+pub(crate) fn log_page<T: CandidType + for<'de> Deserialize<'de>>(
+    log: &StableRecordLog<T>,
+    cursor: Option<&[u8]>,
+) -> Result<StatePage, String> {
+    let start = cursor.map(decode_cursor::<u64>).transpose()?.unwrap_or(0);
+    let end = start.saturating_add(STATE_PAGE_ENTRIES as u64).min(log.len());
+    let entries: Vec<T> = (start..end).filter_map(|index| log.get(index)).map(|entry| entry.0).collect();
+    let next_cursor = (entries.len() == STATE_PAGE_ENTRIES).then(|| encode_cursor(&end));
+    Ok(StatePage::new(entries, next_cursor))
+}
+
+/// Read the page of a stable structure collection at `cursor`, as the entries
+/// of the matching StateCollections field
+pub(crate) fn read_state_page(collection: StateCollection, cursor: Option<&[u8]>) -> Result<StatePage, String> {
+    match collection {
+        StateCollection::Heap => Err("The heap state is a single page frozen by prepare_state_export".to_string()),
+        StateCollection::Balances => BALANCES.with(|b| map_page(&b.borrow(), cursor, |account, balance| (account, balance.0))),
+        StateCollection::PendingDeposits => {
+            PENDING_DEPOSITS.with(|d| map_page(&d.borrow(), cursor, |tx_id, deposit| (tx_id, deposit.0)))
+        }
+        StateCollection::DepositRecords => DEPOSIT_RECORDS.with(|r| log_page(&r.borrow(), cursor)),
+        StateCollection::SwapRecords => SWAP_RECORDS.with(|r| log_page(&r.borrow(), cursor)),
+        StateCollection::ProcessedSwapDeposits => PROCESSED_SWAP_DEPOSITS.with(|d| set_page(&d.borrow(), cursor)),
+        StateCollection::ProcessedSwapDepositLog => PROCESSED_SWAP_DEPOSIT_LOG.with(|l| log_page(&l.borrow(), cursor)),
+        StateCollection::TreasuryWithdrawals => TREASURY_WITHDRAWALS.with(|w| log_page(&w.borrow(), cursor)),
+        StateCollection::Redemptions => REDEMPTIONS.with(|r| map_page(&r.borrow(), cursor, |_, redemption| redemption.0)),
+        StateCollection::DepositAddresses => DEPOSIT_ADDRESSES.with(|a| map_page(&a.borrow(), cursor, |address, owner| (address, owner))),
+        StateCollection::CustodyBalances => {
+            CUSTODY_BALANCES.with(|b| map_page(&b.borrow(), cursor, |address, balance| (address, balance.0)))
+        }
+        StateCollection::SweepRecords => SWEEP_RECORDS.with(|r| map_page(&r.borrow(), cursor, |_, record| record.0)),
+        StateCollection::AutoSwapPreferences => {
+            AUTO_SWAP_PREFERENCES.with(|p| map_page(&p.borrow(), cursor, |_, preference| preference.0))
+        }
+        StateCollection::CustodyCredited => CUSTODY_CREDITED.with(|c| map_page(&c.borrow(), cursor, |key, amount| (key, amount.0))),
+        StateCollection::CustodyCreditedThrough => {
+            CUSTODY_CREDITED_THROUGH.with(|c| map_page(&c.borrow(), cursor, |key, log_length| (key, log_length.0)))
+        }
+        StateCollection::SwapRefunds => SWAP_REFUNDS.with(|r| log_page(&r.borrow(), cursor)),
+        StateCollection::AdminOperations => ADMIN_OPERATIONS.with(|o| map_page(&o.borrow(), cursor, |_, operation| operation.0)),
+        StateCollection::ConfigChanges => CONFIG_CHANGES.with(|c| map_page(&c.borrow(), cursor, |_, change| change.0)),
+        StateCollection::RebalanceProposals => {
+            REBALANCE_PROPOSALS.with(|p| map_page(&p.borrow(), cursor, |_, proposal| proposal.0))
+        }
+    }
+}
+
+pub(crate) fn decode_page_entries<T: CandidType + for<'de> Deserialize<'de>>(data: &[u8], entries: &mut u64) -> Result<Vec<T>, String> {
+    let page: Vec<T> = candid::decode_one(data).map_err(|e| format!("Failed to decode state page: {}", e))?;
+    *entries = page.len() as u64;
+    Ok(page)
+}
+
+/// Decode a page read by read_state_page, with its number of entries
+pub(crate) fn decode_state_page(collection: StateCollection, data: &[u8]) -> Result<(StateCollections, u64), String> {
+    let mut page = StateCollections::default();
+    let mut entries = 0;
+    match collection {
+        StateCollection::Heap => return Err("The heap state page is not a collection".to_string()),
+        StateCollection::Balances => page.balances = decode_page_entries(data, &mut entries)?,
+        StateCollection::PendingDeposits => page.pending_deposits = decode_page_entries(data, &mut entries)?,
+        StateCollection::DepositRecords => page.deposit_records = decode_page_entries(data, &mut entries)?,
+        StateCollection::SwapRecords => page.swap_records = decode_page_entries(data, &mut entries)?,
+        StateCollection::ProcessedSwapDeposits => page.processed_swap_deposits = decode_page_entries(data, &mut entries)?,
+        StateCollection::ProcessedSwapDepositLog => page.processed_swap_deposit_log = decode_page_entries(data, &mut entries)?,
+        StateCollection::TreasuryWithdrawals => page.treasury_withdrawals = decode_page_entries(data, &mut entries)?,
+        StateCollection::Redemptions => page.redemptions = decode_page_entries(data, &mut entries)?,
+        StateCollection::DepositAddresses => page.deposit_addresses = decode_page_entries(data, &mut entries)?,
+        StateCollection::CustodyBalances => page.custody_balances = decode_page_entries(data, &mut entries)?,
+        StateCollection::SweepRecords => page.sweep_records = decode_page_entries(data, &mut entries)?,
+        StateCollection::AutoSwapPreferences => page.auto_swap_preferences = decode_page_entries(data, &mut entries)?,
+        StateCollection::CustodyCredited => page.custody_credited = decode_page_entries(data, &mut entries)?,
+        StateCollection::CustodyCreditedThrough => page.custody_credited_through = decode_page_entries(data, &mut entries)?,
+        StateCollection::SwapRefunds => page.swap_refunds = decode_page_entries(data, &mut entries)?,
+        StateCollection::AdminOperations => page.admin_operations = decode_page_entries(data, &mut entries)?,
+        StateCollection::ConfigChanges => page.config_changes = decode_page_entries(data, &mut entries)?,
+        StateCollection::RebalanceProposals => page.rebalance_proposals = decode_page_entries(data, &mut entries)?,
+    }
+    Ok((page, entries))
+}
+
+/// Chain a page into a snapshot hash: its collection, then its SHA-256
+pub(crate) fn fold_page_hash(hasher: &mut Sha256, collection: StateCollection, page_sha256: &[u8]) {
+    hasher.update([collection as u8]);
+    hasher.update(page_sha256);
+}
+
+/// The page after one of `collection` that named `next_cursor`: None after the last page
+pub(crate) fn following_page(collection: StateCollection, next_cursor: Option<Vec<u8>>) -> Option<(StateCollection, Option<Vec<u8>>)> {
+    if next_cursor.is_some() {
+        return Some((collection, next_cursor));
+    }
+    let index = STATE_COLLECTIONS.iter().position(|c| *c == collection)?;
+    STATE_COLLECTIONS.get(index + 1).map(|next| (*next, None))
+}
+
+pub(crate) fn check_export_current(export: &StateExport) -> Result<(), String> {
+    if maintenance_since() != Some(export.maintenance_since) {
+        return Err("Maintenance mode was left since the export was prepared: prepare a new one".to_string());
+    }
+    Ok(())
+}
+
+/// The page of the export at `cursor` of `collection` (None for the first page)
+pub(crate) fn export_page(export: &StateExport, collection: StateCollection, cursor: Option<Vec<u8>>) -> Result<StateExportPage, String> {
+    let page = match collection {
+        StateCollection::Heap if cursor.is_none() => StatePage { data: export.heap.clone(), entries: 1, next_cursor: None },
+        _ => read_state_page(collection, cursor.as_deref())?,
+    };
+    Ok(StateExportPage {
+        created_at: export.info.created_at,
+        collection,
+        cursor,
+        next_cursor: page.next_cursor,
+        entries: page.entries,
+        sha256: Sha256::digest(&page.data).to_vec(),
+        data: page.data,
+    })
+}
+
+/// Freeze the heap state for a new export, to be hashed by hash_next_export_pages
+pub(crate) fn freeze_state_export() -> Result<StateSnapshotInfo, String> {
+    let maintenance_since = maintenance_since()
+        .ok_or("State export requires maintenance mode: pause every operation class first".to_string())?;
+    // Its timer batches would hash the new export's pages twice
+    if STATE_EXPORT.with(|e| e.borrow().as_ref().is_some_and(|export| export.next_page.is_some())) {
+        return Err("The previous export is still being hashed: retry shortly".to_string());
+    }
+
+    let info = StateSnapshotInfo {
+        version: STATE_VERSION,
+        created_at: time(),
+        pages: 0,
+        entries: 0,
+        sha256: None,
+    };
+    STATE_EXPORT.with(|e| {
+        *e.borrow_mut() = Some(StateExport {
+            info: info.clone(),
+            maintenance_since,
+            heap: encode_state_envelope(&snapshot_heap_state()),
+            hasher: Sha256::new(),
+            next_page: Some((StateCollection::Heap, None)),
+        })
+    });
+    Ok(info)
+}
+
+/// Hash the next `max` export pages into the snapshot hash. Returns true once
+/// there is nothing left to hash; an export that maintenance mode ended under
+/// is dropped.
+pub(crate) fn hash_next_export_pages(max: usize) -> bool {
+    STATE_EXPORT.with(|e| {
+        let mut current = e.borrow_mut();
+        let Some(export) = current.as_mut() else {
+            return true;
+        };
+        if check_export_current(export).is_err() {
+            *current = None;
+            return true;
+        }
+
+        for _ in 0..max {
+            let Some((collection, cursor)) = export.next_page.take() else {
+                break;
+            };
+            let page = export_page(export, collection, cursor).expect("Export cursors are written by the export itself");
+            fold_page_hash(&mut export.hasher, collection, &page.sha256);
+            export.info.pages += 1;
+            export.info.entries += page.entries;
+            export.next_page = following_page(collection, page.next_cursor);
+        }
+
+        let done = export.next_page.is_none();
+        if done && export.info.sha256.is_none() {
+            export.info.sha256 = Some(export.hasher.clone().finalize().to_vec());
+            ic_cdk::println!("State snapshot hashed: {} pages, {} entries", export.info.pages, export.info.entries);
+        }
+        done
+    })
+}
+
+pub(crate) fn schedule_export_hash_batch() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if !hash_next_export_pages(STATE_EXPORT_HASH_BATCH_PAGES) {
+            schedule_export_hash_batch();
+        }
+    });
+}
+
+/// Add imported balances to the running sum, refusing an account seen before
+pub(crate) fn add_imported_balances(sum: &mut Nat, balances: &[(String, Nat)]) -> Result<(), String> {
+    let mut accounts = HashSet::new();
+    for (account, balance) in balances {
+        if !accounts.insert(account) || BALANCES.with(|b| b.borrow().contains_key(account)) {
+            return Err(format!("Snapshot lists account {} twice", account));
+        }
+        *sum += balance.clone();
+    }
+    Ok(())
+}
+
+/// Check the snapshot hash and total supply once every page has been applied,
+/// then restore the heap state, keeping maintenance mode on
+pub(crate) fn finish_state_import(import: &mut StateImport) -> Result<(), String> {
+    if import.hasher.clone().finalize().as_slice() != import.snapshot_sha256.as_slice() {
+        return Err("Snapshot SHA-256 does not match the pages received".to_string());
+    }
+    let heap = import.heap.take().ok_or("The snapshot has no heap state".to_string())?;
+    if import.balance_sum != heap.total_supply {
+        return Err(format!(
+            "Snapshot balances sum to {} but total supply is {}",
+            import.balance_sum, heap.total_supply
+        ));
+    }
+
+    // Restoring brings back the snapshot's own pause state: keep maintenance mode on top of it
+    let maintenance_records = PAUSE_RECORDS.with(|r| r.borrow().clone());
+    restore_heap_state(heap);
+    PAUSE_RECORDS.with(|r| {
+        let mut records = r.borrow_mut();
+        for (class, record) in maintenance_records {
+            records.entry(class).or_insert(record);
+        }
+    });
+    PAUSE_BITMAP.with(|b| {
+        *b.borrow_mut() |= ALL_PAUSE_CLASSES.iter().fold(0, |bits, class| bits | pause_bit(*class))
+    });
+    strip_invalid_role_holders();
+
+    import.status.applied = true;
+    import.status.accounts = Some(BALANCES.with(|b| b.borrow().len()));
+    import.status.total_supply = Some(TOTAL_SUPPLY.with(|s| s.borrow().clone()));
+    Ok(())
+}
+
+/// Check and apply the next page of an import, returning its status and
+/// whether this page completed it. Timers and recertification are left to the
+/// caller.
+pub(crate) fn receive_state_page(snapshot_sha256: Vec<u8>, page: StateExportPage) -> Result<(StateImportStatus, bool), String> {
+    let page_sha256 = Sha256::digest(&page.data).to_vec();
+    if page_sha256 != page.sha256 {
+        return Err(format!("{:?} page does not match its SHA-256: fetch it again", page.collection));
+    }
+
+    STATE_IMPORT.with(|i| {
+        let mut current = i.borrow_mut();
+        if current.is_none() {
+            if page.collection != StateCollection::Heap || page.cursor.is_some() {
+                return Err("No import in progress: start with the Heap page".to_string());
+            }
+            if !is_fresh_canister() {
+                return Err("State can only be imported into a fresh canister".to_string());
+            }
+        }
+        let import = current.get_or_insert_with(|| StateImport {
+            snapshot_sha256: snapshot_sha256.clone(),
+            created_at: page.created_at,
+            hasher: Sha256::new(),
+            heap: None,
+            balance_sum: Nat::from(0u64),
+            last_page: None,
+            status: StateImportStatus {
+                pages: 0,
+                entries: 0,
+                next_collection: Some(StateCollection::Heap),
+                next_cursor: None,
+                applied: false,
+                source_version: None,
+                accounts: None,
+                total_supply: None,
+            },
+        });
+
+        if import.snapshot_sha256 != snapshot_sha256 || import.created_at != page.created_at {
+            return Err("Page belongs to a different snapshot than the import in progress".to_string());
+        }
+        // A retry of the page just applied
+        if import.last_page.as_ref() == Some(&(page.collection, page.cursor.clone(), page_sha256.clone())) {
+            return Ok((import.status.clone(), false));
+        }
+        if import.status.next_collection != Some(page.collection) || import.status.next_cursor != page.cursor {
+            return Err(match import.status.next_collection {
+                Some(collection) => format!(
+                    "Pages must be imported in order: expected the {:?} page at the previous page's next_cursor",
+                    collection
+                ),
+                None if import.status.applied => "The snapshot has already been applied".to_string(),
+                None => "The snapshot failed its final checks: reinstall the canister and import it again".to_string(),
+            });
+        }
+
+        let entries = match page.collection {
+            StateCollection::Heap => {
+                if page.next_cursor.is_some() {
+                    return Err("The heap state is a single page".to_string());
+                }
+                let MigratedState { source_version, state: heap, collections, .. } = decode_state_blob(&page.data)?;
+                // Only the v1 blob carries collections inline
+                add_imported_balances(&mut import.balance_sum, &collections.balances)?;
+                restore_state_collections(collections);
+                import.heap = Some(heap);
+                import.status.source_version = Some(source_version);
+                1
+            }
+            collection => {
+                let (collections, entries) = decode_state_page(collection, &page.data)?;
+                add_imported_balances(&mut import.balance_sum, &collections.balances)?;
+                restore_state_collections(collections);
+                entries
+            }
+        };
+
+        fold_page_hash(&mut import.hasher, page.collection, &page_sha256);
+        import.status.pages += 1;
+        import.status.entries += entries;
+        let (next_collection, next_cursor) = following_page(page.collection, page.next_cursor).unzip();
+        import.status.next_collection = next_collection;
+        import.status.next_cursor = next_cursor.flatten();
+        import.last_page = Some((page.collection, page.cursor, page_sha256));
+
+        if import.status.next_collection.is_some() {
+            return Ok((import.status.clone(), false));
+        }
+        finish_state_import(import)?;
+        Ok((import.status.clone(), true))
+    })
+}
+
+/// Freeze the heap state for an export and start hashing its pages; the
+/// snapshot hash comes from get_state_export_info once they are all hashed.
+/// Requires maintenance mode. Controllers only.
+#[update]
+pub(crate) fn prepare_state_export() -> Result<StateSnapshotInfo, String> {
+    let caller_principal = caller();
+    if !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only controllers can export state. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    let info = freeze_state_export()?;
+    schedule_export_hash_batch();
+
+    ic_cdk::println!("Preparing state snapshot v{} by {}", info.version, caller_principal);
+    Ok(info)
+}
+
+/// Progress of the export, with the snapshot hash once every page is hashed.
+/// An update call, so the hash comes through consensus. Controllers only.
+#[update]
+pub(crate) fn get_state_export_info() -> Result<StateSnapshotInfo, String> {
+    let caller_principal = caller();
+    if !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only controllers can export state. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    STATE_EXPORT.with(|e| {
+        let export = e.borrow();
+        let export = export.as_ref().ok_or("No snapshot prepared: call prepare_state_export first".to_string())?;
+        check_export_current(export)?;
+        Ok(export.info.clone())
+    })
+}
+
+/// Page of the prepared snapshot: start each collection with no cursor and
+/// follow next_cursor until it is None. Controllers only.
+#[query]
+pub(crate) fn export_state(collection: StateCollection, cursor: Option<Vec<u8>>) -> Result<StateExportPage, String> {
+    let caller_principal = caller();
+    if !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only controllers can export state. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    STATE_EXPORT.with(|e| {
+        let export = e.borrow();
+        let export = export.as_ref().ok_or("No snapshot prepared: call prepare_state_export first".to_string())?;
+        check_export_current(export)?;
+        export_page(export, collection, cursor)
+    })
+}
+
+/// Apply the next page of a snapshot, as returned by export_state, with the
+/// source's snapshot hash. Pages go in export order starting from the Heap
+/// page; resending the last page is harmless. The last page verifies the
+/// snapshot and restores the heap state. Requires maintenance mode.
+/// Controllers only.
+#[update]
+pub(crate) fn import_state(snapshot_sha256: Vec<u8>, page: StateExportPage) -> Result<StateImportStatus, String> {
+    let caller_principal = caller();
+    if !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only controllers can import state. Caller: {}",
+            caller_principal
+        ));
+    }
+    if !in_maintenance_mode() {
+        return Err("State import requires maintenance mode: pause every operation class first".to_string());
+    }
+
+    let (status, applied) = receive_state_page(snapshot_sha256, page)?;
+    if applied {
+        start_recertification();
+        schedule_sweep_timer();
+        schedule_rebalance_timer();
+        schedule_rate_refresh_timer();
+        schedule_deposit_watcher_timer();
+        schedule_config_timelock_timer();
+
+        ic_cdk::println!(
+            "Imported state snapshot v{} by {}: {} accounts",
+            status.source_version.unwrap_or(0), caller_principal, status.accounts.unwrap_or(0)
+        );
+    }
+    Ok(status)
+}
@@ -0,0 +1,2477 @@
+use super::*;
+
+// ============================================================================
+// EXCHANGE RATE CANISTER (XRC) TYPES
+// ============================================================================
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum AssetClass {
+    Cryptocurrency,
+    FiatCurrency,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Asset {
+    pub symbol: String,
+    pub class: AssetClass,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct GetExchangeRateRequest {
+    pub base_asset: Asset,
+    pub quote_asset: Asset,
+    pub timestamp: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ExchangeRateMetadata {
+    pub decimals: u32,
+    pub base_asset_num_received_rates: u64,
+    pub base_asset_num_queried_sources: u64,
+    pub quote_asset_num_received_rates: u64,
+    pub quote_asset_num_queried_sources: u64,
+    pub standard_deviation: u64,
+    pub forex_timestamp: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ExchangeRate {
+    pub base_asset: Asset,
+    pub quote_asset: Asset,
+    pub timestamp: u64,
+    pub rate: u64,
+    pub metadata: ExchangeRateMetadata,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ExchangeRateError {
+    AnonymousPrincipalNotAllowed,
+    Pending,
+    CryptoQuoteAssetNotFound,
+    CryptoBaseAssetNotFound,
+    StablecoinRateTooFewRates,
+    StablecoinRateZeroRate,
+    StablecoinRateNotFound,
+    ForexInvalidTimestamp,
+    ForexBaseAssetNotFound,
+    ForexQuoteAssetNotFound,
+    ForexAssetsNotFound,
+    RateLimited,
+    NotEnoughCycles,
+    FailedToAcceptCycles,
+    InconsistentRatesReceived,
+    Other { code: u32, description: String },
+}
+
+pub type GetExchangeRateResult = Result<ExchangeRate, ExchangeRateError>;
+
+// ============================================================================
+// RATE QUALITY GUARD TYPES
+// ============================================================================
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RateGuardConfig {
+    pub min_sources: u64,          // Minimum base_asset_num_received_rates per XRC rate
+    pub max_deviation_bps: u64,    // Max standard_deviation relative to the rate
+    pub max_age_secs: u64,         // Max age of the XRC rate timestamp
+    pub max_jump_bps: u64,         // Circuit breaker: max move vs the last accepted rate
+    pub jump_window_secs: u64,     // Only compare against a last rate younger than this
+}
+
+impl Default for RateGuardConfig {
+    fn default() -> Self {
+        RateGuardConfig {
+            min_sources: 3,
+            max_deviation_bps: 200,  // 2%
+            max_age_secs: 300,
+            max_jump_bps: 1_000,     // 10%
+            jump_window_secs: 3_600,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RateRejection {
+    pub timestamp: u64,
+    pub pair: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct AcceptedRate {
+    pub rate: RateFraction,
+    pub accepted_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RateCacheConfig {
+    pub ttl_secs: u64,          // Reuse an accepted rate for this long before asking XRC again
+    pub timer_refresh: bool,    // Proactively refresh every ttl_secs instead of on demand
+}
+
+impl Default for RateCacheConfig {
+    fn default() -> Self {
+        RateCacheConfig {
+            ttl_secs: 120,
+            timer_refresh: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct CachedAlgoRate {
+    pub asset: String,        // XRC symbol of the input asset, e.g. "ETH"
+    pub rate: f64,
+    pub rate_fraction: RateFraction,
+    pub fetched_at: u64,
+    pub age_secs: u64,
+    pub ttl_secs: u64,
+    pub fresh: bool,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RateGuardStatus {
+    pub config: RateGuardConfig,
+    pub last_accepted: Option<AcceptedRate>,
+    pub circuit_breaker_tripped: Option<String>,
+    pub recent_rejections: Vec<RateRejection>,
+    pub last_accepted_by_asset: Vec<(String, AcceptedRate)>,
+}
+
+// ============================================================================
+// SWAP DATA STRUCTURES
+// ============================================================================
+
+/// Exact <asset>/ALGO rate: ALGO per whole input token = numerator / denominator
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RateFraction {
+    pub numerator: Nat,
+    pub denominator: Nat,
+}
+
+impl RateFraction {
+    /// Lossy decimal value, for display only - never use in swap math
+    pub fn to_f64(&self) -> f64 {
+        self.numerator.0.to_f64().unwrap_or(0.0) / self.denominator.0.to_f64().unwrap_or(1.0)
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SwapRecord {
+    pub user: Principal,
+    pub cketh_in: Nat,        // Pair asset amount in its base units (paid out for FromCkAlgo)
+    pub ckalgo_out: Nat,      // ckALGO amount (burned for FromCkAlgo)
+    pub rate_used: f64,       // <asset>/ALGO rate at time of swap (display only)
+    pub fee_collected: Nat,   // Fee in microALGO
+    pub timestamp: u64,
+    pub tx_id: String,
+    pub rate: Option<RateFraction>,  // Exact rate used (None for swaps before exact math)
+    pub pair: Option<Principal>,     // Pair ledger (None for ckETH swaps before the pair registry)
+    pub direction: Option<SwapDirection>,  // None for swaps before reverse swaps (ToCkAlgo)
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum SwapDirection {
+    ToCkAlgo,    // Pair asset in, ckALGO minted
+    FromCkAlgo,  // ckALGO burned, pair asset paid from the treasury
+}
+
+/// Swap input asset, keyed by its ICRC ledger canister
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SwapPair {
+    pub ledger: Principal,
+    pub symbol: String,       // Display symbol, e.g. "ckETH"
+    pub xrc_symbol: String,   // XRC base asset priced against ALGO, e.g. "ETH"
+    pub decimals: u32,
+    pub fee_bps: u64,
+    pub min_amount: Nat,
+    pub max_amount: Nat,
+    pub enabled: bool,
+}
+
+/// Input asset held by the canister and the ckALGO minted against it
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SwapBacking {
+    pub total_received: Nat,
+    pub ckalgo_minted: Nat,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SwapPairStatus {
+    pub pair: SwapPair,
+    pub total_received: Nat,
+    pub ckalgo_backed: Nat,
+}
+
+/// Agent opt-in for automatic swaps of ckETH landing on its custody subaccount
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct AutoSwapPreference {
+    pub agent: Principal,
+    pub max_slippage_bps: u64,  // Max shortfall vs the rate known when the deposit was detected
+    pub enabled: bool,
+    pub updated_at: u64,
+}
+
+/// Swap deposit returned to its sender instead of being swapped
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SwapRefund {
+    pub refund_id: u64,
+    pub pair: Principal,
+    pub agent: Principal,
+    pub deposit_block_index: Nat,
+    pub deposit_amount: Nat,
+    pub refunded_to: Account,
+    pub amount_returned: Nat,   // deposit_amount - ledger_fee
+    pub ledger_fee: Nat,
+    pub refund_block_index: Nat,
+    pub reason: String,
+    pub requested_by: Principal,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct DepositWatcherConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub max_agents_per_cycle: u64,
+}
+
+impl Default for DepositWatcherConfig {
+    fn default() -> Self {
+        DepositWatcherConfig {
+            enabled: false,
+            interval_secs: 60,
+            max_agents_per_cycle: 50,
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DepositWatcherReport {
+    pub agents_checked: u32,
+    pub swaps_executed: u32,
+    pub treasury_sweeps: u32,
+    pub errors: Vec<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct DepositWatcherStatus {
+    pub config: DepositWatcherConfig,
+    pub watched_agents: u64,
+    pub last_run: u64,
+    pub last_report: Option<DepositWatcherReport>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SwapConfig {
+    pub enabled: bool,
+    pub fee_bps: u64,         // Fee in basis points (30 = 0.3%)
+    pub min_cketh: Nat,       // Minimum swap amount (0.0001 ETH)
+    pub max_cketh: Nat,       // Maximum swap amount (1 ETH)
+    pub cketh_backed_ckalgo: Nat,   // Total ckALGO minted via swaps (not ALGO-backed)
+    pub total_cketh_received: Nat,  // Total ckETH held by canister
+}
+
+/// Firm quote: the swap executes at exactly these amounts until `expires_at`
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct SwapQuote {
+    pub quote_id: u64,
+    pub pair: Principal,      // Input asset ledger canister
+    pub amount_in: Nat,
+    pub amount_out: Nat,      // Net ckALGO (microALGO) after fee
+    pub fee: Nat,
+    pub fee_bps: u64,
+    pub rate: RateFraction,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// get_swap_records arguments. All filters are optional and combine with AND.
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct SwapRecordQuery {
+    pub cursor: Option<u64>,     // Only records with index < cursor (next_cursor of the previous page)
+    pub limit: Option<u32>,      // Default 100, max MAX_SWAP_RECORDS_PAGE
+    pub user: Option<Principal>,
+    pub from_time: Option<u64>,  // Inclusive, nanoseconds
+    pub to_time: Option<u64>,    // Inclusive, nanoseconds
+    pub direction: Option<SwapDirection>,
+    pub pair: Option<Principal>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct IndexedSwapRecord {
+    pub index: u64,
+    pub record: SwapRecord,
+}
+
+/// Page of swap records, newest first. next_cursor is None on the last page.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SwapRecordPage {
+    pub records: Vec<IndexedSwapRecord>,
+    pub next_cursor: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum ProcessedDepositResult {
+    Swapped { swap_index: u64, ckalgo_out: Nat },
+    Refunded { refund_id: u64 },
+    Unknown,  // Processed before the log existed, with no matching swap or refund record
+}
+
+/// Processed swap deposit, appended in processing order
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ProcessedSwapDeposit {
+    pub deposit_key: String,      // Anti-replay key (see swap_deposit_key)
+    pub pair: Principal,
+    pub tx_id: String,            // Deposit block index on the pair ledger
+    pub agent: Option<Principal>, // None only for Unknown results
+    pub amount: Option<Nat>,      // None only for Unknown results
+    pub result: ProcessedDepositResult,
+    pub processed_at: u64,        // 0 for Unknown results
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProcessedSwapDepositPage {
+    pub deposits: Vec<ProcessedSwapDeposit>,  // Newest first
+    pub next_cursor: Option<u64>,
+}
+
+/// Treasury holding drawn on by withdraw_treasury
+#[derive(Clone, Debug, PartialEq, CandidType, Deserialize, Serialize)]
+pub enum TreasuryAsset {
+    CkAlgo,             // Fee ckALGO held by the canister's own principal
+    Ledger(Principal),  // Pair asset in the canister's main account on that ledger
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TreasuryFeeAccrual {
+    pub pair: Principal,
+    pub symbol: String,
+    pub fees_accrued: Nat,  // All-time swap fees in microALGO, both directions
+    pub asset_held: Nat,    // Pair asset held in the main account (swap backing)
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TreasuryStatus {
+    pub treasury_account: Principal,  // Receives fee ckALGO
+    pub ckalgo_balance: Nat,          // ckALGO balance of treasury_account
+    pub fees_by_asset: Vec<TreasuryFeeAccrual>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TreasuryWithdrawal {
+    pub withdrawal_id: u64,
+    pub asset: TreasuryAsset,
+    pub to: Account,
+    pub amount: Nat,
+    pub ledger_fee: Nat,      // Paid by the treasury on top of amount (0 for ckALGO)
+    pub block_index: Option<Nat>, // Ledger block (None for ckALGO, moved internally)
+    pub withdrawn_by: Principal,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SwapResult {
+    pub cketh_in: Nat,
+    pub ckalgo_out: Nat,
+    pub rate_used: f64,
+    pub cketh_block_index: Nat,
+    pub fee_collected: Nat,
+    pub rate: RateFraction,
+    pub pair: Principal,
+    pub direction: SwapDirection,
+}
+
+/// Reverse swap whose ckETH payout had an unknown outcome. The ckALGO stays
+/// burned until resolve_reverse_swap retries the identical transfer.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PendingReverseSwap {
+    pub swap_id: u64,
+    pub user: Principal,
+    pub pair: Principal,
+    pub ckalgo_amount: Nat,
+    pub cketh_out: Nat,
+    pub ledger_fee: Nat,
+    pub fee_collected: Nat,   // Swap fee in microALGO, minted once the payout lands
+    pub rate: RateFraction,
+    pub created_at_time: u64, // Transfer dedup key together with memo
+    pub memo: Vec<u8>,
+    pub last_error: String,
+}
+
+// ============================================================================
+// SWAP FUNCTIONS (registered ICRC assets -> ckALGO)
+// ============================================================================
+
+pub(crate) const CKETH_DECIMALS: u32 = 18;
+pub(crate) const CKALGO_DECIMALS: u32 = 6;
+pub(crate) const BPS_DENOMINATOR: u64 = 10_000;
+
+pub(crate) fn pow10(exp: u32) -> Nat {
+    (0..exp).fold(Nat::from(1u64), |acc, _| acc * Nat::from(10u64))
+}
+
+/// Exact swap output in microALGO: (net amount to user, fee)
+///
+/// With rate = n/d ALGO per whole input token and `decimals` input decimals,
+/// the exact gross output is
+///   amount * n * 10^6 / (d * 10^decimals)  microALGO
+/// Rounding: the user's net output is rounded DOWN from the exact value
+///   net = floor(amount * n * 10^6 * (10000 - fee_bps) / (d * 10^decimals * 10000))
+/// and the fee is floor(gross) - net, so the fee absorbs every rounding
+/// remainder and net + fee never exceeds the exact gross output.
+pub(crate) fn compute_swap_output(
+    amount_in: &Nat,
+    input_decimals: u32,
+    rate: &RateFraction,
+    fee_bps: u64
+) -> Result<(Nat, Nat), String> {
+    if rate.denominator.0.is_zero() {
+        return Err("Exchange rate denominator is zero".to_string());
+    }
+    if fee_bps > BPS_DENOMINATOR {
+        return Err(format!("Fee {} bps exceeds 100%", fee_bps));
+    }
+
+    let numerator = amount_in.clone() * rate.numerator.clone() * pow10(CKALGO_DECIMALS);
+    let denominator = rate.denominator.clone() * pow10(input_decimals);
+
+    let gross = numerator.clone() / denominator.clone();
+    let net = (numerator * Nat::from(BPS_DENOMINATOR - fee_bps)) / (denominator * Nat::from(BPS_DENOMINATOR));
+    let fee = gross - net.clone();
+
+    Ok((net, fee))
+}
+
+/// Exact reverse swap output: (pair asset out in base units, fee in microALGO)
+///
+/// The fee is floor(ckalgo_in * fee_bps / 10000) microALGO and the rest is
+/// converted at rate = n/d ALGO per whole token, rounded DOWN:
+///   out = floor((ckalgo_in - fee) * d * 10^decimals / (n * 10^6))
+pub(crate) fn compute_reverse_swap_output(
+    ckalgo_in: &Nat,
+    output_decimals: u32,
+    rate: &RateFraction,
+    fee_bps: u64
+) -> Result<(Nat, Nat), String> {
+    if rate.numerator.0.is_zero() {
+        return Err("Exchange rate numerator is zero".to_string());
+    }
+    if fee_bps > BPS_DENOMINATOR {
+        return Err(format!("Fee {} bps exceeds 100%", fee_bps));
+    }
+
+    let fee = ckalgo_in.clone() * Nat::from(fee_bps) / Nat::from(BPS_DENOMINATOR);
+    let net_ckalgo = ckalgo_in.clone() - fee.clone();
+    let out = (net_ckalgo * rate.denominator.clone() * pow10(output_decimals))
+        / (rate.numerator.clone() * pow10(CKALGO_DECIMALS));
+
+    Ok((out, fee))
+}
+
+/// Get the current <asset>/ALGO exchange rate from the Exchange Rate Canister (XRC)
+///
+/// Returns how many ALGO one whole `xrc_symbol` token is worth, as an exact
+/// fraction built from XRC's integer rates and decimals:
+///   (asset_rate / 10^asset_dec) / (algo_rate / 10^algo_dec)
+///     = (asset_rate * 10^algo_dec) / (algo_rate * 10^asset_dec)
+/// NO FALLBACK: If XRC unavailable, reject the request
+pub(crate) async fn fetch_asset_algo_rate(xrc_symbol: &str) -> Result<RateFraction, String> {
+    // Don't spend cycles on XRC while the circuit breaker is open
+    if let Some(reason) = RATE_CIRCUIT_BREAKER.with(|b| b.borrow().clone()) {
+        return Err(format!("Rate circuit breaker tripped: {}", reason));
+    }
+
+    let xrc_canister = Principal::from_text(XRC_CANISTER_ID)
+        .map_err(|e| format!("Invalid XRC canister ID: {}", e))?;
+    let asset_usd = format!("{}/USD", xrc_symbol);
+
+    // Get <asset>/USD rate
+    let asset_usd_request = GetExchangeRateRequest {
+        base_asset: Asset {
+            symbol: xrc_symbol.to_string(),
+            class: AssetClass::Cryptocurrency,
+        },
+        quote_asset: Asset {
+            symbol: "USD".to_string(),
+            class: AssetClass::FiatCurrency,
+        },
+        timestamp: None,
+    };
+
+    // XRC requires cycles - send 1B cycles (1T = 1 ICP, 1B = 0.001 ICP)
+    const XRC_CYCLES: u128 = 1_000_000_000; // 1B cycles
+
+    let asset_usd_result: Result<(GetExchangeRateResult,), _> = ic_cdk::api::call::call_with_payment128(
+        xrc_canister,
+        "get_exchange_rate",
+        (asset_usd_request,),
+        XRC_CYCLES
+    ).await;
+
+    // Get ALGO/USD rate
+    let algo_usd_request = GetExchangeRateRequest {
+        base_asset: Asset {
+            symbol: "ALGO".to_string(),
+            class: AssetClass::Cryptocurrency,
+        },
+        quote_asset: Asset {
+            symbol: "USD".to_string(),
+            class: AssetClass::FiatCurrency,
+        },
+        timestamp: None,
+    };
+
+    let algo_usd_result: Result<(GetExchangeRateResult,), _> = ic_cdk::api::call::call_with_payment128(
+        xrc_canister,
+        "get_exchange_rate",
+        (algo_usd_request,),
+        XRC_CYCLES
+    ).await;
+
+    match (asset_usd_result, algo_usd_result) {
+        (Ok((Ok(asset_rate),)), Ok((Ok(algo_rate),))) => {
+            check_rate_quality(&asset_usd, &asset_rate)?;
+            check_rate_quality("ALGO/USD", &algo_rate)?;
+
+            // XRC uses rate with decimals specified in metadata (typically 9)
+            if algo_rate.rate == 0 {
+                return Err(reject_rate("ALGO/USD", "rate is zero".to_string()));
+            }
+
+            // <asset>/ALGO = (<asset>/USD) / (ALGO/USD)
+            let rate = RateFraction {
+                numerator: Nat::from(asset_rate.rate) * pow10(algo_rate.metadata.decimals),
+                denominator: Nat::from(algo_rate.rate) * pow10(asset_rate.metadata.decimals),
+            };
+            check_rate_jump(xrc_symbol, &rate)?;
+
+            LAST_ACCEPTED_RATES.with(|rates| {
+                rates.borrow_mut().insert(
+                    xrc_symbol.to_string(),
+                    AcceptedRate { rate: rate.clone(), accepted_at: time() },
+                );
+            });
+            Ok(rate)
+        }
+        (Ok((Err(e),)), _) => {
+            Err(format!("Failed to get {} rate: {:?}", asset_usd, e))
+        }
+        (_, Ok((Err(e),))) => {
+            Err(format!("Failed to get ALGO/USD rate: {:?}", e))
+        }
+        (Err((code, msg)), _) => {
+            Err(format!("XRC call failed for {}: {:?} - {}", asset_usd, code, msg))
+        }
+        (_, Err((code, msg))) => {
+            Err(format!("XRC call failed for ALGO/USD: {:?} - {}", code, msg))
+        }
+    }
+}
+
+// ============================================================================
+// RATE QUALITY GUARDS
+// ============================================================================
+
+pub(crate) const MAX_RATE_REJECTIONS: usize = 100;
+
+/// Log a rejected rate and return the error message for the caller
+pub(crate) fn reject_rate(pair: &str, reason: String) -> String {
+    ic_cdk::println!("Rejected {} rate: {}", pair, reason);
+    RATE_REJECTIONS.with(|rejections| {
+        let mut rejections = rejections.borrow_mut();
+        if rejections.len() >= MAX_RATE_REJECTIONS {
+            rejections.remove(0);
+        }
+        rejections.push(RateRejection {
+            timestamp: time(),
+            pair: pair.to_string(),
+            reason: reason.clone(),
+        });
+    });
+    format!("Rejected {} rate: {}", pair, reason)
+}
+
+/// Source count, dispersion and staleness checks on a single XRC rate
+pub(crate) fn check_rate_quality(pair: &str, rate: &ExchangeRate) -> Result<(), String> {
+    let config = RATE_GUARD_CONFIG.with(|c| c.borrow().clone());
+
+    if rate.metadata.base_asset_num_received_rates < config.min_sources {
+        return Err(reject_rate(pair, format!(
+            "only {} of {} sources responded (minimum {})",
+            rate.metadata.base_asset_num_received_rates,
+            rate.metadata.base_asset_num_queried_sources,
+            config.min_sources
+        )));
+    }
+
+    // standard_deviation shares the rate's decimals, so compare them directly
+    if rate.rate == 0 || (rate.metadata.standard_deviation as u128) * 10_000
+        > (config.max_deviation_bps as u128) * (rate.rate as u128)
+    {
+        return Err(reject_rate(pair, format!(
+            "standard deviation {} too high for rate {} (max {} bps)",
+            rate.metadata.standard_deviation, rate.rate, config.max_deviation_bps
+        )));
+    }
+
+    // XRC timestamps are in seconds
+    let now_secs = time() / 1_000_000_000;
+    let age_secs = now_secs.saturating_sub(rate.timestamp);
+    if age_secs > config.max_age_secs {
+        return Err(reject_rate(pair, format!(
+            "rate is {}s old (max {}s)",
+            age_secs, config.max_age_secs
+        )));
+    }
+
+    Ok(())
+}
+
+/// Circuit breaker: trip on a jump larger than max_jump_bps vs the last accepted
+/// rate for the same asset
+pub(crate) fn check_rate_jump(xrc_symbol: &str, rate: &RateFraction) -> Result<(), String> {
+    let config = RATE_GUARD_CONFIG.with(|c| c.borrow().clone());
+    let last = match LAST_ACCEPTED_RATES.with(|rates| rates.borrow().get(xrc_symbol).cloned()) {
+        Some(last) if time().saturating_sub(last.accepted_at) <= config.jump_window_secs * 1_000_000_000 => last,
+        _ => return Ok(()),
+    };
+
+    // |new - last| / last, cross-multiplied to stay in integers
+    let new_scaled = rate.numerator.clone() * last.rate.denominator.clone();
+    let last_scaled = last.rate.numerator.clone() * rate.denominator.clone();
+    let diff = if new_scaled > last_scaled {
+        new_scaled - last_scaled.clone()
+    } else {
+        last_scaled.clone() - new_scaled
+    };
+
+    let pair = format!("{}/ALGO", xrc_symbol);
+    if diff * Nat::from(BPS_DENOMINATOR) > last_scaled * Nat::from(config.max_jump_bps) {
+        let reason = format!(
+            "{} moved from {:.4} to {:.4}, more than {} bps",
+            pair, last.rate.to_f64(), rate.to_f64(), config.max_jump_bps
+        );
+        RATE_CIRCUIT_BREAKER.with(|b| *b.borrow_mut() = Some(reason.clone()));
+        return Err(reject_rate(&pair, format!("circuit breaker tripped: {}", reason)));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_rate_guard_config(config: &RateGuardConfig) -> Result<(), String> {
+    if config.min_sources == 0 {
+        return Err("min_sources must be at least 1".to_string());
+    }
+    if config.max_jump_bps == 0 || config.max_deviation_bps == 0 {
+        return Err("Deviation and jump limits must be greater than 0".to_string());
+    }
+    Ok(())
+}
+
+/// Configure rate quality guards (Admin role)
+#[update]
+pub(crate) fn set_rate_guard_config(config: RateGuardConfig) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Configuring rate guards requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_rate_guard_config(&config)?;
+
+    RATE_GUARD_CONFIG.with(|c| *c.borrow_mut() = config.clone());
+    Ok(format!(
+        "Rate guards set: min {} sources, max deviation {} bps, max age {}s, max jump {} bps",
+        config.min_sources, config.max_deviation_bps, config.max_age_secs, config.max_jump_bps
+    ))
+}
+
+/// Reset a tripped circuit breaker (Admin role)
+///
+/// Clears the last accepted rates too, so the next XRC rates become the new baseline.
+#[update]
+pub(crate) fn reset_rate_circuit_breaker() -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Resetting the rate circuit breaker requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    let previous = RATE_CIRCUIT_BREAKER.with(|b| b.borrow_mut().take());
+    LAST_ACCEPTED_RATES.with(|rates| rates.borrow_mut().clear());
+    LAST_RATE_REFRESH_ATTEMPTS.with(|a| a.borrow_mut().clear());
+
+    match previous {
+        Some(reason) => Ok(format!("Circuit breaker reset (was: {})", reason)),
+        None => Ok("Circuit breaker was not tripped; rate baseline cleared".to_string()),
+    }
+}
+
+#[query]
+pub(crate) fn get_rate_guard_status() -> RateGuardStatus {
+    let mut last_accepted_by_asset: Vec<(String, AcceptedRate)> = LAST_ACCEPTED_RATES.with(|rates| {
+        rates.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    });
+    last_accepted_by_asset.sort_by(|a, b| a.0.cmp(&b.0));
+
+    RateGuardStatus {
+        config: RATE_GUARD_CONFIG.with(|c| c.borrow().clone()),
+        last_accepted: LAST_ACCEPTED_RATES.with(|rates| rates.borrow().get(ETH_XRC_SYMBOL).cloned()),
+        circuit_breaker_tripped: RATE_CIRCUIT_BREAKER.with(|b| b.borrow().clone()),
+        recent_rejections: RATE_REJECTIONS.with(|r| r.borrow().iter().rev().cloned().collect()),
+        last_accepted_by_asset,
+    }
+}
+
+// ============================================================================
+// EXCHANGE RATE CACHE
+// ============================================================================
+//
+// Each XRC refresh costs two calls at 1B cycles each. Swaps and quotes reuse
+// the last accepted <asset>/ALGO rate until it is older than ttl_secs, and XRC
+// is asked at most once per TTL per asset - including after a failed refresh.
+// The TTL never exceeds the rate guard's max_age_secs: a cached rate must not
+// outlive the staleness limit it was accepted under.
+
+pub(crate) const MAX_RATE_CACHE_TTL_SECS: u64 = 600;
+pub(crate) const ETH_XRC_SYMBOL: &str = "ETH";
+
+/// Cache TTL, capped by the staleness guard in case max_age_secs was lowered since
+pub(crate) fn rate_cache_ttl_secs() -> u64 {
+    let ttl_secs = RATE_CACHE_CONFIG.with(|c| c.borrow().ttl_secs);
+    ttl_secs.min(RATE_GUARD_CONFIG.with(|c| c.borrow().max_age_secs))
+}
+
+pub(crate) fn cached_rate_if_fresh(xrc_symbol: &str) -> Option<RateFraction> {
+    let ttl_ns = rate_cache_ttl_secs() * 1_000_000_000;
+    LAST_ACCEPTED_RATES.with(|rates| {
+        rates.borrow().get(xrc_symbol)
+            .filter(|cached| time().saturating_sub(cached.accepted_at) < ttl_ns)
+            .map(|cached| cached.rate.clone())
+    })
+}
+
+/// Fetch a new rate from XRC, guarded against concurrent refreshes of the same asset
+pub(crate) async fn refresh_cached_rate(xrc_symbol: &str) -> Result<RateFraction, String> {
+    if !RATE_REFRESHES_IN_PROGRESS.with(|r| r.borrow_mut().insert(xrc_symbol.to_string())) {
+        return Err(format!("{} rate refresh already in progress. Retry shortly.", xrc_symbol));
+    }
+    LAST_RATE_REFRESH_ATTEMPTS.with(|a| a.borrow_mut().insert(xrc_symbol.to_string(), time()));
+
+    let result = fetch_asset_algo_rate(xrc_symbol).await;
+
+    RATE_REFRESHES_IN_PROGRESS.with(|r| r.borrow_mut().remove(xrc_symbol));
+    result
+}
+
+/// <asset>/ALGO rate for pricing: the cached rate while fresh, otherwise one XRC refresh per TTL
+pub(crate) async fn get_cached_rate(xrc_symbol: &str) -> Result<RateFraction, String> {
+    if let Some(reason) = RATE_CIRCUIT_BREAKER.with(|b| b.borrow().clone()) {
+        return Err(format!("Rate circuit breaker tripped: {}", reason));
+    }
+    if let Some(rate) = cached_rate_if_fresh(xrc_symbol) {
+        return Ok(rate);
+    }
+
+    let ttl_ns = rate_cache_ttl_secs() * 1_000_000_000;
+    let last_attempt = LAST_RATE_REFRESH_ATTEMPTS.with(|a| a.borrow().get(xrc_symbol).copied().unwrap_or(0));
+    let since_attempt = time().saturating_sub(last_attempt);
+    if since_attempt < ttl_ns {
+        return Err(format!(
+            "{}/ALGO rate unavailable: last XRC refresh failed, next attempt in {}s",
+            xrc_symbol,
+            (ttl_ns - since_attempt) / 1_000_000_000 + 1
+        ));
+    }
+
+    refresh_cached_rate(xrc_symbol).await
+}
+
+pub(crate) fn schedule_rate_refresh_timer() {
+    if let Some(timer_id) = RATE_REFRESH_TIMER.with(|t| t.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+    }
+
+    let config = RATE_CACHE_CONFIG.with(|c| c.borrow().clone());
+    if !config.timer_refresh {
+        return;
+    }
+
+    let timer_id = ic_cdk_timers::set_timer_interval(Duration::from_secs(rate_cache_ttl_secs().max(1)), || {
+        ic_cdk::spawn(async {
+            if RATE_CIRCUIT_BREAKER.with(|b| b.borrow().is_some())
+                || !SWAP_ENABLED.with(|e| *e.borrow())
+                || state_export_in_progress()
+            {
+                return;
+            }
+
+            // One refresh per distinct XRC symbol among enabled pairs
+            let mut symbols: Vec<String> = SWAP_PAIRS.with(|pairs| {
+                pairs.borrow().values().filter(|p| p.enabled).map(|p| p.xrc_symbol.clone()).collect()
+            });
+            symbols.sort();
+            symbols.dedup();
+
+            for symbol in symbols {
+                if let Err(e) = refresh_cached_rate(&symbol).await {
+                    ic_cdk::println!("Scheduled {} rate refresh failed: {}", symbol, e);
+                }
+            }
+        })
+    });
+    RATE_REFRESH_TIMER.with(|t| *t.borrow_mut() = Some(timer_id));
+}
+
+pub(crate) fn check_rate_cache_config(config: &RateCacheConfig) -> Result<(), String> {
+    let max_ttl_secs = MAX_RATE_CACHE_TTL_SECS.min(RATE_GUARD_CONFIG.with(|c| c.borrow().max_age_secs));
+    if config.ttl_secs == 0 || config.ttl_secs > max_ttl_secs {
+        return Err(format!(
+            "ttl_secs must be between 1 and {} (capped by the rate guard's max_age_secs)",
+            max_ttl_secs
+        ));
+    }
+    Ok(())
+}
+
+/// Configure the rate cache TTL and timer refresh (Admin role)
+#[update]
+pub(crate) fn set_rate_cache_config(config: RateCacheConfig) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Configuring the rate cache requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_rate_cache_config(&config)?;
+
+    RATE_CACHE_CONFIG.with(|c| *c.borrow_mut() = config.clone());
+    schedule_rate_refresh_timer();
+    Ok(format!(
+        "Rate cache set: ttl {}s, timer refresh {}",
+        config.ttl_secs,
+        if config.timer_refresh { "on" } else { "off" }
+    ))
+}
+
+pub(crate) fn cached_rate_view(xrc_symbol: &str) -> Result<CachedAlgoRate, String> {
+    let ttl_secs = rate_cache_ttl_secs();
+    let cached = LAST_ACCEPTED_RATES.with(|rates| rates.borrow().get(xrc_symbol).cloned())
+        .ok_or(format!("No cached {}/ALGO rate yet", xrc_symbol))?;
+    let age_secs = time().saturating_sub(cached.accepted_at) / 1_000_000_000;
+
+    Ok(CachedAlgoRate {
+        asset: xrc_symbol.to_string(),
+        rate: cached.rate.to_f64(),
+        rate_fraction: cached.rate,
+        fetched_at: cached.accepted_at,
+        age_secs,
+        ttl_secs,
+        fresh: age_secs < ttl_secs,
+    })
+}
+
+/// Refresh the cached ETH/ALGO rate if it has expired (Operator role)
+///
+/// A no-op while the cached rate is fresh, so it can be called freely before quoting.
+#[update]
+pub(crate) async fn refresh_eth_algo_rate() -> Result<CachedAlgoRate, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Operator) {
+        return Err(format!(
+            "Unauthorized: refreshing the rate requires the Operator role. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+
+    get_cached_rate(ETH_XRC_SYMBOL).await?;
+    cached_rate_view(ETH_XRC_SYMBOL)
+}
+
+/// Get the cached ETH/ALGO rate and its age (free query, never calls XRC)
+///
+/// `fresh` is false once the rate is older than the cache TTL; swaps will then
+/// refresh it from XRC before pricing.
+#[query]
+pub(crate) fn get_current_eth_algo_rate() -> Result<CachedAlgoRate, String> {
+    cached_rate_view(ETH_XRC_SYMBOL)
+}
+
+/// Get the cached <asset>/ALGO rate for a registered swap pair (free query)
+#[query]
+pub(crate) fn get_pair_rate(pair: Principal) -> Result<CachedAlgoRate, String> {
+    let xrc_symbol = SWAP_PAIRS.with(|pairs| pairs.borrow().get(&pair).map(|p| p.xrc_symbol.clone()))
+        .ok_or(format!("Unsupported swap pair: {}", pair))?;
+    cached_rate_view(&xrc_symbol)
+}
+
+// ============================================================================
+// SWAP PAIR REGISTRY
+// ============================================================================
+//
+// Input assets are keyed by their ICRC ledger canister. ckETH is registered by
+// default; the legacy ckETH endpoints (set_swap_fee_bps, set_swap_limits,
+// get_swap_config, swap_cketh_*) read and write its registry entry.
+
+pub(crate) const MAX_SWAP_FEE_BPS: u64 = 500;
+pub(crate) const MAX_PAIR_DECIMALS: u32 = 18;
+
+pub(crate) fn cketh_ledger() -> Principal {
+    Principal::from_text(CKETH_CANISTER_ID).expect("CKETH_CANISTER_ID is a valid principal")
+}
+
+pub(crate) fn default_swap_pairs() -> HashMap<Principal, SwapPair> {
+    let ledger = cketh_ledger();
+    HashMap::from([(ledger, SwapPair {
+        ledger,
+        symbol: "ckETH".to_string(),
+        xrc_symbol: ETH_XRC_SYMBOL.to_string(),
+        decimals: CKETH_DECIMALS,
+        fee_bps: 30,                                              // 0.3% fee
+        min_amount: Nat::from(100_000_000_000_000u64),            // 0.0001 ETH (18 decimals)
+        max_amount: Nat::from(1_000_000_000_000_000_000u64),      // 1 ETH (18 decimals)
+        enabled: true,
+    })])
+}
+
+/// Registered, enabled pair for `ledger` (also checks the global swap switch and pause)
+pub(crate) fn active_swap_pair(ledger: &Principal) -> Result<SwapPair, String> {
+    ensure_not_paused(PauseClass::Swaps)?;
+    if !SWAP_ENABLED.with(|e| *e.borrow()) {
+        return Err("Swaps are currently disabled".to_string());
+    }
+    let pair = SWAP_PAIRS.with(|pairs| pairs.borrow().get(ledger).cloned())
+        .ok_or(format!("Unsupported swap pair: {}", ledger))?;
+    if !pair.enabled {
+        return Err(format!("Swaps from {} are currently disabled", pair.symbol));
+    }
+    Ok(pair)
+}
+
+pub(crate) fn check_swap_limits(pair: &SwapPair, amount: &Nat) -> Result<(), String> {
+    if *amount < pair.min_amount {
+        return Err(format!(
+            "Swap amount {} below minimum {} for {}",
+            amount, pair.min_amount, pair.symbol
+        ));
+    }
+    if *amount > pair.max_amount {
+        return Err(format!(
+            "Swap amount {} exceeds maximum {} for {}",
+            amount, pair.max_amount, pair.symbol
+        ));
+    }
+    Ok(())
+}
+
+/// Mint swap output to `user` and the fee to the treasury, track backing for the
+/// input asset and record the swap
+pub(crate) fn credit_swap(
+    pair: &SwapPair,
+    user: Principal,
+    amount_in: Nat,
+    price: SwapPrice,
+    tx_id: String,
+    block_index: Nat
+) -> SwapResult {
+    let user_str = user.to_text();
+    let SwapPrice { amount_out: ckalgo_out, fee: fee_nat, rate, .. } = price;
+
+    // Mint ckALGO to user
+    set_balance(&user_str, balance_of(&user_str) + ckalgo_out.clone());
+
+    // Update total supply
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = total.clone() + ckalgo_out.clone();
+    });
+
+    // Track swap-backed ckALGO per input asset (NOT backed by ALGO reserves)
+    SWAP_BACKING.with(|backing| {
+        let mut backing = backing.borrow_mut();
+        let entry = backing.entry(pair.ledger).or_insert_with(|| SwapBacking {
+            total_received: Nat::from(0u64),
+            ckalgo_minted: Nat::from(0u64),
+        });
+        entry.total_received += amount_in.clone();
+        entry.ckalgo_minted += ckalgo_out.clone();
+    });
+
+    // The fee is part of the input value, so it is backed like the user's output
+    let treasury_str = mint_treasury_fee(pair, &fee_nat);
+
+    // Record swap for audit trail
+    let record = SwapRecord {
+        user,
+        cketh_in: amount_in.clone(),
+        ckalgo_out: ckalgo_out.clone(),
+        rate_used: rate.to_f64(),
+        fee_collected: fee_nat.clone(),
+        timestamp: time(),
+        tx_id,
+        rate: Some(rate.clone()),
+        pair: Some(pair.ledger),
+        direction: Some(SwapDirection::ToCkAlgo),
+    };
+    SWAP_RECORDS.with(|records| records.borrow().append(&StableCandid(record)).expect("Failed to append swap record"));
+
+    certify_state(&[&user_str, &treasury_str]);
+
+    SwapResult {
+        cketh_in: amount_in,
+        ckalgo_out,
+        rate_used: rate.to_f64(),
+        cketh_block_index: block_index,
+        fee_collected: fee_nat,
+        rate,
+        pair: pair.ledger,
+        direction: SwapDirection::ToCkAlgo,
+    }
+}
+
+/// Register or update a swap pair (Admin role, timelocked)
+#[update]
+pub(crate) fn set_swap_pair(pair: SwapPair) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Configuring swap pairs requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    validate_swap_pair(&pair)?;
+    Ok(queue_config_change(ConfigChange::SwapPair(pair), caller_principal))
+}
+
+pub(crate) fn validate_swap_pair(pair: &SwapPair) -> Result<(), String> {
+    if pair.xrc_symbol.is_empty() || !pair.xrc_symbol.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+        return Err(format!("Invalid XRC symbol: '{}' (expected e.g. ETH, USDC, ICP)", pair.xrc_symbol));
+    }
+    if pair.xrc_symbol == "ALGO" {
+        return Err("ALGO cannot be swapped into ckALGO".to_string());
+    }
+    if pair.decimals > MAX_PAIR_DECIMALS {
+        return Err(format!("Decimals {} exceed maximum {}", pair.decimals, MAX_PAIR_DECIMALS));
+    }
+    if pair.fee_bps > MAX_SWAP_FEE_BPS {
+        return Err(format!("Fee cannot exceed 5% ({} bps)", MAX_SWAP_FEE_BPS));
+    }
+    if pair.min_amount >= pair.max_amount {
+        return Err("Minimum must be less than maximum".to_string());
+    }
+
+    // Decimals are fixed by the ledger; changing them would misprice existing backing
+    let has_backing = SWAP_BACKING.with(|b| {
+        b.borrow().get(&pair.ledger).map(|b| !b.total_received.0.is_zero()).unwrap_or(false)
+    });
+    let existing = SWAP_PAIRS.with(|pairs| pairs.borrow().get(&pair.ledger).cloned());
+    if let Some(existing) = existing {
+        if has_backing && existing.decimals != pair.decimals {
+            return Err(format!(
+                "Cannot change decimals of {} after it has received deposits",
+                existing.symbol
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn registered_swap_pair(ledger: &Principal) -> Result<SwapPair, String> {
+    SWAP_PAIRS.with(|pairs| pairs.borrow().get(ledger).cloned())
+        .ok_or(format!("Unsupported swap pair: {}", ledger))
+}
+
+/// Enable/disable a single swap pair (Admin role)
+/// Disabling is an emergency pause and applies immediately; enabling is timelocked.
+#[update]
+pub(crate) fn set_swap_pair_enabled(ledger: Principal, enabled: bool) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Enabling/disabling swap pairs requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    registered_swap_pair(&ledger)?;
+    let change = ConfigChange::SwapPairEnabled { ledger, enabled };
+    if !enabled {
+        return apply_config_change(&change);
+    }
+    Ok(queue_config_change(change, caller_principal))
+}
+
+/// All registered swap pairs with their backing totals
+#[query]
+pub(crate) fn get_swap_pairs() -> Vec<SwapPairStatus> {
+    let mut statuses: Vec<SwapPairStatus> = SWAP_PAIRS.with(|pairs| {
+        pairs.borrow().values().map(|pair| {
+            let backing = SWAP_BACKING.with(|b| b.borrow().get(&pair.ledger).cloned());
+            SwapPairStatus {
+                pair: pair.clone(),
+                total_received: backing.as_ref().map(|b| b.total_received.clone()).unwrap_or(Nat::from(0u64)),
+                ckalgo_backed: backing.map(|b| b.ckalgo_minted).unwrap_or(Nat::from(0u64)),
+            }
+        }).collect()
+    });
+    statuses.sort_by(|a, b| a.pair.symbol.cmp(&b.pair.symbol));
+    statuses
+}
+
+// ============================================================================
+// TRANSFER-BASED SWAPS (ICRC-2 transfer_from)
+// ============================================================================
+
+/// Swap a registered ICRC asset for ckALGO
+///
+/// SECURITY: Requires the SwapExecutor role
+///
+/// Flow:
+/// 1. Verify caller is authorized
+/// 2. Verify the pair is enabled and amount within its limits
+/// 3. Get the current <asset>/ALGO rate (cached, from XRC - NO fallback)
+/// 4. Call <ledger>.icrc2_transfer_from(user, this_canister, amount)
+/// 5. Calculate ckALGO output (minus fee)
+/// 6. Mint ckALGO to user
+/// 7. Record swap, update backing for the input asset
+#[update]
+pub(crate) async fn swap_to_ckalgo(
+    pair: Principal,
+    user: Principal,
+    amount_in: Nat,
+    min_ckalgo_out: Option<Nat>,  // Slippage protection
+    quote_id: Option<u64>         // Execute at a firm quote instead of the live rate
+) -> Result<SwapResult, String> {
+    execute_transfer_swap(pair, user, amount_in, min_ckalgo_out, quote_id).await
+}
+
+/// Swap ckETH for ckALGO (the ckETH pair of swap_to_ckalgo)
+#[update]
+pub(crate) async fn swap_cketh_to_ckalgo(
+    user: Principal,
+    cketh_amount: Nat,
+    min_ckalgo_out: Option<Nat>,
+    quote_id: Option<u64>
+) -> Result<SwapResult, String> {
+    execute_transfer_swap(cketh_ledger(), user, cketh_amount, min_ckalgo_out, quote_id).await
+}
+
+pub(crate) async fn execute_transfer_swap(
+    ledger: Principal,
+    user: Principal,
+    amount_in: Nat,
+    min_ckalgo_out: Option<Nat>,
+    quote_id: Option<u64>
+) -> Result<SwapResult, String> {
+    // Authorization check
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::SwapExecutor) {
+        return Err(format!(
+            "Unauthorized: executing swaps requires the SwapExecutor role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    let pair = active_swap_pair(&ledger)?;
+    check_swap_limits(&pair, &amount_in)?;
+
+    // Price the swap: honour a valid quote, otherwise use the (cached) XRC rate
+    // (NO FALLBACK - reject if unavailable). Includes the slippage check.
+    let price = resolve_swap_price(&pair, &amount_in, quote_id, min_ckalgo_out.as_ref()).await?;
+
+    // Execute: Pull the input asset from user via ICRC-2 transfer_from
+    let transfer_args = TransferFromArgs {
+        from: Account {
+            owner: user,
+            subaccount: None,
+        },
+        to: Account {
+            owner: ic_cdk::api::id(),
+            subaccount: None,
+        },
+        amount: amount_in.clone(),
+        fee: None,
+        memo: None,
+        created_at_time: None,
+        spender_subaccount: None,
+    };
+
+    // Inter-canister call to the input ledger
+    let transfer_result: Result<(Result<Nat, TransferFromError>,), _> =
+        ic_cdk::call(pair.ledger, "icrc2_transfer_from", (transfer_args,)).await;
+
+    match transfer_result {
+        Ok((Ok(block_index),)) => {
+            let tx_id = format!("SWAP_{}", block_index);
+            Ok(credit_swap(&pair, user, amount_in, price, tx_id, block_index))
+        }
+        Ok((Err(e),)) => {
+            restore_swap_quote(price.quote);
+            Err(format!("{} transfer_from failed: {:?}", pair.symbol, e))
+        }
+        Err((code, msg)) => {
+            restore_swap_quote(price.quote);
+            Err(format!("Inter-canister call to {} failed: {:?} - {}", pair.symbol, code, msg))
+        }
+    }
+}
+
+// ============================================================================
+// REVERSE SWAPS (ckALGO -> ckETH)
+// ============================================================================
+//
+// Only ckALGO minted by ckETH swaps can exit this way: the burn is capped by the
+// ckETH pair's swap backing, so ALGO-backed ckALGO never drains the treasury.
+//
+// The ckALGO is burned before the payout. If the ledger call fails without a
+// definite answer the burn stands and the swap is kept pending: reversing it
+// could pay the user twice if the transfer had landed. New reverse swaps are
+// refused while MAX_PENDING_REVERSE_SWAPS are waiting to be resolved.
+
+pub(crate) const MAX_PENDING_REVERSE_SWAPS: usize = 1_000;
+
+/// Swap ckALGO for ckETH
+///
+/// Burns `ckalgo_amount` from `user` and pays ckETH from the canister's main
+/// (treasury) account via icrc1_transfer. The ledger fee is paid by the treasury.
+/// The swap fee is re-minted to the treasury once the payout succeeds.
+/// Callable by the user itself or a SwapExecutor.
+#[update]
+pub(crate) async fn swap_ckalgo_to_cketh(
+    user: Principal,
+    ckalgo_amount: Nat,
+    min_cketh_out: Option<Nat>  // Slippage protection
+) -> Result<SwapResult, String> {
+    let caller_principal = caller();
+    if caller_principal != user && !has_role(&caller_principal, Role::SwapExecutor) {
+        return Err(format!(
+            "Unauthorized: only the user or a SwapExecutor can execute reverse swaps. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    let pair = active_swap_pair(&cketh_ledger())?;
+    if ckalgo_amount.0.is_zero() {
+        return Err("Swap amount must be greater than 0".to_string());
+    }
+
+    let user_str = user.to_text();
+    if balance_of(&user_str) < ckalgo_amount {
+        return Err(format!(
+            "Insufficient ckALGO balance: has {}, requested {}",
+            balance_of(&user_str), ckalgo_amount
+        ));
+    }
+
+    // Price at the (cached) XRC rate - NO fallback
+    let rate = get_cached_rate(&pair.xrc_symbol).await?;
+    let (cketh_out, fee_nat) = compute_reverse_swap_output(&ckalgo_amount, pair.decimals, &rate, pair.fee_bps)?;
+    check_swap_limits(&pair, &cketh_out)?;
+    if let Some(min_out) = &min_cketh_out {
+        if cketh_out < *min_out {
+            return Err(format!("Slippage exceeded: output {} < minimum {}", cketh_out, min_out));
+        }
+    }
+    let transfer_fee = ledger_fee(pair.ledger).await?;
+    let cketh_debit = cketh_out.clone() + transfer_fee.clone();
+
+    // Re-check after the awaits: balance and ckETH-backed liquidity may have moved
+    let backing = SWAP_BACKING.with(|b| b.borrow().get(&pair.ledger).cloned())
+        .ok_or("No ckETH-backed ckALGO liquidity".to_string())?;
+    if ckalgo_amount > backing.ckalgo_minted {
+        return Err(format!(
+            "Reverse swap of {} ckALGO exceeds ckETH-backed liquidity {}",
+            ckalgo_amount, backing.ckalgo_minted
+        ));
+    }
+    if cketh_debit > backing.total_received {
+        return Err(format!(
+            "Reverse swap output {} plus ledger fee {} exceeds ckETH held {}",
+            cketh_out, transfer_fee, backing.total_received
+        ));
+    }
+    if balance_of(&user_str) < ckalgo_amount {
+        return Err("Insufficient ckALGO balance".to_string());
+    }
+    if PENDING_REVERSE_SWAPS.with(|p| p.borrow().len()) >= MAX_PENDING_REVERSE_SWAPS {
+        return Err(format!(
+            "{} reverse swaps are pending resolution: resolve them before swapping again",
+            MAX_PENDING_REVERSE_SWAPS
+        ));
+    }
+
+    // Burn BEFORE the transfer await so the same balance cannot be spent twice
+    let swap_id = NEXT_REVERSE_SWAP_ID.with(|n| n.replace_with(|id| *id + 1));
+    let mut swap = PendingReverseSwap {
+        swap_id,
+        user,
+        pair: pair.ledger,
+        ckalgo_amount,
+        cketh_out,
+        ledger_fee: transfer_fee,
+        fee_collected: fee_nat,
+        rate,
+        created_at_time: time(),
+        memo: format!("REVERSE_SWAP_{}", swap_id).into_bytes(),
+        last_error: String::new(),
+    };
+    apply_reverse_swap_burn(&swap, true);
+
+    match pay_reverse_swap(&swap).await {
+        ReversePayout::Paid(block_index) => Ok(complete_reverse_swap(&pair, &swap, block_index)),
+        ReversePayout::Rejected(e) => {
+            apply_reverse_swap_burn(&swap, false);
+            Err(format!("{} transfer failed: {}", pair.symbol, e))
+        }
+        ReversePayout::Unknown(e) => {
+            swap.last_error = e.clone();
+            PENDING_REVERSE_SWAPS.with(|p| p.borrow_mut().insert(swap_id, swap));
+            Err(format!(
+                "{} payout outcome unknown ({}); ckALGO stays burned as pending reverse swap {}. Call resolve_reverse_swap to settle it.",
+                pair.symbol, e, swap_id
+            ))
+        }
+    }
+}
+
+pub(crate) enum ReversePayout {
+    Paid(Nat),
+    Rejected(String),  // The ledger did not execute the transfer
+    Unknown(String),   // It may have; retry with the same created_at_time and memo
+}
+
+/// Burn (or restore) the user's ckALGO and the ckETH backing for a reverse swap
+pub(crate) fn apply_reverse_swap_burn(swap: &PendingReverseSwap, burn: bool) {
+    let user_str = swap.user.to_text();
+    let cketh_debit = swap.cketh_out.clone() + swap.ledger_fee.clone();
+    let current = balance_of(&user_str);
+    let updated = if burn { current - swap.ckalgo_amount.clone() } else { current + swap.ckalgo_amount.clone() };
+    set_balance(&user_str, updated);
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = if burn { total.clone() - swap.ckalgo_amount.clone() } else { total.clone() + swap.ckalgo_amount.clone() };
+    });
+    SWAP_BACKING.with(|b| {
+        if let Some(entry) = b.borrow_mut().get_mut(&swap.pair) {
+            if burn {
+                entry.ckalgo_minted -= swap.ckalgo_amount.clone();
+                entry.total_received -= cketh_debit;
+            } else {
+                entry.ckalgo_minted += swap.ckalgo_amount.clone();
+                entry.total_received += cketh_debit;
+            }
+        }
+    });
+    certify_state(&[&user_str]);
+}
+
+/// Send the reverse swap payout. Identical args on retry let the ledger's
+/// deduplication report an earlier transfer instead of paying twice.
+pub(crate) async fn pay_reverse_swap(swap: &PendingReverseSwap) -> ReversePayout {
+    let transfer_args = TransferArgs {
+        from_subaccount: None,
+        to: Account {
+            owner: swap.user,
+            subaccount: None,
+        },
+        amount: swap.cketh_out.clone(),
+        fee: Some(swap.ledger_fee.clone()),
+        memo: Some(swap.memo.clone()),
+        created_at_time: Some(swap.created_at_time),
+    };
+
+    let transfer_result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(swap.pair, "icrc1_transfer", (transfer_args,)).await;
+
+    match transfer_result {
+        Ok((Ok(block_index),)) | Ok((Err(TransferError::Duplicate { duplicate_of: block_index }),)) => {
+            ReversePayout::Paid(block_index)
+        }
+        Ok((Err(e @ (TransferError::TemporarilyUnavailable | TransferError::TooOld)),)) => {
+            ReversePayout::Unknown(format!("{:?}", e))
+        }
+        Ok((Err(e),)) => ReversePayout::Rejected(format!("{:?}", e)),
+        Err((code, msg)) => ReversePayout::Unknown(format!("{:?} - {}", code, msg)),
+    }
+}
+
+/// Mint the swap fee and record a reverse swap whose payout landed
+pub(crate) fn complete_reverse_swap(pair: &SwapPair, swap: &PendingReverseSwap, block_index: Nat) -> SwapResult {
+    let treasury_str = mint_treasury_fee(pair, &swap.fee_collected);
+    certify_state(&[&treasury_str]);
+
+    let record = SwapRecord {
+        user: swap.user,
+        cketh_in: swap.cketh_out.clone(),
+        ckalgo_out: swap.ckalgo_amount.clone(),
+        rate_used: swap.rate.to_f64(),
+        fee_collected: swap.fee_collected.clone(),
+        timestamp: time(),
+        tx_id: format!("REVERSE_SWAP_{}", block_index),
+        rate: Some(swap.rate.clone()),
+        pair: Some(swap.pair),
+        direction: Some(SwapDirection::FromCkAlgo),
+    };
+    SWAP_RECORDS.with(|records| records.borrow().append(&StableCandid(record)).expect("Failed to append swap record"));
+
+    SwapResult {
+        cketh_in: swap.cketh_out.clone(),
+        ckalgo_out: swap.ckalgo_amount.clone(),
+        rate_used: swap.rate.to_f64(),
+        cketh_block_index: block_index,
+        fee_collected: swap.fee_collected.clone(),
+        rate: swap.rate.clone(),
+        pair: swap.pair,
+        direction: SwapDirection::FromCkAlgo,
+    }
+}
+
+/// Settle a pending reverse swap by retrying its exact payout
+///
+/// Completes if the ledger executes it or reports the original as a duplicate,
+/// restores the burned ckALGO if the ledger rejects it, and stays pending if
+/// the outcome is still unknown. TooOld (past the ledger's dedup window) stays
+/// pending: the ledger history must then be checked by hand.
+/// Callable by the user itself or a SwapExecutor.
+#[update]
+pub(crate) async fn resolve_reverse_swap(swap_id: u64) -> Result<SwapResult, String> {
+    let caller_principal = caller();
+    let swap = PENDING_REVERSE_SWAPS.with(|p| p.borrow().get(&swap_id).cloned())
+        .ok_or(format!("No pending reverse swap {}", swap_id))?;
+    if caller_principal != swap.user && !has_role(&caller_principal, Role::SwapExecutor) {
+        return Err(format!(
+            "Unauthorized: only the user or a SwapExecutor can resolve reverse swaps. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+    let pair = SWAP_PAIRS.with(|pairs| pairs.borrow().get(&swap.pair).cloned())
+        .ok_or(format!("Unsupported swap pair: {}", swap.pair))?;
+
+    // Take it out while awaiting so a concurrent resolve cannot settle it twice
+    PENDING_REVERSE_SWAPS.with(|p| p.borrow_mut().remove(&swap_id));
+    match pay_reverse_swap(&swap).await {
+        ReversePayout::Paid(block_index) => Ok(complete_reverse_swap(&pair, &swap, block_index)),
+        ReversePayout::Rejected(e) => {
+            apply_reverse_swap_burn(&swap, false);
+            Err(format!("{} payout rejected ({}); restored {} ckALGO to {}", pair.symbol, e, swap.ckalgo_amount, swap.user))
+        }
+        ReversePayout::Unknown(e) => {
+            let mut swap = swap;
+            swap.last_error = e.clone();
+            PENDING_REVERSE_SWAPS.with(|p| p.borrow_mut().insert(swap_id, swap));
+            Err(format!("{} payout outcome still unknown: {}", pair.symbol, e))
+        }
+    }
+}
+
+#[query]
+pub(crate) fn get_pending_reverse_swaps() -> Vec<PendingReverseSwap> {
+    let mut pending: Vec<PendingReverseSwap> = PENDING_REVERSE_SWAPS.with(|p| p.borrow().values().cloned().collect());
+    pending.sort_by_key(|swap| swap.swap_id);
+    pending
+}
+
+// ============================================================================
+// SWAP FEE TREASURY
+// ============================================================================
+//
+// Swap fees are denominated in ckALGO (microALGO) in both directions and are
+// minted to the treasury account: forward swaps keep the fee's worth of input
+// asset in the backing, reverse swaps burn the full amount and re-mint the fee.
+// The pair assets themselves accumulate in this canister's main account on each
+// ledger (SwapBacking.total_received) and leave via withdraw_treasury.
+
+pub(crate) fn treasury_principal() -> Principal {
+    TREASURY_ACCOUNT.with(|t| *t.borrow()).unwrap_or_else(ic_cdk::api::id)
+}
+
+/// Mint `fee` ckALGO to the treasury as swap-backed supply for `pair` and accrue
+/// it. Returns the treasury balance key; the caller certifies it.
+pub(crate) fn mint_treasury_fee(pair: &SwapPair, fee: &Nat) -> String {
+    let treasury_str = treasury_principal().to_text();
+    if fee.0.is_zero() {
+        return treasury_str;
+    }
+
+    set_balance(&treasury_str, balance_of(&treasury_str) + fee.clone());
+    TOTAL_SUPPLY.with(|supply| {
+        let mut total = supply.borrow_mut();
+        *total = total.clone() + fee.clone();
+    });
+    SWAP_BACKING.with(|backing| {
+        if let Some(entry) = backing.borrow_mut().get_mut(&pair.ledger) {
+            entry.ckalgo_minted += fee.clone();
+        }
+    });
+    TREASURY_FEES.with(|fees| {
+        *fees.borrow_mut().entry(pair.ledger).or_insert_with(|| Nat::from(0u64)) += fee.clone();
+    });
+
+    treasury_str
+}
+
+pub(crate) fn check_treasury_account(account: Option<Principal>) -> Result<(), String> {
+    if account == Some(Principal::anonymous()) {
+        return Err("The anonymous principal cannot be the treasury account".to_string());
+    }
+    Ok(())
+}
+
+/// Set the principal that receives swap fee ckALGO (controllers or governance)
+/// None reverts to this canister's own principal. Fees already minted stay where they are.
+#[update]
+pub(crate) fn set_treasury_account(account: Option<Principal>) -> Result<String, String> {
+    let caller_principal = caller();
+    if !is_controller_or_governance(&caller_principal) {
+        return Err("Only controllers or governance can set the treasury account".to_string());
+    }
+    ensure_no_state_export()?;
+    check_treasury_account(account)?;
+
+    TREASURY_ACCOUNT.with(|t| *t.borrow_mut() = account);
+    Ok(format!("Swap fees will be minted to {}", treasury_principal()))
+}
+
+/// Treasury account, its ckALGO balance and accrued swap fees per pair asset
+#[query]
+pub(crate) fn get_treasury_status() -> TreasuryStatus {
+    let treasury = treasury_principal();
+    let ckalgo_balance = balance_of(&treasury.to_text());
+
+    let mut fees_by_asset: Vec<TreasuryFeeAccrual> = SWAP_PAIRS.with(|pairs| {
+        pairs.borrow().values().map(|pair| TreasuryFeeAccrual {
+            pair: pair.ledger,
+            symbol: pair.symbol.clone(),
+            fees_accrued: TREASURY_FEES.with(|f| f.borrow().get(&pair.ledger).cloned().unwrap_or(Nat::from(0u64))),
+            asset_held: SWAP_BACKING.with(|b| {
+                b.borrow().get(&pair.ledger).map(|entry| entry.total_received.clone()).unwrap_or(Nat::from(0u64))
+            }),
+        }).collect()
+    });
+    fees_by_asset.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+
+    TreasuryStatus {
+        treasury_account: treasury,
+        ckalgo_balance,
+        fees_by_asset,
+    }
+}
+
+/// Pair asset held beyond the value of the outstanding swap-minted ckALGO at
+/// `rate`, i.e. what can leave the backing without shorting reverse swaps
+pub(crate) fn treasury_backing_surplus(backing: &SwapBacking, decimals: u32, rate: &RateFraction) -> Result<Nat, String> {
+    let (owed, _) = compute_reverse_swap_output(&backing.ckalgo_minted, decimals, rate, 0)?;
+    Ok(if backing.total_received > owed {
+        backing.total_received.clone() - owed
+    } else {
+        Nat::from(0u64)
+    })
+}
+
+/// Withdraw treasury funds (controllers only)
+///
+/// - `CkAlgo` moves fee ckALGO held by this canister's own principal to `to.owner`
+///   (ckALGO balances are per principal, so `to.subaccount` must be None). Fees
+///   minted to an external treasury account are already under its control.
+/// - `Ledger(ledger)` sends the pair asset from the main account on that ledger. The
+///   amount plus the ledger fee is taken out of the pair's swap backing, and is
+///   capped at the surplus over what the outstanding swap-minted ckALGO is worth
+///   at the current rate, so the ckALGO stays redeemable through reverse swaps.
+#[update]
+pub(crate) async fn withdraw_treasury(asset: TreasuryAsset, to: Account, amount: Nat) -> Result<TreasuryWithdrawal, String> {
+    let caller_principal = caller();
+    if !is_controller(&caller_principal) {
+        return Err(format!(
+            "Unauthorized: only controllers can withdraw treasury funds. Caller: {}",
+            caller_principal
+        ));
+    }
+    ensure_no_state_export()?;
+    if amount.0.is_zero() {
+        return Err("Withdrawal amount must be greater than 0".to_string());
+    }
+
+    let (ledger_fee_paid, block_index) = match &asset {
+        TreasuryAsset::CkAlgo => {
+            if to.subaccount.is_some() {
+                return Err("ckALGO balances are per principal: subaccount must be null".to_string());
+            }
+            let from_str = ic_cdk::api::id().to_text();
+            let to_str = to.owner.to_text();
+            let held = balance_of(&from_str);
+            if held < amount {
+                return Err(format!("Insufficient treasury ckALGO: has {}, requested {}", held, amount));
+            }
+            set_balance(&from_str, held - amount.clone());
+            set_balance(&to_str, balance_of(&to_str) + amount.clone());
+            certify_state(&[&from_str, &to_str]);
+            (Nat::from(0u64), None)
+        }
+        TreasuryAsset::Ledger(ledger) => {
+            let ledger = *ledger;
+            let pair = SWAP_PAIRS.with(|p| p.borrow().get(&ledger).cloned())
+                .ok_or(format!("Ledger {} is not a registered swap pair", ledger))?;
+            let symbol = pair.symbol.clone();
+            let transfer_fee = ledger_fee(ledger).await?;
+            let rate = get_cached_rate(&pair.xrc_symbol).await?;
+            let debit = amount.clone() + transfer_fee.clone();
+
+            // Reserve the backing before the transfer await
+            SWAP_BACKING.with(|backing| {
+                let mut backing = backing.borrow_mut();
+                let entry = backing.get_mut(&ledger)
+                    .ok_or(format!("Insufficient treasury {}: no swap backing", symbol))?;
+                let surplus = treasury_backing_surplus(entry, pair.decimals, &rate)?;
+                if surplus < debit {
+                    return Err(format!(
+                        "Insufficient treasury {}: surplus over outstanding ckALGO is {}, withdrawal plus ledger fee is {}",
+                        symbol, surplus, debit
+                    ));
+                }
+                entry.total_received -= debit.clone();
+                Ok(())
+            })?;
+            let restore_backing = || SWAP_BACKING.with(|backing| {
+                if let Some(entry) = backing.borrow_mut().get_mut(&ledger) {
+                    entry.total_received += debit.clone();
+                }
+            });
+
+            let transfer_args = TransferArgs {
+                from_subaccount: None,
+                to: to.clone(),
+                amount: amount.clone(),
+                fee: Some(transfer_fee.clone()),
+                memo: None,
+                created_at_time: None,
+            };
+            let transfer_result: Result<(Result<Nat, TransferError>,), _> =
+                ic_cdk::call(ledger, "icrc1_transfer", (transfer_args,)).await;
+
+            match transfer_result {
+                Ok((Ok(block_index),)) => (transfer_fee, Some(block_index)),
+                Ok((Err(e),)) => {
+                    restore_backing();
+                    return Err(format!("{} transfer failed: {:?}", symbol, e));
+                }
+                Err((code, msg)) => {
+                    restore_backing();
+                    return Err(format!("Inter-canister call to {} failed: {:?} - {}", symbol, code, msg));
+                }
+            }
+        }
+    };
+
+    let withdrawal = TREASURY_WITHDRAWALS.with(|withdrawals| {
+        let withdrawals = withdrawals.borrow();
+        let withdrawal = TreasuryWithdrawal {
+            withdrawal_id: withdrawals.len(),
+            asset,
+            to,
+            amount,
+            ledger_fee: ledger_fee_paid,
+            block_index,
+            withdrawn_by: caller_principal,
+            timestamp: time(),
+        };
+        withdrawals.append(&StableCandid(withdrawal.clone())).expect("Failed to append treasury withdrawal");
+        withdrawal
+    });
+
+    ic_cdk::println!("Treasury withdrawal {}: {:?}", withdrawal.withdrawal_id, withdrawal.asset);
+    Ok(withdrawal)
+}
+
+/// Most recent treasury withdrawals, newest first
+#[query]
+pub(crate) fn get_treasury_withdrawals(limit: Option<u32>) -> Vec<TreasuryWithdrawal> {
+    let limit = limit.unwrap_or(100) as u64;
+    TREASURY_WITHDRAWALS.with(|w| {
+        let withdrawals = w.borrow();
+        let end = withdrawals.len();
+        log_range_rev(&withdrawals, end.saturating_sub(limit), end)
+    })
+}
+
+// ============================================================================
+// SWAP ADMIN FUNCTIONS
+// ============================================================================
+
+/// Enable/disable swaps (Admin role)
+/// Disabling is an emergency pause and applies immediately; enabling is timelocked.
+#[update]
+pub(crate) fn set_swap_enabled(enabled: bool) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Enabling/disabling swaps requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+
+    if !enabled {
+        return apply_config_change(&ConfigChange::SwapEnabled(false));
+    }
+    Ok(queue_config_change(ConfigChange::SwapEnabled(true), caller_principal))
+}
+
+pub(crate) fn check_swap_fee_bps(fee_bps: u64) -> Result<(), String> {
+    if fee_bps > MAX_SWAP_FEE_BPS {
+        return Err("Fee cannot exceed 5% (500 bps)".to_string());
+    }
+    Ok(())
+}
+
+pub(crate) fn check_swap_limits_range(min: &Nat, max: &Nat) -> Result<(), String> {
+    if min >= max {
+        return Err("Minimum must be less than maximum".to_string());
+    }
+    Ok(())
+}
+
+/// Set the ckETH swap fee (Admin role, max 5%, timelocked)
+#[update]
+pub(crate) fn set_swap_fee_bps(fee_bps: u64) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Setting the swap fee requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_swap_fee_bps(fee_bps)?;
+
+    Ok(queue_config_change(ConfigChange::SwapFeeBps(fee_bps), caller_principal))
+}
+
+/// Set ckETH swap limits (Admin role, timelocked)
+#[update]
+pub(crate) fn set_swap_limits(min_cketh: Nat, max_cketh: Nat) -> Result<String, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::Admin) {
+        return Err("Setting swap limits requires the Admin role".to_string());
+    }
+    ensure_no_state_export()?;
+    check_swap_limits_range(&min_cketh, &max_cketh)?;
+
+    Ok(queue_config_change(ConfigChange::SwapLimits { min_cketh, max_cketh }, caller_principal))
+}
+
+/// Query swap configuration (ckETH pair; see get_swap_pairs for all assets)
+#[query]
+pub(crate) fn get_swap_config() -> SwapConfig {
+    let ledger = cketh_ledger();
+    let pair = SWAP_PAIRS.with(|pairs| pairs.borrow().get(&ledger).cloned())
+        .unwrap_or_else(|| default_swap_pairs().remove(&ledger).expect("default ckETH pair"));
+    let backing = SWAP_BACKING.with(|b| b.borrow().get(&ledger).cloned());
+
+    SwapConfig {
+        enabled: SWAP_ENABLED.with(|e| *e.borrow()),
+        fee_bps: pair.fee_bps,
+        min_cketh: pair.min_amount,
+        max_cketh: pair.max_amount,
+        cketh_backed_ckalgo: backing.as_ref().map(|b| b.ckalgo_minted.clone()).unwrap_or(Nat::from(0u64)),
+        total_cketh_received: backing.map(|b| b.total_received).unwrap_or(Nat::from(0u64)),
+    }
+}
+
+pub(crate) const MAX_SWAP_RECORDS_PAGE: u32 = 500;
+
+pub(crate) fn swap_record_matches(record: &SwapRecord, query: &SwapRecordQuery) -> bool {
+    let direction = record.direction.clone().unwrap_or(SwapDirection::ToCkAlgo);
+    let pair = record.pair.unwrap_or_else(cketh_ledger);
+    (query.user.is_none() || query.user == Some(record.user))
+        && (query.direction.is_none() || query.direction == Some(direction))
+        && (query.pair.is_none() || query.pair == Some(pair))
+}
+
+/// Query swap history, newest first, with cursor pagination and filters
+///
+/// Records are appended in time order, so to_time bounds where the scan starts
+/// and from_time where it stops. Pass next_cursor back as cursor for the next page.
+#[query]
+pub(crate) fn get_swap_records(query: SwapRecordQuery) -> SwapRecordPage {
+    let limit = query.limit.unwrap_or(100).min(MAX_SWAP_RECORDS_PAGE) as usize;
+    SWAP_RECORDS.with(|records| {
+        let records = records.borrow();
+        let timestamp_at = |index: u64| records.get(index).map(|r| r.0.timestamp).unwrap_or(u64::MAX);
+        let mut end = records.len();
+        if let Some(cursor) = query.cursor {
+            end = end.min(cursor);
+        }
+        if let Some(to_time) = query.to_time {
+            // Binary search for the first record after to_time
+            let (mut low, mut high) = (0, end);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if timestamp_at(mid) <= to_time {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            end = low;
+        }
+
+        let mut page = Vec::new();
+        let mut next_cursor = None;
+        for index in (0..end).rev() {
+            let Some(StableCandid(record)) = records.get(index) else { continue };
+            if query.from_time.is_some_and(|from| record.timestamp < from) {
+                break;
+            }
+            if !swap_record_matches(&record, &query) {
+                continue;
+            }
+            if page.len() == limit {
+                next_cursor = Some(page.last().map(|r: &IndexedSwapRecord| r.index).unwrap_or(index + 1));
+                break;
+            }
+            page.push(IndexedSwapRecord { index, record });
+        }
+
+        SwapRecordPage { records: page, next_cursor }
+    })
+}
+
+// ============================================================================
+// FIRM SWAP QUOTES
+// ============================================================================
+
+pub(crate) const SWAP_QUOTE_TTL_NS: u64 = 60 * 1_000_000_000; // 60 seconds
+pub(crate) const MAX_OPEN_SWAP_QUOTES: usize = 1_000;
+
+/// Price of a swap plus the quote it consumed (if any), so failures can restore it
+pub(crate) struct SwapPrice {
+    pub(crate) amount_out: Nat,
+    pub(crate) fee: Nat,
+    pub(crate) rate: RateFraction,
+    pub(crate) quote: Option<SwapQuote>,
+}
+
+pub(crate) fn prune_expired_quotes() {
+    let now = time();
+    SWAP_QUOTES.with(|quotes| quotes.borrow_mut().retain(|_, q| q.expires_at > now));
+}
+
+/// Put a consumed quote back if the swap failed before taking funds
+pub(crate) fn restore_swap_quote(quote: Option<SwapQuote>) {
+    if let Some(quote) = quote {
+        if quote.expires_at > time() {
+            SWAP_QUOTES.with(|quotes| quotes.borrow_mut().insert(quote.quote_id, quote));
+        }
+    }
+}
+
+/// Honour a valid quote (single use, no XRC call) or price at the cached XRC rate,
+/// then apply the caller's slippage bound
+pub(crate) async fn resolve_swap_price(
+    pair: &SwapPair,
+    amount_in: &Nat,
+    quote_id: Option<u64>,
+    min_ckalgo_out: Option<&Nat>
+) -> Result<SwapPrice, String> {
+    let price = match quote_id {
+        Some(quote_id) => {
+            // Remove before any await so the quote cannot be used twice
+            let quote = SWAP_QUOTES.with(|quotes| quotes.borrow_mut().remove(&quote_id))
+                .ok_or(format!("Quote {} not found or already used", quote_id))?;
+            if quote.expires_at <= time() {
+                return Err(format!("Quote {} expired at {}", quote_id, quote.expires_at));
+            }
+            if quote.pair != pair.ledger || quote.amount_in != *amount_in {
+                let message = format!(
+                    "Quote {} is for {} of {}, swap requested {} of {}",
+                    quote_id, quote.amount_in, quote.pair, amount_in, pair.ledger
+                );
+                restore_swap_quote(Some(quote));
+                return Err(message);
+            }
+            SwapPrice {
+                amount_out: quote.amount_out.clone(),
+                fee: quote.fee.clone(),
+                rate: quote.rate.clone(),
+                quote: Some(quote),
+            }
+        }
+        None => {
+            let rate = get_cached_rate(&pair.xrc_symbol).await?;
+            let (amount_out, fee) = compute_swap_output(amount_in, pair.decimals, &rate, pair.fee_bps)?;
+            SwapPrice { amount_out, fee, rate, quote: None }
+        }
+    };
+
+    if price.amount_out.0.is_zero() {
+        return Err("Output amount too small after fee".to_string());
+    }
+
+    // Slippage check
+    if let Some(min_out) = min_ckalgo_out {
+        if price.amount_out < *min_out {
+            let message = format!(
+                "Slippage exceeded: output {} < minimum {}",
+                price.amount_out, min_out
+            );
+            restore_swap_quote(price.quote);
+            return Err(message);
+        }
+    }
+
+    Ok(price)
+}
+
+/// Request a firm quote for swapping `amount_in` of the `pair` asset into ckALGO
+///
+/// The quote is valid for 60 seconds and can be passed once to the matching
+/// swap endpoint (transfer- or deposit-based), which then executes at exactly
+/// amount_out without re-querying XRC.
+/// Requires the SwapExecutor role (it pays for XRC calls)
+#[update]
+pub(crate) async fn request_swap_quote(amount_in: Nat, pair: Principal) -> Result<SwapQuote, String> {
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::SwapExecutor) {
+        return Err(format!(
+            "Unauthorized: requesting quotes requires the SwapExecutor role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    let swap_pair = active_swap_pair(&pair)?;
+    check_swap_limits(&swap_pair, &amount_in)?;
+
+    prune_expired_quotes();
+    if SWAP_QUOTES.with(|quotes| quotes.borrow().len()) >= MAX_OPEN_SWAP_QUOTES {
+        return Err(format!("Too many open quotes ({}). Retry shortly.", MAX_OPEN_SWAP_QUOTES));
+    }
+
+    let rate = get_cached_rate(&swap_pair.xrc_symbol).await?;
+    let (amount_out, fee) = compute_swap_output(&amount_in, swap_pair.decimals, &rate, swap_pair.fee_bps)?;
+    if amount_out.0.is_zero() {
+        return Err("Output amount too small after fee".to_string());
+    }
+
+    let now = time();
+    let quote_id = NEXT_QUOTE_ID.with(|id| {
+        let mut id = id.borrow_mut();
+        let quote_id = *id;
+        *id += 1;
+        quote_id
+    });
+
+    let quote = SwapQuote {
+        quote_id,
+        pair,
+        amount_in,
+        amount_out,
+        fee,
+        fee_bps: swap_pair.fee_bps,
+        rate,
+        created_at: now,
+        expires_at: now + SWAP_QUOTE_TTL_NS,
+    };
+    SWAP_QUOTES.with(|quotes| quotes.borrow_mut().insert(quote_id, quote.clone()));
+
+    Ok(quote)
+}
+
+/// Look up an outstanding (unused, unexpired) quote
+#[query]
+pub(crate) fn get_swap_quote(quote_id: u64) -> Option<SwapQuote> {
+    SWAP_QUOTES.with(|quotes| {
+        quotes.borrow().get(&quote_id).filter(|q| q.expires_at > time()).cloned()
+    })
+}
+
+// ============================================================================
+// DEPOSIT-BASED SWAP FUNCTIONS (Autonomous Agent Flow)
+// ============================================================================
+
+/// Derive custody subaccount for a principal (for swap deposits)
+///
+/// Agent should transfer the input asset to:
+///   Account { owner: simplified_bridge_canister, subaccount: Some(result) }
+/// on that asset's ledger. The same subaccount is used for every pair.
+///
+/// The subaccount is SHA256(principal.as_slice())[0:32]
+pub(crate) fn derive_custody_subaccount(principal: &Principal) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(principal.as_slice());
+    let result = hasher.finalize();
+    let mut subaccount = [0u8; 32];
+    subaccount.copy_from_slice(&result[..32]);
+    subaccount
+}
+
+/// Get custody subaccount for swap deposits (query)
+///
+/// Returns the 32-byte subaccount that the agent should deposit to.
+/// Full deposit account: { owner: this_canister, subaccount: result }
+#[query]
+pub(crate) fn get_swap_custody_subaccount(principal: Principal) -> Vec<u8> {
+    derive_custody_subaccount(&principal).to_vec()
+}
+
+/// Anti-replay key for a deposit: the bare tx_id for ckETH (unchanged from
+/// before the pair registry), "<ledger>:<tx_id>" for every other pair
+pub(crate) fn swap_deposit_key(ledger: &Principal, tx_id: &str) -> String {
+    if *ledger == cketh_ledger() {
+        tx_id.to_string()
+    } else {
+        format!("{}:{}", ledger, tx_id)
+    }
+}
+
+/// Check if a swap deposit has already been processed (anti-replay)
+///
+/// Non-ckETH deposits are keyed "<ledger>:<tx_id>".
+#[query]
+pub(crate) fn is_swap_deposit_processed(tx_id: String) -> bool {
+    PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow().contains(&tx_id))
+}
+
+/// Get the most recently processed swap deposit keys (for debugging/audit)
+#[query]
+pub(crate) fn get_processed_swap_deposits(limit: Option<u32>) -> Vec<String> {
+    let limit = limit.unwrap_or(100) as u64;
+    PROCESSED_SWAP_DEPOSIT_LOG.with(|log| {
+        let log = log.borrow();
+        let end = log.len();
+        log_range_rev(&log, end.saturating_sub(limit), end)
+            .into_iter()
+            .map(|d| d.deposit_key)
+            .collect()
+    })
+}
+
+/// Processed swap deposits with their results, newest first
+///
+/// `cursor` is the next_cursor of the previous page (entries with log index < cursor).
+#[query]
+pub(crate) fn get_processed_swap_deposit_history(cursor: Option<u64>, limit: Option<u32>) -> ProcessedSwapDepositPage {
+    let limit = limit.unwrap_or(100).min(MAX_SWAP_RECORDS_PAGE) as u64;
+    PROCESSED_SWAP_DEPOSIT_LOG.with(|log| {
+        let log = log.borrow();
+        let end = cursor.map_or(log.len(), |c| log.len().min(c));
+        let start = end.saturating_sub(limit);
+        ProcessedSwapDepositPage {
+            deposits: log_range_rev(&log, start, end),
+            next_cursor: if start > 0 { Some(start) } else { None },
+        }
+    })
+}
+
+pub(crate) fn log_processed_deposit(
+    pair: Principal,
+    tx_id: String,
+    agent: Principal,
+    amount: Nat,
+    result: ProcessedDepositResult
+) {
+    PROCESSED_SWAP_DEPOSIT_LOG.with(|log| {
+        log.borrow().append(&StableCandid(ProcessedSwapDeposit {
+            deposit_key: swap_deposit_key(&pair, &tx_id),
+            pair,
+            tx_id,
+            agent: Some(agent),
+            amount: Some(amount),
+            result,
+            processed_at: time(),
+        })).expect("Failed to append processed deposit log entry")
+    });
+}
+
+/// Rebuild the processed deposit log from the legacy key set, matching each key
+/// against the swap and refund records
+pub(crate) fn rebuild_processed_deposit_log(
+    keys: Vec<String>,
+    swap_records: &[SwapRecord],
+    refunds: &[SwapRefund]
+) -> Vec<ProcessedSwapDeposit> {
+    let mut log: Vec<ProcessedSwapDeposit> = keys.into_iter().map(|deposit_key| {
+        let (pair, tx_id) = match deposit_key.split_once(':') {
+            Some((ledger, tx_id)) => (
+                Principal::from_text(ledger).unwrap_or_else(|_| cketh_ledger()),
+                tx_id.to_string(),
+            ),
+            None => (cketh_ledger(), deposit_key.clone()),
+        };
+
+        let swap_tx_id = format!("DEPOSIT_SWAP_{}", tx_id);
+        let swap = swap_records.iter().enumerate()
+            .find(|(_, r)| r.tx_id == swap_tx_id && r.pair.unwrap_or_else(cketh_ledger) == pair)
+            .map(|(index, r)| (index as u64, r.clone()));
+        let refund = refunds.iter()
+            .find(|r| r.pair == pair && r.deposit_block_index.0.to_string() == tx_id)
+            .cloned();
+
+        let (agent, amount, result, processed_at) = match (swap, refund) {
+            (Some((swap_index, r)), _) => (
+                Some(r.user),
+                Some(r.cketh_in),
+                ProcessedDepositResult::Swapped { swap_index, ckalgo_out: r.ckalgo_out },
+                r.timestamp,
+            ),
+            (None, Some(r)) => (
+                Some(r.agent),
+                Some(r.deposit_amount),
+                ProcessedDepositResult::Refunded { refund_id: r.refund_id },
+                r.timestamp,
+            ),
+            (None, None) => (None, None, ProcessedDepositResult::Unknown, 0),
+        };
+
+        ProcessedSwapDeposit { deposit_key, pair, tx_id, agent, amount, result, processed_at }
+    }).collect();
+
+    log.sort_by_key(|d| d.processed_at);
+    log
+}
+
+/// Fetch a single transaction from an ICRC-1 ledger, following the archive
+/// callback if the block has been archived
+pub(crate) async fn fetch_ledger_transaction(ledger: Principal, block_index: &Nat) -> Result<LedgerTransaction, String> {
+    let request = GetTransactionsRequest {
+        start: block_index.clone(),
+        length: Nat::from(1u64),
+    };
+
+    let (response,): (GetTransactionsResponse,) = ic_cdk::call(ledger, "get_transactions", (request.clone(),))
+        .await
+        .map_err(|(code, msg)| format!("get_transactions on {} failed: {:?} - {}", ledger, code, msg))?;
+
+    if let Some(tx) = response.transactions.into_iter().next() {
+        if response.first_index == *block_index {
+            return Ok(tx);
+        }
+    }
+
+    let archive = response.archived_transactions.into_iter()
+        .find(|range| range.start <= *block_index && *block_index < range.start.clone() + range.length.clone())
+        .ok_or(format!(
+            "Block {} not found on ledger {} (log length {})",
+            block_index, ledger, response.log_length
+        ))?;
+
+    let (range,): (TransactionRange,) = ic_cdk::call(archive.callback.0.principal, &archive.callback.0.method, (request,))
+        .await
+        .map_err(|(code, msg)| format!("Archive lookup for block {} failed: {:?} - {}", block_index, code, msg))?;
+
+    range.transactions.into_iter().next()
+        .ok_or(format!("Block {} missing from archive {}", block_index, archive.callback.0.principal))
+}
+
+/// Parse a deposit tx_id (ledger block index). Canonicalised so "042" and "42"
+/// cannot be replayed as different deposits.
+pub(crate) fn parse_deposit_block_index(tx_id: &str) -> Result<Nat, String> {
+    tx_id.trim().parse::<u64>()
+        .map(Nat::from)
+        .map_err(|_| format!("Invalid deposit tx_id '{}': expected a ledger block index", tx_id))
+}
+
+/// Fetch `block_index` from the pair's ledger and check it is a transfer into
+/// the agent's custody subaccount on this canister
+pub(crate) async fn fetch_custody_deposit(
+    pair: &SwapPair,
+    agent_principal: &Principal,
+    block_index: &Nat
+) -> Result<LedgerTransfer, String> {
+    let tx = fetch_ledger_transaction(pair.ledger, block_index).await?;
+    check_custody_deposit(pair, agent_principal, block_index, tx, ic_cdk::api::id())
+}
+
+/// Check that a ledger block is a transfer into the agent's custody subaccount
+/// on `canister`
+pub(crate) fn check_custody_deposit(
+    pair: &SwapPair,
+    agent_principal: &Principal,
+    block_index: &Nat,
+    tx: LedgerTransaction,
+    canister: Principal
+) -> Result<LedgerTransfer, String> {
+    let transfer = match (tx.kind.as_str(), tx.transfer) {
+        ("transfer", Some(transfer)) => transfer,
+        (kind, _) => return Err(format!("{} block {} is a {}, not a transfer", pair.symbol, block_index, kind)),
+    };
+
+    let custody = Account {
+        owner: canister,
+        subaccount: Some(derive_custody_subaccount(agent_principal)),
+    };
+    if transfer.to.owner != custody.owner || transfer.to.subaccount != custody.subaccount {
+        return Err(format!(
+            "{} block {} does not pay the custody subaccount of {}",
+            pair.symbol, block_index, agent_principal
+        ));
+    }
+
+    Ok(transfer)
+}
+
+/// Verify that `block_index` on the pair's ledger is a transfer of exactly
+/// `amount` into the agent's custody subaccount on this canister
+pub(crate) async fn verify_swap_deposit(
+    pair: &SwapPair,
+    agent_principal: &Principal,
+    block_index: &Nat,
+    amount: &Nat
+) -> Result<(), String> {
+    let transfer = fetch_custody_deposit(pair, agent_principal, block_index).await?;
+    if transfer.amount != *amount {
+        return Err(format!(
+            "{} block {} transferred {}, swap requested {}",
+            pair.symbol, block_index, transfer.amount, amount
+        ));
+    }
+
+    Ok(())
+}
+
+/// Swap a registered asset for ckALGO - Deposit-Based (Autonomous Agent Flow)
+///
+/// SECURITY: Requires the SwapExecutor role
+///
+/// Flow:
+/// 1. Agent transfers the input asset to its custody subaccount (standard ICRC-1 transfer)
+/// 2. Agent calls backend POST /swap/execute with the transfer's block index as tx_id
+/// 3. Backend calls this function with deposit details
+/// 4. Canister verifies deposit not already processed (anti-replay)
+/// 5. Canister fetches the block from the input ledger (get_transactions) and checks
+///    it is a transfer of `amount_in` into the agent's custody subaccount
+/// 6. Canister gets exchange rate (cached, from XRC)
+/// 7. Canister marks deposit as processed
+/// 8. Canister mints ckALGO to agent's principal
+///
+/// The backend is not trusted: a tx_id that is not a matching ledger transfer is rejected.
+#[update]
+pub(crate) async fn swap_deposit_to_ckalgo(
+    pair: Principal,
+    agent_principal: Principal,
+    amount_in: Nat,
+    tx_id: String,
+    min_ckalgo_out: Option<Nat>,
+    quote_id: Option<u64>
+) -> Result<SwapResult, String> {
+    execute_deposit_swap(pair, agent_principal, amount_in, tx_id, min_ckalgo_out, quote_id).await
+}
+
+/// Swap ckETH for ckALGO - Deposit-Based (the ckETH pair of swap_deposit_to_ckalgo)
+#[update]
+pub(crate) async fn swap_cketh_for_ckalgo_deposit(
+    agent_principal: Principal,
+    cketh_amount: Nat,
+    cketh_tx_id: String,
+    min_ckalgo_out: Option<Nat>,
+    quote_id: Option<u64>
+) -> Result<SwapResult, String> {
+    execute_deposit_swap(cketh_ledger(), agent_principal, cketh_amount, cketh_tx_id, min_ckalgo_out, quote_id).await
+}
+
+pub(crate) async fn execute_deposit_swap(
+    ledger: Principal,
+    agent_principal: Principal,
+    amount_in: Nat,
+    tx_id: String,
+    min_ckalgo_out: Option<Nat>,
+    quote_id: Option<u64>
+) -> Result<SwapResult, String> {
+    // 1. Authorization check
+    let caller_principal = caller();
+    if !has_role(&caller_principal, Role::SwapExecutor) {
+        return Err(format!(
+            "Unauthorized: executing deposit swaps requires the SwapExecutor role. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    // 2. Check the pair is registered and enabled
+    let pair = active_swap_pair(&ledger)?;
+
+    // tx_id is the ledger block index of the deposit transfer
+    let block_index = parse_deposit_block_index(&tx_id)?;
+    let tx_id = block_index.0.to_string();
+
+    // 3. Check for duplicate tx_id (anti-replay protection)
+    let deposit_key = swap_deposit_key(&ledger, &tx_id);
+    if PROCESSED_SWAP_DEPOSITS.with(|deposits| deposits.borrow().contains(&deposit_key)) {
+        return Err(format!("Deposit {} already processed", tx_id));
+    }
+
+    // 4. Validate amount within limits
+    check_swap_limits(&pair, &amount_in)?;
+
+    // 5. Verify the deposit on the input ledger itself, and that the custody
+    // balance still holds it uncredited (the deposit watcher may have swapped it)
+    verify_swap_deposit(&pair, &agent_principal, &block_index, &amount_in).await?;
+    let _custody_guard = CustodyGuard::acquire(ledger, agent_principal)?;
+    check_deposit_not_bulk_credited(&ledger, &agent_principal, &block_index)?;
+    let uncredited = uncredited_custody_balance(&ledger, &agent_principal).await?;
+    if uncredited < amount_in {
+        return Err(format!(
+            "Deposit {} already credited: custody subaccount holds only {} uncredited",
+            tx_id, uncredited
+        ));
+    }
+
+    // 6. Price the swap (firm quote or cached XRC rate) and check slippage
+    let price = resolve_swap_price(&pair, &amount_in, quote_id, min_ckalgo_out.as_ref()).await?;
+
+    // Re-check anti-replay: another call may have processed this deposit while we awaited
+    if PROCESSED_SWAP_DEPOSITS.with(|deposits| deposits.borrow().contains(&deposit_key)) {
+        restore_swap_quote(price.quote);
+        return Err(format!("Deposit {} already processed", tx_id));
+    }
+
+    // 7. Mark deposit as processed BEFORE minting (prevents double-processing on retry)
+    PROCESSED_SWAP_DEPOSITS.with(|deposits| {
+        deposits.borrow_mut().insert(deposit_key);
+    });
+    add_custody_credited(ledger, agent_principal, &amount_in);
+
+    // 8. Mint, track backing, record and return
+    let record_tx_id = format!("DEPOSIT_SWAP_{}", tx_id);
+    let result = credit_swap(
+        &pair,
+        agent_principal,
+        amount_in.clone(),
+        price,
+        record_tx_id,
+        block_index,
+    );
+    let swap_index = SWAP_RECORDS.with(|r| r.borrow().len() - 1);
+    log_processed_deposit(ledger, tx_id, agent_principal, amount_in, ProcessedDepositResult::Swapped {
+        swap_index,
+        ckalgo_out: result.ckalgo_out.clone(),
+    });
+    Ok(result)
+}
+
+// ============================================================================
+// SWAP DEPOSIT REFUNDS
+// ============================================================================
+//
+// A deposit that was never swapped (outside limits, slippage, rate breaker,
+// swaps disabled...) can be returned to the account that sent it, minus the
+// ledger fee. Refunding marks the deposit processed so it can no longer be
+// swapped. A deposit the watcher may already have swapped cannot be refunded:
+// the block must be newer than the watcher's last credit, and the custody
+// balance must still hold it uncredited.
+
+/// Refund an unswapped deposit to its sender
+///
+/// Callable by the agent, the original sender or a SwapExecutor.
+#[update]
+pub(crate) async fn refund_swap_deposit(
+    pair: Principal,
+    agent: Principal,
+    tx_id: String,
+    reason: Option<String>
+) -> Result<SwapRefund, String> {
+    let caller_principal = caller();
+    let swap_pair = SWAP_PAIRS.with(|pairs| pairs.borrow().get(&pair).cloned())
+        .ok_or(format!("Unsupported swap pair: {}", pair))?;
+
+    let block_index = parse_deposit_block_index(&tx_id)?;
+    let tx_id = block_index.0.to_string();
+    let deposit_key = swap_deposit_key(&pair, &tx_id);
+    if PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow().contains(&deposit_key)) {
+        return Err(format!("Deposit {} was already swapped or refunded", tx_id));
+    }
+    ensure_no_state_export()?;
+
+    let deposit = fetch_custody_deposit(&swap_pair, &agent, &block_index).await?;
+    if caller_principal != agent
+        && caller_principal != deposit.from.owner
+        && !has_role(&caller_principal, Role::SwapExecutor)
+    {
+        return Err(format!(
+            "Unauthorized: only the agent, the sender or a SwapExecutor can refund a deposit. Caller: {}",
+            caller_principal
+        ));
+    }
+
+    let _custody_guard = CustodyGuard::acquire(pair, agent)?;
+    check_deposit_not_bulk_credited(&pair, &agent, &block_index)?;
+    let fee = ledger_fee(pair).await?;
+    if deposit.amount <= fee {
+        return Err(format!("Deposit {} of {} does not cover the ledger fee {}", tx_id, deposit.amount, fee));
+    }
+    let uncredited = uncredited_custody_balance(&pair, &agent).await?;
+    if uncredited < deposit.amount {
+        return Err(format!(
+            "Deposit {} already credited: custody subaccount holds only {} uncredited",
+            tx_id, uncredited
+        ));
+    }
+
+    // Claim the deposit before the transfer await so it cannot be swapped meanwhile
+    if !PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow_mut().insert(deposit_key.clone())) {
+        return Err(format!("Deposit {} was already swapped or refunded", tx_id));
+    }
+
+    let amount_returned = deposit.amount.clone() - fee.clone();
+    let transfer_args = TransferArgs {
+        from_subaccount: Some(derive_custody_subaccount(&agent)),
+        to: deposit.from.clone(),
+        amount: amount_returned.clone(),
+        fee: Some(fee.clone()),
+        memo: None,
+        created_at_time: Some(time()),
+    };
+
+    let transfer_result: Result<(Result<Nat, TransferError>,), _> =
+        ic_cdk::call(pair, "icrc1_transfer", (transfer_args,)).await;
+
+    let refund_block_index = match transfer_result {
+        Ok((Ok(block_index),)) => block_index,
+        Ok((Err(e),)) => {
+            PROCESSED_SWAP_DEPOSITS.with(|d| d.borrow_mut().remove(&deposit_key));
+            return Err(format!("{} refund transfer failed: {:?}", swap_pair.symbol, e));
+        }
+        Err((code, msg)) => {
+            // The refund may still have executed, so the deposit stays claimed
+            // until an operator checks the ledger
+            return Err(format!(
+                "{} refund of deposit {} has an unknown outcome ({:?} - {}); the deposit stays marked processed",
+                swap_pair.symbol, tx_id, code, msg
+            ));
+        }
+    };
+
+    let refund = SWAP_REFUNDS.with(|refunds| {
+        let refunds = refunds.borrow();
+        let refund = SwapRefund {
+            refund_id: refunds.len(),
+            pair,
+            agent,
+            deposit_block_index: block_index,
+            deposit_amount: deposit.amount,
+            refunded_to: deposit.from,
+            amount_returned,
+            ledger_fee: fee,
+            refund_block_index,
+            reason: reason.unwrap_or_else(|| "Refund requested".to_string()),
+            requested_by: caller_principal,
+            timestamp: time(),
+        };
+        refunds.append(&StableCandid(refund.clone())).expect("Failed to append swap refund");
+        refund
+    });
+    log_processed_deposit(pair, tx_id, agent, refund.deposit_amount.clone(), ProcessedDepositResult::Refunded {
+        refund_id: refund.refund_id,
+    });
+
+    ic_cdk::println!(
+        "Refunded {} deposit {} of {} to {} (block {})",
+        swap_pair.symbol, refund.deposit_block_index, agent, refund.refunded_to.owner, refund.refund_block_index
+    );
+    Ok(refund)
+}
+
+/// Refund history, most recent first, optionally for one agent
+#[query]
+pub(crate) fn get_swap_refunds(agent: Option<Principal>, limit: Option<u32>) -> Vec<SwapRefund> {
+    let limit = limit.unwrap_or(100) as usize;
+    SWAP_REFUNDS.with(|refunds| {
+        let refunds = refunds.borrow();
+        (0..refunds.len()).rev()
+            .filter_map(|index| refunds.get(index))
+            .map(|refund| refund.0)
+            .filter(|r| agent.is_none() || agent == Some(r.agent))
+            .take(limit)
+            .collect()
+    })
+}
@@ -0,0 +1,75 @@
+#!/bin/bash
+
+# Regenerate stable_storage_v1.bin: the single-blob StableStorage that
+# pre_upgrade wrote in the last release before the state envelope.
+#
+# Usage: ./generate_v1.sh [commit]
+#
+# Checks out that release in a temporary git worktree, makes its pre_upgrade
+# write the stable_save bytes to this directory instead of stable memory, seeds
+# the fixture state (see FIXTURES in src/lib.rs) from a test and runs it.
+# Nothing in the current checkout changes apart from the fixture.
+
+set -e
+
+# Last release deployed with the single-blob layout
+V1_COMMIT="${1:-f897f2adc449b9b48879bd091c26eb516ad862f2}"
+
+FIXTURES_DIR="$(cd "$(dirname "$0")" && pwd)"
+REPO_ROOT="$(git -C "$FIXTURES_DIR" rev-parse --show-toplevel)"
+WORKTREE="$(mktemp -d)"
+
+cleanup() {
+    git -C "$REPO_ROOT" worktree remove --force "$WORKTREE" >/dev/null 2>&1 || true
+    rm -rf "$WORKTREE"
+}
+trap cleanup EXIT
+
+git -C "$REPO_ROOT" worktree add --quiet --detach "$WORKTREE" "$V1_COMMIT"
+LIB="$WORKTREE/src/canisters/simplified_bridge/src/lib.rs"
+
+# stable_save((stable_data,)) writes the candid encoding of the argument tuple
+SAVE='ic_cdk::storage::stable_save((stable_data,))'
+grep -qF "$SAVE" "$LIB" || { echo "pre_upgrade at $V1_COMMIT does not call $SAVE" >&2; exit 1; }
+sed -i "s|ic_cdk::storage::stable_save((stable_data,))|std::fs::write(std::env::var(\"FIXTURE_OUT\").unwrap(), candid::encode_args((stable_data,)).unwrap())|" "$LIB"
+
+cat >> "$LIB" <<'EOF'
+
+#[cfg(test)]
+mod fixture_gen {
+    use super::*;
+
+    #[test]
+    fn write_stable_storage_v1() {
+        let alice = Principal::from_slice(&[1; 29]);
+        let bob = Principal::from_slice(&[2; 29]);
+        let minter = Principal::from_slice(&[3; 29]);
+
+        BALANCES.with(|b| {
+            let mut b = b.borrow_mut();
+            b.insert(alice.to_text(), Nat::from(200_000u64));
+            b.insert(bob.to_text(), Nat::from(100_000u64));
+        });
+        DEPOSIT_RECORDS.with(|r| r.borrow_mut().push(DepositRecord {
+            deposit_id: "DEP1".to_string(),
+            user: alice,
+            custody_address: "CUSTODY".to_string(),
+            amount: Nat::from(300_000u64),
+            algorand_tx_id: "DEP1".to_string(),
+            confirmed_at: 1_700_000_000_000_000_000,
+            minted_ck_algo: Nat::from(300_000u64),
+        }));
+        PROCESSED_SWAP_DEPOSITS.with(|d| { d.borrow_mut().insert("42".to_string()); });
+        TOTAL_SUPPLY.with(|s| *s.borrow_mut() = Nat::from(300_000u64));
+        LOCKED_ALGO_RESERVES.with(|s| *s.borrow_mut() = Nat::from(300_000u64));
+        DEPOSIT_ADDRESSES.with(|a| { a.borrow_mut().insert("CUSTODY".to_string(), alice); });
+        AUTHORIZED_MINTERS.with(|m| m.borrow_mut().push(minter));
+
+        pre_upgrade();
+    }
+}
+EOF
+
+cd "$WORKTREE"
+FIXTURE_OUT="$FIXTURES_DIR/stable_storage_v1.bin" cargo test --quiet -p simplified_bridge write_stable_storage_v1
+echo "Wrote $FIXTURES_DIR/stable_storage_v1.bin"
@@ -3,11 +3,11 @@ use ic_cdk::api::management_canister::schnorr::{
     SchnorrAlgorithm, SchnorrKeyId, SchnorrPublicKeyArgument,
     SignWithSchnorrArgument, SchnorrPublicKeyResponse, SignWithSchnorrResponse,
 };
-use hex;
 use ic_cdk::api::call::call_with_payment;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256, Sha512_256};
+use sha2::{Digest, Sha256};
 use sha2::Sha512_256 as ForcedSha512_256;
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -31,6 +31,74 @@ pub struct SigningError {
 
 type SigningResult<T> = Result<T, SigningError>;
 
+/// Install/upgrade argument. `backend` is the principal the Sippar backend signs
+/// its calls with (SIPPAR_BACKEND_IDENTITY_SEED); on upgrade, None keeps the
+/// current one.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SignerInitArgs {
+    pub backend: Option<Principal>,
+}
+
+// Simplified bridge: signs custody sweeps and hot wallet moves inter-canister
+const SIMPLIFIED_BRIDGE_CANISTER_ID: &str = "hldvt-2yaaa-aaaak-qulxa-cai";
+const UNAUTHORIZED_CODE: u32 = 403;
+
+thread_local! {
+    static BACKEND_PRINCIPAL: RefCell<Option<Principal>> = const { RefCell::new(None) };
+}
+
+#[ic_cdk::init]
+fn init(args: Option<SignerInitArgs>) {
+    BACKEND_PRINCIPAL.with(|b| *b.borrow_mut() = args.and_then(|args| args.backend));
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    let backend = BACKEND_PRINCIPAL.with(|b| *b.borrow());
+    ic_cdk::storage::stable_save((SignerInitArgs { backend },)).expect("Failed to save signer state");
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: Option<SignerInitArgs>) {
+    // Versions before the allowlist saved nothing
+    let (saved,): (SignerInitArgs,) = ic_cdk::storage::stable_restore().unwrap_or_default();
+    let backend = args.and_then(|args| args.backend).or(saved.backend);
+    BACKEND_PRINCIPAL.with(|b| *b.borrow_mut() = backend);
+}
+
+/// The bridge canister, the configured backend principal and controllers may sign
+fn is_signing_caller(caller: &Principal) -> bool {
+    Principal::from_text(SIMPLIFIED_BRIDGE_CANISTER_ID).is_ok_and(|bridge| *caller == bridge)
+        || BACKEND_PRINCIPAL.with(|b| *b.borrow() == Some(*caller))
+        || ic_cdk::api::is_controller(caller)
+}
+
+fn authorize_signing_caller() -> SigningResult<()> {
+    let caller = ic_cdk::caller();
+    if is_signing_caller(&caller) {
+        Ok(())
+    } else {
+        Err(SigningError {
+            code: UNAUTHORIZED_CODE,
+            message: format!("Caller {} is not authorized to sign transactions", caller),
+        })
+    }
+}
+
+/// The migration helper pays for key derivation, so only controllers may call it,
+/// inter-canister as well as through ingress
+fn authorize_controller_caller() -> SigningResult<()> {
+    let caller = ic_cdk::caller();
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(())
+    } else {
+        Err(SigningError {
+            code: UNAUTHORIZED_CODE,
+            message: format!("Caller {} is not a controller", caller),
+        })
+    }
+}
+
 // Key ID name for threshold Schnorr signatures (mainnet Ed25519 key)
 const KEY_NAME: &str = "key_1";
 
@@ -87,6 +155,8 @@ async fn derive_algorand_address(user_principal: Principal) -> SigningResult<Alg
 /// MIGRATION: Derive address using OLD derivation method (for verification)
 #[ic_cdk::update]
 async fn derive_old_algorand_address(user_principal: Principal) -> SigningResult<AlgorandAddress> {
+    authorize_controller_caller()?;
+
     // Use OLD derivation method (raw principal bytes) for migration verification
     let derivation_path = vec![
         user_principal.as_slice().to_vec(),
@@ -128,6 +198,8 @@ async fn sign_migration_transaction(
     user_principal: Principal,
     transaction_bytes: Vec<u8>,
 ) -> SigningResult<SignedTransaction> {
+    authorize_signing_caller()?;
+
     // Use OLD derivation method (raw principal bytes) for migration
     let derivation_path = vec![
         user_principal.as_slice().to_vec(),
@@ -151,7 +223,7 @@ async fn sign_migration_transaction(
         Ok((response,)) => {
             let tx_id = hex::encode(Sha256::digest(&transaction_bytes));
             Ok(SignedTransaction {
-                transaction_bytes: transaction_bytes,
+                transaction_bytes,
                 signature: response.signature,
                 signed_tx_id: tx_id,
             })
@@ -169,6 +241,8 @@ async fn sign_algorand_transaction(
     user_principal: Principal,
     transaction_bytes: Vec<u8>,
 ) -> SigningResult<SignedTransaction> {
+    authorize_signing_caller()?;
+
     // Use same principal hashing as address derivation for consistency
     let principal_hash = Sha256::digest(user_principal.as_slice());
     let derivation_key = &principal_hash[0..4];
@@ -200,7 +274,7 @@ async fn sign_algorand_transaction(
             // This is the working approach from our breakthrough
             
             Ok(SignedTransaction {
-                transaction_bytes: transaction_bytes,
+                transaction_bytes,
                 signature: signature_response.signature,
                 signed_tx_id,
             })
@@ -212,6 +286,44 @@ async fn sign_algorand_transaction(
     }
 }
 
+// Ingress filtering: reject anonymous update calls, drop signing requests from
+// callers outside the signing allowlist, keep the migration address helper to
+// controllers, and cap payload sizes before consensus. Inter-canister calls
+// (e.g. from simplified_bridge) are not inspected, so the sign_* methods check
+// the allowlist, and the migration helper the controllers, themselves.
+const MAX_TRANSACTION_PAYLOAD_BYTES: usize = 16 * 1024; // Algorand transaction + principal
+const MAX_DEFAULT_PAYLOAD_BYTES: usize = 1024;
+
+fn check_ingress(method: &str, caller: &Principal, payload_size: usize) -> Result<(), String> {
+    if *caller == Principal::anonymous() {
+        return Err("Anonymous update calls are not accepted".to_string());
+    }
+
+    let (authorized, max_payload) = match method {
+        "sign_algorand_transaction" | "sign_migration_transaction" => {
+            (is_signing_caller(caller), MAX_TRANSACTION_PAYLOAD_BYTES)
+        }
+        "derive_old_algorand_address" => (ic_cdk::api::is_controller(caller), MAX_DEFAULT_PAYLOAD_BYTES),
+        _ => (true, MAX_DEFAULT_PAYLOAD_BYTES),
+    };
+    if payload_size > max_payload {
+        return Err(format!("Payload of {} bytes exceeds the {} byte limit for {}", payload_size, max_payload, method));
+    }
+    if !authorized {
+        return Err(format!("Caller {} is not authorized to call {}", caller, method));
+    }
+    Ok(())
+}
+
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    match check_ingress(&method, &ic_cdk::caller(), ic_cdk::api::call::arg_data_raw_size()) {
+        Ok(()) => ic_cdk::api::call::accept_message(),
+        Err(reason) => ic_cdk::trap(&reason),
+    }
+}
+
 /// Get the canister's status and available features
 #[ic_cdk::query]
 fn get_canister_status() -> HashMap<String, String> {